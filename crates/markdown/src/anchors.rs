@@ -0,0 +1,124 @@
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+// The characters percent-encoded in URL fragments, the same as `Url::set_fragment`, so
+// that links to headings match the ones built with `get_url`.
+const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
+
+/// How ids are made for headings without an explicit `{#id}`.
+///
+/// Headings left without any letters or digits, like ones that are only emoji, are
+/// numbered whatever the style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnchorStyle {
+    /// Keep letters and digits from any script, like `日本語の見出し`. Links to them are
+    /// percent-encoded.
+    #[default]
+    Unicode,
+    /// Only keep ASCII letters and digits, dropping accents, so `Café` becomes `cafe`.
+    Ascii,
+    /// Number headings in the order they're in, like `section-2` for the second.
+    Numbered,
+}
+
+impl AnchorStyle {
+    /// The id for the `n`th heading, counting from 1, with the given text.
+    pub fn id(self, text: &str, n: usize) -> String {
+        let slug = match self {
+            Self::Unicode => slugify(text),
+            Self::Ascii => slugify(&text.nfkd().filter(char::is_ascii).collect::<String>()),
+            Self::Numbered => String::new(),
+        };
+
+        if slug.is_empty() {
+            format!("section-{n}")
+        } else {
+            slug
+        }
+    }
+}
+
+// Make an id for a heading from its text, which is lowercased, with runs of whitespace,
+// dashes, and underscores turned into a single dash, and other punctuation removed. En and
+// em dashes count as dashes, so that smart punctuation doesn't change ids.
+fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if (c.is_whitespace() || matches!(c, '-' | '–' | '—' | '_')) && !slug.ends_with('-')
+        {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_matches('-').to_owned()
+}
+
+/// The link to the element with the given id in the same page.
+pub fn fragment_href(id: &str) -> String {
+    format!("#{}", utf8_percent_encode(id, FRAGMENT))
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use minijinja::Environment;
+
+    use super::*;
+    use crate::MarkdownRenderer;
+
+    const CONTENT: &str = "---\ntitle = \"Test\"\ntags = []\n---\n\n## 日本語の見出し\n\n## 🚀 Launch Café\n\n## 🎉\n";
+
+    #[test]
+    fn test_anchor_styles() -> Result<()> {
+        let cases = [
+            (
+                AnchorStyle::Unicode,
+                ["日本語の見出し", "launch-café", "section-3"],
+            ),
+            (
+                AnchorStyle::Ascii,
+                ["section-1", "launch-cafe", "section-3"],
+            ),
+            (
+                AnchorStyle::Numbered,
+                ["section-1", "section-2", "section-3"],
+            ),
+        ];
+
+        for (style, expected) in cases {
+            let document = MarkdownRenderer::new::<&str>(None, None)?
+                .with_anchor_style(style)
+                .parse_from_string(CONTENT, &Environment::empty())?;
+            let ids = document
+                .toc
+                .iter()
+                .map(|h| h.id.clone().unwrap_or_default())
+                .collect::<Vec<String>>();
+            assert_eq!(ids, expected, "{style:?}");
+
+            // Ids are written as they are, and percent-encoded in links to them.
+            for id in ids {
+                assert!(
+                    document
+                        .content
+                        .contains(&format!("id=\"{id}\"><a href=\"{}\">", fragment_href(&id))),
+                    "{style:?}: {id}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fragment_href() {
+        assert_eq!(fragment_href("日本語"), "#%E6%97%A5%E6%9C%AC%E8%AA%9E");
+        assert_eq!(fragment_href("launch-café"), "#launch-caf%C3%A9");
+        assert_eq!(fragment_href("a b"), "#a%20b");
+        assert_eq!(fragment_href("part-1"), "#part-1");
+    }
+}
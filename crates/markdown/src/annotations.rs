@@ -0,0 +1,201 @@
+use std::{ops::Range, path::Path};
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+use crate::shortcodes::Expansion;
+
+/// Maps byte offsets in markdown with its shortcodes evaluated back to lines in the file
+/// it was read from.
+pub struct SourceMap<'a> {
+    path: String,
+    file: &'a str,
+    body_start: usize,
+    expansions: &'a [Expansion],
+}
+
+impl<'a> SourceMap<'a> {
+    /// Map offsets in the evaluated `body` of `file`, which was read from `path`.
+    pub fn new(path: &Path, file: &'a str, body: &str, expansions: &'a [Expansion]) -> Self {
+        Self {
+            // A path can't end the comment it's written in.
+            path: path.display().to_string().replace("--", "-%2D"),
+            file,
+            body_start: file.len() - body.len(),
+            expansions,
+        }
+    }
+
+    /// The line in the file the given offset comes from, counting from 1. Offsets in the
+    /// output of a shortcode come from the line the shortcode starts on.
+    pub fn line(&self, offset: usize) -> usize {
+        let mut offset_in_body = offset;
+        for expansion in self.expansions {
+            if offset >= expansion.output.end {
+                offset_in_body = expansion.source.end + (offset - expansion.output.end);
+            } else if offset >= expansion.output.start {
+                offset_in_body = expansion.source.start;
+                // Shortcodes swallow the whitespace before them.
+                let source = &self.file[self.body_start..];
+                offset_in_body += source[expansion.source.clone()].len()
+                    - source[expansion.source.clone()].trim_start().len();
+                break;
+            } else {
+                break;
+            }
+        }
+
+        let offset_in_file = (self.body_start + offset_in_body).min(self.file.len());
+        self.file[..offset_in_file].matches('\n').count() + 1
+    }
+
+    fn annotation(&self, offset: usize) -> String {
+        format!("<!-- yar: {}:{} -->", self.path, self.line(offset))
+    }
+}
+
+/// Put an HTML comment naming the source line before every paragraph, heading, and
+/// shortcode output.
+///
+/// Nothing in code blocks or raw HTML is annotated, so annotations never end up in
+/// `<pre>` blocks or the middle of a tag.
+pub fn annotate<'a, 'b>(
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)> + 'b,
+    map: &'b SourceMap<'b>,
+) -> impl Iterator<Item = (Event<'a>, Range<usize>)> + 'b
+where
+    'a: 'b,
+{
+    let mut in_code_block = false;
+    let mut in_html_block = false;
+    let mut annotated = None;
+
+    events.flat_map(move |(event, range)| {
+        let annotate = match &event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                false
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                false
+            }
+            Event::Start(Tag::HtmlBlock) => {
+                in_html_block = true;
+                starts_expansion(map, range.start)
+            }
+            Event::End(TagEnd::HtmlBlock) => {
+                in_html_block = false;
+                false
+            }
+            Event::Start(Tag::Paragraph | Tag::Heading { .. }) => true,
+            Event::End(_) => false,
+            _ => !in_code_block && !in_html_block && starts_expansion(map, range.start),
+        };
+
+        // Events starting at the same place, like a paragraph and the shortcode output in
+        // it, share an annotation.
+        let annotation = (annotate && annotated != Some(range.start)).then(|| {
+            annotated = Some(range.start);
+            (
+                Event::Html(map.annotation(range.start).into()),
+                range.clone(),
+            )
+        });
+        annotation.into_iter().chain([(event, range)])
+    })
+}
+
+fn starts_expansion(map: &SourceMap, offset: usize) -> bool {
+    map.expansions.iter().any(|e| e.output.start == offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use minijinja::Environment;
+
+    use crate::MarkdownRenderer;
+
+    const CONTENT: &str = r#"---
+title = "Test"
+tags = []
+---
+
+# Heading
+
+Some text.
+
+```rust
+// Not a paragraph.
+fn main() {}
+```
+
+{{! note !}}
+A note
+that's long.
+{{! end !}}
+
+After the note, with an <span title="attribute">attribute</span>.
+
+<div
+  class="multiline">
+Not annotated, since it's raw HTML.
+</div>
+"#;
+
+    fn environment() -> Result<Environment<'static>> {
+        let mut env = Environment::new();
+        env.add_template("note.html", "<div class=\"note\">{{ body }}</div>\n")?;
+        Ok(env)
+    }
+
+    #[test]
+    fn test_annotations() -> Result<()> {
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?.with_debug_annotations();
+        let document = renderer.parse_from_file("posts/foo.md", CONTENT, &environment()?)?;
+        insta::assert_snapshot!(document.content);
+
+        let annotations = document
+            .content
+            .match_indices("<!-- yar: ")
+            .map(|(i, _)| {
+                let rest = &document.content[i..];
+                &rest[..rest.find(" -->").unwrap()]
+            })
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            annotations,
+            [
+                "<!-- yar: posts/foo.md:6",
+                "<!-- yar: posts/foo.md:8",
+                "<!-- yar: posts/foo.md:15",
+                "<!-- yar: posts/foo.md:20",
+            ]
+        );
+
+        let pre = &document.content[document.content.find("<pre").unwrap()..];
+        assert!(!pre[..pre.find("</pre>").unwrap()].contains("<!--"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_annotations() -> Result<()> {
+        let env = environment()?;
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let document = renderer.parse_from_file("posts/foo.md", CONTENT, &env)?;
+        assert!(!document.content.contains("<!--"));
+        assert!(!document.summary.contains("<!--"));
+
+        // Strings don't have a file to name, so they're never annotated.
+        let renderer = renderer.with_debug_annotations();
+        assert!(
+            !renderer
+                .parse_from_string(CONTENT, &env)?
+                .content
+                .contains("<!--")
+        );
+
+        Ok(())
+    }
+}
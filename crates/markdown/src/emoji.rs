@@ -0,0 +1,186 @@
+use std::{borrow::Cow, iter::Peekable, ops::Range};
+
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+
+/// Replace emoji shortcodes like `:rocket:` in text with the emoji they stand for.
+///
+/// Text in code blocks and in the skipped ranges, like the output of shortcodes, is left
+/// as it is, and so are codes that aren't known. Inline code is its own kind of event, so
+/// it's never touched.
+pub fn replace_emoji<'a, 'b>(
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)> + 'b,
+    skip: &'b [Range<usize>],
+) -> impl Iterator<Item = (Event<'a>, Range<usize>)> + 'b {
+    Emoji {
+        events: events.peekable(),
+        skip,
+        in_code_block: false,
+    }
+}
+
+struct Emoji<'b, I: Iterator> {
+    events: Peekable<I>,
+    skip: &'b [Range<usize>],
+    in_code_block: bool,
+}
+
+impl<'a, I: Iterator<Item = (Event<'a>, Range<usize>)>> Iterator for Emoji<'_, I> {
+    type Item = (Event<'a>, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (event, mut range) = self.events.next()?;
+        let text = match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.in_code_block = true;
+                return Some((event, range));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                self.in_code_block = false;
+                return Some((event, range));
+            }
+            Event::Text(text) if !self.in_code_block && !skipped(self.skip, &range) => text,
+            _ => return Some((event, range)),
+        };
+
+        // Text is split around characters like `_` that could have been emphasis, so a
+        // code can be spread across several events.
+        let mut text = text.into_string();
+        while let Some((Event::Text(next), next_range)) = self.events.peek() {
+            if skipped(self.skip, next_range) {
+                break;
+            }
+            text.push_str(next);
+            range.end = next_range.end;
+            self.events.next();
+        }
+
+        let text = match replace_shortcodes(&text) {
+            Cow::Borrowed(_) => text,
+            Cow::Owned(replaced) => replaced,
+        };
+        Some((Event::Text(CowStr::from(text)), range))
+    }
+}
+
+// Whether an event's range is within one of the skipped ranges.
+fn skipped(skip: &[Range<usize>], range: &Range<usize>) -> bool {
+    skip.iter()
+        .any(|s| s.start <= range.start && range.end <= s.end)
+}
+
+// Replace each known shortcode in some text with its emoji.
+fn replace_shortcodes(text: &str) -> Cow<'_, str> {
+    if !text.contains(':') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut replaced = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        replaced.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let found = after.find(':').and_then(|end| {
+            let code = &after[..end];
+            let valid = !code.is_empty()
+                && code
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-'));
+            valid
+                .then(|| emojis::get_by_shortcode(code))
+                .flatten()
+                .map(|e| (e, end))
+        });
+        if let Some((emoji, end)) = found {
+            replaced.push_str(emoji.as_str());
+            rest = &after[end + 1..];
+        } else {
+            // The closing colon of an unknown code could open the next one.
+            replaced.push(':');
+            rest = after;
+        }
+    }
+    replaced.push_str(rest);
+
+    Cow::Owned(replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use minijinja::Environment;
+
+    use super::*;
+    use crate::MarkdownRenderer;
+
+    fn render(body: &str) -> Result<String> {
+        let content = format!("---\ntitle = \"Emoji\"\ntags = []\n---\n\n{body}\n");
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?.with_emoji();
+        Ok(renderer
+            .parse_from_string(&content, &Environment::empty())?
+            .content)
+    }
+
+    #[test]
+    fn test_replace_shortcodes() {
+        assert_eq!(replace_shortcodes("Launch :rocket:!"), "Launch 🚀!");
+        assert_eq!(replace_shortcodes(":+1::smile_cat:"), "👍😸");
+        assert_eq!(replace_shortcodes("No codes here"), "No codes here");
+        assert_eq!(
+            replace_shortcodes(":not_an_emoji: and :rocket"),
+            ":not_an_emoji: and :rocket"
+        );
+        assert_eq!(replace_shortcodes("at 10:30:rocket:"), "at 10:30🚀");
+        assert_eq!(replace_shortcodes("a :: b : c"), "a :: b : c");
+    }
+
+    #[test]
+    fn test_known_and_unknown() -> Result<()> {
+        assert_eq!(
+            render("Ship it :rocket: :smile_cat: *:tada:* :not_an_emoji:")?,
+            "<p>Ship it 🚀 😸 <em>🎉</em> :not_an_emoji:</p>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_code() -> Result<()> {
+        assert_eq!(
+            render("`:rocket:` and :rocket:\n\n```\n:rocket:\n```")?,
+            "<p><code>:rocket:</code> and 🚀</p>\n<pre lang=\"\"><code class=\"language-\">:rocket:\n</code></pre>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shortcodes() -> Result<()> {
+        let content = "---\ntitle = \"Emoji\"\ntags = []\n---\n\n{{! note !}}\n:rocket:\n{{! end !}}\n\n{{! launch !}}{{! end !}}\n\n:tada:\n";
+        let mut env = Environment::new();
+        env.add_template("note.html", "<div class=\"note\">{{ body | safe }}</div>")?;
+        env.add_template("launch.html", "Launch :rocket:")?;
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?.with_emoji();
+
+        // Shortcode bodies and output are left as they are.
+        assert_eq!(
+            renderer.parse_from_string(content, &env)?.content,
+            "<div class=\"note\"><p>:rocket:</p>\n</div>Launch :rocket:\n<p>🎉</p>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_off_by_default() -> Result<()> {
+        let content = "---\ntitle = \"Emoji\"\ntags = []\n---\n\n:rocket:\n";
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        assert_eq!(
+            renderer
+                .parse_from_string(content, &Environment::empty())?
+                .content,
+            "<p>:rocket:</p>\n"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use std::{error::Error, ops::Range, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::FrontmatterFormat;
+
+/// The ways parsing and rendering markdown can fail.
+#[derive(Debug, Error)]
+pub enum MarkdownError {
+    /// The frontmatter isn't valid TOML or YAML, or doesn't have the fields a page needs.
+    ///
+    /// Errors about the frontmatter as a whole, like a missing field, are reported at the
+    /// line it starts on.
+    #[error("Invalid {format} frontmatter at line {line}, column {column}: {message}")]
+    FrontmatterParse {
+        format: FrontmatterFormat,
+        message: String,
+        line: usize,
+        column: usize,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The syntax theme isn't one of the built-in themes.
+    #[error("{name} isn't a built-in syntax theme, the available themes are {}", .available.join(", "))]
+    UnknownTheme {
+        name: String,
+        available: Vec<String>,
+    },
+    /// The syntax theme file couldn't be read or parsed.
+    #[error("Couldn't load the syntax theme at {}", .path.display())]
+    ThemeLoad {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// A shortcode's opening tag, arguments, or end tag are malformed.
+    #[error("Couldn't parse the {name:?} shortcode at line {line}")]
+    ShortcodeParse {
+        name: String,
+        span: Range<usize>,
+        line: usize,
+    },
+    /// There's an end tag without a shortcode for it to close.
+    #[error("There's a shortcode end tag at line {line} without a shortcode to close")]
+    ShortcodeStrayEnd { line: usize },
+    /// There's neither a registered shortcode nor a template for a shortcode.
+    #[error("There's no {name} shortcode at line {line}, since there's no {name}.html template")]
+    ShortcodeTemplateMissing { name: String, line: usize },
+    /// A shortcode argument refers to something that isn't defined, or can't be passed
+    /// to shortcodes.
+    #[error(
+        "Couldn't resolve the {argument} argument of the {name} shortcode at line {line}: {reason}"
+    )]
+    ShortcodeArgument {
+        name: String,
+        argument: String,
+        reason: String,
+        line: usize,
+    },
+    /// A shortcode's template or registered function failed.
+    #[error("Couldn't render the {name} shortcode at line {line}")]
+    ShortcodeRender {
+        name: String,
+        line: usize,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// A date in the frontmatter isn't a date and time like `2025-01-01T12:00:00`.
+    #[error("The {field} date {value:?} isn't a valid date and time")]
+    DateParse {
+        field: &'static str,
+        value: String,
+        source: chrono::ParseError,
+    },
+    /// The levels of headings to put in tables of contents aren't heading levels, or are
+    /// the wrong way around.
+    #[error(
+        "Table of contents levels {min} to {max} aren't valid, they have to be from 1 to 6 with the first no deeper than the second"
+    )]
+    TocLevels { min: u8, max: u8 },
+    /// A code block couldn't be highlighted.
+    #[error("Couldn't highlight a {lang} code block")]
+    Highlight {
+        lang: String,
+        source: arborium::Error,
+    },
+}
+
+impl MarkdownError {
+    // Move the line a shortcode error is reported at down by `lines`, for shortcodes in a
+    // body that starts after that many lines of frontmatter.
+    pub(crate) const fn offset_lines(mut self, lines: usize) -> Self {
+        match &mut self {
+            Self::ShortcodeParse { line, .. }
+            | Self::ShortcodeStrayEnd { line }
+            | Self::ShortcodeTemplateMissing { line, .. }
+            | Self::ShortcodeArgument { line, .. }
+            | Self::ShortcodeRender { line, .. } => *line += lines,
+            _ => (),
+        }
+
+        self
+    }
+}
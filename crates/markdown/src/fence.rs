@@ -0,0 +1,185 @@
+use std::ops::RangeInclusive;
+
+/// A fenced code block, along with the attributes in its info string.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub lang: String,
+    /// The lines to highlight, counting from 1.
+    pub hl_lines: Vec<RangeInclusive<usize>>,
+    /// Whether to number the block's lines, overriding the renderer's setting.
+    pub linenos: Option<bool>,
+    /// The name of the file the code is from, shown above the block.
+    pub filename: Option<String>,
+    pub text: String,
+}
+
+impl CodeBlock {
+    /// Parse a fence's info string, like `rust,hl_lines=2-4 6,linenos,filename="main.rs"`,
+    /// into the language and attributes of an empty code block.
+    ///
+    /// Unknown attributes are ignored. If any attribute can't be parsed, they all are,
+    /// leaving only the language before the first comma.
+    pub fn from_info(info: &str) -> Self {
+        let (lang, attributes) = info.split_once(',').unwrap_or((info, ""));
+        let block = parse_attributes(attributes).unwrap_or_default();
+
+        Self {
+            lang: lang.trim().to_owned(),
+            ..block
+        }
+    }
+
+    /// Whether the given line, counting from 1, is one of the highlighted ones.
+    pub fn is_highlighted(&self, line: usize) -> bool {
+        self.hl_lines.iter().any(|r| r.contains(&line))
+    }
+}
+
+fn parse_attributes(attributes: &str) -> Option<CodeBlock> {
+    let mut block = CodeBlock::default();
+    for attribute in split_attributes(attributes)? {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(unquote(value.trim())?)),
+            None => (attribute.trim(), None),
+        };
+
+        match (key, value) {
+            ("hl_lines", Some(value)) => block.hl_lines = parse_ranges(value)?,
+            ("linenos", None) => block.linenos = Some(true),
+            ("linenos", Some(value)) => block.linenos = Some(value.parse().ok()?),
+            ("filename", Some(value)) if !value.is_empty() => {
+                block.filename = Some(value.to_owned());
+            }
+            ("hl_lines" | "filename", _) => return None,
+            // Left for other tools, like `ignore` for rustdoc.
+            _ => (),
+        }
+    }
+
+    Some(block)
+}
+
+// Split attributes on the commas between them, leaving commas in quoted values alone.
+fn split_attributes(attributes: &str) -> Option<Vec<&str>> {
+    let mut split = vec![];
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in attributes.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                split.push(&attributes[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if in_quotes {
+        return None;
+    }
+    split.push(&attributes[start..]);
+
+    Some(split.into_iter().filter(|a| !a.trim().is_empty()).collect())
+}
+
+// Take the quotes off a value, if it has them.
+fn unquote(value: &str) -> Option<&str> {
+    let unquoted = value
+        .strip_prefix('"')
+        .map_or(Some(value), |rest| rest.strip_suffix('"'))?;
+    (!unquoted.contains('"')).then_some(unquoted)
+}
+
+// Parse ranges of lines separated by spaces, like `1 3-5`.
+fn parse_ranges(value: &str) -> Option<Vec<RangeInclusive<usize>>> {
+    let ranges = value
+        .split_whitespace()
+        .map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+            (start > 0 && start <= end).then_some(start..=end)
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    (!ranges.is_empty()).then_some(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use minijinja::Environment;
+
+    use super::*;
+    use crate::MarkdownRenderer;
+
+    #[test]
+    fn test_from_info() {
+        assert_eq!(
+            CodeBlock::from_info(r#"rust,hl_lines=2-4 6,linenos,filename="src/main, old.rs""#),
+            CodeBlock {
+                lang: "rust".into(),
+                hl_lines: vec![2..=4, 6..=6],
+                linenos: Some(true),
+                filename: Some("src/main, old.rs".into()),
+                text: String::new(),
+            }
+        );
+        assert_eq!(
+            CodeBlock::from_info(" py , linenos=false, ignore,"),
+            CodeBlock {
+                lang: "py".into(),
+                linenos: Some(false),
+                ..CodeBlock::default()
+            }
+        );
+        assert_eq!(CodeBlock::from_info(""), CodeBlock::default());
+    }
+
+    #[test]
+    fn test_invalid_info() {
+        for info in [
+            "rust,hl_lines=4-2",
+            "rust,hl_lines=a",
+            "rust,hl_lines",
+            "rust,linenos=maybe",
+            "rust,filename=\"main.rs",
+            "rust,filename=",
+            "rust,linenos,hl_lines=0",
+        ] {
+            assert_eq!(
+                CodeBlock::from_info(info),
+                CodeBlock {
+                    lang: "rust".into(),
+                    ..CodeBlock::default()
+                },
+                "{info}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_attributes() -> Result<()> {
+        let content = r#"---
+title = "Test"
+tags = []
+---
+
+```rust,hl_lines=2,filename="src/<main>.rs"
+fn main() {
+    println!("Hello");
+}
+```
+
+```py,linenos=false
+x = 1
+```
+"#;
+
+        let document = MarkdownRenderer::new::<&str>(None, None)?
+            .with_line_numbers()
+            .parse_from_string(content, &Environment::empty())?;
+        insta::assert_snapshot!(document.content);
+
+        Ok(())
+    }
+}
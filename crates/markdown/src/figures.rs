@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+use pulldown_cmark_escape::escape_html;
+
+/// Turn paragraphs holding nothing but an image with a title into figures, with the
+/// title as the caption, like `![A cat](cat.png "Naptime")`.
+///
+/// The paragraph is replaced with the `<figure>`, and the image is left for the image
+/// pass to render without its title. Images without a title, or with text around them,
+/// are left as they are.
+pub fn figures<'a>(events: impl Iterator<Item = Event<'a>>) -> impl Iterator<Item = Event<'a>> {
+    Figures {
+        events,
+        pending: VecDeque::new(),
+    }
+}
+
+struct Figures<'a, I> {
+    events: I,
+    // Events read ahead to find out whether a paragraph is a figure.
+    pending: VecDeque<Event<'a>>,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Iterator for Figures<'a, I> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.pop_front() {
+            return Some(event);
+        }
+
+        let event = self.events.next()?;
+        if !matches!(event, Event::Start(Tag::Paragraph)) {
+            return Some(event);
+        }
+        let Some(first) = self.events.next() else {
+            return Some(event);
+        };
+        if !matches!(&first, Event::Start(Tag::Image { title, .. }) if !title.is_empty()) {
+            self.pending.push_back(first);
+            return Some(event);
+        }
+
+        let image = self.read_image(first);
+        match self.events.next() {
+            Some(Event::End(TagEnd::Paragraph)) => {
+                self.pending.extend(figure(image));
+                self.pending.pop_front()
+            }
+            next => {
+                self.pending.extend(image);
+                self.pending.extend(next);
+                Some(event)
+            }
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Figures<'a, I> {
+    // Read the events of an image up to its end, including any images in its alt text.
+    fn read_image(&mut self, start: Event<'a>) -> Vec<Event<'a>> {
+        let mut image = vec![start];
+        let mut depth = 0_usize;
+        for event in self.events.by_ref() {
+            let end = match event {
+                Event::Start(Tag::Image { .. }) => {
+                    depth += 1;
+                    false
+                }
+                Event::End(TagEnd::Image) if depth > 0 => {
+                    depth -= 1;
+                    false
+                }
+                Event::End(TagEnd::Image) => true,
+                _ => false,
+            };
+            image.push(event);
+            if end {
+                break;
+            }
+        }
+
+        image
+    }
+}
+
+// Wrap an image's events in a figure, moving its title to the caption.
+fn figure(mut image: Vec<Event>) -> Vec<Event> {
+    let mut figcaption = String::from("<figcaption>");
+    if let Some(Event::Start(Tag::Image { title, .. })) = image.first_mut() {
+        escape_html(&mut figcaption, title).ok();
+        *title = "".into();
+    }
+    figcaption.push_str("</figcaption></figure>\n");
+
+    image.insert(0, Event::Html("<figure>".into()));
+    image.push(Event::Html(figcaption.into()));
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use minijinja::Environment;
+
+    use crate::MarkdownRenderer;
+
+    fn render(body: &str) -> Result<String> {
+        let content = format!("---\ntitle = \"Figures\"\ntags = []\n---\n\n{body}\n");
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        Ok(renderer
+            .parse_from_string(&content, &Environment::empty())?
+            .content)
+    }
+
+    #[test]
+    fn test_captioned() -> Result<()> {
+        insta::assert_snapshot!(render("![A *sleepy* cat](cat.png \"Naptime & <dreams>\")")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_uncaptioned() -> Result<()> {
+        insta::assert_snapshot!(render("![A cat](cat.png)\n\n![](cat.png \"\")")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_inline_with_text() -> Result<()> {
+        insta::assert_snapshot!(render(
+            "Look: ![A cat](cat.png \"Naptime\")\n\n![A cat](cat.png \"Naptime\") is sleeping.\n\n[![Linked](dog.png \"A dog\")](https://example.com)\n\n- ![In a list](cat.png \"Naptime\")"
+        )?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+use std::fmt;
+
+use minijinja::Value;
+use serde::de::DeserializeOwned;
+
+use crate::MarkdownError;
+
+/// The format frontmatter is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrontmatterFormat {
+    /// Frontmatter between `+++` delimiters, or between `---` delimiters that doesn't
+    /// start with a `key: value` line.
+    #[default]
+    Toml,
+    /// Frontmatter between `---` delimiters that starts with a `key: value` line.
+    Yaml,
+}
+
+impl fmt::Display for FrontmatterFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Toml => write!(f, "TOML"),
+            Self::Yaml => write!(f, "YAML"),
+        }
+    }
+}
+
+/// A file split into its frontmatter and the body after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrontmatterSplit<'a> {
+    /// The text between the delimiters, which is empty if the file has no frontmatter.
+    pub frontmatter: &'a str,
+    pub format: FrontmatterFormat,
+    /// Everything after the closing delimiter.
+    pub body: &'a str,
+    /// The number of lines in the file before the frontmatter.
+    pub line_offset: usize,
+}
+
+/// Split the frontmatter off the start of a file.
+///
+/// Frontmatter is only recognized when the opening `---` or `+++` is the first line that
+/// isn't blank, and it ends at the first line with the same delimiter after that, so
+/// later ones belong to the body. Frontmatter that's never closed runs to the end of the
+/// file. See [`FrontmatterFormat`] for how its format is told apart.
+#[must_use]
+pub fn split_frontmatter(content: &str) -> FrontmatterSplit<'_> {
+    let mut lines = content.split_inclusive('\n');
+    let mut offset = 0;
+    let mut line_offset = 0;
+
+    let delimiter = loop {
+        let Some(line) = lines.next() else {
+            break None;
+        };
+        offset += line.len();
+        line_offset += 1;
+
+        match line.trim() {
+            delimiter @ ("---" | "+++") => break Some(delimiter),
+            "" => (),
+            _ => break None,
+        }
+    };
+    let Some(delimiter) = delimiter else {
+        return FrontmatterSplit {
+            frontmatter: "",
+            format: FrontmatterFormat::default(),
+            body: content,
+            line_offset: 0,
+        };
+    };
+
+    let start = offset;
+    let mut end = None;
+    for line in lines {
+        if line.trim() == delimiter {
+            end = Some((offset, offset + line.len()));
+            break;
+        }
+        offset += line.len();
+    }
+    let (frontmatter, body) = match end {
+        Some((end, body)) => (&content[start..end], &content[body..]),
+        None => (&content[start..], ""),
+    };
+    let format = if delimiter == "---" && starts_with_yaml_pair(frontmatter) {
+        FrontmatterFormat::Yaml
+    } else {
+        FrontmatterFormat::Toml
+    };
+
+    FrontmatterSplit {
+        frontmatter,
+        format,
+        body,
+        line_offset,
+    }
+}
+
+// Whether the first line that isn't blank or a comment is a YAML `key: value` pair, rather
+// than a TOML `key = value` pair or table header.
+fn starts_with_yaml_pair(frontmatter: &str) -> bool {
+    let Some(line) = frontmatter
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty() && !l.starts_with('#'))
+    else {
+        return false;
+    };
+    let Some((key, value)) = line.split_once(':') else {
+        return false;
+    };
+    let key = key.trim_end().trim_matches(|c| c == '"' || c == '\'');
+
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        && (value.is_empty() || value.starts_with(char::is_whitespace))
+}
+
+// Take the ` at line 2 column 5` locations out of a YAML error, since they're relative to
+// the frontmatter rather than the file, which the error's own location is reported in.
+fn strip_locations(message: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = message;
+    while let Some(i) = rest.find(" at line ") {
+        stripped.push_str(&rest[..i]);
+        let after = rest[i + " at line ".len()..].trim_start_matches(|c: char| c.is_ascii_digit());
+        rest = after.strip_prefix(" column ").map_or(after, |column| {
+            column.trim_start_matches(|c: char| c.is_ascii_digit())
+        });
+    }
+    stripped.push_str(rest);
+
+    stripped
+}
+
+impl FrontmatterSplit<'_> {
+    /// Parse the frontmatter in its format, reporting errors at their line and column in
+    /// the whole file.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, MarkdownError> {
+        match self.format {
+            FrontmatterFormat::Toml => toml::from_str(self.frontmatter).map_err(|source| {
+                let index = source.span().map_or(0, |span| span.start);
+                self.error(source.message().to_owned(), index, source.into())
+            }),
+            FrontmatterFormat::Yaml => serde_yaml::from_str(self.frontmatter).map_err(|source| {
+                let index = source.location().map_or(0, |l| l.index());
+                let message = strip_locations(&source.to_string());
+                self.error(message, index, source.into())
+            }),
+        }
+    }
+
+    /// The frontmatter as a template value, with whatever fields it has.
+    pub fn to_value(&self) -> Result<Value, MarkdownError> {
+        Ok(match self.format {
+            FrontmatterFormat::Toml => Value::from_serialize(self.deserialize::<toml::Table>()?),
+            FrontmatterFormat::Yaml => {
+                Value::from_serialize(self.deserialize::<serde_yaml::Mapping>()?)
+            }
+        })
+    }
+
+    fn error(
+        &self,
+        message: String,
+        index: usize,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    ) -> MarkdownError {
+        let before = self.frontmatter.get(..index).unwrap_or_default();
+        let line = self.line_offset + before.matches('\n').count() + 1;
+        let column = before
+            .rfind('\n')
+            .map_or(before, |i| &before[i + 1..])
+            .chars()
+            .count()
+            + 1;
+
+        MarkdownError::FrontmatterParse {
+            format: self.format,
+            message,
+            line,
+            column,
+            source,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Frontmatter;
+
+    #[test]
+    fn test_split_frontmatter() {
+        let content = "\n---\ntitle = \"Test\"\n---\n\nIntro\n\n---\n\nAfter the break\n";
+        assert_eq!(
+            split_frontmatter(content),
+            FrontmatterSplit {
+                frontmatter: "title = \"Test\"\n",
+                format: FrontmatterFormat::Toml,
+                body: "\nIntro\n\n---\n\nAfter the break\n",
+                line_offset: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_no_frontmatter() {
+        let content = "# Hello\n\n---\n\ntitle = \"Not frontmatter\"\n---\n";
+        assert_eq!(
+            split_frontmatter(content),
+            FrontmatterSplit {
+                frontmatter: "",
+                format: FrontmatterFormat::Toml,
+                body: content,
+                line_offset: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_frontmatter_error_line() {
+        let content = "\n---\ntitle = \"Test\"\ntags = [\"a\",\n  oops]\n---\nBody\n";
+        let err = split_frontmatter(content)
+            .deserialize::<toml::Table>()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            MarkdownError::FrontmatterParse {
+                line: 5,
+                column: 3,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Invalid TOML frontmatter at line 5, column 3: invalid array\nexpected `]`"
+        );
+    }
+
+    #[test]
+    fn test_frontmatter_formats() {
+        for (content, format) in [
+            ("---\ntitle = \"Test\"\n---\n", FrontmatterFormat::Toml),
+            (
+                "---\n[extra]\nurl = \"https://a.b\"\n---\n",
+                FrontmatterFormat::Toml,
+            ),
+            ("---\ntitle = \"a: b\"\n---\n", FrontmatterFormat::Toml),
+            ("+++\ntitle: Test\n+++\n", FrontmatterFormat::Toml),
+            ("---\ntitle: Test\n---\n", FrontmatterFormat::Yaml),
+            (
+                "---\n# A comment\n\ntags:\n  - a\n---\n",
+                FrontmatterFormat::Yaml,
+            ),
+            ("---\n\"title\": Test\n---\n", FrontmatterFormat::Yaml),
+        ] {
+            assert_eq!(split_frontmatter(content).format, format, "{content}");
+        }
+    }
+
+    #[test]
+    fn test_toml_delimiters() {
+        let content = "+++\ntitle = \"Test\"\n---\nStill frontmatter = 1\n+++\nBody\n";
+        assert_eq!(
+            split_frontmatter(content),
+            FrontmatterSplit {
+                frontmatter: "title = \"Test\"\n---\nStill frontmatter = 1\n",
+                format: FrontmatterFormat::Toml,
+                body: "Body\n",
+                line_offset: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_yaml_frontmatter() -> Result<(), MarkdownError> {
+        let content = "---\ntitle: Hello: World\ndescription:\ntags: [rust, meta]\ndate: 2025-01-01T12:00:00\nweight: 2\n---\nBody\n";
+        let content = content.replace("Hello: World", "\"Hello: World\"");
+        let frontmatter = split_frontmatter(&content).deserialize::<Frontmatter>()?;
+        assert_eq!(frontmatter.title, "Hello: World");
+        assert_eq!(frontmatter.description, None);
+        assert_eq!(frontmatter.tags, ["rust", "meta"]);
+        assert_eq!(frontmatter.date.as_deref(), Some("2025-01-01T12:00:00"));
+        assert_eq!(frontmatter.weight, Some(2));
+
+        let value = split_frontmatter(&content).to_value()?;
+        assert_eq!(value.get_attr("weight").ok(), Some(Value::from(2)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_yaml_frontmatter_error_line() {
+        let content = "\n---\ntitle: Test\ntags: [a,\n  b\n---\nBody\n";
+        let err = split_frontmatter(content)
+            .deserialize::<Frontmatter>()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            MarkdownError::FrontmatterParse {
+                format: FrontmatterFormat::Yaml,
+                line: 6,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Invalid YAML frontmatter at line 6, column 1: did not find expected ',' or ']', while parsing a flow sequence"
+        );
+
+        let content = "---\ntitle: Test\n---\nBody\n";
+        let err = split_frontmatter(content)
+            .deserialize::<Frontmatter>()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid YAML frontmatter at line 2, column 1: missing field `tags`"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use arborium::{HtmlFormat, theme::Theme};
+use arborium_theme::tag_to_name;
+use serde::{Deserialize, Serialize};
+
+/// How highlighted code is marked up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightMode {
+    /// Short custom elements, like `<a-k>fn</a-k>`.
+    #[default]
+    Elements,
+    /// Spans with a class for each kind of token, like `<span class="keyword">fn</span>`,
+    /// for stylesheets written against class names. Only the broad kinds get a class,
+    /// so `keyword.function` is styled as any other keyword.
+    Classes,
+}
+
+/// A store for highlighted code, so that code blocks that haven't changed don't need to
+/// be highlighted again, like between builds of a site.
+///
+/// The markup only depends on the code, its language, and the mode it's highlighted
+/// with. Themes are applied by stylesheets, so they don't change it.
+pub trait HighlightCache: Send + Sync {
+    /// The HTML `code` in `lang` was highlighted to with `mode`, if it's stored.
+    fn get(&self, lang: &str, mode: HighlightMode, code: &str) -> Option<String>;
+    /// Store the HTML `code` in `lang` was highlighted to with `mode`.
+    fn insert(&self, lang: &str, mode: HighlightMode, code: &str, html: &str);
+}
+
+// The format arborium marks up code in for the given mode.
+pub const fn html_format(mode: HighlightMode) -> HtmlFormat {
+    match mode {
+        HighlightMode::Elements => HtmlFormat::CustomElements,
+        HighlightMode::Classes => HtmlFormat::ClassNames,
+    }
+}
+
+// The stylesheet for code highlighted in `theme` with the given mode, scoped to `pre`.
+pub fn theme_css(theme: &Theme, mode: HighlightMode) -> String {
+    let css = theme.to_css("pre");
+    match mode {
+        HighlightMode::Elements => css,
+        HighlightMode::Classes => css.lines().filter_map(class_rule).collect(),
+    }
+}
+
+// Select a theme's rule for `<a-{tag}>` by the class spans of that tag get instead,
+// dropping rules for tags that don't get a class.
+fn class_rule(line: &str) -> Option<String> {
+    let Some(rule) = line.trim_start().strip_prefix("a-") else {
+        return Some(format!("{line}\n"));
+    };
+    let (tag, declarations) = rule.split_once(' ')?;
+    let name = tag_to_name(tag)?;
+
+    Some(format!("  .{name} {declarations}\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use arborium::theme::builtin;
+    use color_eyre::Result;
+    use minijinja::Environment;
+
+    use super::*;
+    use crate::MarkdownRenderer;
+
+    #[derive(Default)]
+    struct MemoryCache {
+        entries: Mutex<HashMap<(String, HighlightMode, String), String>>,
+        hits: AtomicUsize,
+    }
+
+    impl HighlightCache for MemoryCache {
+        fn get(&self, lang: &str, mode: HighlightMode, code: &str) -> Option<String> {
+            let html = self
+                .entries
+                .lock()
+                .unwrap()
+                .get(&(lang.to_owned(), mode, code.to_owned()))
+                .cloned();
+            if html.is_some() {
+                self.hits.fetch_add(1, Ordering::SeqCst);
+            }
+            html
+        }
+
+        fn insert(&self, lang: &str, mode: HighlightMode, code: &str, html: &str) {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert((lang.to_owned(), mode, code.to_owned()), html.to_owned());
+        }
+    }
+
+    #[test]
+    fn test_highlight_cache() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\n---\n\n```rust\nfn main() {}\n```\n";
+        let cache = Arc::new(MemoryCache::default());
+
+        let renderer =
+            MarkdownRenderer::new::<&str>(None, None)?.with_highlight_cache(cache.clone());
+        let first = renderer.parse_from_string(content, &Environment::empty())?;
+        assert_eq!(cache.hits.load(Ordering::SeqCst), 0);
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+
+        // The same code is looked up rather than highlighted again, even in another page.
+        let second = renderer.parse_from_string(
+            &content.replace("```rust", "Some prose.\n\n```rust"),
+            &Environment::empty(),
+        )?;
+        assert_eq!(cache.hits.load(Ordering::SeqCst), 1);
+        assert!(second.content.ends_with(&first.content));
+
+        // Code highlighted with another mode is marked up differently.
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?
+            .with_highlight_mode(HighlightMode::Classes)
+            .with_highlight_cache(cache.clone());
+        let classes = renderer.parse_from_string(content, &Environment::empty())?;
+        assert_eq!(cache.hits.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.entries.lock().unwrap().len(), 2);
+        assert_ne!(classes.content, first.content);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_protected_pages_skip_highlight_cache() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\nprotected = \"hunter2\"\n---\n\n```rust\nfn main() {}\n```\n\n{{! note() !}}\n```rust\nfn other() {}\n```\n{{! end !}}\n";
+        let cache = Arc::new(MemoryCache::default());
+        let mut env = Environment::new();
+        env.add_template("note.html", "{{ body }}")?;
+
+        // Neither the page's code nor the code in its shortcodes is kept.
+        let renderer =
+            MarkdownRenderer::new::<&str>(None, None)?.with_highlight_cache(cache.clone());
+        let document = renderer.parse_from_string(content, &env)?;
+        assert!(document.content.contains("other"));
+        assert!(cache.entries.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_class_mode() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\n---\n\n```rust\nfn main() {\n    let x = \"hi\";\n}\n```\n";
+
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?
+            .with_highlight_mode(HighlightMode::Classes)
+            .with_line_numbers();
+        let document = renderer.parse_from_string(content, &Environment::empty())?;
+        assert!(
+            document
+                .content
+                .contains(r#"<span class="keyword">fn</span>"#)
+        );
+        assert!(!document.content.contains("<a-"));
+        assert!(!document.content.contains("style="));
+
+        let highlighted = renderer.highlight("rust", "let x = 1;")?;
+        assert!(highlighted.contains("class=\"keyword\""));
+        assert!(!highlighted.contains("style="));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_theme_css() {
+        let theme = builtin::solarized_dark();
+
+        let css = theme_css(&theme, HighlightMode::Classes);
+        assert!(css.starts_with("pre {\n"));
+        assert!(css.ends_with("}\n"));
+        assert!(css.contains("  .keyword {"));
+        assert!(!css.contains("a-"));
+
+        assert_eq!(
+            theme_css(&theme, HighlightMode::Elements),
+            theme.to_css("pre")
+        );
+    }
+}
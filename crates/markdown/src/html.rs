@@ -0,0 +1,258 @@
+use std::collections::BTreeSet;
+
+use chrono::Utc;
+use pulldown_cmark::{Event, Tag, TagEnd, html::push_html};
+
+use crate::{Document, Frontmatter, MarkdownError, TOCHeading, parse_date, truncate_words};
+
+impl Document {
+    /// Create a document from HTML that's already been written, for pages that aren't
+    /// markdown.
+    ///
+    /// The HTML is used as it is. The summary is the start of its text, the table of
+    /// contents is made from its `h2` headings, and every element with an `id` can be
+    /// linked to.
+    pub fn from_html(frontmatter: Frontmatter, html: &str) -> Result<Self, MarkdownError> {
+        let date = frontmatter
+            .date
+            .as_deref()
+            .map_or(Ok(Utc::now()), |d| parse_date("date", d))?;
+        let updated = frontmatter
+            .updated
+            .as_deref()
+            .map_or(Ok(date), |d| parse_date("updated", d))?;
+
+        let toc = if frontmatter.wants_toc() {
+            headings(html)
+        } else {
+            vec![]
+        };
+        let text = text_content(html);
+        let summary_text = truncate_words(&text);
+        let mut summary = String::new();
+        push_html(
+            &mut summary,
+            [
+                Event::Start(Tag::Paragraph),
+                Event::Text(summary_text.clone().into()),
+                Event::End(TagEnd::Paragraph),
+            ]
+            .into_iter(),
+        );
+
+        Ok(Self {
+            date,
+            updated,
+            content: html.to_owned(),
+            has_toc: !toc.is_empty(),
+            toc,
+            toc_ids: ids(html),
+            summary,
+            summary_text,
+            word_count: text.split_whitespace().count(),
+            links: vec![],
+            undefined_footnotes: vec![],
+            frontmatter,
+        })
+    }
+}
+
+// The text of some HTML, without its tags, scripts, or styles.
+fn text_content(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        text.push_str(&decode_entities(&rest[..start]));
+        rest = &rest[start..];
+
+        let Some(end) = rest.find('>') else {
+            return text;
+        };
+        let tag = rest[1..end].to_ascii_lowercase();
+        rest = &rest[end + 1..];
+
+        // Tags are treated as breaks between words, since most of them are blocks.
+        text.push(' ');
+        for skipped in ["script", "style"] {
+            if tag.split_whitespace().next() == Some(skipped) {
+                let close = format!("</{skipped}");
+                rest = rest
+                    .to_ascii_lowercase()
+                    .find(&close)
+                    .map_or("", |i| &rest[i..]);
+            }
+        }
+    }
+    text.push_str(&decode_entities(rest));
+
+    text
+}
+
+// The `h2` headings in some HTML, with their ids.
+fn headings(html: &str) -> Vec<TOCHeading> {
+    let lower = html.to_ascii_lowercase();
+    let mut toc = vec![];
+    let mut offset = 0;
+
+    while let Some(start) = lower[offset..].find("<h2").map(|i| i + offset) {
+        let Some(open_end) = lower[start..].find('>').map(|i| i + start) else {
+            break;
+        };
+        let Some(close) = lower[open_end..].find("</h2>").map(|i| i + open_end) else {
+            break;
+        };
+
+        let attributes = &html[start + 3..open_end];
+        let text = text_content(&html[open_end + 1..close]);
+        toc.push(TOCHeading::new(
+            2,
+            attribute(attributes, "id"),
+            text.split_whitespace().collect::<Vec<&str>>().join(" "),
+        ));
+        offset = close + 5;
+    }
+
+    toc
+}
+
+// The ids of all the elements in some HTML.
+fn ids(html: &str) -> BTreeSet<String> {
+    let mut ids = BTreeSet::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        // Closing tags and comments don't have attributes.
+        if !rest.starts_with(['/', '!']) {
+            ids.extend(attribute(&rest[..end], "id"));
+        }
+        rest = &rest[end + 1..];
+    }
+
+    ids
+}
+
+// The value of an attribute in the attributes of a tag.
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut rest = attributes;
+    while let Some(i) = rest.find(name) {
+        let before = rest[..i].chars().next_back();
+        let after = rest[i + name.len()..].trim_start();
+        rest = &rest[i + name.len()..];
+        if before.is_some_and(|c| !c.is_whitespace()) {
+            continue;
+        }
+        let Some(value) = after.strip_prefix('=') else {
+            continue;
+        };
+
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next()?,
+            _ => value.split_whitespace().next()?,
+        };
+        return Some(decode_entities(value));
+    }
+
+    None
+}
+
+// Decode the character references in some text.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let reference = rest.find(';').map(|end| (&rest[1..end], end));
+        let c = reference.and_then(|(name, end)| {
+            let c = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => '\u{a0}',
+                _ => {
+                    let code = name.strip_prefix('#')?;
+                    let code = match code.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => code.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, end))
+        });
+
+        if let Some((c, end)) = c {
+            decoded.push(c);
+            rest = &rest[end + 1..];
+        } else {
+            decoded.push('&');
+            rest = &rest[1..];
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::split_frontmatter;
+
+    #[test]
+    fn test_from_html() -> Result<()> {
+        let content = r#"---
+title = "Legacy"
+tags = []
+date = "2019-05-01T00:00:00"
+---
+<style>h2 { color: red; }</style>
+<p>An <em>old</em> page &amp; its&nbsp;history.</p>
+<h2 id="first">First <code>part</code></h2>
+<p>More text.</p>
+<h2 class="big" id='second-part'>Second</h2>
+<h2>No id</h2>
+<script>let h2 = "<h2>";</script>
+"#;
+        let split = split_frontmatter(content);
+        let document = Document::from_html(split.deserialize()?, split.body)?;
+
+        assert_eq!(document.content, split.body);
+        assert_eq!(document.date.to_rfc3339(), "2019-05-01T00:00:00+00:00");
+        assert_eq!(
+            document.toc,
+            [
+                TOCHeading::new(2, Some("first".into()), "First part".into()),
+                TOCHeading::new(2, Some("second-part".into()), "Second".into()),
+                TOCHeading::new(2, None, "No id".into()),
+            ]
+        );
+        assert_eq!(
+            document.summary_text,
+            "An old page & its history. First part More text. Second No id"
+        );
+        assert_eq!(
+            document.summary,
+            "<p>An old page &amp; its history. First part More text. Second No id</p>\n"
+        );
+        assert_eq!(document.word_count, 13);
+        assert_eq!(
+            document.toc_ids.iter().collect::<Vec<_>>(),
+            ["first", "second-part"]
+        );
+
+        Ok(())
+    }
+}
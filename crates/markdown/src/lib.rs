@@ -1,57 +1,157 @@
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::missing_panics_doc)]
 
+mod anchors;
+mod annotations;
+mod emoji;
+mod error;
+mod fence;
+mod figures;
+mod frontmatter;
+mod highlight;
+mod html;
+mod passes;
+mod sanitize;
 mod shortcodes;
-
-use std::{fs, path::Path};
+mod task_lists;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    ops::Range,
+    path::Path,
+    sync::Arc,
+};
 
 use arborium::{
-    Highlighter,
+    Config, Highlighter,
     theme::{Theme, builtin},
 };
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
-use color_eyre::Result;
-use minijinja::Environment;
-use pulldown_cmark::{
-    CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd, html::push_html,
-};
-use serde::{Deserialize, Serialize};
+use minijinja::{Environment, context};
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd, html::push_html};
+use pulldown_cmark_escape::{escape_href, escape_html};
+use serde::{Deserialize, Deserializer, Serialize};
 use smol_str::SmolStr;
 
-use crate::shortcodes::evaluate_all_shortcodes;
+pub use crate::{
+    anchors::AnchorStyle,
+    error::MarkdownError,
+    frontmatter::{FrontmatterFormat, FrontmatterSplit, split_frontmatter},
+    highlight::{HighlightCache, HighlightMode},
+    passes::{EventProcessor, ImageResolver},
+    sanitize::SanitizeOptions,
+    shortcodes::{Shortcode, ShortcodeFn, Value},
+};
+use crate::{
+    anchors::fragment_href,
+    annotations::{SourceMap, annotate},
+    emoji::replace_emoji,
+    figures::figures,
+    highlight::{html_format, theme_css},
+    passes::{
+        CodeBlocks, Footnotes, Headings, Images, Links, Processors, Summary, WordCount, run_passes,
+    },
+    sanitize::sanitize_events,
+    shortcodes::evaluate_all_shortcodes,
+    task_lists::task_lists,
+};
 
 /// The frontmatter metadata for a parsed markdown document.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Frontmatter {
     pub title: String,
+    /// A hand-written description of the page, rendered as markdown to be its summary in
+    /// place of the one taken from its content. Empty descriptions are ignored.
+    #[serde(default, deserialize_with = "non_empty")]
+    pub description: Option<String>,
     pub tags: Vec<SmolStr>,
+    /// The authors of this page, by name or by key into the site's author metadata.
+    #[serde(default)]
+    pub authors: Vec<String>,
     pub template: Option<String>,
     pub date: Option<String>,
     pub updated: Option<String>,
     pub slug: Option<String>,
+    /// Where this page goes in its section, with lower weights first. Sections with
+    /// weighted pages are ordered by weight rather than date.
+    pub weight: Option<i32>,
     #[serde(default)]
     pub draft: bool,
+    /// Whether to leave this page out of listings, like the `pages` context and feeds,
+    /// while still rendering it.
+    #[serde(default)]
+    pub hidden: bool,
     #[serde(default)]
     pub requires: Vec<String>,
+    /// Globals loaded from hook outputs that this page's template uses, so that it's
+    /// rendered again once they're loaded in two-pass builds.
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    /// Other paths on the site that should redirect to this page.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Whether to collect a table of contents for this page, which is left empty when
+    /// it's `false`. Headings get ids and anchors either way.
+    pub toc: Option<bool>,
+    /// A passphrase to encrypt this page's content with, so that only readers who know
+    /// it can read the page. Empty passphrases are ignored.
+    #[serde(default, deserialize_with = "non_empty")]
+    pub protected: Option<String>,
+}
+
+impl Frontmatter {
+    /// Whether this page wants a table of contents, which it does unless `toc = false`.
+    #[must_use]
+    pub fn wants_toc(&self) -> bool {
+        self.toc.unwrap_or(true)
+    }
 }
 
+// Deserialize an optional string, treating blank strings as missing.
+fn non_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let s = Option::<String>::deserialize(deserializer)?;
+    Ok(s.filter(|s| !s.trim().is_empty()))
+}
+
+/// A heading in a document's table of contents, along with the deeper headings under it.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct TOCHeading {
+    /// The heading's level, from 1 for `h1` to 6 for `h6`.
+    pub level: u8,
     pub id: Option<String>,
     pub text: String,
+    pub children: Vec<Self>,
 }
 
 impl TOCHeading {
-    const fn new(id: Option<String>, text: String) -> Self {
-        Self { id, text }
+    pub(crate) const fn new(level: u8, id: Option<String>, text: String) -> Self {
+        Self {
+            level,
+            id,
+            text,
+            children: vec![],
+        }
     }
 
-    fn to_html(&self) -> String {
-        let name = self.text.replace(' ', "-");
-        let id = self.id.as_ref().unwrap_or(&name);
-        let html = format!("<h2 id=\"{id}\"><a href=\"#{id}\">{}</a></h2>", self.text);
+    pub(crate) fn to_html(&self) -> String {
+        let id = self.id.as_deref().unwrap_or_default();
+        let (mut escaped_id, mut href, mut text) = (String::new(), String::new(), String::new());
+        // Writing to a string can't fail.
+        let _ = escape_html(&mut escaped_id, id);
+        let _ = escape_html(&mut href, &fragment_href(id));
+        let _ = escape_html(&mut text, &self.text);
+        let level = self.level;
 
-        html
+        format!("<h{level} id=\"{escaped_id}\"><a href=\"{href}\">{text}</a></h{level}>")
+    }
+}
+
+// Add a heading to a table of contents, under the last heading that's shallower than it.
+pub(crate) fn insert_heading(toc: &mut Vec<TOCHeading>, heading: TOCHeading) {
+    match toc.last_mut() {
+        Some(last) if last.level < heading.level => insert_heading(&mut last.children, heading),
+        _ => toc.push(heading),
     }
 }
 
@@ -62,29 +162,60 @@ pub struct Document {
     pub updated: DateTime<Utc>,
     pub content: String,
     pub toc: Vec<TOCHeading>,
+    /// Whether the table of contents has anything in it, so templates don't have to check.
+    pub has_toc: bool,
+    /// The ids in the content that links can point to with a fragment: every heading's,
+    /// whether or not it's in the table of contents, and every footnote's.
+    pub toc_ids: BTreeSet<String>,
+    /// The frontmatter's description if it has one, otherwise the start of the content.
     pub summary: String,
+    /// The summary as plain text, for places HTML can't go, like meta descriptions.
+    pub summary_text: String,
+    /// The number of words in the document's text, not counting code blocks.
+    pub word_count: usize,
+    /// The destinations of links to other markdown files, as they're written, so that
+    /// they can be pointed at what's built from those files with [`Self::rewrite_links`].
+    pub links: Vec<String>,
+    /// The labels of footnotes that are referenced but never defined, whose references
+    /// are left as text.
+    pub undefined_footnotes: Vec<String>,
     pub frontmatter: Frontmatter,
 }
 
-#[derive(Debug)]
-struct CodeBlock {
-    lang: String,
-    text: String,
-}
-
-impl CodeBlock {
-    pub const fn new(lang: String) -> Self {
-        Self {
-            lang,
-            text: String::new(),
+impl Document {
+    /// Point the links in [`Self::links`] somewhere else, like the permalinks of the pages
+    /// built from the files they link to. Links `resolve` returns `None` for are left as
+    /// they are.
+    pub fn rewrite_links(&mut self, mut resolve: impl FnMut(&str) -> Option<String>) {
+        for link in &self.links {
+            let Some(to) = resolve(link) else {
+                continue;
+            };
+            let (from, to) = (href(link), href(&to));
+            self.content = self.content.replace(&from, &to);
+            self.summary = self.summary.replace(&from, &to);
         }
     }
 }
 
-enum Summary {
-    Complete,
-    Incomplete,
-    Finalize,
+// An `href` attribute as it's written when rendering links.
+fn href(dest: &str) -> String {
+    let mut attribute = String::from("href=\"");
+    // Writing to a string can't fail.
+    let _ = escape_href(&mut attribute, dest);
+    attribute.push('"');
+    attribute
+}
+
+/// Options for rendering a fragment of markdown with [`MarkdownRenderer::render_one_off`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Leave out the `<p>` around fragments that are a single paragraph, for markdown
+    /// that goes somewhere inline, like a link or a heading.
+    pub inline: bool,
+    /// Highlight code without the highlight cache, for content that mustn't be kept
+    /// anywhere, like a protected page's.
+    pub skip_highlight_cache: bool,
 }
 
 /// Used to parse and format a markdown document.
@@ -93,22 +224,46 @@ enum Summary {
 pub struct MarkdownRenderer {
     options: Options,
     highlighter: Highlighter,
+    highlight_mode: HighlightMode,
+    highlight_cache: Option<Arc<dyn HighlightCache>>,
+    image_resolver: Option<Arc<dyn ImageResolver>>,
+    sanitizer: Option<SanitizeOptions>,
+    shortcodes: HashMap<String, Arc<ShortcodeFn>>,
+    processors: Vec<Box<dyn EventProcessor>>,
+    toc_levels: (HeadingLevel, HeadingLevel),
+    anchor_style: AnchorStyle,
+    summary_marker: String,
+    summary_length: usize,
+    line_numbers: bool,
+    emoji: bool,
+    debug_annotations: bool,
     pub theme: Theme,
 }
 
 impl MarkdownRenderer {
-    pub fn new<P: AsRef<Path>>(theme_path: Option<P>, theme: Option<&str>) -> Result<Self> {
+    pub fn new<P: AsRef<Path>>(
+        theme_path: Option<P>,
+        theme: Option<&str>,
+    ) -> Result<Self, MarkdownError> {
         let theme = if let Some(path) = theme_path {
             let path = path.as_ref();
-            let theme_def = fs::read_to_string(path)?;
-            Theme::from_toml(&theme_def)?
+            let theme_load = |source| MarkdownError::ThemeLoad {
+                path: path.to_owned(),
+                source,
+            };
+            let theme_def = fs::read_to_string(path).map_err(|e| theme_load(e.into()))?;
+            Theme::from_toml(&theme_def).map_err(|e| theme_load(e.into()))?
+        } else if let Some(name) = theme {
+            let themes = builtin::all();
+            let available = themes.iter().map(|t| t.name.clone()).collect();
+            themes.into_iter().find(|t| t.name == name).ok_or_else(|| {
+                MarkdownError::UnknownTheme {
+                    name: name.to_owned(),
+                    available,
+                }
+            })?
         } else {
-            theme.map_or_else(builtin::solarized_dark, |t| {
-                builtin::all()
-                    .into_iter()
-                    .find(|s| t == s.name)
-                    .unwrap_or_else(builtin::solarized_dark)
-            })
+            builtin::solarized_dark()
         };
 
         let highlighter = Highlighter::new();
@@ -117,220 +272,530 @@ impl MarkdownRenderer {
         options.insert(Options::ENABLE_TABLES);
         options.insert(Options::ENABLE_FOOTNOTES);
         options.insert(Options::ENABLE_STRIKETHROUGH);
-        options.insert(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS);
         options.insert(Options::ENABLE_MATH);
         options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+        options.insert(Options::ENABLE_TASKLISTS);
 
         Ok(Self {
             options,
             highlighter,
+            highlight_mode: HighlightMode::default(),
+            highlight_cache: None,
+            image_resolver: None,
+            sanitizer: None,
+            shortcodes: HashMap::new(),
+            processors: vec![],
+            toc_levels: (HeadingLevel::H1, HeadingLevel::H6),
+            anchor_style: AnchorStyle::default(),
+            summary_marker: DEFAULT_SUMMARY_MARKER.to_owned(),
+            summary_length: DEFAULT_SUMMARY_LENGTH,
+            line_numbers: false,
+            emoji: false,
+            debug_annotations: false,
             theme,
         })
     }
 
-    #[allow(clippy::too_many_lines)]
-    /// Parse markdown and create a `Document` form a given string.
-    pub fn parse_from_string(&self, content: &str, env: &Environment) -> Result<Document> {
-        let mut hl = self.highlighter.fork();
+    /// Only put headings from level `min` to level `max` in tables of contents, like 2 and
+    /// 3 for `h2` and `h3` headings. Every level is included by default.
+    ///
+    /// Headings outside of those levels are rendered as they are, without an anchor.
+    pub fn with_toc_levels(mut self, min: u8, max: u8) -> Result<Self, MarkdownError> {
+        let level = |l: u8| HeadingLevel::try_from(usize::from(l)).ok();
+        match (level(min), level(max)) {
+            (Some(min), Some(max)) if min <= max => {
+                self.toc_levels = (min, max);
+                Ok(self)
+            }
+            _ => Err(MarkdownError::TocLevels { min, max }),
+        }
+    }
 
-        let frontmatter = parse_frontmatter(content)?;
-        let content = evaluate_all_shortcodes(content, env, self)?;
+    /// Make ids for headings without an explicit `{#id}` with the given style, see
+    /// [`AnchorStyle`].
+    #[must_use]
+    pub const fn with_anchor_style(mut self, style: AnchorStyle) -> Self {
+        self.anchor_style = style;
+        self
+    }
 
-        let mut html_output = String::new();
-        let parser = Parser::new_ext(&content, self.options);
+    /// End summaries at `marker`, raw HTML like the default `<!-- more -->`, in documents
+    /// that have it. Everything before the marker is the summary, and the marker itself
+    /// is left out of the content.
+    ///
+    /// Documents without the marker fall back to a summary of their first 150 or so
+    /// characters, see [`Self::with_summary_length`].
+    #[must_use]
+    pub fn with_summary_marker<S: Into<String>>(mut self, marker: S) -> Self {
+        self.summary_marker = marker.into();
+        self
+    }
 
-        let mut codeblock = None;
+    /// Make summaries at least `length` characters of text long, 150 by default. Summaries
+    /// end once every element open at that point is closed, so they're usually longer.
+    #[must_use]
+    pub const fn with_summary_length(mut self, length: usize) -> Self {
+        self.summary_length = length;
+        self
+    }
 
-        let mut current_heading = None;
-        let mut headings = Vec::new();
+    /// Wrap each line of fenced code blocks in a `<span class="line">`, so that CSS can
+    /// number them.
+    #[must_use]
+    pub const fn with_line_numbers(mut self) -> Self {
+        self.line_numbers = true;
+        self
+    }
 
-        let mut character_count = 0;
-        let mut summary_status = Summary::Incomplete;
-        let mut summary_events = Vec::new();
-        let mut summary_open_tags = 0;
+    /// Turn straight quotes into curly ones, `--` and `---` into en and em dashes, and
+    /// `...` into an ellipsis. Code is left as it is.
+    #[must_use]
+    pub fn with_smart_punctuation(mut self) -> Self {
+        self.options.insert(Options::ENABLE_SMART_PUNCTUATION);
+        self
+    }
 
-        let mut in_frontmatter = false;
+    /// Replace emoji shortcodes like `:rocket:` in text with the emoji they stand for.
+    /// Codes in code and in shortcodes are left as they are, and so are unknown ones.
+    #[must_use]
+    pub const fn with_emoji(mut self) -> Self {
+        self.emoji = true;
+        self
+    }
 
-        let parser = parser.filter_map(|event| -> Option<Event<'_>> {
-            // If there are currently less than 150 characters of text that have been parsed, add the
-            // node to the summary. Additionally, make sure that the summary doesn't include unclosed tags and the like.
-            if character_count >= 150 && !matches!(summary_status, Summary::Complete) {
-                summary_status = Summary::Finalize;
-            }
+    /// Mark up highlighted code with the given mode, see [`HighlightMode`]. Custom
+    /// elements are used by default.
+    #[must_use]
+    pub fn with_highlight_mode(mut self, mode: HighlightMode) -> Self {
+        self.highlighter = Highlighter::with_config(Config {
+            html_format: html_format(mode),
+            ..Config::default()
+        });
+        self.highlight_mode = mode;
+        self
+    }
 
-            if matches!(summary_status, Summary::Incomplete | Summary::Finalize)
-                && matches!(event, Event::Start(_))
-            {
-                summary_open_tags += 1;
-            }
-            if matches!(summary_status, Summary::Incomplete | Summary::Finalize)
-                && matches!(event, Event::End(_))
-            {
-                summary_open_tags -= 1;
-            }
+    /// Look the code in fenced code blocks up in `cache` before highlighting it, and store
+    /// the code that's highlighted in it, see [`HighlightCache`].
+    #[must_use]
+    pub fn with_highlight_cache(mut self, cache: Arc<dyn HighlightCache>) -> Self {
+        self.highlight_cache = Some(cache);
+        self
+    }
 
-            let e = match event {
-                // TODO: Highlight line by line.
-                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) => {
-                    let lang = lang.trim();
-                    let begin_html =
-                        format!("<pre lang=\"{lang}\"><code class=\"language-{lang}\">");
-                    codeblock = Some(CodeBlock::new(lang.into()));
-                    Some(Event::Html(begin_html.into()))
-                }
-                Event::End(TagEnd::CodeBlock) => {
-                    if let Some(cb) = &codeblock {
-                        let mut html = if cb.lang.is_empty() {
-                            cb.text.clone()
-                        } else {
-                            hl.highlight(&cb.lang, &cb.text)
-                                .expect("Error while highlighting")
-                        };
-
-                        codeblock = None;
-
-                        html.push_str("</code></pre>\n");
-
-                        Some(Event::Html(html.into()))
-                    } else {
-                        None
-                    }
-                }
-                Event::Start(Tag::Heading {
-                    level: HeadingLevel::H2,
-                    ref id,
-                    ..
-                }) => {
-                    current_heading = Some(TOCHeading::new(
-                        id.as_ref().map(std::string::ToString::to_string),
-                        String::new(),
-                    ));
-                    None
-                }
-                Event::End(TagEnd::Heading(HeadingLevel::H2)) => {
-                    let heading = current_heading.take().expect("Heading end before start?");
-                    let html = heading.to_html();
-                    headings.push(heading);
+    /// Size images with `resolver`, see [`ImageResolver`]. Images are rendered with
+    /// `loading="lazy"` and `decoding="async"` either way.
+    #[must_use]
+    pub fn with_image_resolver(mut self, resolver: Arc<dyn ImageResolver>) -> Self {
+        self.image_resolver = Some(resolver);
+        self
+    }
 
-                    Some(Event::Html(html.into()))
-                }
-                Event::Start(Tag::MetadataBlock(_)) => {
-                    in_frontmatter = true;
-                    Some(event)
-                }
-                Event::End(TagEnd::MetadataBlock(_)) => {
-                    in_frontmatter = false;
-                    Some(event)
-                }
-                Event::Text(ref t) => {
-                    if let Some(cb) = &mut codeblock {
-                        cb.text.push_str(t);
-                        None
-                    } else if let Some(h) = &mut current_heading {
-                        h.text.push_str(t);
-                        None
-                    } else {
-                        if !in_frontmatter {
-                            character_count += t.len();
-                        }
-
-                        Some(event)
-                    }
-                }
-                Event::Code(ref s)
-                | Event::InlineMath(ref s)
-                | Event::DisplayMath(ref s)
-                | Event::InlineHtml(ref s) => {
-                    if let Some(h) = &mut current_heading {
-                        h.text.push_str(s);
-                        None
-                    } else {
-                        Some(event)
-                    }
-                }
-                _ => Some(event),
-            };
+    /// Sanitize raw HTML written in markdown with the given options.
+    ///
+    /// HTML produced by shortcodes is trusted and left as-is. Summaries are derived
+    /// from the same events as the content, so they are sanitized too.
+    #[must_use]
+    pub fn with_sanitizer(mut self, options: SanitizeOptions) -> Self {
+        self.sanitizer = Some(options);
+        self
+    }
 
-            match summary_status {
-                Summary::Incomplete => summary_events.push(e.clone()),
-                Summary::Finalize => {
-                    summary_events.push(e.clone());
-                    if summary_open_tags == 0 {
-                        summary_status = Summary::Complete;
-                    }
-                }
-                Summary::Complete => (),
-            }
+    /// Annotate documents parsed with [`Self::parse_from_file`] with the lines paragraphs,
+    /// headings, and shortcodes come from, as HTML comments like
+    /// `<!-- yar: posts/foo.md:42 -->`, for debugging.
+    #[must_use]
+    pub const fn with_debug_annotations(mut self) -> Self {
+        self.debug_annotations = true;
+        self
+    }
 
-            e
-        });
+    /// Register a shortcode implemented in Rust.
+    ///
+    /// Registered shortcodes are used instead of a template with the same name. The
+    /// shortcode's body is passed as raw markdown.
+    pub fn register_shortcode<S: Into<String>>(&mut self, name: S, f: ShortcodeFn) {
+        self.shortcodes.insert(name.into(), Arc::new(f));
+    }
 
-        push_html(&mut html_output, parser);
+    /// Use the shortcodes registered with another renderer too, like the one this renderer
+    /// replaces.
+    #[must_use]
+    pub fn with_shortcodes_of(mut self, other: &Self) -> Self {
+        self.shortcodes.extend(
+            other
+                .shortcodes
+                .iter()
+                .map(|(name, f)| (name.clone(), Arc::clone(f))),
+        );
+        self
+    }
 
-        let mut summary = String::new();
-        push_html(&mut summary, summary_events.into_iter().flatten());
+    /// Add a processor that transforms the events of every document parsed.
+    ///
+    /// Processors run in the order they're added, see [`EventProcessor`].
+    pub fn add_event_processor(&mut self, processor: Box<dyn EventProcessor>) {
+        self.processors.push(processor);
+    }
 
-        // Extract dates from frontmatter
-        let date = frontmatter.date.as_ref().map_or(
-            Ok::<DateTime<Utc>, color_eyre::Report>(Utc::now()),
-            |d| {
-                let parsed = d.parse::<NaiveDateTime>()?;
-                Ok(Utc.from_utc_datetime(&parsed))
-            },
-        )?;
+    // Parse the given content, sanitizing raw HTML outside of the trusted ranges if enabled,
+    // and annotating it with source lines if there's a source map. Emoji shortcodes are
+    // replaced outside of the trusted ranges if `emoji` is set.
+    fn events<'a, 'b, 's>(
+        &self,
+        content: &'a str,
+        builder: Option<&'b ammonia::Builder<'s>>,
+        trusted: &'b [Range<usize>],
+        source_map: Option<&'b SourceMap<'b>>,
+        emoji: bool,
+    ) -> Box<dyn Iterator<Item = Event<'a>> + 'b>
+    where
+        'a: 'b,
+        's: 'b,
+    {
+        let events = Parser::new_ext(content, self.options).into_offset_iter();
+        let events: Box<dyn Iterator<Item = (Event<'a>, Range<usize>)>> = if emoji {
+            Box::new(replace_emoji(events, trusted))
+        } else {
+            Box::new(events)
+        };
+        let events: Box<dyn Iterator<Item = (Event<'a>, Range<usize>)>> = match builder {
+            Some(b) => Box::new(sanitize_events(events, b, trusted)),
+            None => Box::new(events),
+        };
+        match source_map {
+            Some(map) => Box::new(annotate(events, map).map(|(e, _)| e)),
+            None => Box::new(events.map(|(e, _)| e)),
+        }
+    }
 
-        let updated = frontmatter.updated.as_ref().map_or(
-            Ok::<DateTime<Utc>, color_eyre::Report>(date),
-            |d| {
-                let parsed = d.parse::<NaiveDateTime>()?;
-                Ok(Utc.from_utc_datetime(&parsed))
-            },
-        )?;
+    /// Parse markdown and create a `Document` form a given string.
+    pub fn parse_from_string(
+        &self,
+        content: &str,
+        env: &Environment,
+    ) -> Result<Document, MarkdownError> {
+        self.parse(content, None, env)
+    }
+
+    /// Parse markdown read from the file at `path`, which debug annotations name.
+    pub fn parse_from_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        content: &str,
+        env: &Environment,
+    ) -> Result<Document, MarkdownError> {
+        self.parse(content, Some(path.as_ref()), env)
+    }
+
+    fn parse(
+        &self,
+        file: &str,
+        path: Option<&Path>,
+        env: &Environment,
+    ) -> Result<Document, MarkdownError> {
+        // Only the body is rendered, so that markdown never sees the frontmatter.
+        let split = split_frontmatter(file);
+        let frontmatter: Frontmatter = split.deserialize()?;
+        let ctx = context! { page => split.to_value()? };
+        let body_start = file.len() - split.body.len();
+        let (content, expansions) = evaluate_all_shortcodes(split.body, env, self, &ctx)
+            .map_err(|e| e.offset_lines(file[..body_start].matches('\n').count()))?;
+        // The summary marker is left for the summary to find, since sanitizing would
+        // remove comments.
+        let marker = self.summary_marker.trim();
+        let has_marker = !marker.is_empty() && content.contains(marker);
+        let mut trusted = expansions
+            .iter()
+            .map(|e| e.output.clone())
+            .collect::<Vec<Range<usize>>>();
+        if has_marker {
+            trusted.extend(
+                content
+                    .match_indices(marker)
+                    .map(|(i, _)| i..line_end(&content, i + marker.len())),
+            );
+        }
+        let source_map = path
+            .filter(|_| self.debug_annotations)
+            .map(|path| SourceMap::new(path, file, split.body, &expansions));
+
+        let mut html_output = String::new();
+        let builder = self.sanitizer.as_ref().map(SanitizeOptions::builder);
+        let parser = task_lists(figures(self.events(
+            &content,
+            builder.as_ref(),
+            &trusted,
+            source_map.as_ref(),
+            self.emoji,
+        )));
+
+        let mut processors = Processors(&self.processors);
+        // Protected pages are encrypted, so their code isn't kept in the cache.
+        let mut code_blocks = self.code_blocks(frontmatter.protected.is_none());
+        let mut headings = Headings::new(self.toc_levels, self.anchor_style);
+        if !frontmatter.wants_toc() {
+            headings = headings.without_toc();
+        }
+        let mut summary = if has_marker {
+            Summary::new(self.summary_length).with_marker(marker.to_owned())
+        } else {
+            Summary::new(self.summary_length)
+        };
+        let mut word_count = WordCount::default();
+        let mut footnotes = Footnotes::default();
+        let mut links = Links::default();
+        let mut images = Images::new(self.image_resolver.as_deref(), path);
+        push_html(
+            &mut html_output,
+            run_passes(
+                parser,
+                &mut [
+                    &mut processors,
+                    &mut code_blocks,
+                    &mut word_count,
+                    &mut headings,
+                    &mut links,
+                    &mut images,
+                    &mut footnotes,
+                    &mut summary,
+                ],
+            ),
+        );
+        if let Some(e) = code_blocks.error {
+            return Err(e);
+        }
+        html_output.push_str(&footnotes.finish());
+
+        let (summary_html, summary_text) = if let Some(description) = &frontmatter.description {
+            self.render_description(description)?
+        } else {
+            let summary_events = summary.finish();
+            let summary_text = summary_text(summary_events.iter());
+            let mut summary_html = String::new();
+            push_html(&mut summary_html, summary_events.into_iter());
+            (summary_html, summary_text)
+        };
+
+        // Extract dates from frontmatter
+        let date = frontmatter
+            .date
+            .as_deref()
+            .map_or(Ok(Utc::now()), |d| parse_date("date", d))?;
+        let updated = frontmatter
+            .updated
+            .as_deref()
+            .map_or(Ok(date), |d| parse_date("updated", d))?;
 
         Ok(Document {
             date,
             updated,
             content: html_output,
-            toc: headings,
-            summary,
+            has_toc: !headings.toc.is_empty(),
+            toc: headings.toc,
+            toc_ids: headings.ids.into_iter().chain(footnotes.ids).collect(),
+            summary: summary_html,
+            summary_text,
+            word_count: word_count.words,
+            links: links.links,
+            undefined_footnotes: footnotes.undefined.into_iter().collect(),
             frontmatter,
         })
     }
 
-    /// Render a one-off string to markdown. Doesn't create a `Document`.
-    #[allow(clippy::must_use_candidate)]
-    pub fn render_one_off(&self, content: &str) -> String {
+    /// Highlight a snippet of code in the given language, returning the highlighted html.
+    pub fn highlight(&self, lang: &str, code: &str) -> Result<String, MarkdownError> {
+        self.highlighter
+            .fork()
+            .highlight(lang, code)
+            .map_err(|source| MarkdownError::Highlight {
+                lang: lang.to_owned(),
+                source,
+            })
+    }
+
+    /// The stylesheet for code highlighted by this renderer in its theme, with rules
+    /// scoped to `pre` for the kinds of tokens the theme colors.
+    #[must_use]
+    pub fn theme_css(&self) -> String {
+        theme_css(&self.theme, self.highlight_mode)
+    }
+
+    /// Render a fragment of markdown, like a shortcode's body, with the same options and
+    /// passes as documents. Frontmatter, shortcodes, the table of contents, and the summary
+    /// only make sense for whole documents, so they're left out.
+    pub fn render_one_off(
+        &self,
+        content: &str,
+        options: RenderOptions,
+    ) -> Result<String, MarkdownError> {
+        self.render_fragment(content, &[], options, self.emoji)
+    }
+
+    // Render a fragment, leaving the raw HTML in the trusted ranges unsanitized, and
+    // replacing emoji shortcodes if `emoji` is set.
+    pub(crate) fn render_fragment(
+        &self,
+        content: &str,
+        trusted: &[Range<usize>],
+        options: RenderOptions,
+        emoji: bool,
+    ) -> Result<String, MarkdownError> {
+        let builder = self.sanitizer.as_ref().map(SanitizeOptions::builder);
+        let parser = task_lists(figures(self.events(
+            content,
+            builder.as_ref(),
+            trusted,
+            None,
+            emoji,
+        )));
+
+        let mut processors = Processors(&self.processors);
+        let mut code_blocks = self.code_blocks(!options.skip_highlight_cache);
+        let mut headings = Headings::new(self.toc_levels, self.anchor_style).without_toc();
+        let mut images = Images::new(self.image_resolver.as_deref(), None);
+        let events = run_passes(
+            parser,
+            &mut [
+                &mut processors,
+                &mut code_blocks,
+                &mut headings,
+                &mut images,
+            ],
+        )
+        .collect::<Vec<Event>>();
+        if let Some(e) = code_blocks.error {
+            return Err(e);
+        }
+
+        let events = if options.inline {
+            unwrap_paragraph(events)
+        } else {
+            events
+        };
         let mut html_output = String::new();
-        let parser = Parser::new_ext(content, self.options);
-        push_html(&mut html_output, parser);
-        html_output
+        push_html(&mut html_output, events.into_iter());
+        Ok(html_output)
+    }
+
+    // The pass highlighting code blocks, with the cache if there is one and `cached` is set.
+    fn code_blocks(&self, cached: bool) -> CodeBlocks {
+        let code_blocks = CodeBlocks::new(self.highlighter.fork(), self.line_numbers);
+        match &self.highlight_cache {
+            Some(cache) if cached => code_blocks.with_cache(Arc::clone(cache), self.highlight_mode),
+            _ => code_blocks,
+        }
+    }
+
+    // Render a description from the frontmatter as a summary, along with its text.
+    fn render_description(&self, description: &str) -> Result<(String, String), MarkdownError> {
+        let builder = self.sanitizer.as_ref().map(SanitizeOptions::builder);
+        let events = self
+            .events(description, builder.as_ref(), &[], None, self.emoji)
+            .collect::<Vec<Event>>();
+
+        Ok((
+            self.render_one_off(description, RenderOptions::default())?,
+            summary_text(events.iter()),
+        ))
+    }
+}
+
+// The events of a fragment without the paragraph around them, if it's a single paragraph.
+fn unwrap_paragraph(mut events: Vec<Event>) -> Vec<Event> {
+    let paragraphs = events
+        .iter()
+        .filter(|e| matches!(e, Event::Start(Tag::Paragraph)))
+        .count();
+    if paragraphs == 1
+        && matches!(events.first(), Some(Event::Start(Tag::Paragraph)))
+        && matches!(events.last(), Some(Event::End(TagEnd::Paragraph)))
+    {
+        events.pop();
+        events.remove(0);
     }
+
+    events
 }
 
-fn parse_frontmatter(content: &str) -> Result<Frontmatter> {
-    let mut opening_delim = false;
-    let mut frontmatter_content = String::new();
+// Parse a date from the frontmatter field with the given name.
+fn parse_date(field: &'static str, value: &str) -> Result<DateTime<Utc>, MarkdownError> {
+    let parsed = value
+        .parse::<NaiveDateTime>()
+        .map_err(|source| MarkdownError::DateParse {
+            field,
+            value: value.to_owned(),
+            source,
+        })?;
+    Ok(Utc.from_utc_datetime(&parsed))
+}
 
-    for line in content.lines() {
-        if line.trim() == "---" {
-            if opening_delim {
-                break;
-            }
+// The marker ending summaries, unless another one is set.
+const DEFAULT_SUMMARY_MARKER: &str = "<!-- more -->";
 
-            opening_delim = true;
-            continue;
+// How many characters of text summaries have, unless another length is set.
+const DEFAULT_SUMMARY_LENGTH: usize = 150;
+
+// The offset after the whitespace following `offset`, up to and including the end of its
+// line.
+fn line_end(content: &str, offset: usize) -> usize {
+    let rest = &content[offset..];
+    let trimmed = rest.trim_start_matches([' ', '\t']);
+    let trimmed = trimmed.strip_prefix('\n').unwrap_or(trimmed);
+    offset + rest.len() - trimmed.len()
+}
+
+// The longest a plain text summary can be, in characters.
+const SUMMARY_TEXT_LENGTH: usize = 160;
+
+// Extract the text from the summary events, with whitespace collapsed, and truncated at
+// a word boundary if it's too long.
+fn summary_text<'a>(events: impl Iterator<Item = &'a Event<'a>>) -> String {
+    let mut text = String::new();
+
+    for event in events {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(t),
+            Event::SoftBreak
+            | Event::HardBreak
+            | Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::Item
+                | TagEnd::BlockQuote(_)
+                | TagEnd::TableCell,
+            ) => text.push(' '),
+            _ => (),
         }
+    }
+
+    truncate_words(&text)
+}
 
-        frontmatter_content.push_str(line);
-        frontmatter_content.push('\n');
+// Collapse the whitespace in some text, truncating it at a word boundary if it's too long
+// for a summary.
+fn truncate_words(text: &str) -> String {
+    let words = text.split_whitespace().collect::<Vec<&str>>();
+    let mut truncated = String::new();
+    for word in &words {
+        let len = truncated.chars().count();
+        if len > 0 && len + 1 + word.chars().count() > SUMMARY_TEXT_LENGTH {
+            truncated.push('…');
+            return truncated;
+        }
+        if len > 0 {
+            truncated.push(' ');
+        }
+        truncated.push_str(word);
     }
 
-    let frontmatter = toml::from_str(&frontmatter_content)?;
-    Ok(frontmatter)
+    truncated
 }
 
 #[cfg(test)]
 mod tests {
+    use color_eyre::Result;
+
     use super::*;
 
     fn get_date() -> Result<DateTime<Utc>> {
@@ -386,6 +851,117 @@ hello world
         Ok(())
     }
 
+    #[test]
+    fn test_summary_length() -> Result<()> {
+        let parse = |renderer: MarkdownRenderer, body: &str| {
+            let content = format!("---\ntitle = \"Test\"\ntags = []\n---\n\n{body}");
+            renderer.parse_from_string(&content, &Environment::empty())
+        };
+
+        // Characters are counted rather than bytes, so a first paragraph of 120 Cyrillic
+        // characters, which is 240 bytes, isn't enough for a summary on its own.
+        let first = "Слово ".repeat(20);
+        let body = format!("{first}\n\n🎉 Второй абзац, который чуть длиннее.\n\nТретий абзац.\n");
+        let document = parse(MarkdownRenderer::new::<&str>(None, None)?, &body)?;
+        assert_eq!(
+            document.summary,
+            format!(
+                "<p>{}</p>\n<p>🎉 Второй абзац, который чуть длиннее.</p>\n",
+                first.trim_end()
+            )
+        );
+
+        let document = parse(
+            MarkdownRenderer::new::<&str>(None, None)?.with_summary_length(200),
+            &body,
+        )?;
+        assert!(document.summary.ends_with("<p>Третий абзац.</p>\n"));
+
+        // The length can be reached in the middle of an element, which is still closed.
+        let document = parse(
+            MarkdownRenderer::new::<&str>(None, None)?.with_summary_length(8),
+            "Привет, *дорогой 🌍* мир.\n\nПосле.\n",
+        )?;
+        assert_eq!(
+            document.summary,
+            "<p>Привет, <em>дорогой 🌍</em> мир.</p>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_marker() -> Result<()> {
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let parse = |body: &str| {
+            let content = format!("---\ntitle = \"Test\"\ntags = []\n---\n\n{body}");
+            renderer.parse_from_string(&content, &Environment::empty())
+        };
+
+        // A marker in a paragraph ends the summary there, closing the paragraph.
+        let document = parse("- A list\n- Some *short <!-- more --> text*\n\nAfter.\n")?;
+        assert_eq!(
+            document.summary,
+            "<ul>\n<li>A list</li>\n<li>Some <em>short </em></li>\n</ul>\n"
+        );
+        assert_eq!(document.summary_text, "A list Some short");
+        assert!(!document.content.contains("more"));
+        assert!(document.content.contains("<em>short  text</em>"));
+
+        // A marker right after a code block leaves the whole block in the summary.
+        let document = parse("```\nfn main() {}\n```\n<!-- more -->\nAfter.\n")?;
+        let code = "<pre lang=\"\"><code class=\"language-\">fn main() {}\n</code></pre>\n";
+        assert_eq!(document.summary, code);
+        assert_eq!(document.content, format!("{code}<p>After.</p>\n"));
+
+        // Markers in code aren't markers, so short documents are summarized whole.
+        let document = parse("Before.\n\n    <!-- more -->\n")?;
+        assert!(document.content.contains("&lt;!-- more --&gt;"));
+        assert_eq!(document.summary, document.content);
+
+        // Other markers can be used, and sanitizing doesn't remove them first.
+        let document = MarkdownRenderer::new::<&str>(None, None)?
+            .with_summary_marker("<!--fold-->")
+            .with_sanitizer(SanitizeOptions::default())
+            .parse_from_string(
+                "---\ntitle = \"Test\"\ntags = []\n---\n\nShort.\n\n<!--fold-->\n\nLong.\n",
+                &Environment::empty(),
+            )?;
+        assert_eq!(document.summary, "<p>Short.</p>\n");
+        assert_eq!(document.content, "<p>Short.</p>\n<p>Long.</p>\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_text() -> Result<()> {
+        let content = r#"
+---
+title = "Test"
+tags = []
+---
+Some *emphasis*, a [link](https://example.com), and a "quote".
+Second line.
+        "#;
+
+        let document = MarkdownRenderer::new::<&str>(None, None)?
+            .parse_from_string(content, &Environment::empty())?;
+        assert_eq!(
+            document.summary_text,
+            r#"Some emphasis, a link, and a "quote". Second line."#
+        );
+
+        let long = "word ".repeat(100);
+        let document = MarkdownRenderer::new::<&str>(None, None)?.parse_from_string(
+            &format!("---\ntitle = \"Test\"\ntags = []\n---\n{long}"),
+            &Environment::empty(),
+        )?;
+        assert!(document.summary_text.ends_with("word…"));
+        assert!(document.summary_text.chars().count() <= 161);
+
+        Ok(())
+    }
+
     #[test]
     fn test_toc() -> Result<()> {
         let content = r#"
@@ -404,6 +980,12 @@ Some Content
 
 Some More Content
 
+### Part 2.1
+
+#### Too Deep
+
+### Part 2.2 {#part2-2}
+
 ## Part 3 {#part3}
 
 Even More Content
@@ -411,6 +993,7 @@ Even More Content
         "#;
 
         let document = MarkdownRenderer::new::<&str>(None, None)?
+            .with_toc_levels(2, 3)?
             .parse_from_string(content, &Environment::empty())?;
         insta::assert_yaml_snapshot!(document, {
             ".date" => get_date().unwrap().to_string(),
@@ -419,6 +1002,188 @@ Even More Content
         Ok(())
     }
 
+    #[test]
+    fn test_toc_levels() -> Result<()> {
+        let content =
+            "---\ntitle = \"Test\"\ntags = []\n---\n\n### Deep\n\n# Shallow\n\n## Middle\n";
+        let document = MarkdownRenderer::new::<&str>(None, None)?
+            .parse_from_string(content, &Environment::empty())?;
+
+        // Every level is included by default, with headings before a shallower one left at
+        // the top.
+        let levels = |toc: &[TOCHeading]| toc.iter().map(|h| h.level).collect::<Vec<u8>>();
+        assert_eq!(levels(&document.toc), [3, 1]);
+        assert_eq!(levels(&document.toc[1].children), [2]);
+
+        for (min, max) in [(0, 3), (2, 7), (3, 2)] {
+            let error = MarkdownRenderer::new::<&str>(None, None)?
+                .with_toc_levels(min, max)
+                .err()
+                .unwrap();
+            assert!(matches!(error, MarkdownError::TocLevels { .. }));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_toc_disabled() -> Result<()> {
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let body = "\n## One\n\n### Two\n";
+        let with_toc = renderer.parse_from_string(
+            &format!("---\ntitle = \"Test\"\ntags = []\n---\n{body}"),
+            &Environment::empty(),
+        )?;
+        assert!(with_toc.has_toc);
+        assert_eq!(with_toc.toc.len(), 1);
+
+        let without_toc = renderer.parse_from_string(
+            &format!("---\ntitle = \"Test\"\ntags = []\ntoc = false\n---\n{body}"),
+            &Environment::empty(),
+        )?;
+        assert!(!without_toc.has_toc);
+        assert!(without_toc.toc.is_empty());
+        // Headings are rendered the same either way.
+        assert_eq!(without_toc.content, with_toc.content);
+
+        // Pages without any headings don't have one either.
+        let empty = renderer.parse_from_string(
+            "---\ntitle = \"Test\"\ntags = []\n---\nText\n",
+            &Environment::empty(),
+        )?;
+        assert!(!empty.has_toc);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_toc_ids() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\ntoc = false\n---\n\n# Top {#top}\n\n## Part One\n\nText[^note] and more[^second].\n\n#### Deep\n\n#### Deeper {#deeper}\n\n[^note]: A note.\n\n[^second]: Another.\n";
+        let document = MarkdownRenderer::new::<&str>(None, None)?
+            .with_toc_levels(2, 3)?
+            .parse_from_string(content, &Environment::empty())?;
+
+        // Every id in the content is there, even without a table of contents.
+        let rendered = document
+            .content
+            .split(" id=\"")
+            .skip(1)
+            .filter_map(|s| s.split('"').next())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            document
+                .toc_ids
+                .iter()
+                .map(String::as_str)
+                .collect::<BTreeSet<_>>(),
+            rendered
+        );
+        assert_eq!(
+            document.toc_ids.iter().collect::<Vec<_>>(),
+            [
+                "deeper",
+                "fnref-note",
+                "fnref-second",
+                "note",
+                "part-one",
+                "second",
+                "top"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_links() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\n---\n\nSee [part 1](./part-1.md), [its end](./part-1.md#end), [again](./part-1.md), and [the series](../series/index.md).\n\nNot [a url](https://example.com/a.md), [a site path](/notes.md), [a fragment](#intro), or [a file](./notes.txt).\n";
+        let mut document = MarkdownRenderer::new::<&str>(None, None)?
+            .parse_from_string(content, &Environment::empty())?;
+        assert_eq!(
+            document.links,
+            ["./part-1.md", "./part-1.md#end", "../series/index.md"]
+        );
+
+        document.rewrite_links(|link| {
+            (!link.starts_with("../")).then(|| link.replace("./part-1.md", "/posts/part-1"))
+        });
+        assert!(document.content.starts_with(
+            "<p>See <a href=\"/posts/part-1\">part 1</a>, <a href=\"/posts/part-1#end\">its end</a>, <a href=\"/posts/part-1\">again</a>, and <a href=\"../series/index.md\">the series</a>.</p>"
+        ));
+        assert_eq!(document.summary, document.content);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_heading_ids() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\n---\n\n## What's   New_in v2.0?\n\n## Fish & <Chips>\n\n## Explicit {#Keep_This}\n";
+        let document = MarkdownRenderer::new::<&str>(None, None)?
+            .parse_from_string(content, &Environment::empty())?;
+
+        let ids = document
+            .toc
+            .iter()
+            .map(|h| h.id.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [Some("whats-new-in-v20"), Some("fish"), Some("Keep_This")]
+        );
+        assert!(document.content.contains(
+            "<h2 id=\"whats-new-in-v20\"><a href=\"#whats-new-in-v20\">What&#39;s   New_in v2.0?</a></h2>"
+        ));
+        assert!(
+            document
+                .content
+                .contains("<h2 id=\"fish\"><a href=\"#fish\">Fish &amp; </a></h2>")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_description() -> Result<()> {
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let parse = |description: &str| {
+            let content = format!("---\ntitle = \"Test\"\ntags = []\n{description}\n---\n\nHello");
+            renderer.parse_from_string(&content, &Environment::empty())
+        };
+
+        let document = parse(r#"description = "Hand written""#)?;
+        assert_eq!(
+            document.frontmatter.description.as_deref(),
+            Some("Hand written")
+        );
+        assert_eq!(
+            parse(r#"description = "  ""#)?.frontmatter.description,
+            None
+        );
+        assert_eq!(parse("")?.frontmatter.description, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_description_summary() -> Result<()> {
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let summaries = |description: &str| {
+            let content = format!(
+                "---\ntitle = \"Test\"\ntags = []\n{description}\n---\n\nThe *first* paragraph.\n\nThe second."
+            );
+            renderer
+                .parse_from_string(&content, &Environment::empty())
+                .map(|d| (d.summary, d.summary_text))
+        };
+
+        insta::assert_yaml_snapshot!(summaries("")?);
+        insta::assert_yaml_snapshot!(summaries(
+            r#"description = "A **hand written** [summary](https://example.com)""#
+        )?);
+
+        Ok(())
+    }
+
     #[test]
     fn test_frontmatter() -> Result<()> {
         let content = r#"
@@ -480,6 +1245,43 @@ if __name__ == "__main__":
         Ok(())
     }
 
+    #[test]
+    fn test_line_numbers() -> Result<()> {
+        let content = r#"---
+title = "Test"
+tags = []
+---
+
+```py
+def greet(name):
+    """Say hello,
+    politely."""
+
+    print(f"Hello {name}")
+
+
+```
+"#;
+
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let document = renderer.parse_from_string(content, &Environment::empty())?;
+        insta::assert_snapshot!("without_line_numbers", document.content);
+
+        let document = renderer
+            .with_line_numbers()
+            .parse_from_string(content, &Environment::empty())?;
+        insta::assert_snapshot!("with_line_numbers", document.content);
+
+        // Every line of the block is kept, including the blank ones at the end.
+        assert_eq!(document.content.matches("<span class=\"line\">").count(), 7);
+        // Elements spanning lines, like the docstring, are split between them.
+        for line in document.content.lines().filter(|l| l.starts_with("<span")) {
+            assert_eq!(line.matches("<a-").count(), line.matches("</a-").count());
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_with_shortcode() -> Result<()> {
         let content = r#"
@@ -526,4 +1328,248 @@ this is a note!
 
         Ok(())
     }
+
+    #[test]
+    fn test_smart_punctuation() -> Result<()> {
+        let content = r#"---
+title = "Punctuation"
+tags = []
+---
+
+## "Quoted" heading -- with 1--2 dashes
+
+"Double" and 'single' quotes, it's an ellipsis... and an em dash --- here.
+
+{{! note(title="it's") !}}
+"Quoted" body -- in a shortcode...
+{{! end !}}
+
+Some `"code" -- that's left...` alone.
+"#;
+        let mut env = Environment::new();
+        env.add_template(
+            "note.html",
+            "<aside title=\"{{ arguments.title }}\">{{ body|safe }}</aside>",
+        )?;
+
+        for smart in [false, true] {
+            let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+            let renderer = if smart {
+                renderer.with_smart_punctuation()
+            } else {
+                renderer
+            };
+            let document = renderer.parse_from_string(content, &env)?;
+            insta::assert_yaml_snapshot!(
+                format!("smart_punctuation_{smart}"),
+                (document.content, document.summary_text, document.toc)
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_footnotes() -> Result<()> {
+        let content = "---\ntitle = \"Footnotes\"\ntags = []\n---\n\nFirst[^later], then[^earlier], and the first again[^later].\n\n[^earlier]: Defined *first*, referenced second.\n\n[^unused]: Never referenced.\n\n[^later]: Defined last.\n\n    With a second paragraph.\n";
+        let document = MarkdownRenderer::new::<&str>(None, None)?
+            .parse_from_string(content, &Environment::empty())?;
+
+        insta::assert_snapshot!(document.content);
+        assert!(document.undefined_footnotes.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_undefined_footnotes() -> Result<()> {
+        let content = "---\ntitle = \"Footnotes\"\ntags = []\n---\n\nDefined[^a], missing[^b], and `[^code]`.\n\n    [^indented]\n\n[^a]: Refers to [^c].\n";
+        let document = MarkdownRenderer::new::<&str>(None, None)?
+            .with_emoji()
+            .parse_from_string(content, &Environment::empty())?;
+
+        assert_eq!(document.undefined_footnotes, ["b", "c"]);
+        assert!(document.content.contains("missing[^b]"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shortcodes_in_code() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\n---\n\n```\n{{! note !}}\nfenced\n{{! end !}}\n```\n\n    {{! note !}}indented{{! end !}}\n\nInline `{{! end !}}` code.\n\n{{! note !}}\n```\n{{! note !}}nested{{! end !}}\n```\n{{! end !}}\n";
+        let mut env = Environment::new();
+        env.add_template("note.html", "<div class=\"note\">{{ body|safe }}</div>")?;
+
+        let document =
+            MarkdownRenderer::new::<&str>(None, None)?.parse_from_string(content, &env)?;
+        assert!(
+            document
+                .content
+                .starts_with("<pre lang=\"\"><code class=\"language-\">{{! note !}}\nfenced\n{{! end !}}\n</code></pre>\n<pre><code>{{! note !}}indented{{! end !}}\n</code></pre>\n<p>Inline <code>{{! end !}}</code> code.</p>\n"),
+            "{}",
+            document.content
+        );
+        // Code in the body of a shortcode is left as it is too.
+        assert!(document.content.contains(
+            "<div class=\"note\"><pre lang=\"\"><code class=\"language-\">{{! note !}}nested{{! end !}}\n</code></pre>\n</div>"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shortcode_references() -> Result<()> {
+        let content = r#"
+---
+title = "Test"
+tags = []
+cover = "cover.png"
+---
+
+{{! img(src=page.cover, alt=page.title) !}}{{! end !}}
+       "#;
+
+        let mut env = Environment::new();
+        env.add_template(
+            "img.html",
+            r#"<img src="{{ arguments.src }}" alt="{{ arguments.alt }}">"#,
+        )?;
+
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let document = renderer.parse_from_string(content, &env)?;
+        assert!(
+            document
+                .content
+                .contains(r#"<img src="cover.png" alt="Test">"#)
+        );
+
+        let err = renderer
+            .parse_from_string(&content.replace("page.cover", "page.banner"), &env)
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            MarkdownError::ShortcodeArgument { name, argument, .. } if name == "img" && argument == "src"
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Couldn't resolve the src argument of the img shortcode at line 8: page.banner isn't defined"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_theme() {
+        let err = MarkdownRenderer::new::<&str>(None, Some("base16-ocean.dark")).err();
+        assert!(matches!(
+            err,
+            Some(MarkdownError::UnknownTheme { name, available })
+                if name == "base16-ocean.dark" && available.iter().any(|t| t == "Solarized Dark")
+        ));
+    }
+
+    #[test]
+    fn test_invalid_date() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\ndate = \"2025-01-01\"\n---\n";
+
+        let err = MarkdownRenderer::new::<&str>(None, None)?
+            .parse_from_string(content, &Environment::empty())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            MarkdownError::DateParse { field: "date", value, .. } if value == "2025-01-01"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_language() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\n---\n\n```not-a-language\ncode\n```\n";
+
+        let err = MarkdownRenderer::new::<&str>(None, None)?
+            .parse_from_string(content, &Environment::empty())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            MarkdownError::Highlight { lang, .. } if lang == "not-a-language"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_one_off() -> Result<()> {
+        let snippet = "## Part *One*\n\nSome text<script>alert(1)</script>.\n\n```rust\nfn main() {}\n```\n\n> Quoted\n";
+        let renderer =
+            MarkdownRenderer::new::<&str>(None, None)?.with_sanitizer(SanitizeOptions::default());
+
+        // Fragments are rendered like the body of a document.
+        let document = renderer.parse_from_string(
+            &format!("---\ntitle = \"Test\"\ntags = []\n---\n{snippet}"),
+            &Environment::empty(),
+        )?;
+        let fragment = renderer.render_one_off(snippet, RenderOptions::default())?;
+        assert_eq!(fragment, document.content);
+        assert!(!fragment.contains("<script>"));
+
+        let error = renderer
+            .render_one_off("```not-a-language\ncode\n```\n", RenderOptions::default())
+            .unwrap_err();
+        assert!(matches!(error, MarkdownError::Highlight { .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_one_off_inline() -> Result<()> {
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let inline = RenderOptions {
+            inline: true,
+            ..RenderOptions::default()
+        };
+
+        assert_eq!(
+            renderer.render_one_off("Some *text*", inline)?,
+            "Some <em>text</em>"
+        );
+        // Only single paragraphs are unwrapped.
+        assert_eq!(
+            renderer.render_one_off("One\n\nTwo", inline)?,
+            "<p>One</p>\n<p>Two</p>\n"
+        );
+        assert_eq!(
+            renderer.render_one_off("> Quoted", inline)?,
+            "<blockquote>\n<p>Quoted</p>\n</blockquote>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_thematic_breaks() -> Result<()> {
+        // Blank lines in frontmatter used to turn it into a rule and a heading.
+        let frontmatters = [
+            "---\ntitle = \"Test\"\ntags = []\n---",
+            "---\n\ntitle = \"Test\"\n\ntags = []\n---",
+        ];
+        for (frontmatter, rule) in frontmatters
+            .into_iter()
+            .flat_map(|f| ["---", "***", "___"].map(|r| (f, r)))
+        {
+            let content = format!(
+                "{frontmatter}\n\nBefore the break\n\n{rule}\n\nAfter the break\n\n{rule}\n\nThe end\n"
+            );
+
+            let document = MarkdownRenderer::new::<&str>(None, None)?
+                .parse_from_string(&content, &Environment::empty())?;
+            assert_eq!(
+                document.content,
+                "<p>Before the break</p>\n<hr />\n<p>After the break</p>\n<hr />\n<p>The end</p>\n"
+            );
+            assert_eq!(document.frontmatter.title, "Test");
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,887 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    iter,
+    path::Path,
+    sync::Arc,
+};
+
+use arborium::Highlighter;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Tag, TagEnd};
+use pulldown_cmark_escape::{escape_href, escape_html};
+use url::Url;
+
+use crate::{
+    AnchorStyle, HighlightCache, HighlightMode, MarkdownError, TOCHeading, fence::CodeBlock,
+    insert_heading,
+};
+
+/// A transformation over the events of a markdown document, for customizing how
+/// documents are rendered.
+///
+/// Processors run on every event in order, before the built-in passes (highlighting,
+/// the table of contents, and the summary). Returning `None` drops the event.
+pub trait EventProcessor: Send + Sync {
+    fn process<'a>(&self, event: Event<'a>) -> Option<Event<'a>>;
+}
+
+/// A single pass over the events of a document.
+///
+/// Each pass sees the events output by the passes before it.
+pub trait Pass<'a> {
+    fn process(&mut self, event: Event<'a>) -> Option<Event<'a>>;
+}
+
+/// Run the events through each of the passes in order.
+pub fn run_passes<'a, 'p>(
+    events: impl Iterator<Item = Event<'a>> + 'p,
+    passes: &'p mut [&mut dyn Pass<'a>],
+) -> impl Iterator<Item = Event<'a>> + 'p {
+    events.filter_map(|event| {
+        passes
+            .iter_mut()
+            .try_fold(event, |event, pass| pass.process(event))
+    })
+}
+
+/// Runs the processors registered with the renderer.
+pub struct Processors<'r>(pub &'r [Box<dyn EventProcessor>]);
+
+impl<'a> Pass<'a> for Processors<'_> {
+    fn process(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        self.0
+            .iter()
+            .try_fold(event, |event, processor| processor.process(event))
+    }
+}
+
+/// Highlights fenced code blocks.
+///
+/// Passes can't fail, so the first error highlighting a block is kept in `error` for the
+/// caller to check once the events have been rendered.
+pub struct CodeBlocks {
+    highlighter: Highlighter,
+    line_numbers: bool,
+    cache: Option<(Arc<dyn HighlightCache>, HighlightMode)>,
+    current: Option<CodeBlock>,
+    pub error: Option<MarkdownError>,
+}
+
+impl CodeBlocks {
+    /// Highlight code blocks, wrapping each of their lines in a `<span class="line">` if
+    /// `line_numbers` is set and they don't turn it off with `linenos=false`.
+    pub const fn new(highlighter: Highlighter, line_numbers: bool) -> Self {
+        Self {
+            highlighter,
+            line_numbers,
+            cache: None,
+            current: None,
+            error: None,
+        }
+    }
+
+    /// Look code up in `cache` before highlighting it, storing what's highlighted. The
+    /// mode is the one the highlighter marks code up with.
+    #[must_use]
+    pub fn with_cache(mut self, cache: Arc<dyn HighlightCache>, mode: HighlightMode) -> Self {
+        self.cache = Some((cache, mode));
+        self
+    }
+
+    fn highlight(&mut self, lang: &str, code: &str) -> Result<String, arborium::Error> {
+        let Some((cache, mode)) = &self.cache else {
+            return self.highlighter.highlight(lang, code);
+        };
+        if let Some(html) = cache.get(lang, *mode, code) {
+            return Ok(html);
+        }
+
+        let html = self.highlighter.highlight(lang, code)?;
+        cache.insert(lang, *mode, code, &html);
+        Ok(html)
+    }
+}
+
+impl<'a> Pass<'a> for CodeBlocks {
+    fn process(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                let cb = CodeBlock::from_info(&info);
+                let mut begin_html = String::new();
+                // Writing to a string can't fail.
+                if let Some(filename) = &cb.filename {
+                    begin_html.push_str("<figure class=\"code-block\"><figcaption>");
+                    let _ = escape_html(&mut begin_html, filename);
+                    begin_html.push_str("</figcaption>");
+                }
+                begin_html.push_str("<pre lang=\"");
+                let _ = escape_html(&mut begin_html, &cb.lang);
+                begin_html.push('"');
+                if cb.linenos.unwrap_or(self.line_numbers) {
+                    begin_html.push_str(" data-linenos");
+                }
+                begin_html.push_str("><code class=\"language-");
+                let _ = escape_html(&mut begin_html, &cb.lang);
+                begin_html.push_str("\">");
+
+                self.current = Some(cb);
+                Some(Event::Html(begin_html.into()))
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                // Indented code blocks aren't highlighted, so they're left as they are.
+                let Some(cb) = self.current.take() else {
+                    return Some(event);
+                };
+                let mut html = if cb.lang.is_empty() {
+                    let mut escaped = String::new();
+                    // Writing to a string can't fail.
+                    let _ = escape_html(&mut escaped, &cb.text);
+                    escaped
+                } else {
+                    match self.highlight(&cb.lang, &cb.text) {
+                        Ok(html) => html,
+                        Err(source) => {
+                            self.error.get_or_insert(MarkdownError::Highlight {
+                                lang: cb.lang.clone(),
+                                source,
+                            });
+                            String::new()
+                        }
+                    }
+                };
+                // Highlighting lines needs them to be wrapped too.
+                if cb.linenos.unwrap_or(self.line_numbers) || !cb.hl_lines.is_empty() {
+                    html = wrap_lines(&html, &cb);
+                }
+                html.push_str("</code></pre>\n");
+                if cb.filename.is_some() {
+                    html.push_str("</figure>\n");
+                }
+
+                Some(Event::Html(html.into()))
+            }
+            Event::Text(ref t) => {
+                if let Some(cb) = &mut self.current {
+                    cb.text.push_str(t);
+                    None
+                } else {
+                    Some(event)
+                }
+            }
+            _ => Some(event),
+        }
+    }
+}
+
+// Wrap each line of a highlighted code block in a `<span class="line">`, or
+// `<span class="line hl">` for highlighted lines, closing the elements still open at the
+// end of a line and opening them again at the start of the next, so that every line is a
+// complete element. Highlighting drops trailing newlines, so blank lines at the end are
+// counted from the code itself.
+fn wrap_lines(html: &str, cb: &CodeBlock) -> String {
+    let count = cb.text.lines().count().max(html.lines().count());
+    let mut wrapped = String::with_capacity(html.len() + count * 28);
+    let mut open: Vec<&str> = vec![];
+
+    let lines = html.split('\n').chain(iter::repeat("")).take(count);
+    for (n, line) in lines.enumerate() {
+        if cb.is_highlighted(n + 1) {
+            wrapped.push_str("<span class=\"line hl\">");
+        } else {
+            wrapped.push_str("<span class=\"line\">");
+        }
+        for tag in &open {
+            wrapped.push_str(tag);
+        }
+
+        let mut rest = line;
+        while let Some(start) = rest.find('<') {
+            let end = rest[start..]
+                .find('>')
+                .map_or(rest.len(), |i| start + i + 1);
+            let tag = &rest[start..end];
+            if tag.starts_with("</") {
+                open.pop();
+            } else if !tag.ends_with("/>") {
+                open.push(tag);
+            }
+            rest = &rest[end..];
+        }
+        wrapped.push_str(line);
+
+        for tag in open.iter().rev() {
+            let name = tag[1..]
+                .split(|c: char| c.is_whitespace() || c == '>')
+                .next()
+                .unwrap_or_default();
+            wrapped.push_str("</");
+            wrapped.push_str(name);
+            wrapped.push('>');
+        }
+        wrapped.push_str("</span>\n");
+    }
+
+    wrapped
+}
+
+/// Collects headings into the table of contents, nesting deeper headings under the ones
+/// before them, and renders them with anchors.
+pub struct Headings {
+    levels: (HeadingLevel, HeadingLevel),
+    anchor_style: AnchorStyle,
+    current: Option<TOCHeading>,
+    count: usize,
+    collect: bool,
+    pub toc: Vec<TOCHeading>,
+    /// The ids of every heading, including the ones outside the collected levels that
+    /// have an explicit `{#id}`.
+    pub ids: BTreeSet<String>,
+}
+
+impl Headings {
+    /// Collect the headings from the first level to the second, inclusive, making ids for
+    /// them with the given style.
+    pub const fn new(levels: (HeadingLevel, HeadingLevel), anchor_style: AnchorStyle) -> Self {
+        Self {
+            levels,
+            anchor_style,
+            current: None,
+            count: 0,
+            collect: true,
+            toc: vec![],
+            ids: BTreeSet::new(),
+        }
+    }
+
+    /// Still give headings ids and anchors, but leave the table of contents empty.
+    pub const fn without_toc(mut self) -> Self {
+        self.collect = false;
+        self
+    }
+}
+
+impl<'a> Pass<'a> for Headings {
+    fn process(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        match event {
+            Event::Start(Tag::Heading { level, ref id, .. })
+                if (self.levels.0..=self.levels.1).contains(&level) =>
+            {
+                self.current = Some(TOCHeading::new(
+                    level as u8,
+                    id.as_ref().map(std::string::ToString::to_string),
+                    String::new(),
+                ));
+                None
+            }
+            Event::Start(Tag::Heading {
+                id: Some(ref id), ..
+            }) => {
+                self.ids.insert(id.to_string());
+                Some(event)
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                // Processors can drop the start of a heading, leaving its end as it is.
+                let Some(mut heading) = self.current.take() else {
+                    return Some(event);
+                };
+                // Headings without an explicit `{#id}` get one made from their text.
+                self.count += 1;
+                heading
+                    .id
+                    .get_or_insert_with(|| self.anchor_style.id(&heading.text, self.count));
+                let html = heading.to_html();
+                self.ids.extend(heading.id.clone());
+                if self.collect {
+                    insert_heading(&mut self.toc, heading);
+                }
+
+                Some(Event::Html(html.into()))
+            }
+            Event::Text(ref s)
+            | Event::Code(ref s)
+            | Event::InlineMath(ref s)
+            | Event::DisplayMath(ref s) => {
+                if let Some(h) = &mut self.current {
+                    h.text.push_str(s);
+                    None
+                } else {
+                    Some(event)
+                }
+            }
+            // Heading text is escaped when it's rendered, so HTML in headings is left out.
+            Event::InlineHtml(_) if self.current.is_some() => None,
+            _ => Some(event),
+        }
+    }
+}
+
+/// Numbers footnote references in the order they're first referenced, and moves the
+/// definitions to a list at the end of the document, each linking back to where it's
+/// first referenced.
+///
+/// Also collects the ids footnotes are rendered with, and the labels of references to
+/// footnotes that aren't defined, which the parser leaves as text.
+#[derive(Default)]
+pub struct Footnotes<'a> {
+    pub ids: BTreeSet<String>,
+    pub undefined: BTreeSet<String>,
+    // The number of each footnote referenced, and how many times it's been referenced.
+    numbers: HashMap<String, (usize, usize)>,
+    // The events of each definition, in the order they're defined.
+    definitions: Vec<(String, Vec<Event<'a>>)>,
+    current: Option<(String, Vec<Event<'a>>)>,
+    // Text is split up around brackets, so it's collected until the next event that
+    // isn't text to look for undefined references in.
+    text: String,
+    in_code_block: bool,
+}
+
+impl<'a> Footnotes<'a> {
+    /// The list of footnote definitions, ordered by their numbers. Definitions that are
+    /// never referenced come last, without a link back.
+    pub fn finish(&mut self) -> String {
+        self.find_undefined();
+        let mut definitions = std::mem::take(&mut self.definitions);
+        if definitions.is_empty() {
+            return String::new();
+        }
+        definitions.sort_by_key(|(label, _)| {
+            self.numbers
+                .get(label)
+                .map_or(usize::MAX, |(number, _)| *number)
+        });
+
+        let mut html = String::from("<ol class=\"footnotes\">\n");
+        for (label, mut events) in definitions {
+            html.push_str("<li id=\"");
+            escape_html(&mut html, &label).ok();
+            html.push_str("\">\n");
+            if self.numbers.contains_key(&label) {
+                let mut backref = String::from(" <a href=\"#fnref-");
+                escape_href(&mut backref, &label).ok();
+                backref.push_str("\" class=\"footnote-backref\">\u{21a9}</a>");
+                // The link goes at the end of the last paragraph, rather than on a line
+                // of its own.
+                let at = if matches!(events.last(), Some(Event::End(TagEnd::Paragraph))) {
+                    events.len() - 1
+                } else {
+                    events.len()
+                };
+                events.insert(at, Event::Html(backref.into()));
+            }
+            pulldown_cmark::html::push_html(&mut html, events.into_iter());
+            html.push_str("</li>\n");
+        }
+        html.push_str("</ol>\n");
+
+        html
+    }
+
+    // Render a reference as its number, linking to its definition.
+    fn reference(&mut self, label: &str) -> Event<'a> {
+        let next = self.numbers.len() + 1;
+        let (number, count) = self.numbers.entry(label.to_owned()).or_insert((next, 0));
+        *count += 1;
+        let id = if *count == 1 {
+            format!("fnref-{label}")
+        } else {
+            format!("fnref-{label}-{count}")
+        };
+
+        let mut html = String::from("<sup class=\"footnote-ref\" id=\"");
+        escape_html(&mut html, &id).ok();
+        html.push_str("\"><a href=\"#");
+        escape_href(&mut html, label).ok();
+        write!(html, "\">{number}</a></sup>").ok();
+        self.ids.insert(id);
+
+        Event::Html(html.into())
+    }
+
+    // Look for references like `[^label]` in the text collected since the last event
+    // that wasn't text.
+    fn find_undefined(&mut self) {
+        let mut rest = self.text.as_str();
+        while let Some(start) = rest.find("[^") {
+            rest = &rest[start + 2..];
+            let Some(end) = rest.find(']') else {
+                break;
+            };
+            let label = &rest[..end];
+            if !label.trim().is_empty() && !label.contains('[') {
+                self.undefined.insert(label.to_owned());
+            }
+        }
+        self.text.clear();
+    }
+}
+
+impl<'a> Pass<'a> for Footnotes<'a> {
+    fn process(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        match &event {
+            Event::Text(text) if !self.in_code_block => {
+                self.text.push_str(text);
+            }
+            _ => self.find_undefined(),
+        }
+
+        let event = match event {
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                self.ids.insert(label.to_string());
+                self.current = Some((label.to_string(), vec![]));
+                return None;
+            }
+            Event::End(TagEnd::FootnoteDefinition) => {
+                self.definitions.extend(self.current.take());
+                return None;
+            }
+            Event::FootnoteReference(label) => self.reference(&label),
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.in_code_block = true;
+                event
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                self.in_code_block = false;
+                event
+            }
+            event => event,
+        };
+
+        match &mut self.current {
+            Some((_, events)) => {
+                events.push(event);
+                None
+            }
+            None => Some(event),
+        }
+    }
+}
+
+/// Collects the destinations of links to other markdown files, like `./part-1.md#intro`.
+#[derive(Default)]
+pub struct Links {
+    pub links: Vec<String>,
+}
+
+impl<'a> Pass<'a> for Links {
+    fn process(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        if let Event::Start(Tag::Link { ref dest_url, .. }) = event
+            && is_markdown_link(dest_url)
+            && !self.links.iter().any(|l| l == dest_url.as_ref())
+        {
+            self.links.push(dest_url.to_string());
+        }
+
+        Some(event)
+    }
+}
+
+// Whether a link points to a markdown file relative to the document, rather than to a url
+// or a path on the site.
+fn is_markdown_link(dest: &str) -> bool {
+    let path = dest.split(['#', '?']).next().unwrap_or_default();
+    !dest.starts_with(['/', '#'])
+        && Url::parse(dest).is_err()
+        && std::path::Path::new(path)
+            .extension()
+            .is_some_and(|e| e == "md")
+}
+
+/// Finds the images documents show, so that they're rendered with their size.
+///
+/// Giving images a `width` and `height` lets browsers make room for them before they've
+/// loaded, so the page doesn't shift.
+pub trait ImageResolver: Send + Sync {
+    /// The width and height of the image at `src`, as written in the document at `page`,
+    /// if it's an image the resolver can find.
+    fn dimensions(&self, src: &str, page: Option<&Path>) -> Option<(u32, u32)>;
+}
+
+/// Renders images as `<img>` tags that are loaded lazily, sized by the resolver if there
+/// is one. The alt text is rendered as plain text, like it is without this pass.
+pub struct Images<'r> {
+    resolver: Option<&'r dyn ImageResolver>,
+    page: Option<&'r Path>,
+    current: Option<Image>,
+}
+
+struct Image {
+    src: String,
+    title: String,
+    alt: String,
+    // How many images in the alt text the image is inside of, whose alt text is part of
+    // its own.
+    depth: usize,
+}
+
+impl<'r> Images<'r> {
+    pub const fn new(resolver: Option<&'r dyn ImageResolver>, page: Option<&'r Path>) -> Self {
+        Self {
+            resolver,
+            page,
+            current: None,
+        }
+    }
+
+    fn render(&self, image: &Image) -> String {
+        let mut html = String::from("<img src=\"");
+        escape_href(&mut html, &image.src).ok();
+        html.push_str("\" alt=\"");
+        escape_html(&mut html, &image.alt).ok();
+        html.push('"');
+        if !image.title.is_empty() {
+            html.push_str(" title=\"");
+            escape_html(&mut html, &image.title).ok();
+            html.push('"');
+        }
+        let dimensions = self
+            .resolver
+            .and_then(|r| r.dimensions(&image.src, self.page));
+        if let Some((width, height)) = dimensions {
+            write!(html, " width=\"{width}\" height=\"{height}\"").ok();
+        }
+        html.push_str(" loading=\"lazy\" decoding=\"async\" />");
+
+        html
+    }
+}
+
+impl<'a> Pass<'a> for Images<'_> {
+    fn process(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        let Some(image) = &mut self.current else {
+            if let Event::Start(Tag::Image {
+                dest_url, title, ..
+            }) = event
+            {
+                self.current = Some(Image {
+                    src: dest_url.into_string(),
+                    title: title.into_string(),
+                    alt: String::new(),
+                    depth: 0,
+                });
+                return None;
+            }
+            return Some(event);
+        };
+
+        match event {
+            Event::Start(Tag::Image { .. }) => image.depth += 1,
+            Event::End(TagEnd::Image) if image.depth > 0 => image.depth -= 1,
+            Event::End(TagEnd::Image) => {
+                let image = self.current.take()?;
+                return Some(Event::InlineHtml(self.render(&image).into()));
+            }
+            Event::Text(text)
+            | Event::Code(text)
+            | Event::InlineMath(text)
+            | Event::DisplayMath(text)
+            | Event::Html(text)
+            | Event::InlineHtml(text) => image.alt.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => image.alt.push(' '),
+            _ => (),
+        }
+
+        None
+    }
+}
+
+/// Counts the words in the document's text.
+#[derive(Default)]
+pub struct WordCount {
+    pub words: usize,
+}
+
+impl<'a> Pass<'a> for WordCount {
+    fn process(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        if let Event::Text(ref t) | Event::Code(ref t) = event {
+            self.words += t.split_whitespace().count();
+        }
+
+        Some(event)
+    }
+}
+
+#[derive(Default)]
+enum Status {
+    #[default]
+    Incomplete,
+    Finalize,
+    Complete,
+}
+
+/// Collects the events at the start of the document into its summary.
+///
+/// Once the summary has enough characters of text, it ends as soon as every element it
+/// opened has been closed, however deeply nested they are. Documents with a marker end it
+/// at the marker instead.
+pub struct Summary<'a> {
+    status: Status,
+    length: usize,
+    character_count: usize,
+    // The ends of the elements that are currently open, innermost last.
+    open: Vec<TagEnd>,
+    events: Vec<Event<'a>>,
+    // The raw HTML, like `<!-- more -->`, that ends the summary.
+    marker: Option<String>,
+}
+
+impl<'a> Summary<'a> {
+    /// Collect a summary of at least `length` characters of text.
+    pub const fn new(length: usize) -> Self {
+        Self {
+            status: Status::Incomplete,
+            length,
+            character_count: 0,
+            open: vec![],
+            events: vec![],
+            marker: None,
+        }
+    }
+
+    /// End the summary at `marker`, which is raw HTML left out of the document, rather
+    /// than once it's long enough.
+    #[must_use]
+    pub fn with_marker(mut self, marker: String) -> Self {
+        self.marker = Some(marker);
+        self
+    }
+
+    /// The events of the summary, closing anything still left open.
+    pub fn finish(mut self) -> Vec<Event<'a>> {
+        // The marker never turned up as HTML, like when it's only in a code block, so the
+        // whole document was collected and the summary ends once it's long enough.
+        if self.marker.is_some() && !matches!(self.status, Status::Complete) {
+            let mut fallback = Self::new(self.length);
+            for event in self.events {
+                fallback.process(event);
+            }
+            return fallback.finish();
+        }
+
+        while let Some(end) = self.open.pop() {
+            self.events.push(Event::End(end));
+        }
+        self.events
+    }
+}
+
+impl<'a> Pass<'a> for Summary<'a> {
+    fn process(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        if let Some(marker) = &self.marker {
+            if let Event::Html(html) | Event::InlineHtml(html) = &event
+                && html.trim() == marker
+            {
+                self.status = Status::Complete;
+                return None;
+            }
+        } else {
+            // If there are currently fewer characters of text than the summary's length, add
+            // the node to the summary. Additionally, make sure that the summary doesn't
+            // include unclosed tags and the like.
+            if self.character_count >= self.length && matches!(self.status, Status::Incomplete) {
+                self.status = Status::Finalize;
+            }
+            // Nothing is left open, so the summary ends here, rather than with whatever
+            // comes next, like a rule.
+            if matches!(self.status, Status::Finalize) && self.open.is_empty() {
+                self.status = Status::Complete;
+            }
+        }
+
+        if let Event::Text(ref t) = event {
+            self.character_count += t.chars().count();
+        }
+
+        match self.status {
+            Status::Incomplete | Status::Finalize => {
+                match event {
+                    Event::Start(ref tag) => self.open.push(tag.to_end()),
+                    Event::End(ref end) => {
+                        // Processors can drop the start of an element, leaving its end
+                        // without anything to close.
+                        if self.open.last() != Some(end) {
+                            return Some(event);
+                        }
+                        self.open.pop();
+                    }
+                    _ => (),
+                }
+                self.events.push(event.clone());
+
+                if matches!(self.status, Status::Finalize) && self.open.is_empty() {
+                    self.status = Status::Complete;
+                }
+            }
+            Status::Complete => (),
+        }
+
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use minijinja::Environment;
+
+    use super::*;
+    use crate::MarkdownRenderer;
+
+    struct Uppercase;
+
+    impl EventProcessor for Uppercase {
+        fn process<'a>(&self, event: Event<'a>) -> Option<Event<'a>> {
+            match event {
+                Event::Text(t) => Some(Event::Text(t.to_uppercase().into())),
+                _ => Some(event),
+            }
+        }
+    }
+
+    #[test]
+    fn test_event_processor() -> Result<()> {
+        let content = r#"
+---
+title = "Test"
+tags = []
+---
+
+## Shouting
+
+Hello *world*
+        "#;
+
+        let mut renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        renderer.add_event_processor(Box::new(Uppercase));
+
+        let document = renderer.parse_from_string(content, &Environment::empty())?;
+        assert!(document.content.contains("<p>HELLO <em>WORLD</em></p>"));
+        assert_eq!(document.toc[0].text, "SHOUTING");
+        assert_eq!(document.summary_text, "HELLO WORLD");
+        assert_eq!(document.word_count, 3);
+
+        Ok(())
+    }
+
+    // Check that every tag in an HTML fragment is closed in the order it was opened.
+    fn assert_balanced(html: &str) {
+        let mut open = vec![];
+        for tag in html.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').unwrap()];
+            if tag.ends_with('/') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name), "unbalanced summary {html}");
+            } else {
+                open.push(tag.split_whitespace().next().unwrap());
+            }
+        }
+        assert!(open.is_empty(), "unclosed {open:?} in summary {html}");
+    }
+
+    fn summary(body: &str) -> Result<String> {
+        let content = format!("---\ntitle = \"Test\"\ntags = []\n---\n{body}");
+        let document = MarkdownRenderer::new::<&str>(None, None)?
+            .parse_from_string(&content, &Environment::empty())?;
+        assert_balanced(&document.summary);
+        Ok(document.summary)
+    }
+
+    #[test]
+    fn test_summary_nested() -> Result<()> {
+        let long = "word ".repeat(40);
+
+        let nested = summary(&format!(
+            "> Quoted\n>\n> - {long}\n>   - {long}\n> - After\n\nLater paragraph.\n"
+        ))?;
+        assert!(nested.starts_with("<blockquote>\n<p>Quoted</p>\n<ul>"));
+        assert!(nested.ends_with("</ul>\n</blockquote>\n"));
+        assert!(!nested.contains("Later paragraph"));
+
+        let table = summary(&format!(
+            "| A | B |\n| - | - |\n| {long} | x |\n| y | z |\n\nLater paragraph.\n"
+        ))?;
+        assert!(table.starts_with("<table>"));
+        assert!(table.ends_with("</table>\n"));
+
+        // A rule straight after the summary isn't part of it.
+        let rule = summary(&format!("{long}\n\n---\n\nLater paragraph.\n"))?;
+        assert!(!rule.contains("<hr"));
+
+        Ok(())
+    }
+
+    struct DropBlockquoteStart;
+
+    impl EventProcessor for DropBlockquoteStart {
+        fn process<'a>(&self, event: Event<'a>) -> Option<Event<'a>> {
+            match event {
+                Event::Start(Tag::BlockQuote(_)) => None,
+                _ => Some(event),
+            }
+        }
+    }
+
+    #[test]
+    fn test_summary_dropped_start() -> Result<()> {
+        let content = "---\ntitle = \"Test\"\ntags = []\n---\n> Quoted\n\nAfter\n";
+        let mut renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        renderer.add_event_processor(Box::new(DropBlockquoteStart));
+
+        let document = renderer.parse_from_string(content, &Environment::empty())?;
+        assert_eq!(document.summary, "<p>Quoted</p>\n<p>After</p>\n");
+
+        Ok(())
+    }
+
+    struct Sizes;
+
+    impl ImageResolver for Sizes {
+        fn dimensions(&self, src: &str, page: Option<&Path>) -> Option<(u32, u32)> {
+            match (src, page?.to_str()?) {
+                ("/images/cat.png", "posts/cats.md") => Some((640, 480)),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_images() -> Result<()> {
+        let content = r#"---
+title = "Cats"
+tags = []
+---
+
+Look: ![A *sleepy* cat](/images/cat.png "Naptime")
+
+[![](/images/cat.png)](https://example.com/cats)
+
+![Remote `cat`](https://example.com/cat.png?size=large&dark=1)
+"#;
+        let renderer =
+            MarkdownRenderer::new::<&str>(None, None)?.with_image_resolver(Arc::new(Sizes));
+        let document = renderer.parse_from_file("posts/cats.md", content, &Environment::empty())?;
+
+        assert_eq!(
+            document.content,
+            concat!(
+                r#"<p>Look: <img src="/images/cat.png" alt="A sleepy cat" title="Naptime" width="640" height="480" loading="lazy" decoding="async" /></p>"#,
+                "\n",
+                r#"<p><a href="https://example.com/cats"><img src="/images/cat.png" alt="" width="640" height="480" loading="lazy" decoding="async" /></a></p>"#,
+                "\n",
+                r#"<p><img src="https://example.com/cat.png?size=large&amp;dark=1" alt="Remote cat" loading="lazy" decoding="async" /></p>"#,
+                "\n",
+            )
+        );
+
+        // Without a resolver, images are only loaded lazily.
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let document = renderer.parse_from_string(content, &Environment::empty())?;
+        assert!(document.content.contains(
+            r#"<img src="/images/cat.png" alt="A sleepy cat" title="Naptime" loading="lazy" decoding="async" />"#
+        ));
+
+        Ok(())
+    }
+}
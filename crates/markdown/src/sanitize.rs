@@ -0,0 +1,249 @@
+use std::{borrow::Cow, collections::HashSet, ops::Range};
+
+use pulldown_cmark::{Event, TagEnd};
+use url::Url;
+
+/// Options for sanitizing raw HTML written in markdown.
+///
+/// The default allowlist permits common formatting tags and strips scripts,
+/// event handler attributes, and iframes.
+#[derive(Debug, Clone, Default)]
+pub struct SanitizeOptions {
+    /// Tags permitted in addition to the default allowlist.
+    pub allowed_tags: Vec<String>,
+    /// Hosts that iframes are allowed to embed. Iframes are stripped entirely if this is empty.
+    pub allowed_iframe_hosts: Vec<String>,
+}
+
+impl SanitizeOptions {
+    /// Create the `ammonia` builder for these options.
+    pub(crate) fn builder(&self) -> ammonia::Builder<'_> {
+        let mut builder = ammonia::Builder::default();
+        builder.add_tags(self.allowed_tags.iter().map(String::as_str));
+
+        if !self.allowed_iframe_hosts.is_empty() {
+            let hosts = self.allowed_iframe_hosts.clone();
+            builder
+                .add_tags(["iframe"])
+                .add_tag_attributes(
+                    "iframe",
+                    [
+                        "src",
+                        "width",
+                        "height",
+                        "title",
+                        "allow",
+                        "allowfullscreen",
+                    ],
+                )
+                .attribute_filter(move |element, attribute, value| {
+                    if element == "iframe" && attribute == "src" {
+                        let host = Url::parse(value).ok()?.host_str()?.to_owned();
+                        return hosts.contains(&host).then_some(Cow::Borrowed(value));
+                    }
+                    Some(Cow::Borrowed(value))
+                });
+        }
+
+        builder
+    }
+}
+
+/// Sanitize the raw HTML events in a stream of events.
+///
+/// Events whose source range falls inside of one of the `trusted` ranges (HTML produced
+/// by shortcodes) are passed through untouched. Consecutive lines of an HTML block are
+/// sanitized together so that tags spanning several lines stay balanced.
+pub fn sanitize_events<'a, 'b, 's>(
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)> + 'b,
+    builder: &'b ammonia::Builder<'s>,
+    trusted: &'b [Range<usize>],
+) -> impl Iterator<Item = (Event<'a>, Range<usize>)> + 'b
+where
+    'a: 'b,
+    's: 'b,
+{
+    let allowed_tags = builder.clone_tags();
+    let mut block: Option<(String, Range<usize>)> = None;
+
+    events.flat_map(move |(event, range)| {
+        let is_trusted = trusted
+            .iter()
+            .any(|t| t.start <= range.start && range.end <= t.end);
+
+        let mut out = vec![];
+        match event {
+            Event::Html(html) if !is_trusted => {
+                let (buf, r) = block.get_or_insert_with(|| (String::new(), range.clone()));
+                buf.push_str(&html);
+                r.end = range.end;
+            }
+            Event::InlineHtml(html) if !is_trusted => {
+                out.push((
+                    Event::InlineHtml(sanitize_tag(&html, builder, &allowed_tags).into()),
+                    range,
+                ));
+            }
+            Event::End(TagEnd::HtmlBlock) | Event::Html(_) => {
+                if let Some((buf, r)) = block.take() {
+                    out.push((Event::Html(builder.clean(&buf).to_string().into()), r));
+                }
+                out.push((event, range));
+            }
+            _ => out.push((event, range)),
+        }
+
+        out
+    })
+}
+
+// Sanitize a lone inline tag, e.g. `<span onclick="...">` or `</span>`.
+//
+// `ammonia` operates on fragments and closes any tag left open, so the automatically
+// inserted closing tag is stripped off again.
+fn sanitize_tag(tag: &str, builder: &ammonia::Builder, allowed_tags: &HashSet<&str>) -> String {
+    let name = tag
+        .trim_start_matches('<')
+        .trim_start_matches('/')
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    if tag.starts_with("</") {
+        return if allowed_tags.contains(name.as_str()) {
+            format!("</{name}>")
+        } else {
+            String::new()
+        };
+    }
+
+    let cleaned = builder.clean(tag).to_string();
+    cleaned
+        .strip_suffix(&format!("</{name}>"))
+        .map_or_else(|| cleaned.clone(), ToOwned::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use minijinja::Environment;
+
+    use super::*;
+    use crate::MarkdownRenderer;
+
+    fn renderer() -> Result<MarkdownRenderer> {
+        Ok(
+            MarkdownRenderer::new::<&str>(None, None)?.with_sanitizer(SanitizeOptions {
+                allowed_tags: vec![],
+                allowed_iframe_hosts: vec!["www.youtube.com".to_string()],
+            }),
+        )
+    }
+
+    #[test]
+    fn test_script_tag_stripped() -> Result<()> {
+        let content = r#"
+---
+title = "Test"
+tags = []
+---
+
+Hello World
+
+<script>alert("hi")</script>
+
+Inline <script>alert("hi")</script> script.
+        "#;
+
+        let document = renderer()?.parse_from_string(content, &Environment::empty())?;
+        assert!(!document.content.contains("<script"));
+        assert!(!document.summary.contains("<script"));
+        assert!(document.content.contains("Hello World"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_handler_stripped() -> Result<()> {
+        let content = r#"
+---
+title = "Test"
+tags = []
+---
+
+<div onclick="steal()">
+<b>bold</b>
+</div>
+
+Some <span onclick="steal()">inline</span> text.
+        "#;
+
+        let document = renderer()?.parse_from_string(content, &Environment::empty())?;
+        assert!(!document.content.contains("onclick"));
+        assert!(document.content.contains("<div>\n<b>bold</b>\n</div>"));
+        assert!(document.content.contains("Some <span>inline</span> text."));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iframe_hosts() -> Result<()> {
+        let content = r#"
+---
+title = "Test"
+tags = []
+---
+
+<iframe src="https://www.youtube.com/embed/abc"></iframe>
+
+<iframe src="https://evil.example.com/"></iframe>
+        "#;
+
+        let document = renderer()?.parse_from_string(content, &Environment::empty())?;
+        assert!(
+            document
+                .content
+                .contains(r#"<iframe src="https://www.youtube.com/embed/abc"></iframe>"#)
+        );
+        assert!(!document.content.contains("evil.example.com"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shortcode_output_trusted() -> Result<()> {
+        let content = r#"
+---
+title = "Test"
+tags = []
+---
+
+{{! widget !}}
+<script>alert("body")</script>
+{{! end !}}
+        "#;
+
+        let mut env = Environment::new();
+        env.add_template("widget.html", "<div onclick=\"go()\">{{ body }}</div>")?;
+
+        let document = renderer()?.parse_from_string(content, &env)?;
+        assert!(document.content.contains(r#"<div onclick="go()">"#));
+        assert!(!document.content.contains("alert"));
+
+        // Including the output of shortcodes nested in another's body.
+        env.add_template("widget.html", "<div onclick=\"go()\">{{ body|safe }}</div>")?;
+        let nested = "{{! widget !}}\n{{! widget !}}\n<b>inner</b>\n{{! end !}}\n{{! end !}}\n";
+        let document = renderer()?.parse_from_string(
+            &format!("---\ntitle = \"Test\"\ntags = []\n---\n{nested}"),
+            &env,
+        )?;
+        assert_eq!(
+            document.content.matches(r#"<div onclick="go()">"#).count(),
+            2
+        );
+        assert!(document.content.contains("<b>inner</b>"));
+
+        Ok(())
+    }
+}
@@ -1,20 +1,24 @@
-use std::collections::HashMap;
+use std::{collections::BTreeMap, ops::Range};
 
 use color_eyre::Result;
-use minijinja::{Environment, context};
+use minijinja::{Environment, ErrorKind, context, value::ValueKind};
 use nom::{
-    IResult, Parser,
+    IResult, Offset, Parser,
     branch::alt,
-    bytes::complete::{tag, take_until},
+    bytes::complete::{escaped_transform, is_not, tag, take_until},
     character::complete::{alpha1, alphanumeric1, digit1, multispace0},
-    combinator::{map, map_res, opt, recognize},
+    combinator::{consumed, map, map_res, opt, recognize},
     error::ParseError,
-    multi::{many0, many0_count, separated_list0},
-    sequence::{delimited, pair, preceded},
+    multi::{many0, many0_count, separated_list0, separated_list1},
+    sequence::{delimited, pair, tuple},
 };
-use serde::Serialize;
+use pulldown_cmark::{Event, Options, Parser as MarkdownParser, Tag};
+use serde::{Deserialize, Serialize};
 
-use crate::MarkdownRenderer;
+use crate::{MarkdownError, MarkdownRenderer, RenderOptions};
+
+/// A shortcode implemented in Rust, taking precedence over a template of the same name.
+pub type ShortcodeFn = Box<dyn Fn(&Shortcode) -> Result<String> + Send + Sync>;
 
 #[derive(Debug, PartialEq, Serialize)]
 pub enum Item {
@@ -22,67 +26,337 @@ pub enum Item {
     Shortcode(Shortcode),
 }
 
-#[derive(Debug, PartialEq, Serialize)]
+/// A shortcode invocation, e.g. `{{! note(title="Hi") !}} body {{! end !}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Shortcode {
     pub name: String,
-    pub arguments: HashMap<String, Value>,
+    pub arguments: BTreeMap<String, Value>,
+    /// The markdown between the opening tag and `{{! end !}}`. Shortcodes nested in it
+    /// are evaluated by the time the shortcode is.
     pub body: String,
 }
 
-#[derive(Debug, PartialEq, Serialize)]
+/// A shortcode argument value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Value {
     Bool(bool),
     Number(i32),
+    Float(f64),
     String(String),
     List(Vec<Self>),
+    /// A dotted path into the page's frontmatter or the template globals, like
+    /// `page.cover` or `site.title`, resolved when the shortcode is evaluated.
+    Reference(Vec<String>),
+}
+
+impl Value {
+    // Resolve any references in this value against the given context, or say why they
+    // can't be.
+    fn resolve(&self, ctx: &minijinja::Value, env: &Environment) -> Result<Self, String> {
+        match self {
+            Self::Reference(path) => {
+                let (root, rest) = path.split_first().ok_or("Empty reference")?;
+                let mut value = ctx.get_attr(root).map_err(|e| e.to_string())?;
+                if value.is_undefined() {
+                    value = env
+                        .globals()
+                        .find(|(name, _)| name == root)
+                        .map_or_else(minijinja::Value::default, |(_, v)| v);
+                }
+                for segment in rest {
+                    value = value.get_attr(segment).map_err(|e| e.to_string())?;
+                }
+                if value.is_undefined() || value.is_none() {
+                    return Err(format!("{} isn't defined", path.join(".")));
+                }
+
+                Self::from_context(&value).ok_or_else(|| {
+                    format!(
+                        "{} can't be passed to a shortcode, since it's a {}",
+                        path.join("."),
+                        value.kind()
+                    )
+                })
+            }
+            Self::List(values) => Ok(Self::List(
+                values
+                    .iter()
+                    .map(|v| v.resolve(ctx, env))
+                    .collect::<Result<Vec<Self>, String>>()?,
+            )),
+            _ => Ok(self.clone()),
+        }
+    }
+
+    // Convert a value from a template context, if it's a kind that shortcodes take.
+    fn from_context(value: &minijinja::Value) -> Option<Self> {
+        match value.kind() {
+            ValueKind::Bool => Some(Self::Bool(value.is_true())),
+            ValueKind::Number => i32::try_from(value.clone())
+                .ok()
+                .map(Self::Number)
+                .or_else(|| f64::try_from(value.clone()).ok().map(Self::Float)),
+            ValueKind::String => value.as_str().map(|s| Self::String(s.to_owned())),
+            ValueKind::Seq => value
+                .try_iter()
+                .ok()?
+                .map(|v| Self::from_context(&v))
+                .collect::<Option<Vec<Self>>>()
+                .map(Self::List),
+            _ => None,
+        }
+    }
+}
+
+/// Where the output of a shortcode is, and where the shortcode was in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub output: Range<usize>,
+    pub source: Range<usize>,
 }
 
 /// Evaluate all the shortcodes in a given string.
+///
+/// References in arguments are resolved against `ctx`, then the template globals. Also
+/// returns where the output of each shortcode is, and where it was in the input. Errors
+/// are reported at the line of the input the shortcode starts on.
+///
+/// Shortcodes can be nested in the body of another, and are evaluated inside out, so the
+/// outer shortcode gets the output of the ones in its body.
 pub fn evaluate_all_shortcodes(
     input: &str,
     env: &Environment,
     markdown_renderer: &MarkdownRenderer,
-) -> Result<String> {
-    let mut ret = Vec::new();
-    let ((), items) = parse(input)?;
-
-    for item in items {
-        let parsed = match item {
-            Item::Shortcode(s) => evaluate_shortcode(&s, env, markdown_renderer)?,
-            Item::Text(s) => s,
-        };
+    ctx: &minijinja::Value,
+) -> Result<(String, Vec<Expansion>), MarkdownError> {
+    let mut ret = String::new();
+    let mut expansions = Vec::new();
+    // Shortcode syntax in code is left as it is, so the parser gets the input with the
+    // tags in code hidden. Its offsets are the same, so what it parses is taken from the
+    // input by them.
+    let masked = mask_code(input, &code_ranges(input, markdown_renderer.options));
+    let ((), items) = parse(&masked).map_err(|e| parse_error(&masked, &e))?;
+
+    for (source, item) in items {
+        match item {
+            // An end tag followed by a later one is parsed as a shortcode named `end`.
+            Item::Shortcode(s) if s.name == "end" => {
+                return Err(MarkdownError::ShortcodeStrayEnd {
+                    line: shortcode_line(input, &source),
+                });
+            }
+            Item::Shortcode(mut s) => {
+                let start = ret.len();
+                let line = shortcode_line(input, &source);
+                // Shortcodes nested in the body are evaluated first, with their errors
+                // reported at their line in this input. The body ends right before the end
+                // tag, which is at the end of the source.
+                let body_end = source.start + input[source.clone()].rfind("{{!").unwrap_or(0);
+                let body_start = body_end - s.body.len();
+                let body_line = line_of(input, body_start);
+                input[body_start..body_end].clone_into(&mut s.body);
+                let (body, nested) = evaluate_all_shortcodes(&s.body, env, markdown_renderer, ctx)
+                    .map_err(|e| e.offset_lines(body_line - 1))?;
+                s.body = body;
+                // The output of nested shortcodes is trusted, like it is at the top level.
+                let trusted = nested.into_iter().map(|e| e.output).collect::<Vec<_>>();
+                let output = evaluate_shortcode(&s, &trusted, env, markdown_renderer, ctx, line)?;
+                ret.push_str(&without_blank_lines(&output));
+                expansions.push(Expansion {
+                    output: start..ret.len(),
+                    source,
+                });
+            }
+            Item::Text(_) => ret.push_str(&input[source]),
+        }
+    }
 
-        ret.push(parsed);
+    Ok((ret, expansions))
+}
+
+// The ranges of the input that are code blocks or inline code.
+fn code_ranges(input: &str, options: Options) -> Vec<Range<usize>> {
+    MarkdownParser::new_ext(input, options)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Code(_) => Some(range),
+            _ => None,
+        })
+        .collect()
+}
+
+// Hide the shortcode tags in the given ranges from the parser, without changing the
+// length of the input.
+fn mask_code(input: &str, code: &[Range<usize>]) -> String {
+    let mut masked = input.to_owned();
+    for range in code {
+        let hidden = input[range.clone()].replace("{{!", "{{\0");
+        masked.replace_range(range.clone(), &hidden);
     }
 
-    Ok(ret.join(""))
+    masked
 }
 
+// Evaluate a shortcode, rendering its body as markdown with the raw HTML in the trusted
+// ranges left unsanitized.
 fn evaluate_shortcode(
     shortcode: &Shortcode,
+    trusted: &[Range<usize>],
     env: &Environment,
     markdown_renderer: &MarkdownRenderer,
-) -> Result<String> {
-    let markdown = markdown_renderer.render_one_off(&shortcode.body);
-    let shortcode_template = env.get_template(format!("{}.html", shortcode.name).as_str())?;
-    let rendered = shortcode_template
-        .render(context! { arguments => &shortcode.arguments, body => markdown })?;
-    Ok(rendered)
+    ctx: &minijinja::Value,
+    line: usize,
+) -> Result<String, MarkdownError> {
+    let arguments = shortcode
+        .arguments
+        .iter()
+        .map(|(argument, value)| {
+            let value =
+                value
+                    .resolve(ctx, env)
+                    .map_err(|reason| MarkdownError::ShortcodeArgument {
+                        name: shortcode.name.clone(),
+                        argument: argument.clone(),
+                        reason,
+                        line,
+                    })?;
+            Ok((argument.clone(), value))
+        })
+        .collect::<Result<BTreeMap<String, Value>, MarkdownError>>()?;
+    let shortcode = &Shortcode {
+        arguments,
+        ..shortcode.clone()
+    };
+    let render_error = |source| MarkdownError::ShortcodeRender {
+        name: shortcode.name.clone(),
+        line,
+        source,
+    };
+
+    if let Some(f) = markdown_renderer.shortcodes.get(&shortcode.name) {
+        return f(shortcode).map_err(|e| render_error(e.into()));
+    }
+
+    // Bodies are left as they're written, without emoji, like the shortcode's output.
+    // Protected pages are encrypted, so the code in them isn't kept in the cache.
+    let protected = ctx
+        .get_attr("page")
+        .and_then(|page| page.get_attr("protected"))
+        .is_ok_and(|p| !p.is_undefined() && !p.is_none());
+    let options = RenderOptions {
+        skip_highlight_cache: protected,
+        ..RenderOptions::default()
+    };
+    let markdown = markdown_renderer
+        .render_fragment(&shortcode.body, trusted, options, false)
+        .map_err(|e| render_error(e.into()))?;
+    let shortcode_template = env
+        .get_template(format!("{}.html", shortcode.name).as_str())
+        .map_err(|e| match e.kind() {
+            ErrorKind::TemplateNotFound => MarkdownError::ShortcodeTemplateMissing {
+                name: shortcode.name.clone(),
+                line,
+            },
+            _ => render_error(e.into()),
+        })?;
+    shortcode_template
+        .render(context! { arguments => &shortcode.arguments, body => markdown })
+        .map_err(|e| render_error(e.into()))
+}
+
+// Fill the blank lines between the lines of a shortcode's output with empty comments.
+//
+// The output is parsed as markdown again, and a blank line would end the HTML block it's
+// in, leaving whatever comes after it, like the rest of a code block, to be parsed as
+// markdown.
+fn without_blank_lines(output: &str) -> String {
+    let lines = output.split('\n').collect::<Vec<&str>>();
+    let first = lines.iter().position(|l| !l.trim().is_empty());
+    let last = lines.iter().rposition(|l| !l.trim().is_empty());
+    let (Some(first), Some(last)) = (first, last) else {
+        return output.to_owned();
+    };
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, l)| {
+            if (first..last).contains(&i) && l.trim().is_empty() {
+                "<!---->"
+            } else {
+                l
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+// Describe where parsing the shortcodes in the input stopped.
+fn parse_error(input: &str, error: &nom::Err<nom::error::Error<String>>) -> MarkdownError {
+    let rest = match error {
+        nom::Err::Error(e) | nom::Err::Failure(e) => e.input.as_str(),
+        nom::Err::Incomplete(_) => "",
+    };
+    let start = input.len() - rest.len();
+    let name = rest
+        .strip_prefix("{{!")
+        .and_then(|r| identifier(r.trim_start()).ok())
+        .map_or("", |(_, name)| name);
+    // The span covers the opening tag, or the rest of the line if it isn't closed.
+    let end = rest
+        .find("!}}")
+        .map(|i| i + "!}}".len())
+        .or_else(|| rest.find('\n'))
+        .map_or(input.len(), |i| start + i);
+
+    let line = line_of(input, start);
+    if name == "end" {
+        return MarkdownError::ShortcodeStrayEnd { line };
+    }
+
+    MarkdownError::ShortcodeParse {
+        name: name.to_owned(),
+        span: start..end,
+        line,
+    }
+}
+
+// The line the given offset is on, counting from 1.
+fn line_of(input: &str, offset: usize) -> usize {
+    input[..offset].matches('\n').count() + 1
+}
+
+// The line a shortcode's opening tag is on, since its span starts with the whitespace
+// before it.
+fn shortcode_line(input: &str, source: &Range<usize>) -> usize {
+    let leading = input[source.clone()].find("{{!").unwrap_or(0);
+    line_of(input, source.start + leading)
 }
 
 // TODO: Rewrite all of this to work with the latest version of nom. For now I've just
 // TODO: copy-pasted the code from my previous SSG.
 
+// An item, along with the range of the input it was parsed from.
+type Spanned = (Range<usize>, Item);
+
 #[allow(clippy::redundant_closure_for_method_calls)]
-fn parse(input: &str) -> IResult<(), Vec<Item>, nom::error::Error<String>> {
-    let (input, mut items) = many0(alt((
-        map(shortcode, Item::Shortcode),
-        map(text, Item::Text),
+fn parse(input: &str) -> IResult<(), Vec<Spanned>, nom::error::Error<String>> {
+    let (rest, items) = many0(alt((
+        map(consumed(shortcode), |(raw, s)| (raw, Item::Shortcode(s))),
+        map(consumed(text), |(raw, t)| (raw, Item::Text(t))),
     )))(input)
     .map_err(|e| e.to_owned())?;
 
-    items.push(Item::Text(input.to_string()));
+    let span = |raw: &str| {
+        let start = input.offset(raw);
+        start..start + raw.len()
+    };
+    let mut items = items
+        .into_iter()
+        .map(|(raw, item)| (span(raw), item))
+        .collect::<Vec<_>>();
+    items.push((span(rest), Item::Text(rest.to_string())));
 
     Ok(((), items))
 }
@@ -93,10 +367,9 @@ fn text(input: &str) -> IResult<&str, String> {
 }
 
 fn shortcode(input: &str) -> IResult<&str, Shortcode> {
-    let (input, (name, arguments)) =
-        ws(delimited(tag("{{!"), ws(shortcode_start), tag("!}}")))(input)?;
-    let (input, body) = take_until("{{!")(input)?;
-    let (input, _) = delimited(tag("{{!"), ws(tag("end")), tag("!}}"))(input)?;
+    let (input, (name, arguments)) = ws(start_tag)(input)?;
+    let (input, body) = shortcode_body(input)?;
+    let (input, _) = end_tag(input)?;
 
     Ok((
         input,
@@ -108,7 +381,36 @@ fn shortcode(input: &str) -> IResult<&str, Shortcode> {
     ))
 }
 
-fn shortcode_start(input: &str) -> IResult<&str, (String, HashMap<String, Value>)> {
+fn start_tag(input: &str) -> IResult<&str, (String, BTreeMap<String, Value>)> {
+    delimited(tag("{{!"), ws(shortcode_start), tag("!}}"))(input)
+}
+
+fn end_tag(input: &str) -> IResult<&str, &str> {
+    delimited(tag("{{!"), ws(tag("end")), tag("!}}"))(input)
+}
+
+// The body of a shortcode, up to the end tag closing it. Shortcodes nested in the body are
+// skipped over along with their own end tags.
+fn shortcode_body(input: &str) -> IResult<&str, &str> {
+    let mut depth = 0usize;
+    let mut rest = input;
+    loop {
+        let (tag_start, _) = take_until("{{!")(rest)?;
+        if let Ok((after, _)) = end_tag(tag_start) {
+            if depth == 0 {
+                return Ok((tag_start, &input[..input.offset(tag_start)]));
+            }
+            depth -= 1;
+            rest = after;
+        } else {
+            let (after, _) = start_tag(tag_start)?;
+            depth += 1;
+            rest = after;
+        }
+    }
+}
+
+fn shortcode_start(input: &str) -> IResult<&str, (String, BTreeMap<String, Value>)> {
     let (input, function_name) = ws(recognize(pair(
         alt((alpha1, tag("_"))),
         many0_count(alt((alphanumeric1, tag("_")))),
@@ -141,32 +443,50 @@ fn argument(input: &str) -> IResult<&str, (String, Value)> {
 }
 
 fn value(input: &str) -> IResult<&str, Value> {
-    let boolean = alt((
-        map(tag("true"), |_| Value::Bool(true)),
-        map(tag("false"), |_| Value::Bool(false)),
-    ));
-    let number = alt((
-        map_res(digit1, |digit_str: &str| {
-            digit_str.parse::<i32>().map(Value::Number)
-        }),
-        map(preceded(tag("-"), digit1), |digit_str: &str| {
-            Value::Number(-digit_str.parse::<i32>().unwrap())
-        }),
-    ));
+    // `true` and `false` are parsed as paths too, so that references starting with them
+    // still work.
+    let path = map(
+        separated_list1(tag("."), identifier),
+        |path: Vec<&str>| match path.as_slice() {
+            ["true"] => Value::Bool(true),
+            ["false"] => Value::Bool(false),
+            _ => Value::Reference(path.into_iter().map(ToOwned::to_owned).collect()),
+        },
+    );
+    // Floats are tried first, since their whole part would parse as an integer.
+    let float = map_res(
+        recognize(tuple((opt(tag("-")), digit1, tag("."), digit1))),
+        |digit_str: &str| digit_str.parse::<f64>().map(Value::Float),
+    );
+    let number = map_res(recognize(pair(opt(tag("-")), digit1)), |digit_str: &str| {
+        digit_str.parse::<i32>().map(Value::Number)
+    });
+    // Quotes and backslashes in strings are escaped with a backslash.
     let string = map(
         delimited(
-            tag::<&str, &str, nom::error::Error<_>>("\""),
-            take_until("\""),
+            tag("\""),
+            opt(escaped_transform(
+                is_not("\\\""),
+                '\\',
+                alt((tag("\\"), tag("\""))),
+            )),
             tag("\""),
         ),
-        |s: &str| Value::String(s.to_string()),
+        |s: Option<String>| Value::String(s.unwrap_or_default()),
     );
     let list = map(
         delimited(tag("["), separated_list0(tag(","), ws(value)), tag("]")),
         Value::List,
     );
 
-    alt((boolean, number, string, list))(input)
+    alt((float, number, string, list, path))(input)
+}
+
+fn identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0_count(alt((alphanumeric1, tag("_")))),
+    ))(input)
 }
 
 fn ws<'a, O, E: ParseError<&'a str>, F>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
@@ -197,11 +517,20 @@ hello world
 more text
         ";
 
-        let items = parse(test_input)?;
+        let ((), items) = parse(test_input)?;
+        let (spans, items): (Vec<_>, Vec<_>) = items.into_iter().unzip();
         insta::with_settings!({sort_maps => true}, {
-            insta::assert_yaml_snapshot!(items.1);
+            insta::assert_yaml_snapshot!(items);
         });
 
+        // The items cover the whole input, with the shortcode from its opening tag to its
+        // end tag.
+        assert_eq!(spans.last().map(|s| s.end), Some(test_input.len()));
+        assert!(spans.windows(2).all(|w| w[0].end == w[1].start));
+        let shortcode = &test_input[spans[1].clone()];
+        assert!(shortcode.trim().starts_with("{{! test"));
+        assert!(shortcode.trim().ends_with("{{! end !}}"));
+
         Ok(())
     }
 
@@ -228,7 +557,8 @@ more text
         let mut env = Environment::new();
         env.add_template("note.html", template_str)?;
 
-        let evaluated = evaluate_all_shortcodes(test_input, &env, &markdown_renderer)?;
+        let (evaluated, _) =
+            evaluate_all_shortcodes(test_input, &env, &markdown_renderer, &context! {})?;
         insta::assert_yaml_snapshot!(evaluated);
 
         Ok(())
@@ -257,9 +587,320 @@ more text
         let mut env = Environment::new();
         env.add_template("note.html", template_str)?;
 
-        let evaluated = evaluate_all_shortcodes(test_input, &env, &markdown_renderer)?;
+        let (evaluated, _) =
+            evaluate_all_shortcodes(test_input, &env, &markdown_renderer, &context! {})?;
         insta::assert_yaml_snapshot!(evaluated);
 
         Ok(())
     }
+
+    #[test]
+    fn test_registered_shortcode_precedence() -> Result<()> {
+        let test_input = r#"{{! note(title="testing", n=2) !}}body{{! end !}}"#;
+
+        let mut markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        markdown_renderer.register_shortcode(
+            "note",
+            Box::new(|s: &Shortcode| {
+                let arguments = s
+                    .arguments
+                    .iter()
+                    .map(|(k, v)| format!("{k}={v:?}"))
+                    .collect::<Vec<String>>();
+                Ok(format!("<{}>{}</{}>", arguments.join(" "), s.body, s.name))
+            }),
+        );
+        let mut env = Environment::new();
+        env.add_template("note.html", "template")?;
+
+        let (evaluated, _) =
+            evaluate_all_shortcodes(test_input, &env, &markdown_renderer, &context! {})?;
+        assert_eq!(
+            evaluated,
+            r#"<n=Number(2) title=String("testing")>body</note>"#
+        );
+
+        Ok(())
+    }
+
+    fn img_env() -> Result<Environment<'static>> {
+        let mut env = Environment::new();
+        env.add_template(
+            "img.html",
+            r#"<img src="{{ arguments.src }}" alt="{{ arguments.alt }}" width="{{ arguments.width }}">"#,
+        )?;
+        env.add_global("site", context! { title => "My Site" });
+        Ok(env)
+    }
+
+    #[test]
+    fn test_parse_references() -> Result<()> {
+        let (_, (_, arguments)) =
+            shortcode_start(r"img(src=page.cover, dark=true, sizes=[1, site.sizes.small])")?;
+        assert_eq!(
+            arguments,
+            BTreeMap::from([
+                (
+                    "src".to_string(),
+                    Value::Reference(vec!["page".to_string(), "cover".to_string()])
+                ),
+                ("dark".to_string(), Value::Bool(true)),
+                (
+                    "sizes".to_string(),
+                    Value::List(vec![
+                        Value::Number(1),
+                        Value::Reference(vec![
+                            "site".to_string(),
+                            "sizes".to_string(),
+                            "small".to_string()
+                        ]),
+                    ])
+                ),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_literals() -> Result<()> {
+        for (input, expected) in [
+            ("3", Value::Number(3)),
+            ("-3", Value::Number(-3)),
+            ("0.5", Value::Float(0.5)),
+            ("-2.25", Value::Float(-2.25)),
+            ("true", Value::Bool(true)),
+            (r#""""#, Value::String(String::new())),
+            (r#""say \"hi\"""#, Value::String(r#"say "hi""#.to_string())),
+            (r#""a \\ b""#, Value::String(r"a \ b".to_string())),
+            (
+                "[-1, 1.5]",
+                Value::List(vec![Value::Number(-1), Value::Float(1.5)]),
+            ),
+        ] {
+            assert_eq!(value(input)?, ("", expected), "{input}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mixed_arguments() -> Result<()> {
+        let test_input =
+            r#"{{! chart(scale=0.5, offset=-3, title="say \"hi\"", show=true) !}}{{! end !}}"#;
+
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let mut env = Environment::new();
+        env.add_template(
+            "chart.html",
+            "{{ arguments.scale * 3 }} {{ arguments.offset + 1 }} {{ arguments.scale is number }} {{ arguments.title }} {{ arguments.show }}",
+        )?;
+
+        // Floats reach templates as numbers, not strings.
+        let (evaluated, _) =
+            evaluate_all_shortcodes(test_input, &env, &markdown_renderer, &context! {})?;
+        assert_eq!(evaluated, "1.5 -2 true say &quot;hi&quot; true");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_references() -> Result<()> {
+        let test_input = r"{{! img(src=page.cover, alt=site.title, width=640) !}}{{! end !}}";
+        let ctx = context! { page => context! { cover => "cover.png" } };
+
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let (evaluated, _) =
+            evaluate_all_shortcodes(test_input, &img_env()?, &markdown_renderer, &ctx)?;
+        assert_eq!(
+            evaluated,
+            r#"<img src="cover.png" alt="My Site" width="640">"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dangling_reference() -> Result<()> {
+        let test_input = r#"{{! img(src=page.cover, alt="Cover") !}}{{! end !}}"#;
+        let ctx = context! { page => context! { title => "Hello" } };
+
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let err =
+            evaluate_all_shortcodes(test_input, &img_env()?, &markdown_renderer, &ctx).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Couldn't resolve the src argument of the img shortcode at line 1: page.cover isn't defined"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_shortcode() -> Result<()> {
+        let test_input = "Some text\n\n{{! img(src=) !}}{{! end !}}\n";
+
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let err =
+            evaluate_all_shortcodes(test_input, &img_env()?, &markdown_renderer, &context! {})
+                .unwrap_err();
+        let MarkdownError::ShortcodeParse { name, span, line } = err else {
+            panic!("Expected a parse error, got {err:?}");
+        };
+        assert_eq!(name, "img");
+        assert_eq!(line, 3);
+        assert_eq!(&test_input[span], "{{! img(src=) !}}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_template() -> Result<()> {
+        let test_input = "{{! note !}}body{{! end !}}";
+
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let err =
+            evaluate_all_shortcodes(test_input, &img_env()?, &markdown_renderer, &context! {})
+                .unwrap_err();
+        assert!(matches!(
+            err,
+            MarkdownError::ShortcodeTemplateMissing { name, line: 1 } if name == "note"
+        ));
+
+        Ok(())
+    }
+
+    fn nested_env() -> Result<Environment<'static>> {
+        let mut env = Environment::new();
+        env.add_template(
+            "fancy.html",
+            r#"<div class="{{ arguments.class }}">{{ body | safe }}</div>"#,
+        )?;
+        env.add_template("note.html", "<aside>{{ body | safe }}</aside>")?;
+        env.add_template("badge.html", "<b>{{ arguments.text }}</b>")?;
+        Ok(env)
+    }
+
+    #[test]
+    fn test_nested_shortcodes() -> Result<()> {
+        let test_input = r#"{{! fancy(class="outer") !}}
+Before
+
+{{! note !}}
+A {{! badge(text="new") !}}{{! end !}} note
+{{! end !}}
+
+After
+{{! end !}}"#;
+
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let (evaluated, expansions) =
+            evaluate_all_shortcodes(test_input, &nested_env()?, &markdown_renderer, &context! {})?;
+        assert_eq!(
+            evaluated,
+            "<div class=\"outer\"><p>Before</p>\n<aside><p>A <b>new</b> note</p>\n</aside>\n<p>After</p>\n</div>"
+        );
+        // Only the outermost shortcode is expanded in the input.
+        assert_eq!(expansions.len(), 1);
+        assert_eq!(expansions[0].source, 0..test_input.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sibling_nested_shortcodes() -> Result<()> {
+        let test_input = r#"Text {{! fancy(class="pair") !}}{{! badge(text="one") !}}{{! end !}} and {{! badge(text="two") !}}{{! end !}}{{! end !}} more"#;
+
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let (evaluated, _) =
+            evaluate_all_shortcodes(test_input, &nested_env()?, &markdown_renderer, &context! {})?;
+        assert_eq!(
+            evaluated,
+            "Text <div class=\"pair\"><p><b>one</b> and <b>two</b></p>\n</div> more"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_shortcode_errors() -> Result<()> {
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let env = nested_env()?;
+
+        // Errors in nested shortcodes are reported at their line in the whole input.
+        let test_input =
+            "{{! fancy !}}\n\n{{! note !}}\n{{! missing !}}{{! end !}}\n{{! end !}}\n{{! end !}}";
+        let err = evaluate_all_shortcodes(test_input, &env, &markdown_renderer, &context! {})
+            .unwrap_err();
+        assert!(
+            matches!(&err, MarkdownError::ShortcodeTemplateMissing { name, line: 4 } if name == "missing"),
+            "{err:?}"
+        );
+
+        // A nested shortcode without an end tag leaves the outer one unclosed.
+        let test_input = "Text\n{{! fancy !}}{{! note !}}body{{! end !}}";
+        let err = evaluate_all_shortcodes(test_input, &env, &markdown_renderer, &context! {})
+            .unwrap_err();
+        assert!(
+            matches!(&err, MarkdownError::ShortcodeParse { name, line: 2, .. } if name == "fancy"),
+            "{err:?}"
+        );
+
+        Ok(())
+    }
+
+    // Render a document with a `note` shortcode around the given body.
+    fn render_note(body: &str) -> Result<String> {
+        let mut env = Environment::new();
+        env.add_template("note.html", "<div class=\"note\">\n{{ body|safe }}\n</div>")?;
+        let content = format!(
+            "---\ntitle = \"Test\"\ntags = []\n---\nBefore\n\n{{{{! note !}}}}\n{body}\n{{{{! end !}}}}\n\nAfter\n"
+        );
+
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        Ok(markdown_renderer.parse_from_string(&content, &env)?.content)
+    }
+
+    #[test]
+    fn test_multi_block_bodies() -> Result<()> {
+        assert_eq!(
+            render_note("One\n\nTwo")?,
+            "<p>Before</p>\n<div class=\"note\">\n<p>One</p>\n<p>Two</p>\n<!---->\n</div>\n<p>After</p>\n"
+        );
+        assert_eq!(
+            render_note("- a\n\n- b")?,
+            "<p>Before</p>\n<div class=\"note\">\n<ul>\n<li>\n<p>a</p>\n</li>\n<li>\n<p>b</p>\n</li>\n</ul>\n<!---->\n</div>\n<p>After</p>\n"
+        );
+
+        // Blank lines in code blocks don't end the HTML the shortcode outputs.
+        let html = render_note("```\nfn a() {}\n\n\n    fn b() {}\n```")?;
+        assert!(html.contains("<code class=\"language-\">fn a() {}\n<!---->\n<!---->\n    fn b() {}\n</code></pre>\n"), "{html}");
+        assert!(
+            html.ends_with("</code></pre>\n<!---->\n</div>\n<p>After</p>\n"),
+            "{html}"
+        );
+        assert_eq!(html.matches("<p>").count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stray_end_tag() -> Result<()> {
+        let markdown_renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        let env = img_env()?;
+
+        for input in [
+            "Some text\n\n{{! end !}}\n",
+            "Some text\n\n{{! end !}}\n\n{{! img(src=\"a.png\") !}}{{! end !}}\n",
+        ] {
+            let err =
+                evaluate_all_shortcodes(input, &env, &markdown_renderer, &context! {}).unwrap_err();
+            assert!(
+                matches!(err, MarkdownError::ShortcodeStrayEnd { line: 3 }),
+                "{input:?} gave {err:?}"
+            );
+        }
+
+        Ok(())
+    }
 }
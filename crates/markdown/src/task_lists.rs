@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+/// Mark the items of task lists, like `- [x] Done`, with `class="task-list-item"`, so
+/// that they can be styled. The checkbox itself is rendered as a disabled input.
+///
+/// Both ends of a task item are replaced with HTML, so that passes balancing tags, like
+/// the summary, see neither of them, while the list around them is left as it is.
+pub fn task_lists<'a>(events: impl Iterator<Item = Event<'a>>) -> impl Iterator<Item = Event<'a>> {
+    TaskLists {
+        events,
+        pending: VecDeque::new(),
+        items: vec![],
+    }
+}
+
+struct TaskLists<'a, I> {
+    events: I,
+    // Events read ahead to find out whether an item is a task.
+    pending: VecDeque<Event<'a>>,
+    // Whether each item that's open is a task, innermost last.
+    items: Vec<bool>,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Iterator for TaskLists<'a, I> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.pop_front() {
+            return Some(event);
+        }
+
+        match self.events.next()? {
+            Event::Start(Tag::Item) => {
+                // The marker comes first in tight lists, and in the item's paragraph in
+                // loose ones.
+                let mut first = self.events.next();
+                if matches!(first, Some(Event::Start(Tag::Paragraph))) {
+                    self.pending.extend(first.take());
+                    first = self.events.next();
+                }
+                let task = matches!(first, Some(Event::TaskListMarker(_)));
+                self.pending.extend(first);
+                self.items.push(task);
+
+                Some(if task {
+                    Event::Html(r#"<li class="task-list-item">"#.into())
+                } else {
+                    Event::Start(Tag::Item)
+                })
+            }
+            Event::End(TagEnd::Item) if self.items.pop() == Some(true) => {
+                Some(Event::Html("</li>\n".into()))
+            }
+            event => Some(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use minijinja::Environment;
+
+    use crate::{Document, MarkdownRenderer};
+
+    fn parse(body: &str) -> Result<Document> {
+        let content = format!("---\ntitle = \"Tasks\"\ntags = []\n---\n\n{body}\n");
+        let renderer = MarkdownRenderer::new::<&str>(None, None)?;
+        Ok(renderer.parse_from_string(&content, &Environment::empty())?)
+    }
+
+    #[test]
+    fn test_task_list() -> Result<()> {
+        insta::assert_snapshot!(
+            parse(
+                "- [x] Done\n- [ ] Not done\n  - [x] Nested\n  - Not a task\n- Plain item\n\n1. [ ] Loose\n\n2. [x] Ordered"
+            )?
+            .content
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_task_list_in_summary() -> Result<()> {
+        let document = parse(
+            "A short introduction to the list of things that are left to do.\n\n- [x] Write the parser for the configuration files\n- [ ] Write the renderer, along with the tests for it, which take a while\n- [ ] Release it\n\nAfter the list.",
+        )?;
+
+        // The summary runs past the cutoff until the list it's in is closed.
+        insta::assert_snapshot!(document.summary);
+        assert_eq!(
+            document.summary.matches("<li").count(),
+            document.summary.matches("</li>").count()
+        );
+
+        Ok(())
+    }
+}
@@ -9,7 +9,7 @@ use color_eyre::{Result, eyre::ContextCompat};
 use serde::Serialize;
 use url::Url;
 
-use crate::utils::{build_permalink, fs::ensure_directory};
+use crate::utils::{build_permalink, fs::ensure_directory, output_components};
 
 /// Represents a resource that is passed through an asset pipeline.
 /// This can include things like images, stylesheets, and javascript.
@@ -30,8 +30,8 @@ impl Asset {
         root: Z,
         url: &Url,
     ) -> Result<Self> {
-        let out_path = out_path(&path, &out_dir, root);
-        let (content, out_path) = process_asset(&path, out_path)?;
+        let out_path = rendered_path(&path, &out_dir, root);
+        let content = process_asset(&path)?;
         let permalink = build_permalink(&out_path, out_dir, url)?;
 
         Ok(Self {
@@ -54,24 +54,38 @@ impl Asset {
     }
 }
 
-fn process_asset<P: AsRef<Path>, T: AsRef<Path>>(path: P, out_dir: T) -> Result<(String, PathBuf)> {
-    let mut op = out_dir.as_ref().to_owned();
-    let options = grass::Options::default().style(grass::OutputStyle::Compressed);
-
-    Ok((
-        match path.as_ref().extension().and_then(OsStr::to_str) {
-            Some("scss") => {
-                op.set_extension("css");
-                grass::from_path(path, &options)?
-            }
-            Some(ext) => {
-                op.set_extension(ext);
-                fs::read_to_string(path)?
-            }
-            None => fs::read_to_string(path)?,
-        },
-        op,
-    ))
+fn process_asset<P: AsRef<Path>>(path: P) -> Result<String> {
+    if compiles_to_css(&path) {
+        let options = grass::Options::default().style(grass::OutputStyle::Compressed);
+        Ok(grass::from_path(path, &options)?)
+    } else {
+        Ok(fs::read_to_string(path)?)
+    }
+}
+
+/// The path the asset at `path` is rendered to.
+///
+/// Only the last extension is replaced, and only for stylesheets compiled to CSS, so
+/// `theme.dark.scss` becomes `theme.dark.css` and `app.min.js` is left alone. This is used
+/// for the asset's output path and permalink, and to find unchanged assets in the output.
+pub fn rendered_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
+    path: P,
+    out_dir: T,
+    root: Z,
+) -> PathBuf {
+    let op = out_path(&path, out_dir, root);
+    match op.file_stem().and_then(OsStr::to_str) {
+        Some(stem) if compiles_to_css(&path) => op.with_file_name(format!("{stem}.css")),
+        _ => op,
+    }
+}
+
+// Whether the asset at `path` is a stylesheet that's compiled to CSS.
+fn compiles_to_css<P: AsRef<Path>>(path: P) -> bool {
+    matches!(
+        path.as_ref().extension().and_then(OsStr::to_str),
+        Some("scss" | "sass")
+    )
 }
 
 fn out_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
@@ -80,14 +94,7 @@ fn out_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
     root: Z,
 ) -> PathBuf {
     let out_dir = out_dir.as_ref();
-    let mut components = path
-        .as_ref()
-        .components()
-        .filter(|c| !c.as_os_str().to_str().is_some_and(|s| s.starts_with('_')));
-
-    if root.as_ref() != Path::new(".") {
-        components.next();
-    }
+    let components = output_components(path.as_ref(), root);
 
     out_dir.components().chain(components).collect::<PathBuf>()
 }
@@ -110,4 +117,40 @@ mod tests {
         let path = out_path("style.scss", "public", ".");
         insta::assert_yaml_snapshot!(path);
     }
+
+    #[test]
+    fn test_rendered_names() -> Result<()> {
+        let dir = tempfile::Builder::new()
+            .prefix("test-asset")
+            .tempdir_in(".")?;
+        let root = PathBuf::from(dir.path().file_name().unwrap());
+        let url = Url::parse("https://example.com/")?;
+        let out_dir = PathBuf::from("public");
+
+        for (name, content, rendered) in [
+            (
+                "theme.dark.scss",
+                "a { b { color: red; } }",
+                "theme.dark.css",
+            ),
+            ("app.min.js", "let a;", "app.min.js"),
+            ("library.pkg.css", "a{}", "library.pkg.css"),
+            ("LICENSE", "MIT", "LICENSE"),
+        ] {
+            let path = root.join("assets").join(name);
+            ensure_directory(path.parent().unwrap())?;
+            fs::write(&path, content)?;
+
+            let asset = Asset::new(&path, blake3::hash(b""), &out_dir, &root, &url)?;
+            let expected = out_dir.join("assets").join(rendered);
+            assert_eq!(asset.out_path, expected);
+            assert_eq!(rendered_path(&path, &out_dir, &root), expected);
+            assert_eq!(
+                asset.permalink.as_str(),
+                format!("https://example.com/assets/{rendered}")
+            );
+        }
+
+        Ok(())
+    }
 }
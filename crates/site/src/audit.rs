@@ -0,0 +1,331 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::{Component, Path, PathBuf},
+};
+
+use color_eyre::Result;
+use ignore::WalkBuilder;
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::config::AuditConfig;
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "avif", "svg", "bmp"];
+
+/// An output file that exceeds one of the size limits.
+///
+/// Paths are relative to the output directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SizeWarning {
+    /// A page's HTML is too large.
+    Html { page: PathBuf, size: u64 },
+    /// An image is too large, along with a page using it if there is one.
+    Image {
+        image: PathBuf,
+        page: Option<PathBuf>,
+        size: u64,
+    },
+    /// A page's HTML and the local resources it loads are too large together.
+    PageWeight { page: PathBuf, weight: u64 },
+}
+
+impl fmt::Display for SizeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Html { page, size } => {
+                write!(f, "{} is {} of HTML", page.display(), format_size(*size))
+            }
+            Self::Image { image, page, size } => {
+                write!(f, "{} is {}", image.display(), format_size(*size))?;
+                if let Some(page) = page {
+                    write!(f, ", used by {}", page.display())?;
+                }
+                Ok(())
+            }
+            Self::PageWeight { page, weight } => write!(
+                f,
+                "{} weighs {} with the resources it loads",
+                page.display(),
+                format_size(*weight)
+            ),
+        }
+    }
+}
+
+/// Check the files in the output directory against the size limits.
+///
+/// A page's weight is the size of its HTML plus the local files it references with
+/// `src` attributes or `<link href>`.
+pub fn audit_sizes<P: AsRef<Path>>(
+    output_dir: P,
+    url: &Url,
+    limits: &AuditConfig,
+) -> Result<Vec<SizeWarning>> {
+    let output_dir = output_dir.as_ref();
+
+    let mut sizes = HashMap::new();
+    for entry in WalkBuilder::new(output_dir).standard_filters(false).build() {
+        let entry = entry?;
+        if entry.file_type().is_some_and(|t| t.is_file()) {
+            let path = entry.path().strip_prefix(output_dir)?.to_owned();
+            sizes.insert(path, entry.metadata()?.len());
+        }
+    }
+
+    let mut pages = sizes
+        .keys()
+        .filter(|p| p.extension().is_some_and(|e| e == "html"))
+        .cloned()
+        .collect::<Vec<PathBuf>>();
+    pages.sort();
+
+    let mut warnings = vec![];
+    let mut used_images = HashSet::new();
+
+    for page in pages {
+        let size = sizes[&page];
+        if size > limits.max_html_size {
+            warnings.push(SizeWarning::Html {
+                page: page.clone(),
+                size,
+            });
+        }
+
+        let html = fs::read_to_string(output_dir.join(&page))?;
+        let resources = local_resources(&html, &page, url)
+            .into_iter()
+            .filter(|r| sizes.contains_key(r))
+            .collect::<HashSet<PathBuf>>();
+
+        let mut resources = resources.into_iter().collect::<Vec<PathBuf>>();
+        resources.sort();
+
+        for resource in &resources {
+            let size = sizes[resource];
+            if is_image(resource) && size > limits.max_image_size {
+                used_images.insert(resource.clone());
+                warnings.push(SizeWarning::Image {
+                    image: resource.clone(),
+                    page: Some(page.clone()),
+                    size,
+                });
+            }
+        }
+
+        let weight = size + resources.iter().map(|r| sizes[r]).sum::<u64>();
+        if weight > limits.max_page_weight {
+            warnings.push(SizeWarning::PageWeight { page, weight });
+        }
+    }
+
+    let mut unused = sizes
+        .iter()
+        .filter(|(p, size)| {
+            is_image(p) && **size > limits.max_image_size && !used_images.contains(*p)
+        })
+        .collect::<Vec<_>>();
+    unused.sort();
+    warnings.extend(unused.into_iter().map(|(p, size)| SizeWarning::Image {
+        image: p.clone(),
+        page: None,
+        size: *size,
+    }));
+
+    Ok(warnings)
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+// Find the local files a page loads, as paths relative to the output directory.
+//
+// This is a rough scan over the tags in the page rather than a full HTML parser, which
+// is enough for the markup yar produces.
+fn local_resources(html: &str, page: &Path, url: &Url) -> Vec<PathBuf> {
+    let mut resources = vec![];
+
+    for tag in html.split('<').skip(1) {
+        let tag = tag.split('>').next().unwrap_or_default();
+        let name = tag
+            .split(|c: char| c.is_ascii_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        for (attribute, value) in attributes(tag) {
+            let is_resource = attribute == "src" || (attribute == "href" && name == "link");
+            if is_resource && let Some(path) = resolve(value, page, url) {
+                resources.push(path);
+            }
+        }
+    }
+
+    resources
+}
+
+// Parse the attributes out of the inside of a tag, handling quoted and unquoted values.
+fn attributes(tag: &str) -> Vec<(String, &str)> {
+    let mut attributes = vec![];
+    let mut rest = tag
+        .trim_start_matches(|c: char| !c.is_ascii_whitespace())
+        .trim_start();
+
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|c: char| c == '=' || c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let Some(after_eq) = rest.strip_prefix('=') else {
+            continue;
+        };
+        let after_eq = after_eq.trim_start();
+
+        let (value, remaining) = if let Some(quote @ ('"' | '\'')) = after_eq.chars().next() {
+            let inner = &after_eq[1..];
+            let end = inner.find(quote).unwrap_or(inner.len());
+            (&inner[..end], inner.get(end + 1..).unwrap_or_default())
+        } else {
+            let end = after_eq
+                .find(|c: char| c.is_ascii_whitespace())
+                .unwrap_or(after_eq.len());
+            (&after_eq[..end], &after_eq[end..])
+        };
+
+        attributes.push((name, value));
+        rest = remaining.trim_start();
+    }
+
+    attributes
+}
+
+// Resolve a reference in a page to a path relative to the output directory, if it's local.
+fn resolve(reference: &str, page: &Path, url: &Url) -> Option<PathBuf> {
+    let reference = reference.split(['?', '#']).next()?;
+    if reference.is_empty() {
+        return None;
+    }
+
+    let path = if let Some(rest) = reference.strip_prefix(url.as_str()) {
+        PathBuf::from(rest)
+    } else if reference.starts_with("//") || reference.contains(':') {
+        return None;
+    } else if let Some(rest) = reference.strip_prefix('/') {
+        // Root relative references are relative to the path the site is hosted under.
+        let base = url.path().trim_start_matches('/');
+        PathBuf::from(rest.strip_prefix(base).unwrap_or(rest))
+    } else {
+        page.parent()?.join(reference)
+    };
+
+    let decoded = percent_decode_str(path.to_str()?).decode_utf8().ok()?;
+
+    let mut resolved = PathBuf::new();
+    for component in Path::new(decoded.as_ref()).components() {
+        match component {
+            Component::Normal(c) => resolved.push(c),
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            _ => (),
+        }
+    }
+
+    Some(resolved)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1_000_000 {
+        format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1_000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_sizes() -> Result<()> {
+        let out = tempfile::tempdir()?;
+        fs::create_dir_all(out.path().join("blog/post"))?;
+        fs::create_dir_all(out.path().join("images"))?;
+        fs::create_dir_all(out.path().join("styles"))?;
+
+        fs::write(
+            out.path().join("blog/post/index.html"),
+            "<link href=/styles/main.css rel=stylesheet><img src=\"../../images/huge.png\">\
+             <a href=\"/images/unused.png\">link</a><img src=https://example.org/remote.png>",
+        )?;
+        fs::write(out.path().join("index.html"), "<p>small</p>")?;
+        fs::write(out.path().join("styles/main.css"), vec![b'a'; 300])?;
+        fs::write(out.path().join("images/huge.png"), vec![0; 2_000])?;
+        fs::write(out.path().join("images/unused.png"), vec![0; 1_500])?;
+        fs::write(out.path().join("images/small.png"), vec![0; 10])?;
+
+        let limits = AuditConfig {
+            max_html_size: 100,
+            max_image_size: 1_000,
+            max_page_weight: 2_000,
+        };
+        let warnings = audit_sizes(out.path(), &Url::parse("https://example.com/")?, &limits)?;
+
+        let page = PathBuf::from("blog/post/index.html");
+        let html_size = fs::metadata(out.path().join(&page))?.len();
+        assert_eq!(
+            warnings,
+            vec![
+                SizeWarning::Html {
+                    page: page.clone(),
+                    size: html_size,
+                },
+                SizeWarning::Image {
+                    image: PathBuf::from("images/huge.png"),
+                    page: Some(page.clone()),
+                    size: 2_000,
+                },
+                SizeWarning::PageWeight {
+                    page,
+                    weight: html_size + 2_300,
+                },
+                SizeWarning::Image {
+                    image: PathBuf::from("images/unused.png"),
+                    page: None,
+                    size: 1_500,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let url = Url::parse("https://example.com/blog/")?;
+        let page = Path::new("posts/hello/index.html");
+
+        assert_eq!(
+            resolve("https://example.com/blog/a%20b.png?v=1", page, &url),
+            Some(PathBuf::from("a b.png"))
+        );
+        assert_eq!(
+            resolve("/blog/styles/main.css", page, &url),
+            Some(PathBuf::from("styles/main.css"))
+        );
+        assert_eq!(
+            resolve("cover.png#top", page, &url),
+            Some(PathBuf::from("posts/hello/cover.png"))
+        );
+        assert_eq!(resolve("https://example.org/x.png", page, &url), None);
+        assert_eq!(resolve("data:image/png;base64,abc", page, &url), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::Result;
+use minijinja::{Environment, context};
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+    config::AuthorMeta,
+    generated::{GeneratedKind, GeneratedPage},
+    page::Page,
+    utils::{fs::ensure_directory, minify_html},
+};
+
+/// An author of a page, resolved from the author metadata in the config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Author {
+    /// The key the author is referred to by in frontmatter.
+    pub key: String,
+    pub name: String,
+    pub bio: Option<String>,
+    pub url: Option<String>,
+    /// The link to the author's page, for authors defined in the config.
+    pub permalink: Option<Url>,
+}
+
+impl Author {
+    /// Resolve an author key, falling back to using the key as the name if the author
+    /// isn't defined.
+    pub fn resolve(key: &str, meta: &BTreeMap<String, AuthorMeta>, site_url: &Url) -> Self {
+        meta.get(key).map_or_else(
+            || Self {
+                key: key.to_owned(),
+                name: key.to_owned(),
+                bio: None,
+                url: None,
+                permalink: None,
+            },
+            |m| Self {
+                key: key.to_owned(),
+                name: m.name.clone(),
+                bio: m.bio.clone(),
+                url: m.url.clone(),
+                permalink: site_url.join(&format!("authors/{}/", slug(key))).ok(),
+            },
+        )
+    }
+}
+
+/// All the authors defined in the config, ordered by key.
+pub fn all_authors(meta: &BTreeMap<String, AuthorMeta>, site_url: &Url) -> Vec<Author> {
+    meta.keys()
+        .map(|key| Author::resolve(key, meta, site_url))
+        .collect()
+}
+
+/// Find author keys referenced by pages that aren't defined in the metadata, as warnings.
+///
+/// Sites that don't define any authors use bare names, so nothing is reported for them.
+pub fn unknown_authors(pages: &[Page], meta: &BTreeMap<String, AuthorMeta>) -> Vec<String> {
+    if meta.is_empty() {
+        return vec![];
+    }
+
+    let defined = meta.keys().cloned().collect::<Vec<String>>().join(", ");
+    pages
+        .iter()
+        .flat_map(|page| {
+            page.document
+                .frontmatter
+                .authors
+                .iter()
+                .filter(|key| !meta.contains_key(*key))
+                .map(|key| {
+                    format!(
+                        "{} references unknown author {key} (defined authors: {defined})",
+                        page.path.display()
+                    )
+                })
+                .collect::<Vec<String>>()
+        })
+        .collect()
+}
+
+/// The pages `render_author_pages` writes, one for each defined author.
+pub fn author_pages<P: AsRef<Path>>(
+    pages: &[&Page],
+    meta: &BTreeMap<String, AuthorMeta>,
+    site_url: &Url,
+    output_path: P,
+) -> Vec<GeneratedPage> {
+    all_authors(meta, site_url)
+        .into_iter()
+        .filter_map(|author| {
+            let lastmod = pages
+                .iter()
+                .filter(|p| p.document.frontmatter.authors.contains(&author.key))
+                .map(|p| p.document.updated)
+                .max();
+            Some(GeneratedPage {
+                out_path: out_path(output_path.as_ref(), &author.key),
+                permalink: author.permalink?,
+                kind: GeneratedKind::Author,
+                lastmod,
+            })
+        })
+        .collect()
+}
+
+/// Render a page listing each defined author's posts at `authors/<key>/`, using the
+/// `author.html` template.
+pub fn render_author_pages<P: AsRef<Path>>(
+    pages: &[&Page],
+    meta: &BTreeMap<String, AuthorMeta>,
+    site_url: &Url,
+    output_path: P,
+    env: &Environment,
+) -> Result<()> {
+    let template = env.get_template("author.html")?;
+
+    for author in all_authors(meta, site_url) {
+        let authored = pages
+            .iter()
+            .filter(|p| p.document.frontmatter.authors.contains(&author.key))
+            .collect::<Vec<_>>();
+
+        let out = out_path(output_path.as_ref(), &author.key);
+        ensure_directory(out.parent().expect("Path should have a parent"))?;
+
+        let rendered = template.render(context! {
+            author => author,
+            pages => authored,
+            permalink => author.permalink,
+        })?;
+        fs::write(out, minify_html(&rendered))?;
+    }
+
+    Ok(())
+}
+
+fn out_path(output_path: &Path, key: &str) -> PathBuf {
+    output_path
+        .join("authors")
+        .join(slug(key))
+        .join("index.html")
+}
+
+fn slug(key: &str) -> String {
+    key.trim().to_lowercase().replace(' ', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Site,
+        database::{DatabaseSource, setup_database},
+        test_utils::make_site,
+    };
+
+    fn meta() -> BTreeMap<String, AuthorMeta> {
+        BTreeMap::from([(
+            "ada".to_string(),
+            AuthorMeta {
+                name: "Ada Lovelace".to_string(),
+                bio: Some("Wrote the first program.".to_string()),
+                url: Some("https://example.com/ada".to_string()),
+            },
+        )])
+    }
+
+    #[test]
+    fn test_resolve_author() -> Result<()> {
+        let url = Url::parse("https://example.com/")?;
+
+        let ada = Author::resolve("ada", &meta(), &url);
+        assert_eq!(ada.name, "Ada Lovelace");
+        assert_eq!(
+            ada.permalink.map(String::from),
+            Some("https://example.com/authors/ada/".to_string())
+        );
+
+        let unknown = Author::resolve("Grace Hopper", &meta(), &url);
+        assert_eq!(unknown.name, "Grace Hopper");
+        assert_eq!(unknown.permalink, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_author_pages() -> Result<()> {
+        let page = |title: &str, authors: &str| {
+            format!("---\ntitle = \"{title}\"\ntags = []\nauthors = [{authors}]\n---\n\nHello\n")
+        };
+
+        let (_root, out, mut site) = make_site(
+            &[
+                (
+                    "templates/post.html",
+                    "{% for author in authors %}<a href=\"{{ author.permalink or author.url }}\">{{ author.name }}</a>{% endfor %}",
+                ),
+                (
+                    "templates/author.html",
+                    "<h1>{{ author.name }}</h1><p>{{ author.bio }}</p>{% for page in pages %}<li>{{ page.document.frontmatter.title }}</li>{% endfor %}",
+                ),
+                ("_content/blog/first.md", &page("First", r#""ada""#)),
+                (
+                    "_content/blog/second.md",
+                    &page("Second", r#""ada", "grace""#),
+                ),
+                ("_content/blog/third.md", &page("Third", r#""linus""#)),
+            ],
+            |c| {
+                for (key, name) in [("ada", "Ada Lovelace"), ("grace", "Grace Hopper")] {
+                    c.site.authors_meta.insert(
+                        key.to_string(),
+                        AuthorMeta {
+                            name: name.to_string(),
+                            bio: Some(format!("About {name}")),
+                            url: None,
+                        },
+                    );
+                }
+            },
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let warnings = unknown_authors(&site.library.pages, &site.config.site.authors_meta);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("unknown author linus (defined authors: ada, grace)"));
+
+        let public = out.path().join("public");
+        let ada = fs::read_to_string(public.join("authors/ada/index.html"))?;
+        assert!(ada.contains("<h1>Ada Lovelace</h1>"));
+        assert!(ada.contains("<li>First") && ada.contains("<li>Second"));
+        assert!(!ada.contains("Third"));
+
+        let grace = fs::read_to_string(public.join("authors/grace/index.html"))?;
+        assert!(grace.contains("<li>Second") && !grace.contains("First"));
+        assert!(!public.join("authors/linus").exists());
+
+        let second = fs::read_to_string(public.join("blog/Second/index.html"))?;
+        assert!(second.contains("Ada Lovelace"));
+        assert!(second.contains("http://0.0.0.0:8000/authors/grace/"));
+        let third = fs::read_to_string(public.join("blog/Third/index.html"))?;
+        assert!(third.contains(">linus</a>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_author_pages_follow_authors_meta() -> Result<()> {
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("templates/author.html", "<h1>{{ author.name }}</h1>"),
+                (
+                    "_content/blog/first.md",
+                    "---\ntitle = \"First\"\ntags = []\nauthors = [\"ada\"]\n---\n\nHello\n",
+                ),
+            ],
+            |c| {
+                c.site.authors_meta = meta();
+            },
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let mut config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        // Only the config changed, but the author page is written again with it.
+        config.site.authors_meta.get_mut("ada").unwrap().name = "Ada King".to_string();
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        let ada = fs::read_to_string(out.path().join("public/authors/ada/index.html"))?;
+        assert_eq!(ada, "<h1>Ada King</h1>");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use color_eyre::Result;
+use yar_markdown::ShortcodeFn;
+
+use crate::{
+    Site,
+    config::Config,
+    database::{DatabaseSource, setup_database},
+};
+
+/// Sets up a [`Site`], for embedding `yar` or writing tests against a site's content.
+///
+/// ```no_run
+/// # use yar_site::{SiteBuilder, config::Config};
+/// let site = SiteBuilder::from_config(Config::default())
+///     .with_memory_db()
+///     .load()?;
+/// let html = site.render_page_to_string("_content/hello-world.md", false)?;
+/// # Ok::<(), color_eyre::Report>(())
+/// ```
+pub struct SiteBuilder {
+    config: Config,
+    memory_db: bool,
+    shortcodes: Vec<(String, ShortcodeFn)>,
+}
+
+impl SiteBuilder {
+    /// Start building a site from the given config.
+    #[must_use]
+    pub const fn from_config(config: Config) -> Self {
+        Self {
+            config,
+            memory_db: false,
+            shortcodes: vec![],
+        }
+    }
+
+    /// Keep the cache in memory rather than in the configured database file, so that
+    /// nothing is read from or written to previous builds.
+    #[must_use]
+    pub const fn with_memory_db(mut self) -> Self {
+        self.memory_db = true;
+        self
+    }
+
+    /// Register a shortcode implemented in Rust.
+    #[must_use]
+    pub fn with_shortcode<S: Into<String>>(mut self, name: S, f: ShortcodeFn) -> Self {
+        self.shortcodes.push((name.into(), f));
+        self
+    }
+
+    /// Create the site without loading anything.
+    pub fn build(self) -> Result<Site> {
+        let db = if self.memory_db {
+            setup_database(DatabaseSource::Memory)?
+        } else {
+            setup_database(DatabaseSource::File(&self.config.site.db_file))?
+        };
+
+        Site::with_shortcodes(db, self.config, self.shortcodes)
+    }
+
+    /// Create the site and load all of its entries, ready to be rendered.
+    pub fn load(self) -> Result<Site> {
+        let mut site = self.build()?;
+        site.load()?;
+        Ok(site)
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use blake3::Hash;
+use color_eyre::{Result, eyre::ContextCompat};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use ignore::WalkBuilder;
+use redb::Database;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{BuildFilter, Config},
+    database::{TableDump, dump_tables, get_setting, invalidate_all, restore_tables},
+};
+
+/// The version of what's stored in the database and in exported caches.
+///
+/// It has to change whenever the database's tables or what's stored in them does, such
+/// as the fields of a page. Databases written with another version are emptied when
+/// they're opened.
+pub const CACHE_SCHEMA_VERSION: u32 = 5;
+
+/// The build cache, exported to a file so that builds starting from a clean checkout can
+/// still be incremental.
+///
+/// It only holds the database's tables and the hashes of the output files, never any
+/// file contents, so it stays small.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheExport {
+    config_hash: String,
+    /// The hash of every output file when the cache was exported, relative to the output
+    /// directory. Unchanged files aren't written again, so they have to still be there.
+    outputs: BTreeMap<String, Hash>,
+    tables: TableDump,
+}
+
+/// What happened when importing a cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheImport {
+    /// The cache was imported, and was exported with this many output files.
+    Imported { outputs: usize },
+    /// The cache couldn't be used for the given reason, so everything is built again.
+    Rejected(String),
+}
+
+/// A hash of the parts of the config that change what's built, which a cache has to have
+/// been built with to be used.
+///
+/// Where the site is built to and how many threads it's built with don't change the
+/// output, so they're left out.
+#[must_use]
+pub fn config_hash(config: &Config) -> String {
+    let mut config = config.clone();
+    config.site.output_path = "".into();
+    config.site.db_file = "".into();
+    config.site.jobs = None;
+    config.site.filter = BuildFilter::default();
+
+    let serialized = serde_json::to_vec(&config).expect("Config should serialize to JSON");
+    blake3::hash(&serialized).to_hex().to_string()
+}
+
+/// Export the cache in the database, along with the hashes of the files in the output
+/// directory, to a compressed file. Returns the number of output files.
+pub fn export_cache<P: AsRef<Path>, T: AsRef<Path>>(
+    db: &Database,
+    output_dir: P,
+    file: T,
+) -> Result<usize> {
+    let config_hash = get_setting(db, "config")?
+        .context("The cache is empty, so the site needs to be built before it's exported")?;
+    let outputs = hash_outputs(output_dir.as_ref())?;
+    let export = CacheExport {
+        config_hash,
+        outputs,
+        tables: dump_tables(db)?,
+    };
+
+    let mut encoder = GzEncoder::new(File::create(file)?, Compression::default());
+    encoder.write_all(&postcard::to_stdvec(&CACHE_SCHEMA_VERSION)?)?;
+    encoder.write_all(&postcard::to_stdvec(&export)?)?;
+    encoder.finish()?;
+
+    Ok(export.outputs.len())
+}
+
+/// Import a cache exported with [`export_cache`] into the database, if it was built with
+/// the same config and the output directory still holds what it did when it was exported.
+///
+/// Otherwise, the database is cleared so that everything is built again, and the reason
+/// is returned.
+pub fn import_cache<P: AsRef<Path>, T: AsRef<Path>>(
+    db: &Database,
+    config: &Config,
+    output_dir: P,
+    file: T,
+) -> Result<CacheImport> {
+    match check_import(config, output_dir.as_ref(), file.as_ref())? {
+        Ok(export) => {
+            restore_tables(db, &export.tables)?;
+            Ok(CacheImport::Imported {
+                outputs: export.outputs.len(),
+            })
+        }
+        Err(reason) => {
+            invalidate_all(db)?;
+            Ok(CacheImport::Rejected(reason))
+        }
+    }
+}
+
+// Read an exported cache, or the reason it can't be used.
+fn check_import(
+    config: &Config,
+    output_dir: &Path,
+    file: &Path,
+) -> Result<Result<CacheExport, String>> {
+    let not_an_export = || format!("{} isn't a cache exported by yar", file.display());
+    let f = match File::open(file) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Err(format!("{} doesn't exist", file.display())));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut bytes = vec![];
+    if GzDecoder::new(f).read_to_end(&mut bytes).is_err() {
+        return Ok(Err(not_an_export()));
+    }
+
+    let Ok((version, rest)) = postcard::take_from_bytes::<u32>(&bytes) else {
+        return Ok(Err(not_an_export()));
+    };
+    if version != CACHE_SCHEMA_VERSION {
+        return Ok(Err(format!(
+            "The cache was exported with schema version {version}, but this version of yar uses {CACHE_SCHEMA_VERSION}"
+        )));
+    }
+    let Ok(export) = postcard::from_bytes::<CacheExport>(rest) else {
+        return Ok(Err(not_an_export()));
+    };
+
+    if export.config_hash != config_hash(config) {
+        return Ok(Err(
+            "The config changed since the cache was exported".to_owned()
+        ));
+    }
+
+    let mut missing = vec![];
+    for (path, hash) in &export.outputs {
+        match fs::read(output_dir.join(path)) {
+            Ok(content) if blake3::hash(&content) == *hash => (),
+            _ => missing.push(path.as_str()),
+        }
+    }
+    if let Some(first) = missing.first() {
+        return Ok(Err(format!(
+            "{} of the {} output files the cache was exported with are missing or changed in {}, such as {first}",
+            missing.len(),
+            export.outputs.len(),
+            output_dir.display()
+        )));
+    }
+
+    Ok(Ok(export))
+}
+
+// Hash every file in the output directory, keyed by its path relative to the directory.
+fn hash_outputs(output_dir: &Path) -> Result<BTreeMap<String, Hash>> {
+    let mut outputs = BTreeMap::new();
+    if !output_dir.exists() {
+        return Ok(outputs);
+    }
+
+    for entry in WalkBuilder::new(output_dir).standard_filters(false).build() {
+        let entry = entry?;
+        if entry.file_type().is_some_and(|t| t.is_file()) {
+            let path = entry.path().strip_prefix(output_dir)?;
+            let key = path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            outputs.insert(key, blake3::hash(&fs::read(entry.path())?));
+        }
+    }
+
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::database::{
+        DatabaseSource, get_hashes, insert_hash, insert_setting, setup_database,
+    };
+
+    fn built_database(config: &Config) -> Result<Database> {
+        let db = setup_database(DatabaseSource::Memory)?;
+        let txn = db.begin_write()?;
+        insert_hash(
+            &txn,
+            "site/_content/hello.md",
+            blake3::hash(b"hello").as_bytes(),
+        )?;
+        insert_setting(&txn, "config", &config_hash(config))?;
+        txn.commit()?;
+        Ok(db)
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output_dir = dir.path().join("public");
+        fs::create_dir_all(output_dir.join("hello"))?;
+        fs::write(output_dir.join("hello/index.html"), "hello")?;
+        let file = dir.path().join("cache.gz");
+
+        let config = Config::default();
+        assert_eq!(
+            export_cache(&built_database(&config)?, &output_dir, &file)?,
+            1
+        );
+
+        let db = setup_database(DatabaseSource::Memory)?;
+        assert_eq!(
+            import_cache(&db, &config, &output_dir, &file)?,
+            CacheImport::Imported { outputs: 1 }
+        );
+        assert!(get_hashes(&db)?.contains_key(&PathBuf::from("site/_content/hello.md")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output_dir = dir.path().join("public");
+        fs::create_dir_all(&output_dir)?;
+        fs::write(output_dir.join("index.html"), "hello")?;
+        let file = dir.path().join("cache.gz");
+
+        let config = Config::default();
+        export_cache(&built_database(&config)?, &output_dir, &file)?;
+        let reason = |config: &Config, file: &Path| -> Result<String> {
+            let db = built_database(config)?;
+            let CacheImport::Rejected(reason) = import_cache(&db, config, &output_dir, file)?
+            else {
+                panic!("The cache shouldn't have been imported");
+            };
+            // Nothing is left cached, so everything is built again.
+            assert!(get_hashes(&db)?.is_empty());
+            Ok(reason)
+        };
+
+        // Building on more threads or to somewhere else doesn't matter.
+        let mut moved = config.clone();
+        moved.site.output_path = "elsewhere".into();
+        moved.site.jobs = std::num::NonZeroUsize::new(2);
+        let db = setup_database(DatabaseSource::Memory)?;
+        assert!(matches!(
+            import_cache(&db, &moved, &output_dir, &file)?,
+            CacheImport::Imported { .. }
+        ));
+
+        let mut changed = config.clone();
+        changed.site.two_pass = true;
+        assert_eq!(
+            reason(&changed, &file)?,
+            "The config changed since the cache was exported"
+        );
+
+        let missing = dir.path().join("missing.gz");
+        assert_eq!(
+            reason(&config, &missing)?,
+            format!("{} doesn't exist", missing.display())
+        );
+
+        let garbage = dir.path().join("garbage.gz");
+        fs::write(&garbage, "not a cache")?;
+        assert_eq!(
+            reason(&config, &garbage)?,
+            format!("{} isn't a cache exported by yar", garbage.display())
+        );
+
+        let mut encoder = GzEncoder::new(File::create(&garbage)?, Compression::default());
+        encoder.write_all(&postcard::to_stdvec(&(CACHE_SCHEMA_VERSION + 1))?)?;
+        encoder.finish()?;
+        assert_eq!(
+            reason(&config, &garbage)?,
+            format!(
+                "The cache was exported with schema version {}, but this version of yar uses {CACHE_SCHEMA_VERSION}",
+                CACHE_SCHEMA_VERSION + 1
+            )
+        );
+
+        fs::write(output_dir.join("index.html"), "changed")?;
+        assert_eq!(
+            reason(&config, &file)?,
+            format!(
+                "1 of the 1 output files the cache was exported with are missing or changed in {}, such as index.html",
+                output_dir.display()
+            )
+        );
+
+        Ok(())
+    }
+}
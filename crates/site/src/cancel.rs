@@ -0,0 +1,77 @@
+use std::fmt;
+
+use color_eyre::Result;
+pub use tokio_util::sync::CancellationToken;
+
+/// The error builds stop with once their [`CancellationToken`] is cancelled, so that they
+/// can be told apart from builds that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The build was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Stop with [`Cancelled`] if the token has been cancelled.
+pub fn check(token: &CancellationToken) -> Result<()> {
+    if token.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        ShortcodeFn, Site, cancel,
+        database::{DatabaseSource, setup_database},
+        test_utils::make_site,
+    };
+
+    #[test]
+    fn test_cancellation() -> Result<()> {
+        let page = "---\ntitle = \"Hello\"\ntags = []\n---\n\n{{! newer_change() !}}{{! end !}}\n";
+        let (_root, out, site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", page),
+            ],
+            |_| (),
+        )?;
+
+        // A change coming in while the page is being loaded cancels the build.
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let newer_change: ShortcodeFn = Box::new(move |_| {
+            cancel.cancel();
+            Ok("<p>Loaded".to_owned())
+        });
+        let mut site = Site::with_shortcodes(
+            setup_database(DatabaseSource::Memory)?,
+            site.config,
+            [("newer_change".to_string(), newer_change)],
+        )?;
+        let error = site.load_cancellable(&token).unwrap_err();
+        assert!(error.is::<cancel::Cancelled>());
+
+        // Rendering stops before writing any pages.
+        site.load()?;
+        let error = site.render_cancellable(&token).unwrap_err();
+        assert!(error.is::<cancel::Cancelled>());
+        let output = out.path().join("public/blog/Hello/index.html");
+        assert!(!output.exists());
+
+        site.render()?;
+        assert_eq!(fs::read_to_string(output)?, "<p>Loaded");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+use crate::entry::variant_key;
+
+/// Two sources with permalinks that only differ in case or percent encoding.
+///
+/// They're separate files on case-sensitive hosts, but collide on hosts that ignore
+/// case, such as S3 behind a CDN that lowercases keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermalinkCollision {
+    pub first: PathBuf,
+    pub first_permalink: Url,
+    pub second: PathBuf,
+    pub second_permalink: Url,
+}
+
+impl fmt::Display for PermalinkCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} and {} have permalinks that only differ in case ({} and {}), which collide on hosts that ignore case",
+            self.first.display(),
+            self.second.display(),
+            self.first_permalink.path(),
+            self.second_permalink.path()
+        )
+    }
+}
+
+/// Find the sources with permalinks that are the same once case folded and percent
+/// decoded, comparing each against the first source with that permalink.
+pub fn permalink_collisions<'a, I: IntoIterator<Item = (&'a Path, &'a Url)>>(
+    sources: I,
+) -> Vec<PermalinkCollision> {
+    let mut sources = sources.into_iter().collect::<Vec<_>>();
+    sources.sort_by_key(|(path, _)| *path);
+
+    let mut seen = BTreeMap::<String, (&Path, &Url)>::new();
+    let mut collisions = vec![];
+    for (path, permalink) in sources {
+        let decoded = percent_decode_str(permalink.path()).decode_utf8_lossy();
+        let Some(key) = variant_key(Path::new(decoded.as_ref())) else {
+            continue;
+        };
+
+        match seen.get(&key) {
+            Some((first, first_permalink)) if *first_permalink != permalink => {
+                collisions.push(PermalinkCollision {
+                    first: first.to_path_buf(),
+                    first_permalink: (*first_permalink).clone(),
+                    second: path.to_path_buf(),
+                    second_permalink: permalink.clone(),
+                });
+            }
+            Some(_) => (),
+            None => {
+                seen.insert(key, (path, permalink));
+            }
+        }
+    }
+
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::test_utils::{make_page, make_site};
+
+    #[test]
+    fn test_permalink_collisions() -> Result<()> {
+        let foo = Url::parse("https://example.com/posts/foo/")?;
+        let shouting = Url::parse("https://example.com/Posts/Foo/")?;
+        let cafe = Url::parse("https://example.com/posts/Caf%C3%A9/")?;
+        let lower_cafe = Url::parse("https://example.com/posts/café/")?;
+        let bar = Url::parse("https://example.com/posts/bar/")?;
+
+        let collisions = permalink_collisions([
+            (Path::new("site/Posts/Foo.md"), &shouting),
+            (Path::new("site/posts/foo.md"), &foo),
+            (Path::new("site/posts/bar.md"), &bar),
+            (Path::new("site/posts/cafe.md"), &cafe),
+            (Path::new("site/posts/café.md"), &lower_cafe),
+        ]);
+
+        assert_eq!(
+            collisions
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "site/posts/cafe.md and site/posts/café.md have permalinks that only differ in case (/posts/Caf%C3%A9/ and /posts/caf%C3%A9/), which collide on hosts that ignore case",
+                "site/Posts/Foo.md and site/posts/foo.md have permalinks that only differ in case (/Posts/Foo/ and /posts/foo/), which collide on hosts that ignore case",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_permalink_case_collision() -> Result<()> {
+        let files = [
+            ("templates/post.html", "{{ document.content | safe }}"),
+            ("_content/blog/a.md", &make_page("Hello", "post.html")),
+            ("_content/blog/b.md", &make_page("hello", "post.html")),
+        ];
+
+        // Only a warning by default.
+        let (_root, _out, mut site) = make_site(&files, |_| ())?;
+        site.load()?;
+
+        let (root, _out, mut site) = make_site(&files, |c| c.site.strict_urls = true)?;
+        let error = site.load().unwrap_err().to_string();
+        let root = root.path().display();
+        assert_eq!(
+            error,
+            format!(
+                "Permalinks only differ in case:\n  {root}/_content/blog/a.md and {root}/_content/blog/b.md have permalinks that only differ in case (/blog/Hello and /blog/hello), which collide on hosts that ignore case"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_permalink_override_collision() -> Result<()> {
+        let (root, _out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/about.md", &make_page("About", "post.html")),
+                (
+                    "me.html",
+                    "---\ntitle = \"Me\"\npermalink = \"/About/\"\n---\nMe",
+                ),
+                (
+                    "security.html",
+                    "---\ntitle = \"Security\"\npermalink = \"/.well-known/security.txt\"\n---\nContact: me@example.com",
+                ),
+            ],
+            |_| (),
+        )?;
+        let error = site.load().unwrap_err().to_string();
+        let root = root.path().display();
+        assert_eq!(
+            error,
+            format!(
+                "Found colliding permalinks:\n  {root}/me.html sets its permalink to /About/, which {root}/_content/about.md is rendered to too"
+            )
+        );
+
+        let (_root, out, mut site) = make_site(
+            &[(
+                "security.html",
+                "---\ntitle = \"Security\"\npermalink = \"/.well-known/security.txt\"\n---\nContact: {{ 'me@example.com' }}\n",
+            )],
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+        assert_eq!(
+            fs::read_to_string(out.path().join("public/.well-known/security.txt"))?,
+            "Contact: me@example.com"
+        );
+
+        Ok(())
+    }
+}
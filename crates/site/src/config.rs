@@ -1,7 +1,13 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
 
-use serde::{Deserialize, Serialize};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer, Serialize, de::Error};
 use url::Url;
+use yar_markdown::{AnchorStyle, HighlightMode};
 
 /// Configuration values for a site.
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -17,16 +23,27 @@ pub struct Config {
 /// All of this information is available to templates under the `site` variable.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SiteConfig {
-    /// The url to the site.
+    /// The url to the site. It always ends with a slash, which is added if it's missing,
+    /// so that urls joined onto it keep the whole path of sites hosted under one.
+    #[serde(deserialize_with = "deserialize_url")]
     pub url: Url,
     /// The authors of the site.
     pub authors: Option<Vec<String>>,
+    /// Metadata for the authors pages can reference in their frontmatter, by key.
+    pub authors_meta: BTreeMap<String, AuthorMeta>,
+    /// Tags to merge into another tag, e.g. `"rustlang" = "rust"`. The tag merged into
+    /// is used as the canonical form.
+    pub tag_aliases: BTreeMap<String, String>,
     /// The title of the site.
     pub title: Option<String>,
     /// The description of the site.
     pub description: Option<String>,
     /// An email to accompany the site with.
     pub email: Option<String>,
+    /// The endpoint webmentions to the site are sent to, like
+    /// `https://webmention.io/example.com/webmention`. It's linked from the head of pages
+    /// with `head_links`, and from `.well-known/host-meta`.
+    pub webmention_endpoint: Option<Url>,
     /// The path to the root of the site.
     ///
     /// This is where the static site generator will read in and process files from.
@@ -35,11 +52,277 @@ pub struct SiteConfig {
     pub output_path: PathBuf,
     /// Whether or not a development build is being run.
     pub development: bool,
+    /// The timezone dates are displayed in by the `in_timezone` filter, such as
+    /// `America/New_York`. Dates are stored in UTC.
+    #[serde(default, deserialize_with = "deserialize_timezone")]
+    pub timezone: Option<Tz>,
+    /// The path archive pages are under, which `post_calendar` links each month to, like
+    /// `archive/2025/03/` for `archive`.
+    pub archive_path: String,
     /// The syntax highlighting theme.
     pub syntax_theme: String,
     /// A path for discovering syntax highlighting themes.
     pub syntax_theme_path: Option<PathBuf>,
     pub db_file: PathBuf,
+    /// The extensions of files that are rendered as pages.
+    pub content_extensions: Vec<String>,
+    /// The extensions of files that are passed through the asset pipeline. `scss` and
+    /// `sass` files are compiled to CSS.
+    pub asset_extensions: Vec<String>,
+    /// The size in bytes above which static files are hashed as they're read, rather
+    /// than being read into memory.
+    pub max_in_memory_file_size: u64,
+    /// The size in bytes above which files are skipped with a warning.
+    pub max_file_size: Option<u64>,
+    /// The number of threads to build with, or every core if unset. With a single job,
+    /// builds run sequentially and in a deterministic order.
+    pub jobs: Option<NonZeroUsize>,
+    /// Whether `get_url` links to missing targets, and permalinks that only differ in case,
+    /// fail the build rather than warning.
+    pub strict_urls: bool,
+    /// Whether to render pages depending on hook outputs again once the post hooks have
+    /// run. See [`HookOutput`].
+    pub two_pass: bool,
+    /// Configuration for markdown rendering.
+    pub markdown: MarkdownConfig,
+    /// Restricts which pages are rendered, for partial builds.
+    pub filter: BuildFilter,
+    /// Size limits that output files are checked against after a build.
+    pub audit: AuditConfig,
+    /// Configuration for the sitemap.
+    pub sitemap: SitemapConfig,
+    /// Configuration for published images.
+    pub images: ImagesConfig,
+    /// The host specific redirects file to generate from page aliases and `redirects`.
+    pub redirects_format: RedirectsFormat,
+    /// Redirects on top of the ones from page aliases.
+    pub redirects: Vec<Redirect>,
+}
+
+/// Add the trailing slash urls are joined onto to a site url, returning whether it was missing.
+///
+/// Without it, joining `atom.xml` onto `https://example.com/blog` replaces `blog` rather
+/// than going under it.
+pub fn add_trailing_slash(url: &mut Url) -> bool {
+    if url.path().ends_with('/') {
+        return false;
+    }
+
+    url.set_path(&format!("{}/", url.path()));
+    true
+}
+
+// Deserialize a site url, which needs to be one relative urls can be joined onto, adding
+// its trailing slash if it's missing.
+fn deserialize_url<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Url, D::Error> {
+    let mut url = Url::deserialize(deserializer)?;
+    if url.cannot_be_a_base() || url.query().is_some() || url.fragment().is_some() {
+        return Err(D::Error::custom(format!(
+            "{url} can't be the site's url, it needs a path for pages to go under, like \"https://example.com/\", without a query or fragment"
+        )));
+    }
+    if add_trailing_slash(&mut url) {
+        println!("Warning: The site's url should end with a slash, using {url}");
+    }
+
+    Ok(url)
+}
+
+fn deserialize_timezone<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Tz>, D::Error> {
+    let Some(name) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    name.parse().map(Some).map_err(|_| {
+        D::Error::custom(format!(
+            "{name} isn't a known timezone. Timezones are names from the IANA database, like \"UTC\", \"America/New_York\", \"Europe/London\", or \"Asia/Tokyo\""
+        ))
+    })
+}
+
+/// Restricts which pages are rendered in a build.
+///
+/// The full site is still loaded, so cross links and the global context stay correct.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct BuildFilter {
+    /// Only render pages using this template. Assets and static files aren't copied.
+    pub only_template: Option<String>,
+    /// Only render pages in this section, and copy the assets and static files in
+    /// directories named after it.
+    pub only_section: Option<String>,
+    /// Render the feed and sitemap with only the selected pages, rather than skipping them.
+    pub filter_feeds: bool,
+}
+
+impl BuildFilter {
+    /// Whether this filter restricts the build at all.
+    #[must_use]
+    pub const fn is_partial(&self) -> bool {
+        self.only_template.is_some() || self.only_section.is_some()
+    }
+}
+
+/// Metadata for an author.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuthorMeta {
+    /// The name the author is displayed with.
+    pub name: String,
+    pub bio: Option<String>,
+    pub url: Option<String>,
+}
+
+/// Size limits, in bytes, that output files are checked against after a build.
+///
+/// Files over a limit are only reported, and don't fail the build.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// The largest a page's HTML can be.
+    pub max_html_size: u64,
+    /// The largest an image can be.
+    pub max_image_size: u64,
+    /// The largest a page's HTML and the local resources it loads can be together.
+    pub max_page_weight: u64,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            max_html_size: 500_000,
+            max_image_size: 1_000_000,
+            max_page_weight: 3_000_000,
+        }
+    }
+}
+
+/// Configuration for the sitemap.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SitemapConfig {
+    /// Whether hidden pages are listed in the sitemap. They're always left out of the
+    /// `pages` context, feeds, and tags.
+    pub include_hidden: bool,
+}
+
+impl Default for SitemapConfig {
+    fn default() -> Self {
+        Self {
+            include_hidden: true,
+        }
+    }
+}
+
+/// Configuration for published images.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ImagesConfig {
+    /// Whether to strip the EXIF metadata, like GPS coordinates, from JPEG, PNG, and WebP
+    /// static files as they're copied. Images that metadata rotates are rotated instead.
+    pub strip_exif: bool,
+}
+
+/// The kinds of redirects file that can be generated for hosts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectsFormat {
+    /// A Netlify `_redirects` file.
+    Netlify,
+    /// A `vercel.json` with only the `redirects` key.
+    Vercel,
+    /// An nginx snippet of `location` blocks, to include in a `server` block.
+    Nginx,
+    /// Don't generate a redirects file.
+    #[default]
+    None,
+}
+
+impl RedirectsFormat {
+    /// The file the redirects are written to, relative to the output directory.
+    #[must_use]
+    pub const fn file_name(self) -> Option<&'static str> {
+        match self {
+            Self::Netlify => Some("_redirects"),
+            Self::Vercel => Some("vercel.json"),
+            Self::Nginx => Some("redirects.conf"),
+            Self::None => None,
+        }
+    }
+}
+
+/// A redirect from one path on the site to another path or URL.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    /// The path being redirected, such as `/old/post`.
+    pub from: String,
+    /// A path on the site, which has to exist, or a full URL elsewhere.
+    pub to: String,
+    /// The HTTP status to redirect with.
+    #[serde(default = "default_redirect_status")]
+    pub status: u16,
+}
+
+const fn default_redirect_status() -> u16 {
+    301
+}
+
+/// Configuration for markdown rendering.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct MarkdownConfig {
+    /// Whether raw HTML in markdown should be sanitized rather than passed through.
+    ///
+    /// HTML produced by shortcodes and templates is trusted and never sanitized.
+    /// Summaries are derived from the rendered markdown, so they are sanitized too.
+    pub sanitize_html: bool,
+    /// Tags permitted in sanitized HTML on top of the default allowlist.
+    pub allowed_tags: Vec<String>,
+    /// Hosts that iframes in sanitized HTML may point to.
+    pub allowed_iframe_hosts: Vec<String>,
+    /// The shallowest and deepest levels of headings put in tables of contents, from 1
+    /// to 6.
+    pub toc_levels: (u8, u8),
+    /// How ids are made for headings that don't have one.
+    pub anchor_style: AnchorStyle,
+    /// The raw HTML in markdown that ends a page's summary, like `<!-- more -->`.
+    pub summary_marker: String,
+    /// How many characters of text a page's summary has, at least, without a marker.
+    pub summary_length: usize,
+    /// Whether to wrap each line of code blocks in a `<span class="line">`, so that CSS
+    /// can number them.
+    pub line_numbers: bool,
+    /// Whether to replace emoji shortcodes like `:rocket:` in text with the emoji.
+    pub emoji: bool,
+    /// Whether to turn straight quotes into curly ones, `--` and `---` into en and em
+    /// dashes, and `...` into an ellipsis.
+    pub smart_punctuation: bool,
+    /// How highlighted code is marked up, with `elements` like `<a-k>` or `classes` like
+    /// `<span class="keyword">`.
+    pub highlight_mode: HighlightMode,
+    /// Whether to annotate rendered markdown with the lines it came from, as HTML comments.
+    pub debug_annotations: bool,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        Self {
+            sanitize_html: false,
+            allowed_tags: vec![],
+            allowed_iframe_hosts: vec![],
+            toc_levels: (1, 6),
+            anchor_style: AnchorStyle::default(),
+            summary_marker: "<!-- more -->".to_owned(),
+            summary_length: 150,
+            line_numbers: false,
+            emoji: false,
+            smart_punctuation: false,
+            highlight_mode: HighlightMode::default(),
+            debug_annotations: false,
+        }
+    }
 }
 
 /// Configuration for hooks.
@@ -58,6 +341,30 @@ pub struct PostHook {
     pub cmd: String,
     /// An optional help message.
     pub help: Option<String>,
+    /// The files the hook writes that are loaded back into templates.
+    #[serde(default)]
+    pub outputs: Vec<HookOutput>,
+    /// The directory the command is run in, relative to the project's root.
+    pub cwd: Option<PathBuf>,
+    /// Environment variables the command is run with on top of yar's own, where `${VAR}`
+    /// in a value is replaced with the variable from yar's environment.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// How long the command can run for before it's killed, failing the build.
+    pub timeout_secs: Option<u64>,
+}
+
+/// A JSON file written by a hook, available to templates as a global.
+///
+/// The global is an empty map until the hooks have run, so in two-pass builds the pages
+/// and template pages listing it in their `dependencies` are rendered a second time with
+/// the file's contents.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HookOutput {
+    /// The name of the global.
+    pub global: String,
+    /// The path the hook writes the file to, relative to the working directory.
+    pub path: PathBuf,
 }
 
 impl Default for SiteConfig {
@@ -65,15 +372,173 @@ impl Default for SiteConfig {
         Self {
             url: Url::parse("http://0.0.0.0:8000/").expect("Invalid default URL?"),
             authors: None,
+            authors_meta: BTreeMap::new(),
+            tag_aliases: BTreeMap::new(),
             title: None,
             description: None,
             email: None,
+            webmention_endpoint: None,
             root: Path::new("site/").to_owned(),
             output_path: Path::new("public/").to_owned(),
             development: false,
-            syntax_theme: String::from("base16-ocean.dark"),
+            timezone: None,
+            archive_path: String::from("archive"),
+            syntax_theme: String::from("Solarized Dark"),
             syntax_theme_path: None,
             db_file: Path::new("site.redb").to_owned(),
+            content_extensions: vec!["md".to_string(), "markdown".to_string()],
+            asset_extensions: vec!["css".to_string(), "scss".to_string(), "js".to_string()],
+            max_in_memory_file_size: 16_000_000,
+            max_file_size: None,
+            jobs: None,
+            strict_urls: false,
+            two_pass: false,
+            markdown: MarkdownConfig::default(),
+            filter: BuildFilter::default(),
+            audit: AuditConfig::default(),
+            sitemap: SitemapConfig::default(),
+            images: ImagesConfig::default(),
+            redirects_format: RedirectsFormat::default(),
+            redirects: Vec::new(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use color_eyre::Result;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        Site,
+        test_utils::{make_page, make_site},
+    };
+
+    fn with_timezone(name: &str) -> Result<SiteConfig, serde_json::Error> {
+        let mut value = serde_json::to_value(SiteConfig::default())?;
+        value["timezone"] = name.into();
+        serde_json::from_value(value)
+    }
+
+    fn with_url(url: &str) -> Result<SiteConfig, serde_json::Error> {
+        let mut value = serde_json::to_value(SiteConfig::default())?;
+        value["url"] = url.into();
+        serde_json::from_value(value)
+    }
+
+    #[test]
+    fn test_url() -> Result<()> {
+        for (url, expected) in [
+            ("https://example.com", "https://example.com/"),
+            ("https://example.com/blog", "https://example.com/blog/"),
+            ("https://example.com/blog/", "https://example.com/blog/"),
+            ("http://localhost:8000/a/b", "http://localhost:8000/a/b/"),
+        ] {
+            assert_eq!(with_url(url)?.url.as_str(), expected);
+        }
+
+        for url in [
+            "example.com",
+            "mailto:me@example.com",
+            "https://example.com/?page=1",
+            "https://example.com/#top",
+        ] {
+            assert!(with_url(url).is_err(), "{url}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timezone() -> Result<()> {
+        let config = with_timezone("America/New_York")?;
+        assert_eq!(config.timezone, Some(chrono_tz::America::New_York));
+
+        let error = with_timezone("America/Gotham").unwrap_err().to_string();
+        assert!(error.starts_with("America/Gotham isn't a known timezone."));
+        assert!(error.contains("\"Europe/London\""));
+
+        Ok(())
+    }
+
+    fn partial_site(configure: impl FnOnce(&mut Config)) -> Result<(TempDir, TempDir, Site)> {
+        make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("templates/docs.html", "{{ document.content | safe }}"),
+                (
+                    "_content/docs/install.md",
+                    &make_page("Install", "docs.html"),
+                ),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+                ("_content/docs/diagram.svg", "<svg></svg>"),
+                ("static/blog/photo.txt", "photo"),
+                ("styles/main.css", "body {}"),
+            ],
+            configure,
+        )
+    }
+
+    #[test]
+    fn test_pages_by_template() -> Result<()> {
+        let (_root, _out, mut site) = partial_site(|_| ())?;
+        site.load()?;
+
+        let grouped = site.pages_by_template();
+        assert_eq!(grouped["docs.html"].len(), 1);
+        assert_eq!(
+            grouped["docs.html"][0].document.frontmatter.title,
+            "Install"
+        );
+        assert_eq!(grouped["post.html"][0].document.frontmatter.title, "Hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_build_by_template() -> Result<()> {
+        let (_root, out, mut site) = partial_site(|c| {
+            c.site.filter.only_template = Some("docs.html".to_string());
+        })?;
+        site.load()?;
+        site.render()?;
+
+        let public = Path::new(out.path()).join("public");
+        assert!(public.join("docs/Install/index.html").exists());
+        assert!(!public.join("blog/Hello/index.html").exists());
+        assert!(!public.join("atom.xml").exists());
+        assert!(!public.join("sitemap.xml").exists());
+        // Assets and static files don't use a template, so none of them are copied.
+        assert!(!public.join("docs/diagram.svg").exists());
+        assert!(!public.join("styles/main.css").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_build_filters_feeds() -> Result<()> {
+        let (_root, out, mut site) = partial_site(|c| {
+            c.site.filter.only_section = Some("blog".to_string());
+            c.site.filter.filter_feeds = true;
+        })?;
+        site.load()?;
+        site.render()?;
+
+        let public = Path::new(out.path()).join("public");
+        assert!(public.join("blog/Hello/index.html").exists());
+        assert!(!public.join("docs/Install/index.html").exists());
+        // Only the files in the section's directories are copied.
+        assert!(public.join("static/blog/photo.txt").exists());
+        assert!(!public.join("docs/diagram.svg").exists());
+        assert!(!public.join("styles/main.css").exists());
+
+        let feed = fs::read_to_string(public.join("atom.xml"))?;
+        assert!(feed.contains("Hello from Hello"));
+        assert!(!feed.contains("Hello from Install"));
+
+        Ok(())
+    }
+}
@@ -1,23 +1,55 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     path::{Path, PathBuf},
 };
 
+use blake3::Hash;
 use color_eyre::{Result, eyre::ContextCompat};
 use redb::{
-    Database, ReadableDatabase, ReadableTable, TableDefinition, WriteTransaction,
+    Database, MultimapTableDefinition, ReadOnlyDatabase, ReadableDatabase, ReadableMultimapTable,
+    ReadableTable, ReadableTableMetadata, TableDefinition, TableHandle, WriteTransaction,
     backends::InMemoryBackend,
 };
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
-use crate::page::Page;
+use crate::{
+    cache::CACHE_SCHEMA_VERSION, highlights::CachedHighlight, page::Page,
+    templates::template_page::RenderedState, thumbnails::Thumbnail,
+};
 
 const PAGES: TableDefinition<&str, &[u8]> = TableDefinition::new("pages");
 const HASHES: TableDefinition<&str, &[u8]> = TableDefinition::new("hashes");
+const DEPENDENCIES: TableDefinition<&str, &[u8]> = TableDefinition::new("dependencies");
+const TEMPLATE_PAGES: TableDefinition<&str, &[u8]> = TableDefinition::new("template_pages");
+const SETTINGS: TableDefinition<&str, &str> = TableDefinition::new("settings");
+const THUMBNAILS: TableDefinition<&str, &[u8]> = TableDefinition::new("thumbnails");
+const TEMPLATE_PAGE_OUTPUTS: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("template_page_outputs");
+const HIGHLIGHTS: TableDefinition<&str, &[u8]> = TableDefinition::new("highlights");
+// An index of the pages in each section, by their date as a timestamp in milliseconds and
+// their path, so that a section's pages are read in order without reading every page.
+// It's built from the pages table, so it's never dumped or copied.
+const PAGE_SECTIONS: MultimapTableDefinition<&str, (i64, &str)> =
+    MultimapTableDefinition::new("page_sections");
+
+// Every table other than the settings, which hold strings.
+const BINARY_TABLES: [TableDefinition<&str, &[u8]>; 7] = [
+    PAGES,
+    HASHES,
+    DEPENDENCIES,
+    TEMPLATE_PAGES,
+    THUMBNAILS,
+    TEMPLATE_PAGE_OUTPUTS,
+    HIGHLIGHTS,
+];
 
 #[derive(Debug, Clone, Copy)]
 pub enum DatabaseSource<'a> {
     Memory,
     File(&'a Path),
+    /// An in-memory copy of a database file, if it exists, so that builds using it leave
+    /// the file as it was. Used for dry runs.
+    Snapshot(&'a Path),
 }
 
 /// Initializes the database, either in-memory or from a file on disk.
@@ -25,6 +57,13 @@ pub fn setup_database(source: DatabaseSource) -> Result<Database> {
     let db = match source {
         DatabaseSource::File(p) => Database::create(p)?,
         DatabaseSource::Memory => Database::builder().create_with_backend(InMemoryBackend::new())?,
+        DatabaseSource::Snapshot(p) => {
+            let db = Database::builder().create_with_backend(InMemoryBackend::new())?;
+            if p.exists() {
+                copy_tables(&ReadOnlyDatabase::open(p)?, &db)?;
+            }
+            db
+        }
     };
 
     // redb requires an initial write before a read if the table is empty.
@@ -32,25 +71,156 @@ pub fn setup_database(source: DatabaseSource) -> Result<Database> {
     {
         write_txn.open_table(HASHES)?;
         write_txn.open_table(PAGES)?;
+        write_txn.open_table(DEPENDENCIES)?;
+        write_txn.open_table(TEMPLATE_PAGES)?;
+        write_txn.open_table(SETTINGS)?;
+        write_txn.open_table(THUMBNAILS)?;
+        write_txn.open_table(TEMPLATE_PAGE_OUTPUTS)?;
+        write_txn.open_table(HIGHLIGHTS)?;
+        clear_stale_schema(&write_txn)?;
+        // Databases from before the index was added, or copied without it, have their
+        // pages indexed from scratch.
+        let unindexed = write_txn.open_multimap_table(PAGE_SECTIONS)?.is_empty()?;
+        if unindexed {
+            index_sections(&write_txn)?;
+        }
     }
     write_txn.commit()?;
 
     Ok(db)
 }
 
-/// Get all hashes
-pub fn get_hashes(db: &Database) -> Result<HashMap<PathBuf, [u8; 32]>> {
-    let read_txn = db.begin_read()?;
-    let table = read_txn.open_table(HASHES)?;
+// Rows aren't self-describing, so a database written with a different schema version,
+// or from before it was recorded, can't be read. Everything but the settings is cleared
+// instead, so that the site is built again from scratch, and the current version is
+// recorded.
+fn clear_stale_schema(txn: &WriteTransaction) -> Result<()> {
+    let version = CACHE_SCHEMA_VERSION.to_string();
+    let mut settings = txn.open_table(SETTINGS)?;
+    let previous = settings
+        .get("schema_version")?
+        .map(|v| v.value().to_owned());
+    if previous.as_deref() == Some(version.as_str()) {
+        return Ok(());
+    }
 
-    Ok(table
+    if previous.is_some() || !txn.open_table(HASHES)?.is_empty()? {
+        println!("The cache was built by another version of yar, building everything again");
+    }
+    for definition in BINARY_TABLES {
+        txn.open_table(definition)?.retain(|_, _| false)?;
+    }
+    txn.delete_multimap_table(PAGE_SECTIONS)?;
+    settings.insert("schema_version", version.as_str())?;
+
+    Ok(())
+}
+
+// Index the section of every page in the pages table, replacing the index.
+fn index_sections(txn: &WriteTransaction) -> Result<()> {
+    let mut index = txn.open_multimap_table(PAGE_SECTIONS)?;
+    let sections = index
         .iter()?
-        .filter_map(|e| {
-            let (k, v) = e.ok()?;
-            let hash: [u8; 32] = v.value().try_into().ok()?;
-            Some((PathBuf::from(k.value()), hash))
-        })
-        .collect())
+        .map(|row| Ok(row?.0.value().to_owned()))
+        .collect::<Result<Vec<String>>>()?;
+    for section in sections {
+        index.remove_all(section.as_str())?;
+    }
+    for row in txn.open_table(PAGES)?.iter()? {
+        let (_, bytes) = row?;
+        let page = decode::<Page>(bytes.value())?;
+        if let Some(section) = page.section() {
+            index.insert(section, section_entry(&page)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Copy every table of one database into another.
+fn copy_tables(from: &ReadOnlyDatabase, to: &Database) -> Result<()> {
+    let read_txn = from.begin_read()?;
+    let write_txn = to.begin_write()?;
+    for definition in BINARY_TABLES {
+        // Tables that don't exist yet have nothing to copy.
+        let Ok(source) = read_txn.open_table(definition) else {
+            continue;
+        };
+        let mut table = write_txn.open_table(definition)?;
+        for row in source.iter()? {
+            let (key, value) = row?;
+            table.insert(key.value(), value.value())?;
+        }
+    }
+    if let Ok(source) = read_txn.open_table(SETTINGS) {
+        let mut table = write_txn.open_table(SETTINGS)?;
+        for row in source.iter()? {
+            let (key, value) = row?;
+            table.insert(key.value(), value.value())?;
+        }
+    }
+    write_txn.commit()?;
+
+    Ok(())
+}
+
+/// The rows of every table in the database, keyed by table name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableDump {
+    pub tables: BTreeMap<String, Vec<(String, Vec<u8>)>>,
+    pub settings: Vec<(String, String)>,
+}
+
+/// Read every row of the database.
+pub fn dump_tables(db: &Database) -> Result<TableDump> {
+    let read_txn = db.begin_read()?;
+    let mut dump = TableDump::default();
+    for definition in BINARY_TABLES {
+        let rows = read_txn
+            .open_table(definition)?
+            .iter()?
+            .map(|row| {
+                let (key, value) = row?;
+                Ok((key.value().to_owned(), value.value().to_vec()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        dump.tables.insert(definition.name().to_owned(), rows);
+    }
+    for row in read_txn.open_table(SETTINGS)?.iter()? {
+        let (key, value) = row?;
+        dump.settings
+            .push((key.value().to_owned(), value.value().to_owned()));
+    }
+
+    Ok(dump)
+}
+
+/// Replace the contents of the database with a dump. Tables missing from the dump are
+/// left empty.
+pub fn restore_tables(db: &Database, dump: &TableDump) -> Result<()> {
+    let write_txn = db.begin_write()?;
+    for definition in BINARY_TABLES {
+        let mut table = write_txn.open_table(definition)?;
+        table.retain(|_, _| false)?;
+        for (key, value) in dump.tables.get(definition.name()).into_iter().flatten() {
+            table.insert(key.as_str(), value.as_slice())?;
+        }
+    }
+    let mut table = write_txn.open_table(SETTINGS)?;
+    table.retain(|_, _| false)?;
+    for (key, value) in &dump.settings {
+        table.insert(key.as_str(), value.as_str())?;
+    }
+    drop(table);
+    index_sections(&write_txn)?;
+    write_txn.commit()?;
+
+    Ok(())
+}
+
+/// Get all hashes
+pub fn get_hashes(db: &Database) -> Result<HashMap<PathBuf, [u8; 32]>> {
+    read_hashes(db, HASHES)
 }
 
 /// Get all the pages stored in the database, filtering out any ones with invalidated paths that were passed in.
@@ -69,12 +239,236 @@ pub fn get_pages<S: ::std::hash::BuildHasher>(
             if invalidated.contains(&path) {
                 return None;
             }
-            let page = postcard::from_bytes(bytes.value()).map_err(Into::into);
-            Some(page)
+            Some(decode(bytes.value()))
         })
         .collect::<Result<Vec<Page>>>()
 }
 
+/// Get the pages in a section, oldest first, leaving out drafts unless `drafts` is set.
+pub fn get_pages_in_section(db: &Database, section: &str, drafts: bool) -> Result<Vec<Page>> {
+    let read_txn = db.begin_read()?;
+    let index = read_txn.open_multimap_table(PAGE_SECTIONS)?;
+    let table = read_txn.open_table(PAGES)?;
+
+    let mut pages = vec![];
+    for entry in index.get(section)? {
+        let entry = entry?;
+        let (_, path) = entry.value();
+        let Some(bytes) = table.get(path)? else {
+            continue;
+        };
+        let page = decode::<Page>(bytes.value())?;
+        if drafts || !page.document.frontmatter.draft {
+            pages.push(page);
+        }
+    }
+
+    Ok(pages)
+}
+
+/// Get the templates that depend on any of the given paths.
+pub fn get_dependents<S: ::std::hash::BuildHasher>(
+    db: &Database,
+    paths: &HashSet<PathBuf, S>,
+) -> Result<HashSet<String>> {
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(DEPENDENCIES)?;
+
+    let mut dependents = HashSet::new();
+    for path in paths {
+        let Some(path_str) = path.to_str() else {
+            continue;
+        };
+        if let Some(bytes) = table.get(path_str)? {
+            dependents.extend(decode::<Vec<String>>(bytes.value())?);
+        }
+    }
+
+    Ok(dependents)
+}
+
+/// Record the templates depending on a file, adding to any already recorded.
+pub fn insert_dependents<P: AsRef<Path>, S: ::std::hash::BuildHasher>(
+    txn: &WriteTransaction,
+    path: P,
+    templates: &HashSet<String, S>,
+) -> Result<()> {
+    let mut table = txn.open_table(DEPENDENCIES)?;
+    let path_str = path_key(path.as_ref())?;
+
+    let mut dependents = match table.get(path_str)? {
+        Some(bytes) => decode::<Vec<String>>(bytes.value())?,
+        None => vec![],
+    };
+    for template in templates {
+        if !dependents.contains(template) {
+            dependents.push(template.clone());
+        }
+    }
+
+    table.insert(path_str, postcard::to_stdvec(&dependents)?.as_slice())?;
+
+    Ok(())
+}
+
+/// Forget the files the given templates depend on, so that the files they include when
+/// they're rendered again replace them.
+pub fn remove_dependents<S: ::std::hash::BuildHasher>(
+    txn: &WriteTransaction,
+    templates: &HashSet<String, S>,
+) -> Result<()> {
+    let mut table = txn.open_table(DEPENDENCIES)?;
+
+    let mut changed = vec![];
+    for row in table.iter()? {
+        let (path, bytes) = row?;
+        let dependents = decode::<Vec<String>>(bytes.value())?;
+        if dependents.iter().any(|t| templates.contains(t)) {
+            let kept = dependents
+                .into_iter()
+                .filter(|t| !templates.contains(t))
+                .collect::<Vec<String>>();
+            changed.push((path.value().to_owned(), kept));
+        }
+    }
+
+    for (path, dependents) in changed {
+        if dependents.is_empty() {
+            table.remove(path.as_str())?;
+        } else {
+            table.insert(path.as_str(), postcard::to_stdvec(&dependents)?.as_slice())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Get what was rendered for each template page in previous builds.
+pub fn get_rendered_states(db: &Database) -> Result<HashMap<PathBuf, RenderedState>> {
+    read_rows(db, TEMPLATE_PAGES)
+}
+
+/// Record what was rendered for a template page, replacing any previous entry.
+pub fn insert_rendered_state<P: AsRef<Path>>(
+    txn: &WriteTransaction,
+    path: P,
+    state: &RenderedState,
+) -> Result<()> {
+    write_row(txn, TEMPLATE_PAGES, path.as_ref(), state)
+}
+
+/// Get the hashes of the files template pages were rendered to, keyed by their path
+/// relative to the output directory.
+pub fn get_template_page_outputs(db: &Database) -> Result<HashMap<PathBuf, Hash>> {
+    Ok(read_hashes(db, TEMPLATE_PAGE_OUTPUTS)?
+        .into_iter()
+        .map(|(path, hash)| (path, Hash::from_bytes(hash)))
+        .collect())
+}
+
+/// Record the hash of a file a template page was rendered to, replacing any previous entry.
+pub fn insert_template_page_output<P: AsRef<Path>>(
+    txn: &WriteTransaction,
+    out_path: P,
+    hash: &Hash,
+) -> Result<()> {
+    txn.open_table(TEMPLATE_PAGE_OUTPUTS)?
+        .insert(path_key(out_path.as_ref())?, hash.as_bytes().as_slice())?;
+
+    Ok(())
+}
+
+/// Forget a file a template page was rendered to.
+pub fn remove_template_page_output<P: AsRef<Path>>(
+    txn: &WriteTransaction,
+    out_path: P,
+) -> Result<()> {
+    txn.open_table(TEMPLATE_PAGE_OUTPUTS)?
+        .remove(path_key(out_path.as_ref())?)?;
+
+    Ok(())
+}
+
+/// Get the thumbnails generated by previous builds, keyed by their output path.
+pub fn get_thumbnails(db: &Database) -> Result<HashMap<PathBuf, Thumbnail>> {
+    read_rows(db, THUMBNAILS)
+}
+
+/// Record a generated thumbnail.
+pub fn insert_thumbnail(txn: &WriteTransaction, thumbnail: &Thumbnail) -> Result<()> {
+    write_row(txn, THUMBNAILS, &thumbnail.out_path(), thumbnail)
+}
+
+/// Forget a generated thumbnail.
+pub fn remove_thumbnail<P: AsRef<Path>>(txn: &WriteTransaction, out_path: P) -> Result<()> {
+    txn.open_table(THUMBNAILS)?
+        .remove(path_key(out_path.as_ref())?)?;
+
+    Ok(())
+}
+
+/// Get the code blocks highlighted by previous builds, keyed by a hash of their code.
+pub fn get_highlights(db: &Database) -> Result<HashMap<String, CachedHighlight>> {
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(HIGHLIGHTS)?;
+
+    table
+        .iter()?
+        .map(|res| {
+            let (k, bytes) = res?;
+            Ok((k.value().to_owned(), decode(bytes.value())?))
+        })
+        .collect()
+}
+
+/// Record a highlighted code block, replacing any previous entry.
+pub fn insert_highlight(
+    txn: &WriteTransaction,
+    key: &str,
+    highlight: &CachedHighlight,
+) -> Result<()> {
+    txn.open_table(HIGHLIGHTS)?
+        .insert(key, postcard::to_stdvec(highlight)?.as_slice())?;
+
+    Ok(())
+}
+
+/// Forget a highlighted code block.
+pub fn remove_highlight(txn: &WriteTransaction, key: &str) -> Result<()> {
+    txn.open_table(HIGHLIGHTS)?.remove(key)?;
+
+    Ok(())
+}
+
+/// Get a setting the site was last built with.
+pub fn get_setting(db: &Database, key: &str) -> Result<Option<String>> {
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(SETTINGS)?;
+
+    Ok(table.get(key)?.map(|v| v.value().to_owned()))
+}
+
+/// Record a setting the site was built with, replacing any previous value.
+pub fn insert_setting(txn: &WriteTransaction, key: &str, value: &str) -> Result<()> {
+    txn.open_table(SETTINGS)?.insert(key, value)?;
+
+    Ok(())
+}
+
+/// Forget the hashes of every file and everything generated from them, so that everything
+/// is built and written again.
+pub fn invalidate_all(db: &Database) -> Result<()> {
+    let write_txn = db.begin_write()?;
+    write_txn.open_table(HASHES)?.retain(|_, _| false)?;
+    write_txn.open_table(THUMBNAILS)?.retain(|_, _| false)?;
+    write_txn
+        .open_table(TEMPLATE_PAGE_OUTPUTS)?
+        .retain(|_, _| false)?;
+    write_txn.commit()?;
+
+    Ok(())
+}
+
 /// Insert a hash into the database. If there is already a hash for the given path, the existing entry is updated.
 pub fn insert_hash<P: AsRef<Path>, B: AsRef<[u8]>>(
     txn: &WriteTransaction,
@@ -83,28 +477,301 @@ pub fn insert_hash<P: AsRef<Path>, B: AsRef<[u8]>>(
 ) -> Result<()> {
     // let write_txn = db.begin_write()?;
     let mut table = txn.open_table(HASHES)?;
-    let path_str = path
-        .as_ref()
-        .to_str()
-        .context("Could not convert path to string.")?;
+    let path_str = path_key(path.as_ref())?;
 
     table.insert(path_str, hash.as_ref())?;
 
     Ok(())
 }
 
+/// Remove a page, along with its hash, from the database.
+pub fn remove_page<P: AsRef<Path>>(txn: &WriteTransaction, path: P) -> Result<()> {
+    let path_str = path_key(path.as_ref())?;
+
+    unindex_section(txn, path_str)?;
+    txn.open_table(PAGES)?.remove(path_str)?;
+    txn.open_table(HASHES)?.remove(path_str)?;
+
+    Ok(())
+}
+
+/// Move everything cached for a file to the path it was renamed to, returning its page as
+/// it was cached, if it's a page.
+pub fn rename_entry<P: AsRef<Path>, T: AsRef<Path>>(
+    db: &Database,
+    from: P,
+    to: T,
+) -> Result<Option<Page>> {
+    let from_str = path_key(from.as_ref())?;
+    let to_str = path_key(to.as_ref())?;
+
+    let write_txn = db.begin_write()?;
+    let mut page = None;
+    {
+        for definition in [HASHES, DEPENDENCIES, TEMPLATE_PAGES] {
+            let mut table = write_txn.open_table(definition)?;
+            let value = table.remove(from_str)?.map(|v| v.value().to_vec());
+            if let Some(value) = value {
+                table.insert(to_str, value.as_slice())?;
+            }
+        }
+
+        unindex_section(&write_txn, from_str)?;
+        let mut pages = write_txn.open_table(PAGES)?;
+        let cached = pages.remove(from_str)?.map(|v| v.value().to_vec());
+        if let Some(bytes) = cached {
+            let mut moved = decode::<Page>(&bytes)?;
+            page = Some(moved.clone());
+            to.as_ref().clone_into(&mut moved.path);
+            pages.insert(to_str, postcard::to_stdvec(&moved)?.as_slice())?;
+            if let Some(section) = moved.section() {
+                write_txn
+                    .open_multimap_table(PAGE_SECTIONS)?
+                    .insert(section, section_entry(&moved)?)?;
+            }
+        }
+    }
+    write_txn.commit()?;
+
+    Ok(page)
+}
+
 /// Insert a page into the database. If the page already exists, the existing entry is updated.
 pub fn insert_page(txn: &WriteTransaction, page: &Page) -> Result<()> {
-    let path_str = page
-        .path
-        .to_str()
-        .context("Could not convert path to string.")?;
+    let path_str = path_key(&page.path)?;
+    unindex_section(txn, path_str)?;
+    write_row(txn, PAGES, &page.path, page)?;
+    insert_hash(txn, &page.path, page.source_hash.as_bytes())?;
+    if let Some(section) = page.section() {
+        txn.open_multimap_table(PAGE_SECTIONS)?
+            .insert(section, section_entry(page)?)?;
+    }
 
-    let mut table = txn.open_table(PAGES)?;
-    let serialized_page = postcard::to_stdvec(page)?;
-    table.insert(path_str, serialized_page.as_slice())?;
+    Ok(())
+}
 
-    insert_hash(txn, path_str, page.source_hash.as_bytes())?;
+// The entry for a page in the section index.
+fn section_entry(page: &Page) -> Result<(i64, &str)> {
+    Ok((page.document.date.timestamp_millis(), path_key(&page.path)?))
+}
+
+// Remove the cached page at a path from the section index, using the section and date it
+// was cached with. Pages cached in a format that can't be read anymore are skipped, since
+// they can't be in the index either.
+fn unindex_section(txn: &WriteTransaction, path: &str) -> Result<()> {
+    let Some(bytes) = txn
+        .open_table(PAGES)?
+        .get(path)?
+        .map(|v| v.value().to_vec())
+    else {
+        return Ok(());
+    };
+    let Ok(page) = decode::<Page>(&bytes) else {
+        return Ok(());
+    };
+    if let Some(section) = page.section() {
+        txn.open_multimap_table(PAGE_SECTIONS)?
+            .remove(section, section_entry(&page)?)?;
+    }
+
+    Ok(())
+}
+
+// Paths are stored as strings, so they need to be valid unicode.
+fn path_key(path: &Path) -> Result<&str> {
+    path.to_str()
+        .with_context(|| format!("{} isn't valid unicode", path.display()))
+}
+
+// Read every row of a table, keyed by the path it's stored under.
+fn read_rows<T: DeserializeOwned>(
+    db: &Database,
+    definition: TableDefinition<&str, &[u8]>,
+) -> Result<HashMap<PathBuf, T>> {
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(definition)?;
+
+    table
+        .iter()?
+        .map(|res| {
+            let (k, bytes) = res?;
+            Ok((PathBuf::from(k.value()), decode(bytes.value())?))
+        })
+        .collect()
+}
+
+// Write a row to a table under the given path, replacing any previous one.
+fn write_row<T: Serialize>(
+    txn: &WriteTransaction,
+    definition: TableDefinition<&str, &[u8]>,
+    path: &Path,
+    value: &T,
+) -> Result<()> {
+    txn.open_table(definition)?
+        .insert(path_key(path)?, postcard::to_stdvec(value)?.as_slice())?;
 
     Ok(())
 }
+
+// Read a table of hashes, skipping any that aren't the right length.
+fn read_hashes(
+    db: &Database,
+    definition: TableDefinition<&str, &[u8]>,
+) -> Result<HashMap<PathBuf, [u8; 32]>> {
+    let read_txn = db.begin_read()?;
+    let table = read_txn.open_table(definition)?;
+
+    Ok(table
+        .iter()?
+        .filter_map(|e| {
+            let (k, v) = e.ok()?;
+            let hash: [u8; 32] = v.value().try_into().ok()?;
+            Some((PathBuf::from(k.value()), hash))
+        })
+        .collect())
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    Ok(postcard::from_bytes(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use minijinja::Environment;
+    use url::Url;
+    use yar_markdown::MarkdownRenderer;
+
+    use super::*;
+
+    fn page(path: &str, date: &str, extra: &str) -> Result<Page> {
+        let content =
+            format!("---\ntitle = \"{path}\"\ntags = []\ndate = \"{date}\"\n{extra}\n---\n");
+        Page::new(
+            path,
+            &content,
+            blake3::hash(content.as_bytes()),
+            "public/",
+            "site/",
+            &Url::parse("https://example.com")?,
+            &MarkdownRenderer::new::<&str>(None, None)?,
+            &Environment::empty(),
+        )
+    }
+
+    fn paths(pages: &[Page]) -> Vec<&str> {
+        pages.iter().map(|p| p.path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_pages_in_section() -> Result<()> {
+        let db = setup_database(DatabaseSource::Memory)?;
+        let txn = db.begin_write()?;
+        for page in [
+            page("site/_content/blog/b.md", "2025-02-01T00:00:00", "")?,
+            page(
+                "site/_content/blog/c.md",
+                "2025-03-01T00:00:00",
+                "draft = true",
+            )?,
+            page("site/_content/blog/a.md", "2025-01-01T00:00:00", "")?,
+            page("site/_content/docs/d.md", "2024-01-01T00:00:00", "")?,
+        ] {
+            insert_page(&txn, &page)?;
+        }
+        txn.commit()?;
+
+        assert_eq!(
+            paths(&get_pages_in_section(&db, "blog", false)?),
+            ["site/_content/blog/a.md", "site/_content/blog/b.md"]
+        );
+        assert_eq!(
+            paths(&get_pages_in_section(&db, "blog", true)?),
+            [
+                "site/_content/blog/a.md",
+                "site/_content/blog/b.md",
+                "site/_content/blog/c.md"
+            ]
+        );
+        assert!(get_pages_in_section(&db, "missing", true)?.is_empty());
+
+        // Pages that changed date, moved, or were removed are only listed where they are now.
+        let txn = db.begin_write()?;
+        insert_page(
+            &txn,
+            &page("site/_content/blog/b.md", "2024-12-01T00:00:00", "")?,
+        )?;
+        remove_page(&txn, "site/_content/docs/d.md")?;
+        txn.commit()?;
+        rename_entry(&db, "site/_content/blog/a.md", "site/_content/blog/A.md")?;
+
+        assert_eq!(
+            paths(&get_pages_in_section(&db, "blog", false)?),
+            ["site/_content/blog/b.md", "site/_content/blog/A.md"]
+        );
+        assert!(get_pages_in_section(&db, "docs", true)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backfill_sections() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("yar.db");
+
+        let db = setup_database(DatabaseSource::File(&file))?;
+        let txn = db.begin_write()?;
+        insert_page(
+            &txn,
+            &page("site/_content/blog/b.md", "2025-02-01T00:00:00", "")?,
+        )?;
+        insert_page(
+            &txn,
+            &page("site/_content/blog/a.md", "2025-01-01T00:00:00", "")?,
+        )?;
+        txn.commit()?;
+        // Like a database from before pages were indexed by section.
+        let txn = db.begin_write()?;
+        txn.delete_multimap_table(PAGE_SECTIONS)?;
+        txn.commit()?;
+        drop(db);
+
+        let db = setup_database(DatabaseSource::File(&file))?;
+        assert_eq!(
+            paths(&get_pages_in_section(&db, "blog", false)?),
+            ["site/_content/blog/a.md", "site/_content/blog/b.md"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_schema() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("yar.db");
+
+        let db = setup_database(DatabaseSource::File(&file))?;
+        let txn = db.begin_write()?;
+        // Rows written by another version, which this one can't decode.
+        txn.open_table(PAGES)?
+            .insert("site/_content/blog/a.md", [0xff; 4].as_slice())?;
+        insert_hash(&txn, "site/_content/blog/a.md", [0; 32])?;
+        insert_setting(&txn, "schema_version", "1")?;
+        insert_setting(&txn, "url", "https://example.com/")?;
+        txn.commit()?;
+        drop(db);
+
+        let db = setup_database(DatabaseSource::File(&file))?;
+        assert!(get_pages(&db, &HashSet::new())?.is_empty());
+        assert!(get_hashes(&db)?.is_empty());
+        assert_eq!(
+            get_setting(&db, "schema_version")?,
+            Some(CACHE_SCHEMA_VERSION.to_string())
+        );
+        assert_eq!(
+            get_setting(&db, "url")?.as_deref(),
+            Some("https://example.com/")
+        );
+
+        Ok(())
+    }
+}
@@ -1,31 +1,77 @@
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{fs, io};
 
 use blake3::Hash;
-use color_eyre::Result;
+use color_eyre::{Result, eyre::bail};
 use crossbeam::channel::bounded;
 use ignore::{WalkBuilder, WalkState};
 use redb::Database;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::database::get_hashes;
+use crate::{config::SiteConfig, database::get_hashes};
 
 /// An enum representing the type an entry can take
 pub enum Typ {
     Markdown,
+    /// A page written in HTML rather than markdown, which is any HTML file in `_content`.
+    HtmlPage,
     Asset,
     Template,
     TemplatePage,
     StaticFile,
 }
 
+impl Typ {
+    /// The type of the entry at the given path.
+    pub fn of(path: &Path, config: &SiteConfig) -> Self {
+        let is_in = |extensions: &[String], ext: &str| extensions.iter().any(|e| e == ext);
+
+        match path.extension().and_then(OsStr::to_str) {
+            Some(ext) if is_in(&config.content_extensions, ext) => Self::Markdown,
+            Some(ext) if is_in(&config.asset_extensions, ext) => Self::Asset,
+            Some("html") => {
+                let in_content = path
+                    .strip_prefix(&config.root)
+                    .unwrap_or(path)
+                    .components()
+                    .next()
+                    .is_some_and(|c| c.as_os_str() == "_content");
+                if in_content {
+                    Self::HtmlPage
+                } else if path
+                    .parent()
+                    .is_some_and(|p| p.file_name().is_some_and(|s| s == "templates"))
+                {
+                    Self::Template
+                } else {
+                    Self::TemplatePage
+                }
+            }
+            _ => Self::StaticFile,
+        }
+    }
+
+    /// Whether entries of this type are built as pages.
+    pub const fn is_page(&self) -> bool {
+        matches!(self, Self::Markdown | Self::HtmlPage)
+    }
+}
+
 /// Any item that is to be processed by the static site generator.
 #[derive(Debug, PartialEq, Eq)]
 pub struct Entry {
     pub path: PathBuf,
+    /// The contents of the file, which are left empty for static files that were
+    /// streamed, since they're copied from their path.
     pub raw_content: Vec<u8>,
     pub hash: Hash,
+    /// The path the entry was cached under, if it was renamed from a path that only
+    /// differs in case or unicode normalization.
+    pub renamed_from: Option<PathBuf>,
 }
 
 impl Entry {
@@ -34,70 +80,446 @@ impl Entry {
             path,
             raw_content,
             hash,
+            renamed_from: None,
         }
     }
 
-    pub fn entry_type(&self) -> Typ {
-        match self.path.extension().and_then(OsStr::to_str) {
-            Some("md") => Typ::Markdown,
-            Some("css" | "scss" | "js") => Typ::Asset,
-            Some("html") => {
-                if self
-                    .path
-                    .parent()
-                    .is_some_and(|p| p.file_name().is_some_and(|s| s == "templates"))
-                {
-                    Typ::Template
-                } else {
-                    Typ::TemplatePage
-                }
-            }
-            _ => Typ::StaticFile,
-        }
+    pub fn entry_type(&self, config: &SiteConfig) -> Typ {
+        Typ::of(&self.path, config)
     }
 }
 
 /// Recursively traverse the files in the given path, read each one, hash it, and
 /// filter out only the ones that have changed or have been newly created since the
-/// last run of yar.
-pub fn discover_entries<P: AsRef<Path>>(db: &Database, path: P) -> Result<Vec<Entry>> {
+/// last run of yar. Entries are sorted by path, so that builds are reproducible.
+///
+/// Symbolic links are followed, so the same file can be found at several paths. It's
+/// only built from one of them, the one with the fewest components, and then the first
+/// alphabetically.
+///
+/// Paths are stored as strings, so files with names that aren't valid unicode can't be
+/// built. Static files with such names are skipped with a warning, and any other file
+/// is an error.
+///
+/// Static files larger than `max_in_memory_file_size` are hashed in chunks, and never
+/// read into memory. Files larger than `max_file_size` are skipped with a warning.
+///
+/// Files that aren't cached under their path, but are under one only differing in case
+/// or unicode normalization that wasn't found, are treated as renamed. See [`find_renames`].
+pub fn discover_entries<P: AsRef<Path>>(
+    db: &Database,
+    path: P,
+    config: &SiteConfig,
+) -> Result<Vec<Entry>> {
     let (tx, rx) = bounded(100);
 
     let hashes = Arc::new(get_hashes(db)?);
 
     let handle = std::thread::spawn(move || rx.into_iter().collect());
 
-    WalkBuilder::new(path).build_parallel().run(|| {
-        let tx = tx.clone();
-        let hashes = hashes.clone();
+    WalkBuilder::new(path)
+        .follow_links(true)
+        .threads(config.jobs.map_or(0, NonZeroUsize::get))
+        .build_parallel()
+        .run(|| {
+            let tx = tx.clone();
+            let hashes = hashes.clone();
 
-        Box::new(move |entry| {
-            let entry = match entry {
-                Ok(e) if e.file_type().is_some_and(|t| t.is_file()) => e,
-                _ => return WalkState::Continue,
-            };
+            Box::new(move |entry| {
+                let entry = match entry {
+                    Ok(e) if e.file_type().is_some_and(|t| t.is_file()) => e,
+                    _ => return WalkState::Continue,
+                };
 
-            let path = entry.into_path();
-            let content = fs::read(&path).expect("Error reading from file.");
+                let path = entry.into_path();
+                if path.to_str().is_none() && matches!(Typ::of(&path, config), Typ::StaticFile) {
+                    println!(
+                        "Warning: skipping {}, since its path isn't valid unicode",
+                        path.display()
+                    );
+                    return WalkState::Continue;
+                }
 
-            let hash = blake3::hash(&content);
+                let Ok(canonical) = path.canonicalize() else {
+                    return WalkState::Continue;
+                };
+                let size = fs::metadata(&path).map_or(0, |m| m.len());
+                if config.max_file_size.is_some_and(|max| size > max) {
+                    println!(
+                        "Warning: skipping {}, since it's larger than `max_file_size` ({size} bytes)",
+                        path.display()
+                    );
+                    return WalkState::Continue;
+                }
 
-            let original_hash = hashes.get(&path);
+                let (content, hash) = if size > config.max_in_memory_file_size
+                    && matches!(Typ::of(&path, config), Typ::StaticFile)
+                {
+                    (vec![], hash_file(&path).expect("Error reading from file."))
+                } else {
+                    let content = fs::read(&path).expect("Error reading from file.");
+                    let hash = blake3::hash(&content);
+                    (content, hash)
+                };
 
-            // Create a new entry to be built if the hash has changed since or is newly created.
-            if original_hash.is_none_or(|h| h != hash.as_bytes()) {
-                tx.send(Entry::new(path, content, hash))
-                    .expect("Error while sending");
-            }
+                let original_hash = hashes.get(&path);
 
-            WalkState::Continue
-        })
-    });
+                // Create a new entry to be built if the hash has changed since or is newly created.
+                let entry = original_hash
+                    .is_none_or(|h| h != hash.as_bytes())
+                    .then(|| Entry::new(path.clone(), content, hash));
+                tx.send(Found {
+                    path,
+                    canonical,
+                    entry,
+                })
+                .expect("Error while sending");
+
+                WalkState::Continue
+            })
+        });
 
     drop(tx);
 
-    let ret: Vec<Entry> = handle
+    let found: Vec<Found> = handle
         .join()
         .map_err(|e| io::Error::other(format!("Collector thread panicked: {e:?}")))?;
+    let walked = found
+        .iter()
+        .map(|f| f.path.clone())
+        .collect::<HashSet<PathBuf>>();
+    let mut ret = deduplicate(found);
+    find_renames(&mut ret, &hashes, &walked);
+
+    let mut invalid = ret
+        .iter()
+        .filter(|e| e.path.to_str().is_none())
+        .map(|e| format!("  {}", e.path.display()))
+        .collect::<Vec<String>>();
+    if !invalid.is_empty() {
+        invalid.sort();
+        bail!(
+            "These files can't be built, since their paths aren't valid unicode:\n{}",
+            invalid.join("\n")
+        );
+    }
+
     Ok(ret)
 }
+
+/// Match entries that aren't cached under their path against cached paths that weren't
+/// found, and only differ from theirs in case or unicode normalization, setting their
+/// `renamed_from`.
+///
+/// Case-insensitive filesystems, like the ones macOS uses by default, only report a file
+/// renamed from `About.md` to `about.md` under its new name, and normalize names to
+/// NFC or NFD depending on the filesystem, so the cached path is never seen again.
+pub fn find_renames(
+    entries: &mut [Entry],
+    hashes: &HashMap<PathBuf, [u8; 32]>,
+    walked: &HashSet<PathBuf>,
+) {
+    let mut variants = hashes
+        .keys()
+        .filter(|p| !walked.contains(*p))
+        .filter_map(|p| Some((variant_key(p)?, p)))
+        .collect::<HashMap<String, &PathBuf>>();
+
+    for entry in entries.iter_mut() {
+        if hashes.contains_key(&entry.path) {
+            continue;
+        }
+        if let Some(previous) = variant_key(&entry.path).and_then(|k| variants.remove(&k)) {
+            entry.renamed_from = Some(previous.clone());
+        }
+    }
+}
+
+/// The form of a path that variants of it in case or unicode normalization share.
+pub fn variant_key(path: &Path) -> Option<String> {
+    Some(path.to_str()?.nfc().collect::<String>().to_lowercase())
+}
+
+// Hash a file in chunks, without reading all of it into memory.
+fn hash_file(path: &Path) -> io::Result<Hash> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(fs::File::open(path)?)?;
+    Ok(hasher.finalize())
+}
+
+// A file found while walking the site, with its entry if it has changed.
+struct Found {
+    path: PathBuf,
+    canonical: PathBuf,
+    entry: Option<Entry>,
+}
+
+// Keep one path for each file, and the entries of the changed files, sorted by path.
+//
+// Whether a file changed has to be checked for the path that's kept, since the other
+// paths it was found at aren't in the cache.
+fn deduplicate(mut found: Vec<Found>) -> Vec<Entry> {
+    found.sort_by(|a, b| {
+        (a.path.components().count(), &a.path).cmp(&(b.path.components().count(), &b.path))
+    });
+
+    let mut kept: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut entries = vec![];
+    for f in found {
+        if let Some(path) = kept.get(&f.canonical) {
+            println!(
+                "Found {} more than once, building it from {}",
+                f.path.display(),
+                path.display()
+            );
+            continue;
+        }
+
+        kept.insert(f.canonical, f.path);
+        entries.extend(f.entry);
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Site;
+    use crate::database::{DatabaseSource, setup_database};
+    use crate::test_utils::{make_page, make_site};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_unicode_paths() -> Result<()> {
+        use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+        let root = tempfile::tempdir()?;
+        let db = setup_database(DatabaseSource::Memory)?;
+        let config = SiteConfig::default();
+
+        let name = |ext: &str| {
+            let mut name = b"archive-\xff".to_vec();
+            name.extend_from_slice(ext.as_bytes());
+            PathBuf::from(OsString::from_vec(name))
+        };
+
+        fs::write(root.path().join("fine.txt"), "fine")?;
+        fs::write(root.path().join(name(".png")), "image")?;
+        let entries = discover_entries(&db, root.path(), &config)?;
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].path.ends_with("fine.txt"));
+
+        fs::write(root.path().join(name(".md")), "page")?;
+        let err = discover_entries(&db, root.path(), &config).unwrap_err();
+        assert!(err.to_string().contains("archive-\u{FFFD}.md"));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_duplicate_paths() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let root = tempfile::tempdir()?;
+        let shared = tempfile::tempdir()?;
+        let db = setup_database(DatabaseSource::Memory)?;
+        let config = SiteConfig::default();
+
+        fs::create_dir_all(root.path().join("_content"))?;
+        fs::write(root.path().join("_content/b.md"), "b")?;
+        fs::write(root.path().join("_content/a.md"), "a")?;
+        fs::write(shared.path().join("c.txt"), "c")?;
+
+        // A link back into the root, and two directories linking to the same place.
+        symlink(root.path(), root.path().join("_content/loop"))?;
+        symlink(shared.path(), root.path().join("one"))?;
+        symlink(shared.path(), root.path().join("two"))?;
+
+        let entries = discover_entries(&db, root.path(), &config)?;
+        let paths = entries
+            .iter()
+            .map(|e| e.path.strip_prefix(root.path()))
+            .collect::<Result<Vec<&Path>, _>>()?;
+        assert_eq!(
+            paths,
+            [
+                Path::new("_content/a.md"),
+                Path::new("_content/b.md"),
+                Path::new("one/c.txt"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_renames() -> Result<()> {
+        use crate::database::insert_hash;
+
+        let root = tempfile::tempdir()?;
+        let db = setup_database(DatabaseSource::Memory)?;
+        let config = SiteConfig::default();
+
+        // The cache is seeded with the old names, since whether both can exist at once
+        // depends on the filesystem.
+        let txn = db.begin_write()?;
+        insert_hash(&txn, root.path().join("About.md"), [0; 32])?;
+        insert_hash(&txn, root.path().join("cafe\u{301}.md"), [0; 32])?;
+        insert_hash(&txn, root.path().join("Gone.md"), [0; 32])?;
+        txn.commit()?;
+
+        fs::write(root.path().join("about.md"), "about")?;
+        fs::write(root.path().join("caf\u{e9}.md"), "cafe")?;
+        fs::write(root.path().join("new.md"), "new")?;
+
+        let entries = discover_entries(&db, root.path(), &config)?;
+        let renames = entries
+            .iter()
+            .map(|e| {
+                let from = e
+                    .renamed_from
+                    .as_deref()
+                    .map(|p| p.strip_prefix(root.path()));
+                Ok((e.path.strip_prefix(root.path())?, from.transpose()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            renames,
+            [
+                (Path::new("about.md"), Some(Path::new("About.md"))),
+                (Path::new("caf\u{e9}.md"), Some(Path::new("cafe\u{301}.md"))),
+                (Path::new("new.md"), None),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_static_files() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let db = setup_database(DatabaseSource::Memory)?;
+        let config = SiteConfig {
+            max_in_memory_file_size: 1_000_000,
+            max_file_size: Some(8_000_000),
+            ..SiteConfig::default()
+        };
+
+        let video = b"frame".repeat(800_000);
+        fs::write(root.path().join("video.mp4"), &video)?;
+        fs::write(root.path().join("huge.mp4"), vec![0; 9_000_000])?;
+        fs::write(root.path().join("small.txt"), "small")?;
+
+        let entries = discover_entries(&db, root.path(), &config)?;
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].path, root.path().join("small.txt"));
+        assert_eq!(entries[0].raw_content, b"small");
+
+        // Large files are streamed, so their contents aren't kept around.
+        assert_eq!(entries[1].path, root.path().join("video.mp4"));
+        assert!(entries[1].raw_content.is_empty());
+        assert_eq!(entries[1].hash, blake3::hash(&video));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_extensions() -> Result<()> {
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                (
+                    "_content/blog/imported.markdown",
+                    &make_page("Imported", "post.html"),
+                ),
+                ("assets/app.mjs", "export const a = 1;"),
+                ("assets/theme.sass", "body\n  color: red\n"),
+            ],
+            |config| {
+                config
+                    .site
+                    .asset_extensions
+                    .extend(["mjs".to_string(), "sass".to_string()]);
+            },
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let public = out.path().join("public");
+        assert!(
+            fs::read_to_string(public.join("blog/Imported/index.html"))?
+                .contains("Hello from Imported")
+        );
+        assert!(!public.join("blog/imported.markdown").exists());
+        assert_eq!(site.library.assets.len(), 2);
+        assert!(fs::read_to_string(public.join("assets/theme.css"))?.contains("color:red"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_content_extension() -> Result<()> {
+        let (_root, _out, mut site) =
+            make_site(&[("_content/notes.markdown", "not a page")], |config| {
+                config.site.content_extensions = vec!["markdown".to_string()];
+            })?;
+
+        let err = site.load().unwrap_err();
+        assert!(err.to_string().contains("notes.markdown as a page"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_content_extensions() -> Result<()> {
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/notes.mdx", &make_page("Notes", "post.html")),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let mut config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        let public = out.path().join("public");
+        let copied = public.join("blog/notes.mdx");
+        let rendered = public.join("blog/Notes/index.html");
+        assert!(copied.exists());
+
+        // The unchanged file is rendered as a page, and its copy is removed.
+        config.site.content_extensions.push("mdx".to_string());
+        let mut site = Site::new(
+            setup_database(DatabaseSource::File(&db_path))?,
+            config.clone(),
+        )?;
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        assert!(!copied.exists());
+        assert!(fs::read_to_string(&rendered)?.contains("Hello from Notes"));
+        assert_eq!(site.removed_outputs(), [PathBuf::from("blog/notes.mdx")]);
+        drop(site);
+
+        // And back again, leaving no page behind.
+        config.site.content_extensions.pop();
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+        assert!(copied.exists());
+        assert!(!rendered.exists());
+        assert!(site.library.pages.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use url::Url;
+
+/// What generated a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GeneratedKind {
+    /// A page listing an author's posts.
+    Author,
+    /// A chunk of a paginated template page.
+    Pagination,
+}
+
+/// A page written by a build that doesn't have a source file of its own.
+///
+/// These are listed in the sitemap, and can be linked to with `get_url`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GeneratedPage {
+    pub out_path: PathBuf,
+    pub permalink: Url,
+    pub kind: GeneratedKind,
+    /// When the pages listed on this page were last updated.
+    pub lastmod: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::{config::AuthorMeta, test_utils::make_site};
+
+    #[test]
+    fn test_generated_pages() -> Result<()> {
+        let archive =
+            "---\ntitle = \"Archive\"\n\n[pagination]\nfrom = \"pages\"\nevery = 1\n---\n";
+        let page = r#"---
+title = "Hello"
+tags = []
+authors = ["ada"]
+updated = "2025-03-01T00:00:00"
+---
+"#;
+        let (_root, out, mut site) = make_site(
+            &[
+                (
+                    "templates/post.html",
+                    r#"{{ get_url("authors/ada") }} {{ get_url("archive/0") }}"#,
+                ),
+                ("templates/author.html", "{{ author.name }}"),
+                ("archive/index.html", archive),
+                ("_content/blog/hello.md", page),
+            ],
+            |c| {
+                c.site.strict_urls = true;
+                c.site.authors_meta.insert(
+                    "ada".to_string(),
+                    AuthorMeta {
+                        name: "Ada Lovelace".to_string(),
+                        bio: None,
+                        url: None,
+                    },
+                );
+            },
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let generated = site
+            .generated_pages()
+            .iter()
+            .map(|g| (g.permalink.as_str(), g.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            generated,
+            [
+                ("http://0.0.0.0:8000/archive/0/", GeneratedKind::Pagination),
+                ("http://0.0.0.0:8000/authors/ada/", GeneratedKind::Author),
+            ]
+        );
+
+        // Links to generated pages pass the strict link checker.
+        let public = out.path().join("public");
+        let hello = fs::read_to_string(public.join("blog/Hello/index.html"))?;
+        assert_eq!(
+            hello,
+            "http://0.0.0.0:8000/authors/ada/ http://0.0.0.0:8000/archive/0/"
+        );
+
+        let sitemap = fs::read_to_string(public.join("sitemap.xml"))?;
+        let sitemap = roxmltree::Document::parse(&sitemap)?;
+        let urls = sitemap
+            .descendants()
+            .filter(|n| n.has_tag_name("url"))
+            .map(|n| {
+                n.children()
+                    .filter(roxmltree::Node::is_element)
+                    .map(|c| c.text().unwrap_or_default())
+                    .collect::<Vec<&str>>()
+                    .join(" ")
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(
+            urls,
+            [
+                "http://0.0.0.0:8000/blog/Hello 2025-03-01T00:00:00Z",
+                "http://0.0.0.0:8000/archive/0/ 2025-03-01T00:00:00Z",
+                "http://0.0.0.0:8000/authors/ada/ 2025-03-01T00:00:00Z",
+            ]
+        );
+
+        Ok(())
+    }
+}
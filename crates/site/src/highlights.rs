@@ -0,0 +1,257 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use yar_markdown::{HighlightCache, HighlightMode};
+
+/// How many builds highlighting code can go by without using a highlighted code block
+/// before it's forgotten.
+const UNUSED_BUILDS: u64 = 30;
+
+/// A code block as it was highlighted by a previous build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedHighlight {
+    pub html: String,
+    /// The last build that used this code block.
+    pub last_used: u64,
+}
+
+/// The code highlighted by a site's builds, keyed by a hash of the code, its language,
+/// and the mode it was highlighted with.
+///
+/// Builds are counted from the ones that highlighted code, so that code blocks no build
+/// has used for a while can be forgotten.
+#[derive(Debug, Clone, Default)]
+pub struct Highlights(Arc<Mutex<HighlightsInner>>);
+
+#[derive(Debug, Default)]
+struct HighlightsInner {
+    build: u64,
+    cached: HashMap<String, CachedHighlight>,
+    // The code blocks looked up or highlighted by this build.
+    used: HashSet<String>,
+}
+
+impl Highlights {
+    /// Replace the code blocks highlighted by previous builds, starting a build after
+    /// the last one that used any.
+    pub fn set_cached(&self, cached: HashMap<String, CachedHighlight>) {
+        let mut inner = self.0.lock().expect("Highlights lock poisoned");
+        inner.build = cached.values().map(|c| c.last_used).max().unwrap_or(0) + 1;
+        inner.cached = cached;
+        inner.used.clear();
+    }
+
+    /// The code blocks used by this build, to be stored with it as their last use.
+    pub fn used(&self) -> Vec<(String, CachedHighlight)> {
+        let inner = self.0.lock().expect("Highlights lock poisoned");
+        inner
+            .used
+            .iter()
+            .filter_map(|key| {
+                let cached = inner.cached.get(key)?;
+                Some((
+                    key.clone(),
+                    CachedHighlight {
+                        html: cached.html.clone(),
+                        last_used: inner.build,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// The code blocks no build has used in the last `UNUSED_BUILDS` builds.
+    pub fn stale(&self) -> Vec<String> {
+        let inner = self.0.lock().expect("Highlights lock poisoned");
+        inner
+            .cached
+            .iter()
+            .filter(|(key, cached)| {
+                !inner.used.contains(*key) && cached.last_used + UNUSED_BUILDS <= inner.build
+            })
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+
+impl HighlightCache for Highlights {
+    fn get(&self, lang: &str, mode: HighlightMode, code: &str) -> Option<String> {
+        let key = highlight_key(lang, mode, code);
+        let mut inner = self.0.lock().expect("Highlights lock poisoned");
+        let html = inner.cached.get(&key)?.html.clone();
+        inner.used.insert(key);
+        drop(inner);
+
+        Some(html)
+    }
+
+    fn insert(&self, lang: &str, mode: HighlightMode, code: &str, html: &str) {
+        let key = highlight_key(lang, mode, code);
+        let mut inner = self.0.lock().expect("Highlights lock poisoned");
+        let highlight = CachedHighlight {
+            html: html.to_owned(),
+            last_used: inner.build,
+        };
+        inner.cached.insert(key.clone(), highlight);
+        inner.used.insert(key);
+    }
+}
+
+// Hash each part with its length, so that a language can't run into the code.
+fn highlight_key(lang: &str, mode: HighlightMode, code: &str) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for part in [format!("{mode:?}").as_str(), lang, code] {
+        hasher.update(&(part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+
+    hasher.finalize().to_hex().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::{
+        Site,
+        database::{DatabaseSource, get_highlights, insert_highlight, setup_database},
+        test_utils::make_site,
+    };
+
+    #[test]
+    fn test_highlights() {
+        let highlights = Highlights::default();
+        highlights.set_cached(HashMap::new());
+        assert_eq!(
+            highlights.get("rust", HighlightMode::Elements, "fn a() {}"),
+            None
+        );
+
+        highlights.insert(
+            "rust",
+            HighlightMode::Elements,
+            "fn a() {}",
+            "<a-k>fn</a-k>",
+        );
+        assert_eq!(
+            highlights.get("rust", HighlightMode::Elements, "fn a() {}"),
+            Some("<a-k>fn</a-k>".to_owned())
+        );
+        assert_eq!(
+            highlights.get("rust", HighlightMode::Classes, "fn a() {}"),
+            None
+        );
+        assert_eq!(
+            highlights.get("rs", HighlightMode::Elements, "fn a() {}"),
+            None
+        );
+
+        let used = highlights.used();
+        assert_eq!(used.len(), 1);
+        assert_eq!(used[0].1.last_used, 1);
+        assert!(highlights.stale().is_empty());
+    }
+
+    #[test]
+    fn test_stale_highlights() {
+        let cached = |last_used| CachedHighlight {
+            html: String::new(),
+            last_used,
+        };
+        let highlights = Highlights::default();
+        highlights.set_cached(HashMap::from([
+            ("old".to_owned(), cached(1)),
+            ("recent".to_owned(), cached(10)),
+            ("latest".to_owned(), cached(UNUSED_BUILDS + 1)),
+        ]));
+
+        assert_eq!(highlights.stale(), vec!["old".to_owned()]);
+    }
+
+    #[test]
+    fn test_highlight_classes() -> Result<()> {
+        let page = "---\ntitle = \"Hello\"\ntags = []\n---\n\n```rust\nfn main() {}\n```\n";
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", page),
+            ],
+            |c| c.site.markdown.highlight_mode = HighlightMode::Classes,
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        assert!(rendered.contains("<span class=keyword>fn</span>"));
+        assert!(!rendered.contains("style="));
+        let css = fs::read_to_string(out.path().join("public/styles/_syntax.css"))?;
+        assert!(css.contains(".keyword {"));
+        assert!(!css.contains("a-k "));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_highlight_cache() -> Result<()> {
+        let page =
+            "---\ntitle = \"Hello\"\ntags = []\n---\n\nFirst\n\n```rust\nfn main() {}\n```\n";
+        let (root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", page),
+            ],
+            |_| {},
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let mut config = site.config.clone();
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        // Swap what the code was highlighted to, to tell when it's used.
+        let db = setup_database(DatabaseSource::File(&db_path))?;
+        let highlights = get_highlights(&db)?;
+        assert_eq!(highlights.len(), 1);
+        let (key, highlight) = highlights.into_iter().next().unwrap();
+        assert_eq!(highlight.last_used, 1);
+        let txn = db.begin_write()?;
+        let cached = CachedHighlight {
+            html: "cached code".to_owned(),
+            last_used: 1,
+        };
+        insert_highlight(&txn, &key, &cached)?;
+        txn.commit()?;
+
+        // Changing the page's prose looks the unchanged code up.
+        let hello = root.path().join("_content/blog/hello.md");
+        fs::write(&hello, page.replace("First", "Second"))?;
+        let mut site = Site::new(db, config.clone())?;
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        assert!(rendered.contains("Second"));
+        assert!(rendered.contains("cached code"));
+        assert_eq!(get_highlights(&site.db)?[&key].last_used, 2);
+        drop(site);
+
+        // Highlighting with another mode doesn't.
+        config.site.markdown.highlight_mode = HighlightMode::Classes;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        assert!(!rendered.contains("cached code"));
+        assert!(rendered.contains("<span class=keyword>fn</span>"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    env,
+    io::Read,
+    process::{Child, Command, Output, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{OptionExt, WrapErr, bail},
+};
+
+use crate::config::PostHook;
+
+/// How often a hook with a timeout is checked on.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Run a hook's command in its working directory with its environment variables, and
+/// collect what it printed.
+///
+/// A hook running for longer than its timeout is killed along with anything it started,
+/// and is an error.
+pub fn run(hook: &PostHook) -> Result<Output> {
+    let mut split = hook.cmd.split_whitespace();
+    let cmd = split
+        .next()
+        .ok_or_eyre(format!("Post hook command {} not valid.", hook.cmd))?;
+
+    let mut command = Command::new(cmd);
+    command
+        .args(split)
+        .envs(hook.env.iter().map(|(k, v)| (k, expand_env(v))))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Relative directories are relative to yar's working directory, which is the
+    // project's root.
+    if let Some(cwd) = &hook.cwd {
+        command.current_dir(cwd);
+    }
+    // The hook gets a process group of its own, so that it can be killed along with
+    // whatever it started.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+    let mut child = command
+        .spawn()
+        .wrap_err_with(|| format!("Couldn't run post hook {}", hook.cmd))?;
+    // The output is read while the hook runs, so that it never blocks on a full pipe.
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+
+    let status = if let Some(secs) = hook.timeout_secs {
+        let deadline = Instant::now() + Duration::from_secs(secs);
+        loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                kill(&mut child)?;
+                bail!(
+                    "Post hook {} timed out after {secs} seconds and was killed",
+                    hook.cmd
+                );
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    } else {
+        child.wait()?
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_to_end<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut out = vec![];
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut out).ok();
+        }
+        out
+    })
+}
+
+#[cfg(unix)]
+fn kill(child: &mut Child) -> Result<()> {
+    // The hook's process group has the same id as the hook.
+    let group = libc::pid_t::try_from(child.id())?;
+    // SAFETY: `kill` only sends a signal, and the group was made for the hook.
+    unsafe {
+        libc::kill(-group, libc::SIGKILL);
+    }
+    child.wait()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) -> Result<()> {
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
+
+// Replace each `${VAR}` in a value with the variable from yar's environment, or nothing
+// if it isn't set.
+fn expand_env(value: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&env::var(&rest[start + 2..start + 2 + len]).unwrap_or_default());
+        rest = &rest[start + 3 + len..];
+    }
+    expanded.push_str(rest);
+
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, fs, path::PathBuf, sync::Arc};
+
+    use minijinja::Value;
+
+    use super::*;
+    use crate::{
+        Site,
+        config::HookOutput,
+        database::{DatabaseSource, setup_database},
+        test_utils::make_site,
+    };
+
+    fn hook(cmd: String) -> PostHook {
+        PostHook {
+            cmd,
+            help: None,
+            outputs: vec![],
+            cwd: None,
+            env: BTreeMap::new(),
+            timeout_secs: None,
+        }
+    }
+
+    #[test]
+    fn test_expand_env() {
+        let path = env::var("PATH").unwrap();
+        assert_eq!(expand_env("${PATH}"), path);
+        assert_eq!(expand_env("a:${PATH}:b"), format!("a:{path}:b"));
+        assert_eq!(expand_env("${YAR_UNSET_VARIABLE}!"), "!");
+        assert_eq!(expand_env("$PATH ${PATH"), "$PATH ${PATH");
+    }
+
+    #[test]
+    fn test_cwd_and_env() -> Result<()> {
+        // Relative directories are relative to the working directory.
+        let dir = tempfile::Builder::new()
+            .prefix("test-hook")
+            .tempdir_in(".")?;
+        let script = dir.path().join("hook.sh");
+        fs::write(&script, "pwd\necho \"$GREETING\"\n")?;
+
+        let mut hook = hook(format!("sh {}", fs::canonicalize(&script)?.display()));
+        hook.cwd = Some(PathBuf::from(dir.path().file_name().unwrap()));
+        hook.env
+            .insert("GREETING".to_owned(), "hello ${PATH}".to_owned());
+        let output = run(&hook)?;
+
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout)?,
+            format!(
+                "{}\nhello {}\n",
+                fs::canonicalize(dir.path())?.display(),
+                env::var("PATH")?
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeout() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let script = dir.path().join("hook.sh");
+        // The hook waits on a command it started, which is killed with it.
+        fs::write(&script, "sleep 30 &\nwait\n")?;
+
+        let mut hook = hook(format!("sh {}", script.display()));
+        hook.timeout_secs = Some(1);
+        let start = Instant::now();
+        let error = run(&hook).unwrap_err();
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Post hook sh {} timed out after 1 seconds and was killed",
+                script.display()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_two_pass() -> Result<()> {
+        let page = |title: &str, dependencies: &str| {
+            format!(
+                "---\ntitle = \"{title}\"\ntags = []\ndependencies = [{dependencies}]\n---\n\nHello\n"
+            )
+        };
+        let (root, out, site) = make_site(
+            &[
+                ("templates/post.html", "integrity: {{ sri[\"main.css\"] }}"),
+                (
+                    "integrity/index.html",
+                    "---\ntitle = \"Integrity\"\ndependencies = [\"sri\"]\n---\n{{ sri[\"main.css\"] }}",
+                ),
+                ("_content/blog/pinned.md", &page("Pinned", "\"sri\"")),
+                ("_content/blog/other.md", &page("Other", "")),
+            ],
+            |_| (),
+        )?;
+
+        // A fake hook, standing in for one that hashes the output for subresource integrity.
+        let sri = out.path().join("sri.json");
+        let script = root.path().join("hook.sh");
+        fs::write(
+            &script,
+            format!(
+                "printf '{{\"main.css\": \"sha384-abc\"}}' > {}",
+                sri.display()
+            ),
+        )?;
+        let mut config = site.config;
+        config.site.two_pass = true;
+        config.hooks.post.push(PostHook {
+            cmd: format!("sh {}", script.display()),
+            help: None,
+            outputs: vec![HookOutput {
+                global: "sri".to_string(),
+                path: sri,
+            }],
+            cwd: None,
+            env: BTreeMap::new(),
+            timeout_secs: None,
+        });
+
+        let mut site = Site::new(setup_database(DatabaseSource::Memory)?, config)?;
+        site.load()?;
+        site.render()?;
+        let public = out.path().join("public");
+        assert_eq!(fs::read_to_string(public.join("integrity/index.html"))?, "");
+
+        site.run_post_hooks()?;
+        site.render_second_pass()?;
+        assert_eq!(
+            fs::read_to_string(public.join("integrity/index.html"))?,
+            "sha384-abc"
+        );
+        assert!(
+            fs::read_to_string(public.join("blog/Pinned/index.html"))?
+                .contains("integrity: sha384-abc")
+        );
+        // Pages that don't depend on the hook output aren't rendered again.
+        assert!(!fs::read_to_string(public.join("blog/Other/index.html"))?.contains("sha384-abc"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_globals_refreshed_on_load() -> Result<()> {
+        let post = |tags: &str| format!("---\ntitle = \"Post\"\ntags = [{tags}]\n---\n\nHello\n");
+        let (root, _out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/a.md", &post(r#""rust""#)),
+                ("_content/blog/b.md", &post(r#""rust", "go""#)),
+            ],
+            |config| {
+                config.hooks.post.push(PostHook {
+                    cmd: "true".to_string(),
+                    help: None,
+                    outputs: vec![HookOutput {
+                        global: "sri".to_string(),
+                        path: PathBuf::from("sri.json"),
+                    }],
+                    cwd: None,
+                    env: BTreeMap::new(),
+                    timeout_secs: None,
+                });
+            },
+        )?;
+        let stats = |site: &Site| -> Result<(usize, usize)> {
+            let stats = site.globals()["stats"].clone();
+            Ok((
+                usize::try_from(stats.get_attr("pages")?)?,
+                usize::try_from(stats.get_attr("tags")?)?,
+            ))
+        };
+
+        site.load()?;
+        assert_eq!(
+            site.globals().keys().collect::<Vec<_>>(),
+            ["authors", "site", "sri", "stats"]
+        );
+        assert_eq!(stats(&site)?, (2, 2));
+
+        // Like the outputs a previous build's hooks loaded.
+        Arc::make_mut(&mut site.environment).add_global("sri", Value::from("sha384-abc"));
+        fs::write(root.path().join("_content/blog/b.md"), post(r#""rust""#))?;
+        site.load()?;
+        assert_eq!(
+            site.globals().keys().collect::<Vec<_>>(),
+            ["authors", "site", "sri", "stats"]
+        );
+        assert_eq!(stats(&site)?, (2, 1));
+        assert_eq!(site.globals()["sri"].len(), Some(0));
+
+        Ok(())
+    }
+}
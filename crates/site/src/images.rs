@@ -0,0 +1,301 @@
+use std::{
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+};
+
+use color_eyre::Result;
+use image::{ImageFormat, codecs::jpeg::JpegEncoder, metadata::Orientation};
+use img_parts::{Bytes, DynImage, ImageEXIF};
+use percent_encoding::percent_decode_str;
+use url::Url;
+use yar_markdown::ImageResolver;
+
+/// The extensions of the images whose metadata is checked, and stripped if configured.
+const METADATA_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// The quality JPEGs are encoded with when they're rotated.
+const JPEG_QUALITY: u8 = 90;
+
+/// Finds the images pages show in the site's root, so that they're rendered with their
+/// size.
+///
+/// Paths starting with `/`, and URLs under the site's URL, are relative to the site root,
+/// like the paths files are copied to in the output. Relative paths are relative to the
+/// page's directory. Anything else is remote.
+pub struct SiteImages {
+    root: PathBuf,
+    url: Url,
+}
+
+impl SiteImages {
+    pub const fn new(root: PathBuf, url: Url) -> Self {
+        Self { root, url }
+    }
+
+    // The file an image's src points to, if it's in the site.
+    fn path(&self, src: &str, page: Option<&Path>) -> Option<PathBuf> {
+        let src = src.split(['?', '#']).next()?;
+        let (dir, relative) = if let Some(rest) = src.strip_prefix(self.url.as_str()) {
+            (self.root.as_path(), rest)
+        } else if src.starts_with("//") || Url::parse(src).is_ok() {
+            return None;
+        } else if src.starts_with('/') {
+            // Sites hosted under a path have it in front of every absolute path.
+            (self.root.as_path(), src.strip_prefix(self.url.path())?)
+        } else {
+            (page?.parent()?, src)
+        };
+
+        let relative = PathBuf::from(percent_decode_str(relative).decode_utf8().ok()?.as_ref());
+        relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            .then(|| dir.join(relative))
+    }
+}
+
+impl ImageResolver for SiteImages {
+    fn dimensions(&self, src: &str, page: Option<&Path>) -> Option<(u32, u32)> {
+        image::image_dimensions(self.path(src, page)?).ok()
+    }
+}
+
+/// Whether the file at `path` is an image whose metadata is checked.
+pub fn has_metadata_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| METADATA_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Whether an image's EXIF metadata has GPS coordinates.
+pub fn has_gps(source: &Bytes) -> bool {
+    let Some(raw) = raw_exif(source) else {
+        return false;
+    };
+
+    exif::Reader::new()
+        .read_raw(raw.to_vec())
+        .is_ok_and(|exif| exif.fields().any(|f| f.tag.context() == exif::Context::Gps))
+}
+
+/// Remove the EXIF metadata from a JPEG, PNG, or WebP image, or return `None` if it has
+/// none.
+///
+/// Only the metadata is removed, unless it rotates or flips the image, in which case the
+/// image is encoded again with the rotation applied, so that it's still shown the same
+/// way.
+pub fn strip_metadata(source: &Bytes) -> Result<Option<Vec<u8>>> {
+    let Some(mut image) = DynImage::from_bytes(source.clone())? else {
+        return Ok(None);
+    };
+    let Some(exif) = image.exif() else {
+        return Ok(None);
+    };
+
+    let orientation = Orientation::from_exif_chunk(&exif).unwrap_or(Orientation::NoTransforms);
+    let mut out = vec![];
+    if orientation == Orientation::NoTransforms {
+        image.set_exif(None);
+        image.encoder().write_to(&mut out)?;
+        return Ok(Some(out));
+    }
+
+    let format = image::guess_format(source)?;
+    let mut decoded = image::load_from_memory_with_format(source, format)?;
+    decoded.apply_orientation(orientation);
+    if format == ImageFormat::Jpeg {
+        decoded.write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))?;
+    } else {
+        decoded.write_to(&mut Cursor::new(&mut out), format)?;
+    }
+
+    Ok(Some(out))
+}
+
+// The raw EXIF metadata of a JPEG, PNG, or WebP image.
+fn raw_exif(source: &Bytes) -> Option<Bytes> {
+    DynImage::from_bytes(source.clone()).ok()??.exif()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+    use img_parts::jpeg::Jpeg;
+
+    use super::*;
+    use crate::{
+        static_file::{Metadata, StaticFile},
+        thumbnails::{Fit, Thumbnail},
+    };
+
+    // EXIF metadata with the given orientation and a GPS latitude.
+    fn exif_with_gps(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8_u32.to_le_bytes());
+        // The first IFD, with the orientation and a pointer to the GPS IFD after it.
+        tiff.extend(2_u16.to_le_bytes());
+        tiff.extend([0x12, 0x01, 3, 0]);
+        tiff.extend(1_u32.to_le_bytes());
+        tiff.extend(orientation.to_le_bytes());
+        tiff.extend([0, 0]);
+        tiff.extend([0x25, 0x88, 4, 0]);
+        tiff.extend(1_u32.to_le_bytes());
+        tiff.extend(38_u32.to_le_bytes());
+        tiff.extend(0_u32.to_le_bytes());
+        // The GPS IFD, with the latitude's reference.
+        tiff.extend(1_u16.to_le_bytes());
+        tiff.extend([0x01, 0x00, 2, 0]);
+        tiff.extend(2_u32.to_le_bytes());
+        tiff.extend(*b"N\0\0\0");
+        tiff.extend(0_u32.to_le_bytes());
+
+        tiff
+    }
+
+    // A JPEG twice as wide as it's tall, red on the left and blue on the right, with
+    // EXIF metadata if there's an orientation.
+    fn photo(orientation: Option<u16>) -> Result<Bytes> {
+        let image = RgbImage::from_fn(16, 8, |x, _| {
+            if x < 8 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            }
+        });
+        let mut out = vec![];
+        DynamicImage::ImageRgb8(image).write_to(&mut Cursor::new(&mut out), ImageFormat::Jpeg)?;
+        let mut jpeg = Jpeg::from_bytes(out.into())?;
+        if let Some(orientation) = orientation {
+            jpeg.set_exif(Some(exif_with_gps(orientation).into()));
+        }
+
+        Ok(jpeg.encoder().bytes())
+    }
+
+    fn is_red(image: &DynamicImage, x: u32, y: u32) -> bool {
+        let [r, _, b, _] = image.get_pixel(x, y).0;
+        r > 200 && b < 60
+    }
+
+    #[test]
+    fn test_strip_metadata() -> Result<()> {
+        // A photo rotated a quarter turn clockwise by its metadata is rotated itself, so
+        // it's shown the same way without it.
+        let rotated = photo(Some(6))?;
+        assert!(has_gps(&rotated));
+        let stripped = Bytes::from(strip_metadata(&rotated)?.unwrap());
+        assert_eq!(raw_exif(&stripped), None);
+        assert!(!has_gps(&stripped));
+        let image = image::load_from_memory(&stripped)?;
+        assert_eq!(image.dimensions(), (8, 16));
+        assert!(is_red(&image, 4, 2));
+        assert!(!is_red(&image, 4, 13));
+
+        // Upright photos only lose their metadata.
+        assert_eq!(
+            strip_metadata(&photo(Some(1))?)?,
+            Some(photo(None)?.to_vec())
+        );
+
+        // Images without metadata, and files that aren't images, are left alone.
+        assert_eq!(strip_metadata(&photo(None)?)?, None);
+        assert_eq!(strip_metadata(&Bytes::from_static(b"not an image"))?, None);
+        assert!(!has_gps(&photo(None)?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_static_file_metadata() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let out = root.path().join("public");
+        fs::create_dir_all(root.path().join("static"))?;
+        let photo_path = root.path().join("static/photo.JPG");
+        let clean_path = root.path().join("static/clean.jpg");
+        let rotated = photo(Some(6))?;
+        let clean = photo(None)?;
+        fs::write(&photo_path, &rotated)?;
+        fs::write(&clean_path, &clean)?;
+
+        let url = Url::parse("https://example.com/")?;
+        let static_file =
+            |path: &Path| StaticFile::new(path, blake3::hash(b""), &out, root.path(), &url);
+        let (photo, clean_photo) = (static_file(&photo_path)?, static_file(&clean_path)?);
+
+        // Without stripping, photos with GPS coordinates are copied as they are.
+        assert_eq!(photo.render(false)?, Metadata::Gps);
+        assert_eq!(fs::read(&photo.out_path)?, rotated);
+
+        assert_eq!(photo.render(true)?, Metadata::Stripped);
+        assert!(!has_gps(&Bytes::from(fs::read(&photo.out_path)?)));
+        assert_eq!(image::open(&photo.out_path)?.dimensions(), (8, 16));
+        // The source is never changed.
+        assert_eq!(fs::read(&photo_path)?, rotated);
+
+        assert_eq!(clean_photo.render(true)?, Metadata::Kept);
+        assert_eq!(fs::read(&clean_photo.out_path)?, clean);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_thumbnail_orientation() -> Result<()> {
+        let out = tempfile::tempdir()?;
+        let thumbnail = Thumbnail {
+            source: PathBuf::from("static/photo.jpg"),
+            source_hash: blake3::hash(b""),
+            width: 4,
+            height: None,
+            fit: Fit::Contain,
+        };
+        thumbnail.generate(&photo(Some(6))?, out.path())?;
+
+        let image = image::open(out.path().join(thumbnail.out_path()))?;
+        assert_eq!(image.dimensions(), (4, 8));
+        assert!(is_red(&image, 2, 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dimensions() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::create_dir_all(root.path().join("static/my images"))?;
+        fs::create_dir_all(root.path().join("_content/blog"))?;
+        image::RgbImage::new(80, 40).save(root.path().join("static/my images/cat.png"))?;
+        image::RgbImage::new(20, 10).save(root.path().join("_content/blog/dog.png"))?;
+        fs::write(root.path().join("static/notes.txt"), "not an image")?;
+
+        let images = SiteImages::new(
+            root.path().to_owned(),
+            Url::parse("https://example.com/project/")?,
+        );
+        let page = root.path().join("_content/blog/post.md");
+        let dimensions = |src: &str| images.dimensions(src, Some(&page));
+
+        assert_eq!(
+            dimensions("/project/static/my%20images/cat.png"),
+            Some((80, 40))
+        );
+        assert_eq!(
+            dimensions("https://example.com/project/static/my%20images/cat.png?v=2"),
+            Some((80, 40))
+        );
+        assert_eq!(dimensions("dog.png"), Some((20, 10)));
+        assert_eq!(dimensions("./dog.png#large"), Some((20, 10)));
+        assert_eq!(images.dimensions("dog.png", None), None);
+
+        // Remote images, paths outside the site, and files that aren't images are left
+        // without a size.
+        assert_eq!(dimensions("https://cdn.example.com/cat.png"), None);
+        assert_eq!(dimensions("//example.com/project/static/cat.png"), None);
+        assert_eq!(dimensions("/static/my%20images/cat.png"), None);
+        assert_eq!(dimensions("../../static/my%20images/cat.png"), None);
+        assert_eq!(dimensions("/project/static/notes.txt"), None);
+        assert_eq!(dimensions("/project/static/missing.png"), None);
+
+        Ok(())
+    }
+}
@@ -1,43 +1,132 @@
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::missing_panics_doc)]
 
+pub mod cache;
+pub mod cancel;
 pub mod config;
 pub mod database;
 
 mod asset;
+mod audit;
+mod authors;
+mod builder;
+mod collisions;
 mod entry;
+mod generated;
+mod highlights;
+mod hooks;
+mod images;
 mod page;
+mod protected;
+mod redirects;
+mod report;
+mod sections;
 mod static_file;
+mod stats;
+mod tags;
 mod templates;
+#[cfg(test)]
+mod test_utils;
+mod thumbnails;
+mod timings;
 mod utils;
 
-use std::{collections::HashSet, fs, path::PathBuf, process::Command};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use chrono::Utc;
-use color_eyre::{Result, eyre::OptionExt};
-use config::Config;
-use entry::{Entry, Typ, discover_entries};
-use minijinja::{Environment, context};
+use color_eyre::{
+    Result,
+    eyre::{OptionExt, WrapErr, bail},
+};
+use config::{Config, add_trailing_slash};
+use entry::{Entry, Typ, discover_entries, variant_key};
+use minijinja::{Environment, Value, context, value::ValueKind};
 use rayon::prelude::*;
 use redb::Database;
-use yar_markdown::MarkdownRenderer;
+use url::Url;
+use yar_markdown::{MarkdownRenderer, SanitizeOptions};
 
-use crate::{
-    asset::Asset,
-    database::{get_pages, insert_hash, insert_page},
+use crate::cancel::{CancellationToken, check};
+
+pub use crate::{
+    audit::SizeWarning,
+    builder::SiteBuilder,
+    generated::{GeneratedKind, GeneratedPage},
     page::Page,
-    static_file::StaticFile,
-    templates::{Template, create_environment, template_page::TemplatePage},
-    utils::fs::ensure_directory,
+    report::{BuildPlan, BuildReport, Change, ChangedFile},
+    sections::Neighbors,
+    stats::SiteStats,
+    tags::{Tag, TagMember},
+    templates::DEFAULT_TEMPLATES,
+    timings::{PageTiming, TemplateTiming, Timings},
+    utils::fs::retry_io,
+};
+pub use yar_markdown::{Shortcode, ShortcodeFn, split_frontmatter};
+
+use crate::{
+    asset::{Asset, rendered_path},
+    authors::{author_pages, render_author_pages, unknown_authors},
+    collisions::permalink_collisions,
+    database::{
+        get_dependents, get_hashes, get_highlights, get_pages, get_rendered_states, get_setting,
+        get_template_page_outputs, get_thumbnails, insert_dependents, insert_hash,
+        insert_highlight, insert_page, insert_rendered_state, insert_setting,
+        insert_template_page_output, insert_thumbnail, invalidate_all, remove_dependents,
+        remove_highlight, remove_page, remove_template_page_output, remove_thumbnail, rename_entry,
+    },
+    highlights::Highlights,
+    images::SiteImages,
+    sections::section_neighbors,
+    static_file::{Metadata, StaticFile},
+    tags::collect_tags,
+    templates::{
+        Dependencies, Outputs, Template, create_environment,
+        template_page::{self, RenderedState, TemplatePage, TemplatePageOutputs},
+    },
+    thumbnails::Thumbnails,
+    utils::{build_page_url, collect_errors, fs::ensure_directory, minify_html, normalize_path},
 };
 
 struct Library {
     pub pages: Vec<Page>,
+    /// The pages that aren't hidden, which are the ones listed in the `pages` context,
+    /// feeds, and tags.
+    pub listed_pages: Vec<Page>,
     pub assets: Vec<Asset>,
     pub static_files: Vec<StaticFile>,
     pub template_pages: Vec<TemplatePage>,
     pub templates: Vec<Template>,
     pub invalidated_pages: HashSet<PathBuf>,
+    /// The hashes of the collections each template page depends on.
+    pub collection_hashes: HashMap<PathBuf, blake3::Hash>,
+    /// What was rendered for each template page in previous builds.
+    pub previous_states: HashMap<PathBuf, RenderedState>,
+    /// What was rendered for template pages this build.
+    pub rendered_states: Vec<(PathBuf, RenderedState)>,
+    /// Outputs from previous builds that this build removed, relative to the output directory.
+    pub removed_outputs: Vec<PathBuf>,
+    /// Cached pages whose source has since been deleted.
+    pub deleted_pages: Vec<PathBuf>,
+    /// The tags used across all pages.
+    pub tags: Vec<Tag>,
+    /// Totals across the site, for the `stats` global.
+    pub stats: SiteStats,
+    /// The pages written by this build that don't have a source file of their own.
+    pub generated_pages: Vec<GeneratedPage>,
+    /// Template pages that aren't rendered again, since nothing they depend on changed.
+    pub unchanged_template_pages: Vec<TemplatePage>,
+    /// Thumbnails from previous builds whose source image has since changed or been removed.
+    pub stale_thumbnails: Vec<PathBuf>,
+    /// Pagination chunks from previous builds that this build removed, relative to the
+    /// output directory.
+    pub stale_chunks: Vec<PathBuf>,
 }
 
 impl Library {
@@ -45,25 +134,63 @@ impl Library {
     pub fn new() -> Self {
         Self {
             pages: vec![],
+            listed_pages: vec![],
             assets: vec![],
             static_files: vec![],
             template_pages: vec![],
             templates: vec![],
             invalidated_pages: HashSet::new(),
+            collection_hashes: HashMap::new(),
+            previous_states: HashMap::new(),
+            rendered_states: vec![],
+            removed_outputs: vec![],
+            deleted_pages: vec![],
+            tags: vec![],
+            stats: SiteStats::default(),
+            generated_pages: vec![],
+            unchanged_template_pages: vec![],
+            stale_thumbnails: vec![],
+            stale_chunks: vec![],
         }
     }
 }
 
+/// Limit builds to the given number of threads, or use every core if it's `None`.
+///
+/// Builds run on rayon's global thread pool, which can only be configured once, so this
+/// has to be called before anything is built. With a single job, everything runs
+/// sequentially on one thread in a deterministic order.
+pub fn configure_jobs(jobs: Option<NonZeroUsize>) -> Result<()> {
+    if let Some(jobs) = jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs.get())
+            .build_global()?;
+    }
+
+    Ok(())
+}
+
 /// A site to be built.
-pub struct Site<'a> {
+///
+/// Sites are `Send`, so a site can be moved into a thread or task to be rebuilt there.
+pub struct Site {
     db: Database,
     config: Config,
-    environment: Environment<'a>,
-    markdown_renderer: MarkdownRenderer,
+    environment: Arc<Environment<'static>>,
+    markdown_renderer: Arc<MarkdownRenderer>,
+    highlights: Highlights,
+    dependencies: Dependencies,
+    outputs: Outputs,
+    thumbnails: Thumbnails,
+    template_page_outputs: TemplatePageOutputs,
     library: Library,
+    timings: Timings,
 }
 
 /// A helper enum that holds the different outputs `yar` works with.
+// Entries are only processed into these on their way into the library, so pages aren't
+// boxed to save space.
+#[allow(clippy::large_enum_variant)]
 enum Processed {
     Page(Page),
     Asset(Asset),
@@ -72,40 +199,134 @@ enum Processed {
     Template(Template),
 }
 
-impl Site<'_> {
+impl Site {
     /// Create a new site.
     pub fn new(db: Database, config: Config) -> Result<Self> {
-        let markdown_renderer = MarkdownRenderer::new(
-            config.site.syntax_theme_path.as_ref(),
-            Some(&config.site.syntax_theme),
+        Self::with_shortcodes(db, config, [])
+    }
+
+    /// Create a new site with shortcodes implemented in Rust, for embedding `yar` as a
+    /// library.
+    ///
+    /// See [`MarkdownRenderer::register_shortcode`].
+    pub fn with_shortcodes<I: IntoIterator<Item = (String, ShortcodeFn)>>(
+        db: Database,
+        mut config: Config,
+        shortcodes: I,
+    ) -> Result<Self> {
+        // Configs made in code skip the check made when they're deserialized.
+        add_trailing_slash(&mut config.site.url);
+        let highlights = Highlights::default();
+        let mut markdown_renderer = markdown_renderer(&config, &highlights)?;
+        for (name, f) in shortcodes {
+            markdown_renderer.register_shortcode(name, f);
+        }
+        let markdown_renderer = Arc::new(markdown_renderer);
+        let dependencies = Dependencies::default();
+        let outputs = Outputs::default();
+        let thumbnails = Thumbnails::default();
+        let env = create_environment(
+            &config,
+            &markdown_renderer,
+            &dependencies,
+            &outputs,
+            &thumbnails,
         )?;
-        let env = create_environment(&config)?;
 
-        Ok(Self {
+        let mut site = Self {
             db,
             config,
-            environment: env,
+            environment: Arc::new(env),
             markdown_renderer,
+            highlights,
+            dependencies,
+            outputs,
+            thumbnails,
+            template_page_outputs: TemplatePageOutputs::default(),
             library: Library::new(),
-        })
+            timings: Timings::default(),
+        };
+        site.refresh_globals();
+
+        Ok(site)
+    }
+
+    /// The globals templates are rendered with, by name, leaving out functions.
+    #[must_use]
+    pub fn globals(&self) -> BTreeMap<String, Value> {
+        self.environment
+            .globals()
+            .filter(|(_, value)| value.kind() != ValueKind::Plain)
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect()
+    }
+
+    // Replace the globals computed from what's loaded, so that templates never see the
+    // ones from a previous build. They're refreshed as a set whenever the library or the
+    // environment changes. Hook outputs are empty until this build's hooks have run, when
+    // `render_second_pass` loads them.
+    fn refresh_globals(&mut self) {
+        let mut globals = vec![(
+            "stats".to_owned(),
+            Value::from_serialize(&self.library.stats),
+        )];
+        for output in self.config.hooks.post.iter().flat_map(|h| &h.outputs) {
+            let empty = Value::from(HashMap::<String, Value>::new());
+            globals.push((output.global.clone(), empty));
+        }
+
+        let environment = Arc::make_mut(&mut self.environment);
+        for (name, value) in globals {
+            environment.add_global(name, value);
+        }
     }
 
     /// Load all entries and process them.
     pub fn load(&mut self) -> Result<()> {
-        let entries = discover_entries(&self.db, &self.config.site.root)?;
+        self.load_cancellable(&CancellationToken::new())
+    }
+
+    /// Load all entries and process them, stopping with [`cancel::Cancelled`] before the
+    /// next entry once `token` is cancelled.
+    ///
+    /// Nothing is cached until [`Self::save_to_cache`], so the next build picks up
+    /// everything a cancelled one didn't finish.
+    pub fn load_cancellable(&mut self, token: &CancellationToken) -> Result<()> {
+        // Anything loaded by a previous build, finished or not, is loaded again.
+        self.library = Library::new();
+        self.refresh_globals();
+        self.invalidate_changed_settings()?;
+        self.thumbnails.set_cached(get_thumbnails(&self.db)?);
+        self.highlights.set_cached(get_highlights(&self.db)?);
+        self.template_page_outputs = TemplatePageOutputs::new(
+            &self.config.site.output_path,
+            get_template_page_outputs(&self.db)?,
+        );
+
+        let entries = discover_entries(&self.db, &self.config.site.root, &self.config.site)?;
         println!("Discovered {} entries to build", entries.len());
+        let renamed_pages = self.carry_renames(&entries)?;
+        self.remove_retyped_outputs(&entries)?;
+
+        // Templates including a changed file need the pages using them to be rebuilt.
+        let changed = entries
+            .iter()
+            .map(|e| e.path.clone())
+            .collect::<HashSet<PathBuf>>();
+        let dependents = get_dependents(&self.db, &changed)?;
 
         // Process the entries and collect all of the outputs.
         let processed = entries
             .into_par_iter()
             .map(|entry| {
-                Ok(match entry.entry_type() {
-                    Typ::Markdown => process_page(
+                check(token)?;
+                Ok(match entry.entry_type(&self.config.site) {
+                    Typ::Markdown | Typ::HtmlPage => Processed::Page(process_page(
                         entry,
                         &self.config,
                         &self.markdown_renderer,
                         &self.environment,
-                    )?,
+                    )?),
                     Typ::Asset => process_asset(entry, &self.config)?,
                     Typ::StaticFile => process_static_file(entry, &self.config)?,
                     Typ::TemplatePage => process_template_page(entry, &self.config)?,
@@ -126,26 +347,464 @@ impl Site<'_> {
             }
         }
 
+        self.remove_renamed_outputs(&renamed_pages, &processed_pages)?;
+
         // Get the paths of all the pages that were processed in this run, and thus
         // invalidated, and use that to pull all of the cached pages that are still valid.
-        let invalidated_pages = processed_pages
+        let mut invalidated_pages = processed_pages
             .iter()
             .map(|p| p.path.clone())
             .collect::<HashSet<PathBuf>>();
-        let cached_pages = get_pages(&self.db, &invalidated_pages)?;
+        // Pages whose extension is no longer a content extension are gone as well.
+        let (cached_pages, deleted_pages): (Vec<Page>, Vec<Page>) =
+            get_pages(&self.db, &invalidated_pages)?
+                .into_iter()
+                .partition(|p| p.path.exists() && Typ::of(&p.path, &self.config.site).is_page());
+        self.remove_deleted_outputs(&deleted_pages)?;
+        self.library.deleted_pages = deleted_pages.into_iter().map(|p| p.path).collect();
+        invalidated_pages.extend(
+            cached_pages
+                .iter()
+                .filter(|p| dependents.contains(p.template()))
+                .map(|p| p.path.clone()),
+        );
+
+        invalidated_pages.extend(self.neighbor_pages(&processed_pages, &cached_pages));
+
+        // Pages parsed again because a page they link to changed are invalidated too.
+        let cached_pages = self.link_pages(&mut processed_pages, cached_pages, token)?;
+        invalidated_pages.extend(processed_pages.iter().map(|p| p.path.clone()));
 
         self.library.invalidated_pages = invalidated_pages;
         self.library.pages = processed_pages
             .into_iter()
             .chain(cached_pages)
             .collect::<Vec<Page>>();
+        self.library.listed_pages = self
+            .library
+            .pages
+            .iter()
+            .filter(|p| !p.document.frontmatter.hidden)
+            .cloned()
+            .collect();
+
+        for warning in unknown_authors(&self.library.pages, &self.config.site.authors_meta) {
+            println!("Warning: {warning}");
+        }
+        for page in &self.library.pages {
+            let undefined = &page.document.undefined_footnotes;
+            if !undefined.is_empty() {
+                println!(
+                    "Warning: {} references footnotes that aren't defined: {}",
+                    page.path.display(),
+                    undefined.join(", ")
+                );
+            }
+        }
+
+        check(token)?;
+        self.load_dependent_template_pages()?;
+        self.check_permalink_collisions()?;
+        self.check_permalink_overrides()?;
 
         println!("Built entries");
         Ok(())
     }
 
+    // The cached pages in a section where a page was added, edited, or removed, since
+    // pages link to their neighbors in their section.
+    fn neighbor_pages(&self, processed: &[Page], cached: &[Page]) -> Vec<PathBuf> {
+        let changed_sections = processed
+            .iter()
+            .map(|p| p.path.as_path())
+            .chain(self.library.deleted_pages.iter().map(PathBuf::as_path))
+            .filter_map(|p| p.parent()?.file_name())
+            .map(ToOwned::to_owned)
+            .collect::<HashSet<_>>();
+
+        cached
+            .iter()
+            .filter(|p| {
+                p.path
+                    .parent()
+                    .and_then(Path::file_name)
+                    .is_some_and(|s| changed_sections.contains(s))
+            })
+            .map(|p| p.path.clone())
+            .collect()
+    }
+
+    // Resolve the links between pages and encrypt the protected ones, once every page is
+    // known. Cached pages linking to a page that changed are parsed again and added to the
+    // processed pages, and the pages that are still cached are returned.
+    fn link_pages(
+        &self,
+        processed: &mut Vec<Page>,
+        cached: Vec<Page>,
+        token: &CancellationToken,
+    ) -> Result<Vec<Page>> {
+        let (reparsed, cached) = self.reparse_linking_pages(processed, cached, token)?;
+        processed.extend(reparsed);
+
+        self.resolve_links(processed, &cached)?;
+        for page in processed {
+            page.protect()?;
+        }
+
+        Ok(cached)
+    }
+
+    // Parse the cached pages linking to a page that was changed, added, or removed again,
+    // since the permalink they link to could have changed with it. Cached pages already
+    // have their links rewritten, so they're parsed from their source.
+    //
+    // Returns the pages that were parsed again and the ones that are still cached.
+    fn reparse_linking_pages(
+        &self,
+        processed: &[Page],
+        cached: Vec<Page>,
+        token: &CancellationToken,
+    ) -> Result<(Vec<Page>, Vec<Page>)> {
+        let changed = processed
+            .iter()
+            .map(|p| &p.path)
+            .chain(&self.library.deleted_pages)
+            .map(normalize_path)
+            .collect::<HashSet<PathBuf>>();
+        let (linking, cached): (Vec<Page>, Vec<Page>) = cached
+            .into_iter()
+            .partition(|p| p.links.iter().any(|l| changed.contains(l)));
+
+        let reparsed = linking
+            .into_par_iter()
+            .map(|page| {
+                check(token)?;
+                let raw_content = fs::read(&page.path)?;
+                process_page(
+                    Entry::new(page.path, raw_content, page.source_hash),
+                    &self.config,
+                    &self.markdown_renderer,
+                    &self.environment,
+                )
+            })
+            .collect::<Result<Vec<Page>>>()?;
+
+        Ok((reparsed, cached))
+    }
+
+    // Point the links to markdown files in the processed pages at the permalinks of the
+    // pages built from them. Links to pages that don't exist, or drafts outside of
+    // development, fail the build, and are only warned about in development.
+    fn resolve_links(&self, processed: &mut [Page], cached: &[Page]) -> Result<()> {
+        let development = self.config.site.development;
+        let permalinks = processed
+            .iter()
+            .chain(cached)
+            .filter(|p| development || !p.document.frontmatter.draft)
+            .map(|p| (normalize_path(&p.path), p.permalink.clone()))
+            .collect::<HashMap<PathBuf, Url>>();
+        let broken = processed
+            .iter_mut()
+            .flat_map(|p| p.resolve_links(&permalinks))
+            .collect::<Vec<String>>();
+
+        if development {
+            for link in &broken {
+                println!("Warning: {link}");
+            }
+        } else if !broken.is_empty() {
+            bail!("Found broken links:\n{}", broken.join("\n"));
+        }
+
+        Ok(())
+    }
+
+    // Build everything again when a setting baked into every output changed since the
+    // last build.
+    fn invalidate_changed_settings(&self) -> Result<()> {
+        let mut changed = false;
+        for (key, value, message) in build_settings(&self.config)? {
+            if let Some(previous) = get_setting(&self.db, key)?
+                && previous != value
+            {
+                println!("{message}, building everything again");
+                changed = true;
+            }
+        }
+        if changed {
+            invalidate_all(&self.db)?;
+        }
+
+        Ok(())
+    }
+
+    // Move the cache of files renamed to a different case or unicode normalization to
+    // their new path, returning the pages as they were cached. On case-insensitive
+    // filesystems their old path still seems to exist, so it would never be pruned.
+    fn carry_renames(&self, entries: &[Entry]) -> Result<Vec<Page>> {
+        let mut renamed = vec![];
+        for entry in entries {
+            let Some(from) = &entry.renamed_from else {
+                continue;
+            };
+            println!("{} was renamed to {}", from.display(), entry.path.display());
+            renamed.extend(rename_entry(&self.db, from, &entry.path)?);
+        }
+
+        Ok(renamed)
+    }
+
+    // Remove what renamed pages were rendered to, if they're now rendered somewhere else.
+    //
+    // Outputs only differing in case are left alone, since on case-insensitive
+    // filesystems they're the same file as the page's new output.
+    fn remove_renamed_outputs(&mut self, renamed: &[Page], processed: &[Page]) -> Result<()> {
+        for previous in renamed {
+            let key = variant_key(&previous.path);
+            let Some(page) = processed.iter().find(|p| variant_key(&p.path) == key) else {
+                continue;
+            };
+            if variant_key(&page.out_path) == variant_key(&previous.out_path) {
+                continue;
+            }
+
+            if previous.out_path.exists() {
+                retry_io(|| fs::remove_file(&previous.out_path))?;
+            }
+            if let Ok(relative) = previous
+                .out_path
+                .strip_prefix(&self.config.site.output_path)
+            {
+                self.library.removed_outputs.push(relative.to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Remove what static files and assets were written to, if the content or asset
+    // extensions changed since the last build so that they're built as something else.
+    fn remove_retyped_outputs(&mut self, entries: &[Entry]) -> Result<()> {
+        let Some(previous) = get_setting(&self.db, "extensions")? else {
+            return Ok(());
+        };
+        let mut config = self.config.site.clone();
+        (config.content_extensions, config.asset_extensions) = serde_json::from_str(&previous)?;
+
+        for entry in entries {
+            let out_path = match (
+                Typ::of(&entry.path, &config),
+                entry.entry_type(&self.config.site),
+            ) {
+                (Typ::StaticFile, Typ::StaticFile) | (Typ::Asset, Typ::Asset) => continue,
+                (Typ::StaticFile, _) => {
+                    static_file::out_path(&entry.path, &config.output_path, &config.root)
+                }
+                (Typ::Asset, _) => rendered_path(&entry.path, &config.output_path, &config.root),
+                _ => continue,
+            };
+            if out_path.is_file() {
+                retry_io(|| fs::remove_file(&out_path))?;
+            }
+            if let Ok(relative) = out_path.strip_prefix(&config.output_path) {
+                self.library.removed_outputs.push(relative.to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Remove the outputs of pages whose source files were deleted since the last build.
+    fn remove_deleted_outputs(&mut self, deleted: &[Page]) -> Result<()> {
+        for page in deleted {
+            if page.out_path.is_file() {
+                retry_io(|| fs::remove_file(&page.out_path))?;
+            }
+            if let Ok(relative) = page.out_path.strip_prefix(&self.config.site.output_path) {
+                self.library.removed_outputs.push(relative.to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Warn about pages and template pages with permalinks only differing in case, or
+    // fail the build under `strict_urls`.
+    fn check_permalink_collisions(&self) -> Result<()> {
+        let pages = self
+            .library
+            .pages
+            .iter()
+            .filter(|p| self.config.site.development || !p.document.frontmatter.draft)
+            .map(|p| (p.path.as_path(), &p.permalink));
+        let template_pages = self
+            .library
+            .template_pages
+            .iter()
+            .chain(&self.library.unchanged_template_pages)
+            .filter(|t| self.config.site.development || !t.frontmatter.draft)
+            .map(|t| (t.path.as_path(), &t.permalink));
+        let collisions = permalink_collisions(pages.chain(template_pages));
+
+        if self.config.site.strict_urls && !collisions.is_empty() {
+            bail!(
+                "Permalinks only differ in case:\n{}",
+                collisions
+                    .iter()
+                    .map(|c| format!("  {c}"))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            );
+        }
+        for collision in collisions {
+            println!("Warning: {collision}");
+        }
+
+        Ok(())
+    }
+
+    // Fail the build when a template page setting its permalink is rendered to the same
+    // file as another page or template page.
+    fn check_permalink_overrides(&self) -> Result<()> {
+        let included = |draft: bool| self.config.site.development || !draft;
+        let template_pages = self
+            .library
+            .template_pages
+            .iter()
+            .chain(&self.library.unchanged_template_pages)
+            .filter(|t| included(t.frontmatter.draft) && t.frontmatter.pagination.is_none())
+            .collect::<Vec<&TemplatePage>>();
+        let mut outputs = HashMap::<PathBuf, Vec<&Path>>::new();
+        for page in self
+            .library
+            .pages
+            .iter()
+            .filter(|p| included(p.document.frontmatter.draft))
+        {
+            outputs
+                .entry(page.out_path.clone())
+                .or_default()
+                .push(&page.path);
+        }
+        for template_page in &template_pages {
+            outputs
+                .entry(template_page.output_file())
+                .or_default()
+                .push(&template_page.path);
+        }
+
+        let mut collisions = vec![];
+        for template_page in template_pages {
+            let Some(permalink) = &template_page.frontmatter.permalink else {
+                continue;
+            };
+            let others = outputs[&template_page.output_file()]
+                .iter()
+                .filter(|p| **p != template_page.path)
+                .map(|p| p.display().to_string())
+                .collect::<Vec<String>>();
+            if !others.is_empty() {
+                collisions.push(format!(
+                    "  {} sets its permalink to {permalink}, which {} is rendered to too",
+                    template_page.path.display(),
+                    others.join(" and ")
+                ));
+            }
+        }
+        if !collisions.is_empty() {
+            collisions.sort();
+            bail!("Found colliding permalinks:\n{}", collisions.join("\n"));
+        }
+
+        Ok(())
+    }
+
+    // Template pages that depend on the pages or a global are rendered again whenever its
+    // contents change, even if their own source hasn't.
+    fn load_dependent_template_pages(&mut self) -> Result<()> {
+        self.library.previous_states = get_rendered_states(&self.db)?;
+
+        let loaded = self
+            .library
+            .template_pages
+            .iter()
+            .map(|t| t.path.clone())
+            .collect::<HashSet<PathBuf>>();
+        let mut cached = vec![];
+        for (path, hash) in get_hashes(&self.db)? {
+            if !matches!(Typ::of(&path, &self.config.site), Typ::TemplatePage)
+                || loaded.contains(&path)
+            {
+                continue;
+            }
+            // The template page may have been deleted since it was last built.
+            let Ok(content) = fs::read(&path) else {
+                continue;
+            };
+
+            let entry = Entry::new(path, content, blake3::Hash::from_bytes(hash));
+            if let Processed::TemplatePage(t) = process_template_page(entry, &self.config)? {
+                cached.push(t);
+            }
+        }
+
+        // Template pages can be tagged, so the tags are collected once every template page
+        // is loaded, whether or not it's rendered again.
+        let template_pages = self
+            .library
+            .template_pages
+            .iter()
+            .chain(&cached)
+            .collect::<Vec<&TemplatePage>>();
+        self.library.tags = collect_tags(
+            &self.library.listed_pages,
+            &template_pages,
+            &self.config.site.tag_aliases,
+        );
+        self.library.stats = SiteStats::collect(
+            &self.library.listed_pages,
+            &self.library.tags,
+            self.config.site.development,
+        );
+        self.refresh_globals();
+
+        for t in cached {
+            let collection_hash = t.collection_hash(
+                &self.library.listed_pages,
+                &self.library.tags,
+                &self.environment,
+            )?;
+            let previous = self.library.previous_states.get(&t.path);
+            if previous.is_none_or(|s| s.collection_hash != collection_hash) {
+                self.library.template_pages.push(t);
+            } else {
+                self.library.unchanged_template_pages.push(t);
+            }
+        }
+
+        for t in &self.library.template_pages {
+            let collection_hash = t.collection_hash(
+                &self.library.listed_pages,
+                &self.library.tags,
+                &self.environment,
+            )?;
+            self.library
+                .collection_hashes
+                .insert(t.path.clone(), collection_hash);
+        }
+
+        Ok(())
+    }
+
     /// Render the site to disk.
     pub fn render(&mut self) -> Result<()> {
+        self.render_cancellable(&CancellationToken::new())
+    }
+
+    /// Render the site to disk, stopping with [`cancel::Cancelled`] before the next page
+    /// once `token` is cancelled.
+    pub fn render_cancellable(&mut self, token: &CancellationToken) -> Result<()> {
+        check(token)?;
         ensure_directory(&self.config.site.output_path)?;
         println!("Rendering site to disk");
 
@@ -154,33 +813,85 @@ impl Site<'_> {
             self.reload_environment()?;
         }
 
-        self.render_pages()?;
-        self.library
+        self.timings.clear();
+        self.dependencies.clear();
+        self.library.generated_pages = self.find_generated_pages()?;
+        self.remove_stale_thumbnails()?;
+        self.index_outputs()?;
+        let rendered_chunks = self.render_pages(token)?;
+        check(token)?;
+        self.remove_stale_chunks(rendered_chunks)?;
+        // Assets and static files are independent of each other, so every failure is
+        // reported instead of stopping at the first.
+        let assets = self
+            .library
             .assets
             .par_iter()
-            .map(Asset::render)
-            .collect::<Result<Vec<_>>>()?;
+            .filter(|a| self.is_file_selected(&a.path))
+            .map(|a| {
+                a.render()
+                    .wrap_err_with(|| format!("Couldn't render {}", a.path.display()))
+            })
+            .collect::<Vec<Result<()>>>();
+        let static_files = self.render_static_files();
+        collect_errors(assets.into_iter().chain(static_files))?;
 
-        self.library
+        println!("Rendered site");
+        Ok(())
+    }
+
+    // Copy the static files to the output directory, warning about images with GPS
+    // coordinates in their metadata, or saying how many were stripped of it.
+    fn render_static_files(&self) -> Vec<Result<()>> {
+        let strip_exif = self.config.site.images.strip_exif;
+        let rendered = self
+            .library
             .static_files
             .par_iter()
-            .map(StaticFile::render)
-            .collect::<Result<Vec<_>>>()?;
+            .filter(|s| self.is_file_selected(&s.path))
+            .map(|s| {
+                s.render(strip_exif)
+                    .map(|metadata| (s, metadata))
+                    .wrap_err_with(|| format!("Couldn't copy {}", s.path.display()))
+            })
+            .collect::<Vec<Result<(&StaticFile, Metadata)>>>();
 
-        println!("Rendered site");
-        Ok(())
+        let mut stripped = 0;
+        for (static_file, metadata) in rendered.iter().flatten() {
+            match metadata {
+                Metadata::Gps => println!(
+                    "Warning: {} has GPS coordinates in its metadata, which `strip_exif` under `[site.images]` removes",
+                    static_file.path.display()
+                ),
+                Metadata::Stripped => stripped += 1,
+                Metadata::Kept => (),
+            }
+        }
+        if stripped > 0 {
+            println!("Stripped the metadata from {stripped} images");
+        }
+
+        rendered.into_iter().map(|r| r.map(|_| ())).collect()
     }
 
     /// Save the site to cache.
     pub fn save_to_cache(&mut self) -> Result<()> {
         println!("Caching site");
 
+        // Pages left out of a partial build haven't been rendered, so they aren't cached
+        // in order for the next build to pick them up.
         let invalididated_pages = self
             .library
             .pages
             .iter()
-            .filter(|p| self.library.invalidated_pages.contains(&p.path))
+            .filter(|p| self.library.invalidated_pages.contains(&p.path) && self.is_selected(p))
             .collect::<Vec<&Page>>();
+        // The templates of the pages rendered replace what they depended on before, so
+        // files they no longer include don't rebuild them.
+        let rendered_templates = invalididated_pages
+            .iter()
+            .map(|p| p.template().to_owned())
+            .collect::<HashSet<String>>();
 
         let txn = self.db.begin_write()?;
 
@@ -188,12 +899,21 @@ impl Site<'_> {
             insert_page(&txn, page)?;
         }
 
+        for path in &self.library.deleted_pages {
+            remove_page(&txn, path)?;
+        }
+
+        // Nor are the assets and static files left out of a partial build.
         for asset in &self.library.assets {
-            insert_hash(&txn, &asset.path, asset.source_hash.as_bytes())?;
+            if self.is_file_selected(&asset.path) {
+                insert_hash(&txn, &asset.path, asset.source_hash.as_bytes())?;
+            }
         }
 
         for static_file in &self.library.static_files {
-            insert_hash(&txn, &static_file.path, static_file.source_hash.as_bytes())?;
+            if self.is_file_selected(&static_file.path) {
+                insert_hash(&txn, &static_file.path, static_file.source_hash.as_bytes())?;
+            }
         }
 
         for template_page in &self.library.template_pages {
@@ -208,17 +928,530 @@ impl Site<'_> {
             insert_hash(&txn, &template.path, template.source_hash.as_bytes())?;
         }
 
+        for (path, state) in &self.library.rendered_states {
+            insert_rendered_state(&txn, path, state)?;
+        }
+
+        for thumbnail in self.thumbnails.generated() {
+            insert_thumbnail(&txn, &thumbnail)?;
+        }
+
+        for path in &self.library.stale_thumbnails {
+            remove_thumbnail(&txn, path)?;
+        }
+
+        for (key, highlight) in self.highlights.used() {
+            insert_highlight(&txn, &key, &highlight)?;
+        }
+
+        for key in self.highlights.stale() {
+            remove_highlight(&txn, &key)?;
+        }
+
+        for (path, hash) in self.template_page_outputs.written() {
+            insert_template_page_output(&txn, path, &hash)?;
+        }
+
+        for path in &self.library.stale_chunks {
+            remove_template_page_output(&txn, path)?;
+        }
+
+        remove_dependents(&txn, &rendered_templates)?;
+        for (path, templates) in self.dependencies.by_file() {
+            insert_dependents(&txn, path, &templates)?;
+        }
+
+        insert_setting(&txn, "config", &cache::config_hash(&self.config))?;
+        for (key, value, _) in build_settings(&self.config)? {
+            insert_setting(&txn, key, &value)?;
+        }
+
         txn.commit()?;
 
         Ok(())
     }
 
+    /// Check the files in the given output directory against the configured size limits.
+    ///
+    /// This is separate from rendering, since builds render to a directory holding only
+    /// the files that changed.
+    pub fn audit_sizes<P: AsRef<Path>>(&self, output_dir: P) -> Result<Vec<SizeWarning>> {
+        audit::audit_sizes(output_dir, &self.config.site.url, &self.config.site.audit)
+    }
+
+    /// Get every file the site's output holds as of the last render, relative to the
+    /// output directory, including files written by previous builds that are unchanged.
+    #[must_use]
+    pub fn output_files(&self) -> Vec<PathBuf> {
+        self.outputs.paths()
+    }
+
+    /// Plan what the last render would change in `previous_dir`, the output of previous
+    /// builds, for dry runs.
+    ///
+    /// The render is expected to have been to a directory of its own, and nothing is
+    /// written to `previous_dir`.
+    pub fn plan<P: AsRef<Path>>(&self, previous_dir: P, elapsed: Duration) -> Result<BuildPlan> {
+        let out_dir = &self.config.site.output_path;
+        let report = BuildReport::compare(
+            out_dir,
+            previous_dir,
+            &self.library.removed_outputs,
+            elapsed,
+        )?;
+        let relative = |p: &Path| p.strip_prefix(out_dir).map(Path::to_path_buf).ok();
+
+        let pages = self
+            .library
+            .pages
+            .iter()
+            .filter(|p| self.config.site.development || !p.document.frontmatter.draft)
+            .collect::<Vec<&Page>>();
+        let page_outputs = pages
+            .iter()
+            .filter_map(|p| relative(&p.out_path))
+            .collect::<HashSet<PathBuf>>();
+        let pages_changed = |change| {
+            report
+                .changed
+                .iter()
+                .filter(|c| c.change == change && page_outputs.contains(&c.path))
+                .count()
+        };
+
+        let mut permalinks = pages
+            .iter()
+            .map(|p| p.permalink.clone())
+            .collect::<Vec<Url>>();
+        permalinks.sort();
+
+        Ok(BuildPlan {
+            pages_created: pages_changed(Change::Created),
+            pages_updated: pages_changed(Change::Modified),
+            removed: self.library.removed_outputs.clone(),
+            assets: self
+                .library
+                .assets
+                .iter()
+                .filter_map(|a| relative(&a.out_path))
+                .collect(),
+            static_files: self
+                .library
+                .static_files
+                .iter()
+                .filter_map(|s| relative(&s.out_path))
+                .collect(),
+            permalinks,
+            report,
+        })
+    }
+
+    /// Get the pages the last render wrote that don't have a source file of their own,
+    /// such as author pages and the chunks of paginated template pages.
+    #[must_use]
+    pub fn generated_pages(&self) -> &[GeneratedPage] {
+        &self.library.generated_pages
+    }
+
+    /// Get the tags used across all the loaded pages, with variants of the same tag merged.
+    #[must_use]
+    pub fn tags(&self) -> &[Tag] {
+        &self.library.tags
+    }
+
+    /// Render a loaded page to a string, exactly as a build would but without writing it to
+    /// disk, and only minifying it if `minified` is set.
+    ///
+    /// `path` is the page's source path, either as discovered or relative to the site's root.
+    pub fn render_page_to_string<P: AsRef<Path>>(&self, path: P, minified: bool) -> Result<String> {
+        let path = self.source_path(path);
+        let page = self
+            .library
+            .pages
+            .iter()
+            .find(|p| path.contains(&p.path))
+            .ok_or_eyre(format!("No page at {} has been loaded", path[0].display()))?;
+
+        let neighbors = self.neighbors();
+        let rendered = page.render_to_string(
+            &self.library.listed_pages,
+            &self.library.tags,
+            neighbors
+                .get(page.path.as_path())
+                .copied()
+                .unwrap_or_default(),
+            &self.environment,
+            &self.config.site.authors_meta,
+            &self.config.site.url,
+        )?;
+        Ok(finish_string(rendered, minified))
+    }
+
+    /// Render a loaded template page to a string, exactly as a build would but without
+    /// writing it to disk, and only minifying it if `minified` is set.
+    ///
+    /// `path` is the template page's source path, either as discovered or relative to the
+    /// site's root. Paginated template pages aren't supported.
+    pub fn render_template_page_to_string<P: AsRef<Path>>(
+        &self,
+        path: P,
+        minified: bool,
+    ) -> Result<String> {
+        let path = self.source_path(path);
+        let template_page = self
+            .library
+            .template_pages
+            .iter()
+            .find(|t| path.contains(&t.path))
+            .ok_or_eyre(format!(
+                "No template page at {} has been loaded",
+                path[0].display()
+            ))?;
+
+        let rendered = template_page.render_to_string(
+            &self.library.listed_pages,
+            &self.library.tags,
+            &self.environment,
+        )?;
+        Ok(finish_string(rendered, minified))
+    }
+
+    // The paths a source file given by the user could have been discovered at.
+    fn source_path<P: AsRef<Path>>(&self, path: P) -> [PathBuf; 2] {
+        let path = path.as_ref();
+        [path.to_owned(), self.config.site.root.join(path)]
+    }
+
+    /// Get all the loaded pages, grouped by the template they're rendered with.
+    #[must_use]
+    pub fn pages_by_template(&self) -> HashMap<&str, Vec<&Page>> {
+        let mut grouped: HashMap<&str, Vec<&Page>> = HashMap::new();
+        for page in &self.library.pages {
+            grouped.entry(page.template()).or_default().push(page);
+        }
+
+        grouped
+    }
+
+    // Whether a page is selected by the build filter.
+    fn is_selected(&self, page: &Page) -> bool {
+        let filter = &self.config.site.filter;
+        filter
+            .only_template
+            .as_deref()
+            .is_none_or(|t| page.template() == t)
+            && filter
+                .only_section
+                .as_deref()
+                .is_none_or(|s| page.section() == Some(s))
+    }
+
+    // Whether a template page is selected by the build filter. Template pages don't use a
+    // template, so they're left out of builds filtered by template.
+    fn is_template_page_selected(&self, template_page: &TemplatePage) -> bool {
+        let filter = &self.config.site.filter;
+        filter.only_template.is_none()
+            && filter.only_section.as_ref().is_none_or(|s| {
+                template_page
+                    .path
+                    .parent()
+                    .and_then(|p| p.file_name())
+                    .is_some_and(|n| n == s.as_str())
+            })
+    }
+
+    // Whether an asset or static file is selected by the build filter, which is when it's
+    // in the selected section's directory. They don't use a template, so they're left out
+    // of builds filtered by template.
+    fn is_file_selected(&self, path: &Path) -> bool {
+        let filter = &self.config.site.filter;
+        let relative = path.strip_prefix(&self.config.site.root).unwrap_or(path);
+        filter.only_template.is_none()
+            && filter.only_section.as_ref().is_none_or(|s| {
+                relative
+                    .parent()
+                    .is_some_and(|p| p.components().any(|c| c.as_os_str() == s.as_str()))
+            })
+    }
+
+    /// Create the markdown renderer again, keeping its shortcodes, so that changes to the
+    /// syntax theme's file or the markdown options are picked up without starting over,
+    /// such as while serving.
+    ///
+    /// Pages rendered with the old renderer are built again by the next load.
+    pub fn reload_renderer(&mut self) -> Result<()> {
+        let markdown_renderer = markdown_renderer(&self.config, &self.highlights)?
+            .with_shortcodes_of(&self.markdown_renderer);
+        self.markdown_renderer = Arc::new(markdown_renderer);
+        self.reload_environment()
+    }
+
+    /// Take the markdown options and the syntax theme from `config`, like one read again
+    /// after `Config.toml` changed, and [reload the renderer](Self::reload_renderer).
+    ///
+    /// Debug annotations are turned on from the command line, so they're kept as they are.
+    pub fn update_renderer_config(&mut self, config: &Config) -> Result<()> {
+        let debug_annotations = self.config.site.markdown.debug_annotations;
+        self.config.site.markdown = config.site.markdown.clone();
+        self.config.site.markdown.debug_annotations = debug_annotations;
+        self.config
+            .site
+            .syntax_theme
+            .clone_from(&config.site.syntax_theme);
+        self.config
+            .site
+            .syntax_theme_path
+            .clone_from(&config.site.syntax_theme_path);
+
+        self.reload_renderer()
+    }
+
     fn reload_environment(&mut self) -> Result<()> {
-        self.environment = create_environment(&self.config)?;
+        self.environment = Arc::new(create_environment(
+            &self.config,
+            &self.markdown_renderer,
+            &self.dependencies,
+            &self.outputs,
+            &self.thumbnails,
+        )?);
+        self.refresh_globals();
+        Ok(())
+    }
+
+    // The pages this build writes that don't have a source file of their own, so that
+    // they can be linked to and listed in the sitemap.
+    fn find_generated_pages(&self) -> Result<Vec<GeneratedPage>> {
+        let out_dir = &self.config.site.output_path;
+        let url = &self.config.site.url;
+        let development = self.config.site.development;
+        let pages = self
+            .library
+            .listed_pages
+            .iter()
+            .filter(|p| development || !p.document.frontmatter.draft)
+            .collect::<Vec<&Page>>();
+
+        let mut generated = vec![];
+        if self.environment.get_template("author.html").is_ok() {
+            generated.extend(author_pages(
+                &pages,
+                &self.config.site.authors_meta,
+                url,
+                out_dir,
+            ));
+        }
+
+        let lastmod = pages.iter().map(|p| p.document.updated).max();
+        let mut chunks = vec![];
+        for t in &self.library.template_pages {
+            if development || !t.frontmatter.draft {
+                for name in t.chunk_names(
+                    &self.library.listed_pages,
+                    &self.library.tags,
+                    &self.environment,
+                )? {
+                    chunks.push(t.out_path.join(name));
+                }
+            }
+        }
+        // Unchanged template pages aren't loaded, so their chunks are the ones they were
+        // last rendered to.
+        for (path, state) in &self.library.previous_states {
+            if self.library.template_pages.iter().any(|t| &t.path == path) || !path.exists() {
+                continue;
+            }
+            let dir = template_page::out_path(path, out_dir, &self.config.site.root);
+            chunks.extend(state.chunks.iter().map(|name| dir.join(name)));
+        }
+        for dir in chunks {
+            let out_path = dir.join("index.html");
+            generated.push(GeneratedPage {
+                permalink: build_page_url(&out_path, out_dir, url)?,
+                out_path,
+                kind: GeneratedKind::Pagination,
+                lastmod,
+            });
+        }
+
+        generated.sort_by(|a, b| a.out_path.cmp(&b.out_path));
+        Ok(generated)
+    }
+
+    // Record everything in the site, so templates can link to it with `get_url`.
+    //
+    // Unchanged assets and static files aren't loaded, so their paths are worked out
+    // from the hashes stored for them.
+    fn index_outputs(&self) -> Result<()> {
+        let out_dir = &self.config.site.output_path;
+        let root = &self.config.site.root;
+        let insert = |path: &Path, hash: Option<blake3::Hash>| {
+            if let Ok(p) = path.strip_prefix(out_dir) {
+                self.outputs.insert(p.to_owned(), hash);
+            }
+        };
+
+        for (path, hash) in get_hashes(&self.db)? {
+            match Typ::of(&path, &self.config.site) {
+                Typ::Asset => insert(
+                    &asset::rendered_path(&path, out_dir, root),
+                    Some(blake3::Hash::from_bytes(hash)),
+                ),
+                Typ::StaticFile => insert(
+                    &static_file::out_path(&path, out_dir, root),
+                    Some(blake3::Hash::from_bytes(hash)),
+                ),
+                _ => (),
+            }
+        }
+
+        for page in &self.library.pages {
+            if self.config.site.development || !page.document.frontmatter.draft {
+                insert(&page.out_path, None);
+            }
+        }
+        for template_page in &self.library.template_pages {
+            if template_page.frontmatter.pagination.is_none() {
+                insert(&template_page.output_file(), None);
+            }
+        }
+        for asset in &self.library.assets {
+            insert(&asset.out_path, Some(asset.source_hash));
+        }
+        for static_file in &self.library.static_files {
+            insert(&static_file.out_path, Some(static_file.source_hash));
+        }
+        for generated in &self.library.generated_pages {
+            insert(&generated.out_path, None);
+        }
+        for template_page in &self.library.unchanged_template_pages {
+            if template_page.frontmatter.pagination.is_none() {
+                insert(&template_page.output_file(), None);
+            }
+        }
+        for thumbnail in self.thumbnails.cached() {
+            self.outputs.insert(thumbnail.out_path(), None);
+        }
+        for name in ["404.html", "atom.xml", "sitemap.xml", "styles/_syntax.css"] {
+            self.outputs.insert(PathBuf::from(name), None);
+        }
+        if let Some(name) = self.config.site.redirects_format.file_name() {
+            self.outputs.insert(PathBuf::from(name), None);
+        }
+        if self.config.site.webmention_endpoint.is_some() {
+            self.outputs
+                .insert(PathBuf::from(".well-known/host-meta"), None);
+        }
+
+        Ok(())
+    }
+
+    // The neighbors of each page in its section, out of the pages that are rendered and
+    // listed.
+    fn neighbors(&self) -> HashMap<&Path, Neighbors<'_>> {
+        let pages = self
+            .library
+            .listed_pages
+            .iter()
+            .filter(|p| self.config.site.development || !p.document.frontmatter.draft)
+            .collect::<Vec<&Page>>();
+        section_neighbors(&pages)
+    }
+
+    // Record what was rendered for each template page, and remove the pagination chunks
+    // that previous builds wrote but this one didn't.
+    fn remove_stale_chunks(&mut self, rendered_chunks: Vec<(PathBuf, Vec<String>)>) -> Result<()> {
+        let output_path = &self.config.site.output_path;
+
+        for (path, chunks) in rendered_chunks {
+            let Some(template_page) = self.library.template_pages.iter().find(|t| t.path == path)
+            else {
+                continue;
+            };
+
+            if let Some(previous) = self.library.previous_states.get(&path) {
+                for stale in previous.chunks.iter().filter(|c| !chunks.contains(c)) {
+                    let dir = template_page.out_path.join(stale);
+                    if dir.exists() {
+                        retry_io(|| fs::remove_dir_all(&dir))?;
+                    }
+                    if let Ok(relative) = dir.strip_prefix(output_path) {
+                        self.library.removed_outputs.push(relative.to_owned());
+                        self.library.stale_chunks.push(relative.join("index.html"));
+                    }
+                }
+            }
+
+            let collection_hash = self.library.collection_hashes[&path];
+            self.library.rendered_states.push((
+                path,
+                RenderedState {
+                    collection_hash,
+                    chunks,
+                },
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Remove the thumbnails that previous builds generated from images that have since
+    // changed or been removed. Pages using them depend on the image, so they're rendered
+    // again with new thumbnails.
+    fn remove_stale_thumbnails(&mut self) -> Result<()> {
+        let root = &self.config.site.root;
+        let current = self
+            .library
+            .static_files
+            .iter()
+            .map(|s| (s.path.as_path(), s.source_hash))
+            .chain(
+                self.library
+                    .assets
+                    .iter()
+                    .map(|a| (a.path.as_path(), a.source_hash)),
+            )
+            .collect::<HashMap<&Path, blake3::Hash>>();
+
+        for thumbnail in self.thumbnails.cached() {
+            let source = root.join(&thumbnail.source);
+            let changed = current
+                .get(source.as_path())
+                .is_some_and(|hash| *hash != thumbnail.source_hash);
+            if !changed && source.exists() {
+                continue;
+            }
+
+            let out_path = thumbnail.out_path();
+            self.thumbnails.remove(&out_path);
+            let file = self.config.site.output_path.join(&out_path);
+            if file.exists() {
+                retry_io(|| fs::remove_file(&file))?;
+            }
+            self.library.removed_outputs.push(out_path.clone());
+            self.library.stale_thumbnails.push(out_path);
+        }
+
         Ok(())
     }
 
-    fn render_pages(&self) -> Result<()> {
+    /// Outputs from previous builds that the last render removed, such as pagination chunks
+    /// that are no longer needed, relative to the output directory.
+    ///
+    /// Builds render to a directory of their own, so these need removing from wherever
+    /// the site's output is kept.
+    #[must_use]
+    pub fn removed_outputs(&self) -> &[PathBuf] {
+        &self.library.removed_outputs
+    }
+
+    /// How long each page took to render in the last render, including pages rendered
+    /// again with hook outputs.
+    #[must_use]
+    pub const fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    fn render_pages(&self, token: &CancellationToken) -> Result<Vec<(PathBuf, Vec<String>)>> {
         let pages_to_build = self
             .library
             .pages
@@ -226,25 +1459,114 @@ impl Site<'_> {
             .filter(|p| self.library.invalidated_pages.contains(&p.path))
             .collect::<Vec<&Page>>();
 
+        let neighbors = self.neighbors();
         pages_to_build
             .par_iter()
             .filter(|p| self.config.site.development || !p.document.frontmatter.draft)
-            .map(|p| p.render(&self.library.pages, &self.environment))
+            .filter(|p| self.is_selected(p))
+            .map(|p| {
+                check(token)?;
+                p.render(
+                    &self.library.listed_pages,
+                    &self.library.tags,
+                    neighbors.get(p.path.as_path()).copied().unwrap_or_default(),
+                    &self.environment,
+                    &self.config.site.authors_meta,
+                    &self.config.site.url,
+                    &self.timings,
+                )
+            })
             .collect::<Result<Vec<_>>>()?;
 
-        self.library
+        let filter = &self.config.site.filter;
+        let rendered_chunks = self
+            .library
             .template_pages
             .par_iter()
             .filter(|t| self.config.site.development || !t.frontmatter.draft)
-            .map(|t| t.render(&self.library.pages, &self.environment))
+            .filter(|t| self.is_template_page_selected(t))
+            .map(|t| {
+                check(token)?;
+                Ok((
+                    t.path.clone(),
+                    t.render(
+                        &self.library.listed_pages,
+                        &self.library.tags,
+                        &self.environment,
+                        &self.template_page_outputs,
+                        &self.timings,
+                    )?,
+                ))
+            })
             .collect::<Result<Vec<_>>>()?;
 
+        // Author pages list pages from across the site, so they're regenerated whenever a
+        // page changes, and left out of partial builds.
+        let has_author_template = self.environment.get_template("author.html").is_ok();
+        if has_author_template && !pages_to_build.is_empty() && !filter.is_partial() {
+            let pages = self
+                .library
+                .listed_pages
+                .iter()
+                .filter(|p| self.config.site.development || !p.document.frontmatter.draft)
+                .collect::<Vec<&Page>>();
+            render_author_pages(
+                &pages,
+                &self.config.site.authors_meta,
+                &self.config.site.url,
+                &self.config.site.output_path,
+                &self.environment,
+            )?;
+        }
+
         // Generate 404 page.
         let out_path = self.config.site.output_path.join("404.html");
         let template = self.environment.get_template("404.html")?;
         let rendered = template.render(context! {})?;
         fs::write(out_path, rendered)?;
 
+        // A feed or sitemap missing half the site shouldn't be published, so under
+        // partial builds they're skipped unless explicitly filtered.
+        if filter.is_partial() && !filter.filter_feeds {
+            println!("Partial build, skipping atom feed and sitemap");
+        } else {
+            let pages = self
+                .library
+                .pages
+                .iter()
+                .filter(|p| self.config.site.development || !p.document.frontmatter.draft)
+                .filter(|p| self.is_selected(p))
+                .collect::<Vec<&Page>>();
+            // Generated pages list pages from across the site, so they're left out of
+            // partial builds.
+            let generated = if filter.is_partial() {
+                &[]
+            } else {
+                self.library.generated_pages.as_slice()
+            };
+            self.render_feeds(&pages, generated)?;
+        }
+        self.render_redirects()?;
+        self.render_host_meta()?;
+
+        // Write syntax theme.
+        let out_path = self.config.site.output_path.join("styles/_syntax.css");
+        ensure_directory(out_path.parent().unwrap())?;
+        // TODO: Allow configurable selector prefix.
+        let css = self.markdown_renderer.theme_css();
+        fs::write(out_path, css)?;
+
+        Ok(rendered_chunks)
+    }
+
+    // Hidden pages are left out of the feed, and only listed in the sitemap if
+    // `sitemap.include_hidden` is set.
+    fn render_feeds(&self, pages: &[&Page], generated_pages: &[GeneratedPage]) -> Result<()> {
+        let listed = pages
+            .iter()
+            .filter(|p| !p.document.frontmatter.hidden)
+            .collect::<Vec<_>>();
+
         // Generate atom feed.
         let out_path = self.config.site.output_path.join("atom.xml");
         let template = self.environment.get_template("atom.xml")?;
@@ -254,24 +1576,183 @@ impl Site<'_> {
         let rendered = template.render(context! {
             last_updated => last_updated,
             feed_url => feed_url,
-            pages => &self.library.pages,
+            pages => listed,
         })?;
         fs::write(out_path, rendered)?;
 
         // Generate sitemap.
         let out_path = self.config.site.output_path.join("sitemap.xml");
         let template = self.environment.get_template("sitemap.xml")?;
+        let sitemap_pages = if self.config.site.sitemap.include_hidden {
+            pages.to_vec()
+        } else {
+            listed.into_iter().copied().collect()
+        };
         let rendered = template.render(context! {
-            pages => &self.library.pages,
+            pages => sitemap_pages,
+            generated_pages => generated_pages,
         })?;
         fs::write(out_path, rendered)?;
 
-        // Write syntax theme.
-        let out_path = self.config.site.output_path.join("styles/_syntax.css");
+        Ok(())
+    }
+
+    // Redirects are compiled from the aliases of every page, along with the ones in the
+    // config, into the file for the configured host.
+    fn render_redirects(&self) -> Result<()> {
+        let format = self.config.site.redirects_format;
+        let Some(file_name) = format.file_name() else {
+            return Ok(());
+        };
+
+        let pages = self
+            .library
+            .pages
+            .iter()
+            .filter(|p| self.config.site.development || !p.document.frontmatter.draft)
+            .collect::<Vec<&Page>>();
+        let url = &self.config.site.url;
+        let mut rules = redirects::alias_rules(&pages, url);
+        rules.extend(redirects::config_rules(
+            &self.config.site.redirects,
+            &self.outputs,
+            url,
+        )?);
+        let rules = redirects::compile(rules)?;
+
+        if let Some(rendered) = redirects::render(&rules, format)? {
+            fs::write(self.config.site.output_path.join(file_name), rendered)?;
+        }
+
+        Ok(())
+    }
+
+    // Sites with a webmention endpoint advertise it in `.well-known/host-meta` too, for
+    // senders that discover endpoints for the whole host rather than from a page.
+    fn render_host_meta(&self) -> Result<()> {
+        let Some(endpoint) = &self.config.site.webmention_endpoint else {
+            return Ok(());
+        };
+
+        let out_path = self.config.site.output_path.join(".well-known/host-meta");
         ensure_directory(out_path.parent().unwrap())?;
-        // TODO: Allow configurable selector prefix.
-        let css = self.markdown_renderer.theme.to_css("pre");
-        fs::write(out_path, css)?;
+        let href = endpoint
+            .as_str()
+            .replace('&', "&amp;")
+            .replace('"', "&quot;");
+        fs::write(
+            out_path,
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<XRD xmlns="http://docs.oasis-open.org/ns/xri/xrd-1.0">
+    <Link rel="webmention" href="{href}" />
+</XRD>
+"#
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    /// Render the pages and template pages depending on hook outputs again, now that the
+    /// post hooks have written them. Does nothing unless `two_pass` is set.
+    ///
+    /// Hooks aren't run again afterwards, so a build never takes more than two passes.
+    pub fn render_second_pass(&mut self) -> Result<()> {
+        if !self.config.site.two_pass {
+            return Ok(());
+        }
+
+        let mut loaded = HashSet::new();
+        for hook in &self.config.hooks.post {
+            for output in &hook.outputs {
+                let content = fs::read_to_string(&output.path).wrap_err_with(|| {
+                    format!(
+                        "Couldn't read {}, which hook {} should have written",
+                        output.path.display(),
+                        hook.cmd
+                    )
+                })?;
+                let value = serde_json::from_str::<serde_json::Value>(&content)
+                    .wrap_err_with(|| format!("{} isn't valid JSON", output.path.display()))?;
+                Arc::make_mut(&mut self.environment)
+                    .add_global(output.global.clone(), Value::from_serialize(value));
+                loaded.insert(output.global.as_str());
+            }
+        }
+        if loaded.is_empty() {
+            return Ok(());
+        }
+
+        let development = self.config.site.development;
+        let pages = self
+            .library
+            .pages
+            .iter()
+            .filter(|p| {
+                p.document
+                    .frontmatter
+                    .dependencies
+                    .iter()
+                    .any(|d| loaded.contains(d.as_str()))
+            })
+            .filter(|p| development || !p.document.frontmatter.draft)
+            .filter(|p| self.is_selected(p))
+            .collect::<Vec<&Page>>();
+        let template_pages = self
+            .library
+            .template_pages
+            .iter()
+            .chain(&self.library.unchanged_template_pages)
+            .filter(|t| t.collections().any(|c| loaded.contains(c)))
+            .filter(|t| development || !t.frontmatter.draft)
+            .filter(|t| self.is_template_page_selected(t))
+            .collect::<Vec<&TemplatePage>>();
+
+        // The pagination chunks were already worked out in the first pass.
+        for t in &template_pages {
+            if let Some(pagination) = &t.frontmatter.pagination
+                && loaded.contains(pagination.from.as_str())
+            {
+                bail!(
+                    "Template page {} can't paginate over {}, since it's loaded from a hook output",
+                    t.path.display(),
+                    pagination.from
+                );
+            }
+        }
+
+        println!(
+            "Rendering {} pages again with hook outputs",
+            pages.len() + template_pages.len()
+        );
+        let neighbors = self.neighbors();
+        pages
+            .par_iter()
+            .map(|p| {
+                p.render(
+                    &self.library.listed_pages,
+                    &self.library.tags,
+                    neighbors.get(p.path.as_path()).copied().unwrap_or_default(),
+                    &self.environment,
+                    &self.config.site.authors_meta,
+                    &self.config.site.url,
+                    &self.timings,
+                )
+            })
+            .collect::<Result<Vec<()>>>()?;
+        template_pages
+            .par_iter()
+            .map(|t| {
+                t.render(
+                    &self.library.listed_pages,
+                    &self.library.tags,
+                    &self.environment,
+                    &self.template_page_outputs,
+                    &self.timings,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(())
     }
@@ -280,13 +1761,7 @@ impl Site<'_> {
     pub fn run_post_hooks(&self) -> Result<()> {
         for hook in &self.config.hooks.post {
             println!("Running hook with command {}", hook.cmd);
-            let mut split = hook.cmd.split_whitespace();
-            let cmd = split
-                .next()
-                .ok_or_eyre(format!("Post hook command {} not valid.", hook.cmd))?;
-            let args = split.collect::<Vec<&str>>();
-
-            let output = Command::new(cmd).args(args).output()?;
+            let output = hooks::run(hook)?;
             println!("Hook completed with status {}", output.status);
             println!("STDERR: {}", String::from_utf8_lossy(&output.stderr));
             println!("STDOUT: {}", String::from_utf8_lossy(&output.stdout));
@@ -296,23 +1771,141 @@ impl Site<'_> {
     }
 }
 
+// Create the markdown renderer with the options in the config.
+fn markdown_renderer(config: &Config, highlights: &Highlights) -> Result<MarkdownRenderer> {
+    let (min, max) = config.site.markdown.toc_levels;
+    let mut markdown_renderer = MarkdownRenderer::new(
+        config.site.syntax_theme_path.as_ref(),
+        Some(&config.site.syntax_theme),
+    )?
+    .with_toc_levels(min, max)?
+    .with_anchor_style(config.site.markdown.anchor_style)
+    .with_summary_marker(&config.site.markdown.summary_marker)
+    .with_summary_length(config.site.markdown.summary_length)
+    .with_highlight_mode(config.site.markdown.highlight_mode)
+    .with_highlight_cache(Arc::new(highlights.clone()))
+    .with_image_resolver(Arc::new(SiteImages::new(
+        config.site.root.clone(),
+        config.site.url.clone(),
+    )));
+    if config.site.markdown.line_numbers {
+        markdown_renderer = markdown_renderer.with_line_numbers();
+    }
+    if config.site.markdown.emoji {
+        markdown_renderer = markdown_renderer.with_emoji();
+    }
+    if config.site.markdown.smart_punctuation {
+        markdown_renderer = markdown_renderer.with_smart_punctuation();
+    }
+    if config.site.markdown.debug_annotations {
+        markdown_renderer = markdown_renderer.with_debug_annotations();
+    }
+    if config.site.markdown.sanitize_html {
+        markdown_renderer = markdown_renderer.with_sanitizer(SanitizeOptions {
+            allowed_tags: config.site.markdown.allowed_tags.clone(),
+            allowed_iframe_hosts: config.site.markdown.allowed_iframe_hosts.clone(),
+        });
+    }
+
+    Ok(markdown_renderer)
+}
+
+// The settings baked into every output, along with what changed when one differs from
+// the last build.
+//
+// Permalinks are cached with the pages, so they're built again under a different url,
+// files with a different extension than before are built as something else, and
+// images copied before stripping was turned on still have their metadata. Author
+// pages are only written along with pages, which show the authors' names too.
+fn build_settings(config: &Config) -> Result<[(&'static str, String, &'static str); 5]> {
+    Ok([
+        ("url", config.site.url.to_string(), "The site's url changed"),
+        (
+            "authors",
+            serde_json::to_string(&config.site.authors_meta)?,
+            "The authors changed",
+        ),
+        (
+            "extensions",
+            serde_json::to_string(&(
+                &config.site.content_extensions,
+                &config.site.asset_extensions,
+            ))?,
+            "The content or asset extensions changed",
+        ),
+        (
+            "strip_exif",
+            config.site.images.strip_exif.to_string(),
+            "Stripping image metadata was turned on or off",
+        ),
+        (
+            "renderer",
+            renderer_hash(config)?,
+            "The markdown options or the syntax theme changed",
+        ),
+    ])
+}
+
+// A hash of what the markdown renderer is created from: the markdown options, and the
+// syntax theme, along with the contents of its file.
+fn renderer_hash(config: &Config) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&serde_json::to_vec(&(
+        &config.site.markdown,
+        &config.site.syntax_theme,
+        &config.site.syntax_theme_path,
+    ))?);
+    if let Some(path) = &config.site.syntax_theme_path {
+        hasher.update(&fs::read(path)?);
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+// Minify rendered output if asked to.
+fn finish_string(rendered: String, minified: bool) -> String {
+    if minified {
+        String::from_utf8_lossy(&minify_html(&rendered)).into_owned()
+    } else {
+        rendered
+    }
+}
+
 fn process_page(
     entry: Entry,
     config: &Config,
     markdown_renderer: &MarkdownRenderer,
     env: &Environment,
-) -> Result<Processed> {
-    let page = Page::new(
+) -> Result<Page> {
+    // Files with a configured content extension that aren't actually markdown pages
+    // shouldn't fail with a bare parsing error.
+    let path = entry.path.clone();
+    let error = || {
+        if path.extension().is_some_and(|e| e == "html") {
+            format!(
+                "Couldn't build {} as a page. HTML files in `_content` must start with frontmatter",
+                path.display()
+            )
+        } else {
+            format!(
+                "Couldn't build {} as a page. Files with an extension listed in `content_extensions` must be markdown with frontmatter",
+                path.display()
+            )
+        }
+    };
+
+    let content = String::from_utf8(entry.raw_content).wrap_err_with(error)?;
+    Page::new(
         entry.path,
-        String::from_utf8(entry.raw_content)?.as_str(),
+        &content,
         entry.hash,
         &config.site.output_path,
         &config.site.root,
         &config.site.url,
         markdown_renderer,
         env,
-    )?;
-    Ok(Processed::Page(page))
+    )
+    .wrap_err_with(error)
 }
 
 fn process_asset(entry: Entry, config: &Config) -> Result<Processed> {
@@ -352,3 +1945,253 @@ fn process_template_page(entry: Entry, config: &Config) -> Result<Processed> {
 fn process_template(entry: Entry) -> Processed {
     Processed::Template(Template::new(entry.path, entry.hash))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{DatabaseSource, setup_database},
+        test_utils::{make_page, make_site},
+    };
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn test_site_is_send() {
+        assert_send::<Site>();
+    }
+
+    #[test]
+    fn test_debug_annotations() -> Result<()> {
+        let page = "---\ntitle = \"Hello\"\ntags = []\n---\n\nFirst.\n\n## Second\n";
+        let (root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", page),
+            ],
+            |c| c.site.markdown.debug_annotations = true,
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let mut config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        let source = root.path().join("_content/blog/hello.md");
+        let source = source.display();
+        assert!(rendered.contains(&format!("<!-- yar: {source}:6 --> <p>First.")));
+        assert!(rendered.contains(&format!("<!-- yar: {source}:8 --><h2")));
+
+        // Turning annotations off builds the unchanged page again without them.
+        config.site.markdown.debug_annotations = false;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        assert!(!rendered.contains("<!--"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_numbers() -> Result<()> {
+        let page = "---\ntitle = \"Hello\"\ntags = []\n---\n\n```py\nx = 1\n\ny = 2\n```\n";
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", page),
+            ],
+            |c| c.site.markdown.line_numbers = true,
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let mut config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        assert_eq!(rendered.matches("<span class=line>").count(), 3);
+
+        // Turning line numbers off builds the unchanged page again without them.
+        config.site.markdown.line_numbers = false;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        assert!(!rendered.contains("class=line"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_smart_punctuation_rebuilds() -> Result<()> {
+        let page = "---\ntitle = \"Hello\"\ntags = []\n---\n\n\"Quoted\" -- text\n";
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", page),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let mut config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        // Turning smart punctuation on builds the unchanged page again with it.
+        config.site.markdown.smart_punctuation = true;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        assert!(rendered.contains("\u{201c}Quoted\u{201d} \u{2013} text"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload_renderer() -> Result<()> {
+        let page =
+            "---\ntitle = \"Hello\"\ntags = []\n---\n\n:rocket:\n\n```rust\nfn main() {}\n```\n";
+        let (root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", page),
+                ("theme.toml", "keyword = \"#ff0000\""),
+            ],
+            |c| c.site.syntax_theme_path = Some(c.site.root.join("theme.toml")),
+        )?;
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+
+        let css_path = out.path().join("public/styles/_syntax.css");
+        assert!(fs::read_to_string(&css_path)?.contains("#ff0000"));
+
+        // The theme's file changing builds everything again with the new theme.
+        fs::write(root.path().join("theme.toml"), "keyword = \"#00ff00\"")?;
+        site.reload_renderer()?;
+        site.load()?;
+        assert_eq!(site.library.invalidated_pages.len(), 1);
+        site.render()?;
+        site.save_to_cache()?;
+
+        let css = fs::read_to_string(&css_path)?;
+        assert!(css.contains("#00ff00"));
+        assert!(!css.contains("#ff0000"));
+
+        // So do the markdown options in a config read again.
+        let mut config = site.config.clone();
+        config.site.markdown.emoji = true;
+        site.update_renderer_config(&config)?;
+        site.load()?;
+        site.render()?;
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        assert!(rendered.contains("🚀"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_rename() -> Result<()> {
+        let (root, out, site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/About.md", &make_page("About", "post.html")),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        let config = site.config;
+        let build = || -> Result<Vec<PathBuf>> {
+            let mut site = Site::new(
+                setup_database(DatabaseSource::File(&db_path))?,
+                config.clone(),
+            )?;
+            site.load()?;
+            site.render()?;
+            site.save_to_cache()?;
+            Ok(site.removed_outputs().to_vec())
+        };
+        build()?;
+        let public = out.path().join("public");
+        assert!(public.join("blog/About/index.html").exists());
+
+        // Case-insensitive filesystems only report the new name, which is what removing
+        // the old file does here.
+        fs::remove_file(root.path().join("_content/blog/About.md"))?;
+        fs::write(
+            root.path().join("_content/blog/about.md"),
+            make_page("About Me", "post.html"),
+        )?;
+        assert_eq!(build()?, [PathBuf::from("blog/About/index.html")]);
+        assert!(!public.join("blog/About/index.html").exists());
+        assert!(public.join("blog/About-Me/index.html").exists());
+
+        let db = setup_database(DatabaseSource::File(&db_path))?;
+        let new_path = config.site.root.join("_content/blog/about.md");
+        let pages = get_pages(&db, &HashSet::new())?;
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].path, new_path);
+        let hashes = get_hashes(&db)?;
+        assert!(hashes.contains_key(&new_path));
+        assert!(!hashes.contains_key(&config.site.root.join("_content/blog/About.md")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_registered_shortcodes() -> Result<()> {
+        let page = r#"---
+title = "Hello"
+tags = []
+---
+
+{{! stars(repo="yar") !}}{{! end !}}
+"#;
+
+        let (_root, out, site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("templates/stars.html", "from a template"),
+                ("_content/blog/hello.md", page),
+            ],
+            |_| (),
+        )?;
+
+        let stars: ShortcodeFn = Box::new(|s| {
+            Ok(format!(
+                "<span>{:?} has 42 stars</span>",
+                s.arguments["repo"]
+            ))
+        });
+        let mut site = Site::with_shortcodes(
+            setup_database(DatabaseSource::Memory)?,
+            site.config,
+            [("stars".to_string(), stars)],
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        assert!(rendered.contains(r#"String("yar") has 42 stars"#));
+        assert!(!rendered.contains("from a template"));
+
+        Ok(())
+    }
+}
@@ -1,20 +1,29 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Debug;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use blake3::Hash;
 use color_eyre::Result;
-use color_eyre::eyre::ContextCompat;
-use minify_html::{Cfg, minify};
+use color_eyre::eyre::{ContextCompat, WrapErr};
 use minijinja::{Environment, Value, context};
+use percent_encoding::percent_decode_str;
 use serde::{Deserialize, Serialize};
 use std::hash::Hash as StdHash;
 use url::Url;
-use yar_markdown::{Document, MarkdownRenderer};
+use yar_markdown::{Document, Frontmatter, MarkdownRenderer, split_frontmatter};
 
-use crate::templates::PageContext;
-use crate::utils::build_permalink;
+use crate::authors::Author;
+use crate::config::AuthorMeta;
+use crate::protected::Protected;
+use crate::sections::Neighbors;
+use crate::tags::Tag;
+use crate::templates::{Dependencies, PageContext};
+use crate::timings::{PageTiming, Timings};
 use crate::utils::fs::ensure_directory;
+use crate::utils::{build_permalink, minify_html, normalize_path, output_components, safe_join};
 
 /// A single page in the site.
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -24,9 +33,18 @@ pub struct Page {
     pub out_path: PathBuf,
     pub permalink: Url,
     pub document: Document,
+    /// The page's content, encrypted with the passphrase in its frontmatter, if it has
+    /// one. The content and summary are left empty, so that only the title is shown
+    /// anywhere the page is listed.
+    pub protected: Option<Protected>,
+    /// The pages this page links to, by their path, so that it's built again when one of
+    /// them changes.
+    pub links: Vec<PathBuf>,
 }
 
 impl Page {
+    /// Parse a page. Its links to other pages are resolved and it's encrypted afterwards,
+    /// with [`Self::resolve_links`] and [`Self::protect`], since that needs every page.
     #[allow(clippy::too_many_arguments)]
     pub fn new<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
         path: P,
@@ -38,14 +56,20 @@ impl Page {
         markdown_renderer: &MarkdownRenderer,
         env: &Environment,
     ) -> Result<Self> {
-        let document = markdown_renderer.parse_from_string(content, env)?;
+        // HTML pages are already rendered, so only their frontmatter is parsed.
+        let document = if path.as_ref().extension().is_some_and(|e| e == "html") {
+            let split = split_frontmatter(content);
+            Document::from_html(split.deserialize::<Frontmatter>()?, split.body)?
+        } else {
+            markdown_renderer.parse_from_file(&path, content, env)?
+        };
         let out_path = out_path(
             &path,
             &out_dir,
             root,
             &document.frontmatter.title,
             document.frontmatter.slug.as_deref(),
-        );
+        )?;
         let permalink = build_permalink(&out_path, out_dir, url)?;
 
         Ok(Self {
@@ -54,34 +78,181 @@ impl Page {
             source_hash,
             permalink,
             document,
+            protected: None,
+            links: vec![],
         })
     }
 
-    pub fn render(&self, index: &[Self], env: &Environment) -> Result<()> {
+    /// The name of the template this page is rendered with.
+    #[must_use]
+    pub fn template(&self) -> &str {
+        self.document
+            .frontmatter
+            .template
+            .as_deref()
+            .unwrap_or("post.html")
+    }
+
+    /// The section this page is in, which is the name of its parent directory.
+    #[must_use]
+    pub fn section(&self) -> Option<&str> {
+        self.path.parent()?.file_name()?.to_str()
+    }
+
+    /// Point the links to other markdown files in this page at the permalinks of the pages
+    /// built from them, which are looked up by their normalized path.
+    ///
+    /// Returns a description of each link that doesn't point to a page.
+    pub fn resolve_links(&mut self, permalinks: &HashMap<PathBuf, Url>) -> Vec<String> {
+        let dir = self.path.parent().unwrap_or(&self.path);
+        let mut links = vec![];
+        let mut broken = vec![];
+
+        self.document.rewrite_links(|link| {
+            let (target, fragment) = link
+                .split_once('#')
+                .map_or((link, None), |(t, f)| (t, Some(f)));
+            let target = percent_decode_str(target).decode_utf8_lossy();
+            let path = normalize_path(dir.join(target.as_ref()));
+            // Links that don't resolve are kept too, so the page is built again once the
+            // page they point to is added.
+            links.push(path.clone());
+            let Some(permalink) = permalinks.get(&path) else {
+                broken.push(format!(
+                    "{} links to {link}, which isn't a page",
+                    self.path.display()
+                ));
+                return None;
+            };
+
+            let mut permalink = permalink.clone();
+            permalink.set_fragment(fragment);
+            Some(permalink.to_string())
+        });
+        self.links = links;
+
+        broken
+    }
+
+    /// Encrypt this page's content if its frontmatter has a passphrase.
+    pub fn protect(&mut self) -> Result<()> {
+        self.protected = protect(&mut self.document)?;
+        Ok(())
+    }
+
+    /// Render this page to disk, recording how long it took in `timings`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        index: &[Self],
+        tags: &[Tag],
+        neighbors: Neighbors,
+        env: &Environment,
+        authors_meta: &BTreeMap<String, AuthorMeta>,
+        site_url: &Url,
+        timings: &Timings,
+    ) -> Result<()> {
         ensure_directory(
             self.out_path
                 .parent()
                 .context("Path should have a parent")?,
         )?;
 
-        let frontmatter = &self.document.frontmatter;
-        let template = frontmatter.template.as_ref().map_or("post.html", |v| v);
-        let template = env.get_template(template)?;
+        let start = Instant::now();
+        let rendered_html =
+            self.render_to_string(index, tags, neighbors, env, authors_meta, site_url)?;
+        let render = start.elapsed();
+
+        let start = Instant::now();
+        let minified = minify_html(&rendered_html);
+
+        fs::write(&self.out_path, minified)?;
+
+        timings.record(PageTiming {
+            path: self.path.clone(),
+            template: self.template().to_owned(),
+            render,
+            write: start.elapsed(),
+        });
+
+        Ok(())
+    }
+
+    /// Render this page with its template, without minifying it or writing it to disk.
+    pub fn render_to_string(
+        &self,
+        index: &[Self],
+        tags: &[Tag],
+        neighbors: Neighbors,
+        env: &Environment,
+        authors_meta: &BTreeMap<String, AuthorMeta>,
+        site_url: &Url,
+    ) -> Result<String> {
+        let template = env.get_template(self.template())?;
+        // Protected pages are rendered with the form that decrypts their content in place
+        // of it.
+        let mut document = Cow::Borrowed(&self.document);
+        if let Some(protected) = &self.protected {
+            document.to_mut().content = env.get_template("protected.html")?.render(context! {
+                protected,
+                permalink => self.permalink,
+            })?;
+        }
 
         let ctx = Value::from_object(PageContext {
             pages: index.to_vec(),
+            tags: tags.to_vec(),
         });
-        let rendered_html = template.render(context! {
-            document => self.document,  permalink => self.permalink, ..ctx
-        })?;
+        let authors = self
+            .document
+            .frontmatter
+            .authors
+            .iter()
+            .map(|key| Author::resolve(key, authors_meta, site_url))
+            .collect::<Vec<Author>>();
+
+        Dependencies::rendering_page(self.template(), || {
+            Ok(template.render(context! {
+                document,
+                permalink => self.permalink,
+                authors => authors,
+                section_previous => neighbors.previous,
+                section_next => neighbors.next,
+                ..ctx
+            })?)
+        })
+    }
+}
 
-        let cfg = Cfg::new();
-        let minified = minify(rendered_html.as_bytes(), &cfg);
+// Encrypt a document's content if its frontmatter has a passphrase, replacing the
+// document with one only holding its dates and frontmatter, so that nothing derived from
+// the content, like its summary, headings, or links, gives it away. The description and
+// the passphrase itself are dropped, so that they're never cached or given to templates.
+fn protect(document: &mut Document) -> Result<Option<Protected>> {
+    let Some(passphrase) = document.frontmatter.protected.take() else {
+        return Ok(None);
+    };
+    let protected = Protected::encrypt(&passphrase, &document.content)?;
 
-        fs::write(&self.out_path, minified)?;
+    *document = Document {
+        date: document.date,
+        updated: document.updated,
+        content: String::new(),
+        toc: vec![],
+        has_toc: false,
+        toc_ids: BTreeSet::new(),
+        summary: String::new(),
+        summary_text: String::new(),
+        word_count: 0,
+        links: vec![],
+        undefined_footnotes: vec![],
+        frontmatter: Frontmatter {
+            description: None,
+            ..document.frontmatter.clone()
+        },
+    };
 
-        Ok(())
-    }
+    Ok(Some(protected))
 }
 
 impl StdHash for Page {
@@ -96,47 +267,50 @@ fn out_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
     root: Z,
     title: &str,
     slug: Option<&str>,
-) -> PathBuf {
-    let out_dir = out_dir.as_ref();
-
-    let ending = if path.as_ref().ends_with("index.md") {
-        PathBuf::from("index.html")
-    } else {
-        PathBuf::from(slug.map_or_else(|| title.replace(' ', "-"), ToOwned::to_owned))
-            .join("index.html")
-    };
-
-    let mut components = path
+) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let parent = path.parent().unwrap_or(path);
+    let components = output_components(parent, root);
+    let dir = out_dir
         .as_ref()
-        .parent()
-        .unwrap_or_else(|| path.as_ref())
         .components()
-        .filter(|c| !c.as_os_str().to_str().is_some_and(|s| s.starts_with('_')));
+        .chain(components)
+        .collect::<PathBuf>();
 
-    if root.as_ref() != Path::new(".") {
-        components.next();
+    if path.ends_with("index.md") {
+        return Ok(dir.join("index.html"));
     }
+    let (field, ending) = slug.map_or_else(
+        || ("title", title.replace(' ', "-")),
+        |slug| ("slug", slug.to_owned()),
+    );
+    let dir = safe_join(dir, ending).wrap_err_with(|| {
+        format!(
+            "The {field} of {} can't be used as its path",
+            path.display()
+        )
+    })?;
 
-    out_dir
-        .components()
-        .chain(components)
-        .collect::<PathBuf>()
-        .join(ending)
+    Ok(dir.join("index.html"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        builder::SiteBuilder,
+        test_utils::{make_page, make_site},
+    };
 
     #[test]
-    fn test_out_path() {
+    fn test_out_path() -> Result<()> {
         let path = out_path(
             "site/_content/posts/hello-world.md",
             "public",
             "site",
             "hello world",
             None,
-        );
+        )?;
         insta::assert_yaml_snapshot!(path);
 
         let path = out_path(
@@ -145,7 +319,7 @@ mod tests {
             "site",
             "hello world",
             Some("thisisaslug"),
-        );
+        )?;
         insta::assert_yaml_snapshot!(path);
 
         let path = out_path(
@@ -154,10 +328,10 @@ mod tests {
             ".",
             "hello world",
             None,
-        );
+        )?;
         insta::assert_yaml_snapshot!(path);
 
-        let path = out_path("hello-world.md", "public", ".", "hello world", None);
+        let path = out_path("hello-world.md", "public", ".", "hello world", None)?;
         insta::assert_yaml_snapshot!(path);
 
         let path = out_path(
@@ -166,7 +340,7 @@ mod tests {
             "site",
             "this is a series",
             None,
-        );
+        )?;
         insta::assert_yaml_snapshot!(path);
 
         let path = out_path(
@@ -175,10 +349,279 @@ mod tests {
             "site",
             "Part One",
             None,
-        );
+        )?;
         insta::assert_yaml_snapshot!(path);
 
-        let path = out_path("site/_content/index.md", "public", "site", "", None);
+        let path = out_path("site/_content/index.md", "public", "site", "", None)?;
         insta::assert_yaml_snapshot!(path);
+
+        // Only the components under the root are part of the output path, whatever the
+        // directories above it are called.
+        let path = out_path(
+            "public/site/_content/about.md",
+            "public/site/public",
+            "public/site",
+            "About",
+            Some("about"),
+        )?;
+        assert_eq!(path, Path::new("public/site/public/about/index.html"));
+
+        let path = out_path(
+            "/home/_drafts/site/_content/about.md",
+            "/home/_drafts/site/public",
+            "/home/_drafts/site",
+            "About",
+            Some("about"),
+        )?;
+        assert_eq!(
+            path,
+            Path::new("/home/_drafts/site/public/about/index.html")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_out_path_outside_output_dir() {
+        let out_path = |title: &str, slug: Option<&str>| {
+            out_path("site/_content/posts/post.md", "public", "site", title, slug)
+        };
+
+        for slug in [
+            "../../etc/whatever",
+            "/etc/passwd",
+            "nested/../../../up",
+            "aux",
+        ] {
+            let error = out_path("Title", Some(slug)).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains("The slug of site/_content/posts/post.md")
+            );
+        }
+        let error = out_path("..", None).unwrap_err();
+        assert!(error.to_string().starts_with("The title of"));
+    }
+
+    #[test]
+    fn test_render_to_string() -> Result<()> {
+        let (root, _out, site) = make_site(&[], |_| ())?;
+        let listing = "---\ntitle = \"Listing\"\n---\n<ul>{% for page in pages %}<li>{{ page.document.frontmatter.title }}</li>{% endfor %}</ul>";
+        let paginated =
+            "---\ntitle = \"Paginated\"\n[pagination]\nevery = 1\nfrom = \"pages\"\n---\n";
+        for (path, content) in [
+            (
+                "templates/post.html",
+                "<div>  {{ document.content | safe }}  </div>",
+            ),
+            ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+            ("listing.html", listing),
+            ("paginated.html", paginated),
+        ] {
+            let path = root.path().join(path);
+            ensure_directory(path.parent().unwrap())?;
+            fs::write(path, content)?;
+        }
+
+        let site = SiteBuilder::from_config(site.config)
+            .with_memory_db()
+            .load()?;
+
+        let page = site.render_page_to_string("_content/blog/hello.md", false)?;
+        assert_eq!(page, "<div>  <p>Hello from Hello</p>\n  </div>");
+        let page = site.render_page_to_string("_content/blog/hello.md", true)?;
+        assert_eq!(page, "<div><p>Hello from Hello</div>");
+
+        let listing = site.render_template_page_to_string("listing.html", false)?;
+        assert_eq!(listing, "<ul><li>Hello</li></ul>");
+
+        assert!(
+            site.render_template_page_to_string("paginated.html", false)
+                .is_err()
+        );
+        assert!(
+            site.render_page_to_string("_content/missing.md", false)
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_html_page() -> Result<()> {
+        let page = r#"---
+title = "Legacy"
+tags = []
+---
+<p>Written <em>before</em> markdown.</p>
+<h2 id="history">History</h2>
+<p>Lots of it.</p>
+<h2 id="future">The future</h2>
+"#;
+        let (_root, out, mut site) = make_site(
+            &[
+                (
+                    "templates/post.html",
+                    "{% for h in document.toc %}<a href=\"#{{ h.id }}\">{{ h.text }}</a>{% endfor %}{{ document.content | safe }}",
+                ),
+                ("_content/blog/legacy.html", page),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let page = site
+            .library
+            .pages
+            .iter()
+            .find(|p| p.path.ends_with("legacy.html"))
+            .unwrap();
+        assert_eq!(page.permalink.path(), "/blog/Legacy");
+        assert_eq!(
+            page.document.summary_text,
+            "Written before markdown. History Lots of it. The future"
+        );
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Legacy/index.html"))?;
+        assert!(rendered.contains("<a href=#history>History</a><a href=#future>The future</a>"));
+        assert!(rendered.contains("<p>Written <em>before</em> markdown."));
+        assert!(site.library.template_pages.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_toc() -> Result<()> {
+        let template = "{% for h in document.toc recursive %}<li>{{ h.text }}{% if h.children %}<ul>{{ loop(h.children) }}</ul>{% endif %}</li>{% endfor %}";
+        let page = "---\ntitle = \"Hello\"\ntags = []\n---\n\n## One\n\n### One A\n\n#### Deep\n\n## Two\n";
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", template),
+                ("_content/blog/hello.md", page),
+            ],
+            |c| c.site.markdown.toc_levels = (2, 3),
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        // Minifying leaves out the optional closing tags.
+        assert_eq!(rendered, "<li>One<ul><li>One A</ul><li>Two");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicode_anchors() -> Result<()> {
+        let template = "{% for h in document.toc %}<a href=\"#{{ h.id | urlencode }}\"></a>{% endfor %}{{ document.content | safe }}";
+        let page = "---\ntitle = \"Hello\"\ntags = []\n---\n\n## 日本語の見出し\n";
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", template),
+                ("_content/blog/hello.md", page),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+
+        // Links in templates and in the rendered headings are encoded the same way.
+        let rendered = fs::read_to_string(out.path().join("public/blog/Hello/index.html"))?;
+        let href = "#%E6%97%A5%E6%9C%AC%E8%AA%9E%E3%81%AE%E8%A6%8B%E5%87%BA%E3%81%97";
+        assert_eq!(
+            rendered,
+            format!(
+                "<a href={href}></a><h2 id=日本語の見出し><a href={href}>日本語の見出し</a></h2>"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_markdown_links() -> Result<()> {
+        let page = |title: &str, slug: &str, body: &str| {
+            format!("---\ntitle = \"{title}\"\ntags = []\nslug = \"{slug}\"\n---\n\n{body}\n")
+        };
+        let (root, _out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                (
+                    "_content/series/rust/index.md",
+                    &page(
+                        "Rust",
+                        "rust",
+                        "Start with [part one](./part-1.md), or [skip ahead](part-1.md#part3).",
+                    ),
+                ),
+                (
+                    "_content/series/rust/part-1.md",
+                    &page("Part One", "one", "Back to [the series](index.md)."),
+                ),
+                (
+                    "_content/blog/hello.md",
+                    &page(
+                        "Hello",
+                        "hello",
+                        "I wrote [a series](../series/rust/index.md).",
+                    ),
+                ),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+
+        let index = site.render_page_to_string("_content/series/rust/index.md", false)?;
+        assert!(index.contains(r#"<a href="http://0.0.0.0:8000/series/rust/one">part one</a>"#));
+        assert!(index.contains(r#"<a href="http://0.0.0.0:8000/series/rust/one#part3">"#));
+        let part = site.render_page_to_string("_content/series/rust/part-1.md", false)?;
+        assert!(part.contains(r#"<a href="http://0.0.0.0:8000/series/rust">"#));
+        let hello = site.render_page_to_string("_content/blog/hello.md", false)?;
+        assert!(hello.contains(r#"<a href="http://0.0.0.0:8000/series/rust">"#));
+        site.render()?;
+        site.save_to_cache()?;
+
+        // Pages linking to a page are built again when its permalink changes.
+        fs::write(
+            root.path().join("_content/series/rust/part-1.md"),
+            page("Part One", "first", "Back to [the series](index.md)."),
+        )?;
+        site.load()?;
+        let invalidated = &site.library.invalidated_pages;
+        assert!(invalidated.contains(&site.config.site.root.join("_content/series/rust/index.md")));
+        assert!(!invalidated.contains(&site.config.site.root.join("_content/blog/hello.md")));
+        let index = site.render_page_to_string("_content/series/rust/index.md", false)?;
+        assert!(index.contains(r#"<a href="http://0.0.0.0:8000/series/rust/first#part3">"#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_broken_markdown_links() -> Result<()> {
+        let files = [
+            ("templates/post.html", "{{ document.content | safe }}"),
+            (
+                "_content/blog/hello.md",
+                "---\ntitle = \"Hello\"\ntags = []\n---\n\nSee [the sequel](./sequel.md).\n",
+            ),
+        ];
+
+        let (_root, _out, mut site) = make_site(&files, |_| ())?;
+        let error = format!("{:?}", site.load().unwrap_err());
+        assert!(error.contains(&format!(
+            "{} links to ./sequel.md, which isn't a page",
+            site.config.site.root.join("_content/blog/hello.md").display()
+        )));
+
+        // Development builds only warn, leaving the link as it is.
+        let (_root, _out, mut site) = make_site(&files, |c| c.site.development = true)?;
+        site.load()?;
+        let hello = site.render_page_to_string("_content/blog/hello.md", false)?;
+        assert!(hello.contains(r#"<a href="./sequel.md">"#));
+
+        Ok(())
     }
 }
@@ -0,0 +1,226 @@
+use aes_gcm::{
+    AeadCore, Aes256Gcm, Key, KeyInit,
+    aead::{Aead, OsRng, rand_core::RngCore},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use color_eyre::{Result, eyre::eyre};
+use pbkdf2::pbkdf2_hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// The PBKDF2 iterations keys are derived with, as recommended for PBKDF2-HMAC-SHA256.
+/// Tests use fewer, since they're too slow unoptimized.
+const ITERATIONS: u32 = if cfg!(test) { 1_000 } else { 600_000 };
+const SALT_LEN: usize = 16;
+
+/// A page's content, encrypted with the passphrase in its frontmatter.
+///
+/// The content is encrypted with AES-256-GCM, with a key derived from the passphrase
+/// with PBKDF2-HMAC-SHA256, since browsers can decrypt it with the Web Crypto API without
+/// any more script than the `protected.html` template has. Everything is base64 encoded,
+/// and the ciphertext ends with the authentication tag, as Web Crypto expects.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Protected {
+    pub salt: String,
+    pub nonce: String,
+    pub iterations: u32,
+    pub ciphertext: String,
+}
+
+impl Protected {
+    /// Encrypt the content with the passphrase, using a new salt and nonce.
+    pub fn encrypt(passphrase: &str, content: &str) -> Result<Self> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt, ITERATIONS));
+        let ciphertext = cipher
+            .encrypt(&nonce, content.as_bytes())
+            .map_err(|_| eyre!("Couldn't encrypt the page"))?;
+
+        Ok(Self {
+            salt: STANDARD.encode(salt),
+            nonce: STANDARD.encode(nonce),
+            iterations: ITERATIONS,
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+    }
+
+    /// Decrypt the content with the passphrase, as the `protected.html` template does.
+    #[cfg(test)]
+    pub fn decrypt(&self, passphrase: &str) -> Result<String> {
+        let salt = STANDARD.decode(&self.salt)?;
+        let nonce = STANDARD.decode(&self.nonce)?;
+        let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt, self.iterations));
+        let content = cipher
+            .decrypt(
+                aes_gcm::Nonce::from_slice(&nonce),
+                STANDARD.decode(&self.ciphertext)?.as_slice(),
+            )
+            .map_err(|_| eyre!("Wrong passphrase"))?;
+
+        Ok(String::from_utf8(content)?)
+    }
+}
+
+// Derive the key content is encrypted with from a passphrase.
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Key<Aes256Gcm> {
+    let mut key = Key::<Aes256Gcm>::default();
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, fs};
+
+    use super::*;
+    use crate::{
+        database::{dump_tables, get_pages},
+        test_utils::make_site,
+    };
+
+    #[test]
+    fn test_encrypt() -> Result<()> {
+        let content = "<p>Only for friends</p>";
+        let protected = Protected::encrypt("hunter2", content)?;
+
+        assert!(!protected.ciphertext.contains("friends"));
+        assert_eq!(protected.decrypt("hunter2")?, content);
+        assert!(protected.decrypt("hunter3").is_err());
+
+        // Every encryption gets its own salt and nonce.
+        let again = Protected::encrypt("hunter2", content)?;
+        assert_ne!(again.salt, protected.salt);
+        assert_ne!(again.nonce, protected.nonce);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_protected_page() -> Result<()> {
+        let page = "---\ntitle = \"Friends only\"\ntags = []\ndescription = \"Plans for the party\"\nslug = \"friends\"\nprotected = \"hunter2\"\n---\n\n## Secret plans\n\nMeet at the old mill at midnight.\n";
+        let (_root, out, mut site) = make_site(
+            &[
+                (
+                    "templates/post.html",
+                    "<meta content=\"{{ document.summary_text }}\">{% for h in document.toc %}{{ h.text }}{% endfor %}{{ document.content | safe }}",
+                ),
+                (
+                    "index.html",
+                    "---\ntitle = \"Index\"\n---\n{% for page in pages %}{{ page.document.frontmatter.title }}: {{ page.document.summary | safe }}{% endfor %}",
+                ),
+                ("_content/blog/party.md", page),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+
+        let public = out.path().join("public");
+        for file in [
+            "blog/friends/index.html",
+            "index.html",
+            "atom.xml",
+            "sitemap.xml",
+        ] {
+            let rendered = fs::read_to_string(public.join(file))?;
+            for plaintext in ["Secret plans", "old mill", "midnight", "party", "hunter2"] {
+                assert!(!rendered.contains(plaintext), "{file} contains {plaintext}");
+            }
+        }
+        assert!(fs::read_to_string(public.join("index.html"))?.contains("Friends only:"));
+        assert!(
+            fs::read_to_string(public.join("atom.xml"))?.contains("<title>Friends only</title>")
+        );
+
+        // Only the ciphertext is cached, and it decrypts to the page's content.
+        let cached = get_pages(&site.db, &HashSet::new())?;
+        let protected = cached[0].protected.as_ref().unwrap();
+        assert!(cached[0].document.content.is_empty());
+        assert_eq!(cached[0].document.frontmatter.protected, None);
+        let rendered = fs::read_to_string(public.join("blog/friends/index.html"))?;
+        assert!(rendered.contains(&protected.ciphertext));
+        assert_eq!(
+            protected.decrypt("hunter2")?,
+            "<h2 id=\"secret-plans\"><a href=\"#secret-plans\">Secret plans</a></h2>\n<p>Meet at the old mill at midnight.</p>\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_protected_code_isnt_cached() -> Result<()> {
+        let page = "---\ntitle = \"Friends only\"\ntags = []\nprotected = \"hunter2\"\n---\n\n```rust\nlet secret_plans = \"old mill\";\n```\n";
+        let (_root, _out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/party.md", page),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+
+        let cached = get_pages(&site.db, &HashSet::new())?;
+        assert_eq!(cached[0].document.word_count, 0);
+
+        let dump = dump_tables(&site.db)?;
+        let rows = dump.tables.values().flatten();
+        for (key, value) in rows {
+            for plaintext in ["secret_plans", "old mill"] {
+                assert!(!key.contains(plaintext));
+                assert!(
+                    !value
+                        .windows(plaintext.len())
+                        .any(|w| w == plaintext.as_bytes()),
+                    "{key} contains {plaintext}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_protected_page_serialized() -> Result<()> {
+        let page = "---\ntitle = \"Friends only\"\ntags = []\nprotected = \"hunter2\"\n---\n\n## Secret plans\n\nMeet at [the old mill](directions.md) at midnight.[^lantern]\n";
+        let (_root, _out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/party.md", page),
+                (
+                    "_content/blog/directions.md",
+                    "---\ntitle = \"Directions\"\ntags = []\n---\n\nTurn left.\n",
+                ),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+
+        let page = site
+            .library
+            .pages
+            .iter()
+            .find(|p| p.protected.is_some())
+            .unwrap();
+        assert!(page.document.links.is_empty());
+        assert!(page.document.undefined_footnotes.is_empty());
+
+        let serialized = [serde_json::to_vec(page)?, postcard::to_stdvec(page)?];
+        for plaintext in ["Secret plans", "old mill", "midnight", "lantern", "hunter2"] {
+            for bytes in &serialized {
+                assert!(
+                    !bytes
+                        .windows(plaintext.len())
+                        .any(|w| w == plaintext.as_bytes()),
+                    "the page contains {plaintext}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+
+use color_eyre::{Result, eyre::bail};
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    config::{Redirect, RedirectsFormat},
+    page::Page,
+    templates::Outputs,
+};
+
+/// A single redirect, along with where it was defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub from: String,
+    pub to: String,
+    pub status: u16,
+    /// The page or config entry the rule comes from, for error messages.
+    pub source: String,
+}
+
+/// The rules for the aliases of the given pages, which redirect to the page's permalink.
+pub fn alias_rules(pages: &[&Page], url: &Url) -> Vec<Rule> {
+    pages
+        .iter()
+        .flat_map(|page| {
+            page.document.frontmatter.aliases.iter().map(|alias| Rule {
+                from: normalize(alias, url),
+                to: page.permalink.path().to_owned(),
+                status: 301,
+                source: page.path.display().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The rules for the redirects in the config, checking that the ones pointing within the
+/// site point to something that's been output.
+pub fn config_rules(redirects: &[Redirect], outputs: &Outputs, url: &Url) -> Result<Vec<Rule>> {
+    redirects
+        .iter()
+        .map(|redirect| {
+            if !matches!(redirect.status, 301 | 302 | 303 | 307 | 308) {
+                bail!(
+                    "Redirect from {} has status {}, which isn't a redirect status",
+                    redirect.from,
+                    redirect.status
+                );
+            }
+
+            let to = if Url::parse(&redirect.to).is_ok() {
+                redirect.to.clone()
+            } else {
+                if !exists(&redirect.to, outputs) {
+                    bail!(
+                        "Redirect from {} points to {}, which doesn't exist in the site",
+                        redirect.from,
+                        redirect.to
+                    );
+                }
+                normalize(&redirect.to, url)
+            };
+
+            Ok(Rule {
+                from: normalize(&redirect.from, url),
+                to,
+                status: redirect.status,
+                source: format!("[[site.redirects]] entry for {}", redirect.from),
+            })
+        })
+        .collect()
+}
+
+/// Merge rules into one per path, sorted by the path being redirected.
+///
+/// Rules from the same path to the same place are merged, while ones to different places
+/// are an error.
+pub fn compile(rules: Vec<Rule>) -> Result<Vec<Rule>> {
+    let mut compiled = BTreeMap::<String, Rule>::new();
+    for rule in rules {
+        if rule
+            .from
+            .chars()
+            .chain(rule.to.chars())
+            .any(char::is_whitespace)
+        {
+            bail!(
+                "Redirect from {} in {} contains whitespace, which should be percent encoded",
+                rule.from,
+                rule.source
+            );
+        }
+
+        match compiled.get(&rule.from) {
+            Some(existing) if existing.to != rule.to || existing.status != rule.status => bail!(
+                "Conflicting redirects from {}: {} redirects to {} ({}), but {} redirects to {} ({})",
+                rule.from,
+                existing.source,
+                existing.to,
+                existing.status,
+                rule.source,
+                rule.to,
+                rule.status
+            ),
+            Some(_) => (),
+            None => {
+                compiled.insert(rule.from.clone(), rule);
+            }
+        }
+    }
+
+    Ok(compiled.into_values().collect())
+}
+
+/// Write the rules in the given format, or `None` if no file should be generated.
+pub fn render(rules: &[Rule], format: RedirectsFormat) -> Result<Option<String>> {
+    let mut rendered = String::new();
+    match format {
+        RedirectsFormat::Netlify => {
+            for r in rules {
+                writeln!(rendered, "{}  {}  {}", r.from, r.to, r.status)?;
+            }
+        }
+        RedirectsFormat::Vercel => {
+            let redirects = rules
+                .iter()
+                .map(|r| json!({ "source": r.from, "destination": r.to, "statusCode": r.status }))
+                .collect::<Vec<_>>();
+            rendered = serde_json::to_string_pretty(&json!({ "redirects": redirects }))?;
+            rendered.push('\n');
+        }
+        RedirectsFormat::Nginx => {
+            for r in rules {
+                writeln!(
+                    rendered,
+                    "location = \"{}\" {{\n    return {} \"{}\";\n}}",
+                    escape_nginx(&r.from),
+                    r.status,
+                    escape_nginx(&r.to)
+                )?;
+            }
+        }
+        RedirectsFormat::None => return Ok(None),
+    }
+
+    Ok(Some(rendered))
+}
+
+// Paths in the site are written from the root of the host, under the path the site is
+// hosted under, if any.
+fn normalize(path: &str, url: &Url) -> String {
+    format!("{}{}", url.path(), path.trim_start_matches('/'))
+}
+
+// A path either points to a file, or to a directory with an index page.
+fn exists(path: &str, outputs: &Outputs) -> bool {
+    let path = path
+        .split(['#', '?'])
+        .next()
+        .unwrap_or_default()
+        .trim_matches('/');
+    outputs.get(Path::new(path)).is_some()
+        || outputs.get(&Path::new(path).join("index.html")).is_some()
+}
+
+fn escape_nginx(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::test_utils::make_site;
+
+    fn rule(from: &str, to: &str, source: &str) -> Rule {
+        Rule {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            status: 301,
+            source: source.to_owned(),
+        }
+    }
+
+    fn rules() -> Result<Vec<Rule>> {
+        compile(vec![
+            rule("/old/hello", "/blog/hello/", "content/blog/hello.md"),
+            rule("/hi", "/blog/hello/", "content/blog/hello.md"),
+            rule("/hi", "/blog/hello/", "[[site.redirects]] entry for /hi"),
+            Rule {
+                status: 302,
+                ..rule("/feed", "https://example.com/atom.xml", "config")
+            },
+        ])
+    }
+
+    #[test]
+    fn test_netlify() -> Result<()> {
+        let rendered = render(&rules()?, RedirectsFormat::Netlify)?.unwrap();
+        insta::assert_snapshot!(rendered);
+
+        for line in rendered.lines() {
+            assert_eq!(line.split_whitespace().count(), 3);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vercel() -> Result<()> {
+        let rendered = render(&rules()?, RedirectsFormat::Vercel)?.unwrap();
+        insta::assert_snapshot!(rendered);
+
+        let parsed = serde_json::from_str::<serde_json::Value>(&rendered)?;
+        assert_eq!(parsed["redirects"].as_array().map(Vec::len), Some(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nginx() -> Result<()> {
+        let rendered = render(&rules()?, RedirectsFormat::Nginx)?.unwrap();
+        insta::assert_snapshot!(rendered);
+
+        assert_eq!(rendered.matches('{').count(), rendered.matches('}').count());
+        assert_eq!(rendered.matches("return ").count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_format() -> Result<()> {
+        assert_eq!(render(&rules()?, RedirectsFormat::None)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicting_rules() {
+        let error = compile(vec![
+            rule("/hi", "/blog/hello/", "content/blog/hello.md"),
+            rule("/hi", "/blog/other/", "content/blog/other.md"),
+        ])
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.starts_with("Conflicting redirects from /hi"));
+        assert!(error.contains("content/blog/hello.md redirects to /blog/hello/"));
+        assert!(error.contains("content/blog/other.md redirects to /blog/other/"));
+    }
+
+    #[test]
+    fn test_config_targets() -> Result<()> {
+        let outputs = Outputs::default();
+        outputs.insert(PathBuf::from("blog/hello/index.html"), None);
+        outputs.insert(PathBuf::from("atom.xml"), None);
+
+        let redirect = |from: &str, to: &str| Redirect {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            status: 301,
+        };
+        let rules = config_rules(
+            &[
+                redirect("old", "/blog/hello/#top"),
+                redirect("/rss", "atom.xml"),
+                redirect("/elsewhere", "https://example.com/"),
+            ],
+            &outputs,
+            &Url::parse("https://example.com/")?,
+        )?;
+        assert_eq!(
+            rules.iter().map(|r| r.from.as_str()).collect::<Vec<_>>(),
+            ["/old", "/rss", "/elsewhere"]
+        );
+        assert_eq!(rules[1].to, "/atom.xml");
+
+        let error = config_rules(
+            &[redirect("/old", "/blog/missing/")],
+            &outputs,
+            &Url::parse("https://example.com/")?,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            error,
+            "Redirect from /old points to /blog/missing/, which doesn't exist in the site"
+        );
+
+        // Sites hosted under a path redirect from and to paths under it.
+        let rules = config_rules(
+            &[
+                redirect("old", "/blog/hello/#top"),
+                redirect("/rss", "atom.xml"),
+            ],
+            &outputs,
+            &Url::parse("https://example.com/project/")?,
+        )?;
+        assert_eq!(rules[0].from, "/project/old");
+        assert_eq!(rules[0].to, "/project/blog/hello/#top");
+        assert_eq!(rules[1].to, "/project/atom.xml");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_redirects_file() -> Result<()> {
+        let page = |title: &str, aliases: &str| {
+            format!("---\ntitle = \"{title}\"\ntags = []\naliases = [{aliases}]\n---\n\nHello\n")
+        };
+        let files = [
+            ("templates/post.html", "{{ document.content | safe }}"),
+            (
+                "_content/blog/hello.md",
+                &page("Hello", r#""/old/hello", "hi""#),
+            ),
+            ("_content/blog/other.md", &page("Other", "")),
+        ];
+
+        let (_root, out, mut site) = make_site(&files, |c| {
+            c.site.redirects_format = RedirectsFormat::Netlify;
+            c.site.redirects.push(Redirect {
+                from: "/latest".to_owned(),
+                to: "/blog/Other/".to_owned(),
+                status: 302,
+            });
+        })?;
+        site.load()?;
+        site.render()?;
+
+        let rendered = fs::read_to_string(out.path().join("public/_redirects"))?;
+        assert_eq!(
+            rendered,
+            "/hi  /blog/Hello  301\n/latest  /blog/Other/  302\n/old/hello  /blog/Hello  301\n"
+        );
+
+        // An alias clashing with a configured redirect fails the build, naming both.
+        let (_root, _out, mut site) = make_site(&files, |c| {
+            c.site.redirects_format = RedirectsFormat::Nginx;
+            c.site.redirects.push(Redirect {
+                from: "/hi".to_owned(),
+                to: "/blog/Other/".to_owned(),
+                status: 301,
+            });
+        })?;
+        site.load()?;
+        let error = site.render().unwrap_err().to_string();
+        assert!(error.starts_with("Conflicting redirects from /hi"));
+        assert!(error.contains("hello.md redirects to /blog/Hello"));
+        assert!(error.contains("[[site.redirects]] entry for /hi redirects to /blog/Other/"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::Result;
+use ignore::WalkBuilder;
+use serde::Serialize;
+use url::Url;
+
+use crate::timings::{TemplateTiming, Timings};
+
+/// How an output file changed in a build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// An output file that changed in a build, relative to the output directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    pub change: Change,
+}
+
+/// A report of the output files a build changed, for deploy tooling.
+#[derive(Debug, Serialize)]
+pub struct BuildReport {
+    pub changed: Vec<ChangedFile>,
+    pub created: usize,
+    pub modified: usize,
+    pub deleted: usize,
+    /// Files that were written, but are byte for byte the same as before.
+    pub unchanged: usize,
+    pub elapsed_ms: u128,
+    /// How long the pages using each template took to render, by the total time spent
+    /// rendering them. Left out unless timings were asked for.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<TemplateTiming>,
+}
+
+impl BuildReport {
+    /// Compare the files written by a build against the previous output.
+    ///
+    /// `build_dir` should only hold the files written this build, and `previous_dir` the
+    /// output of previous builds. Files are compared by their contents, so rewriting a file
+    /// without changing it doesn't count as a change. `removed` are the outputs the build
+    /// removed, relative to the output directory, like [`Site::removed_outputs`], and the
+    /// files under them in `previous_dir` are reported as deleted.
+    ///
+    /// [`Site::removed_outputs`]: crate::Site::removed_outputs
+    pub fn compare<P: AsRef<Path>, T: AsRef<Path>>(
+        build_dir: P,
+        previous_dir: T,
+        removed: &[PathBuf],
+        elapsed: Duration,
+    ) -> Result<Self> {
+        let build_dir = build_dir.as_ref();
+        let previous_dir = previous_dir.as_ref();
+
+        let mut changed = vec![];
+        let mut unchanged = 0;
+
+        for entry in WalkBuilder::new(build_dir).standard_filters(false).build() {
+            let entry = entry?;
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+
+            let path = entry.path().strip_prefix(build_dir)?.to_owned();
+            let previous = previous_dir.join(&path);
+            let change = if !previous.is_file() {
+                Change::Created
+            } else if blake3::hash(&fs::read(entry.path())?) != blake3::hash(&fs::read(previous)?) {
+                Change::Modified
+            } else {
+                unchanged += 1;
+                continue;
+            };
+
+            changed.push(ChangedFile { path, change });
+        }
+
+        for removed in removed {
+            let removed = previous_dir.join(removed);
+            if !removed.exists() {
+                continue;
+            }
+
+            for entry in WalkBuilder::new(&removed).standard_filters(false).build() {
+                let entry = entry?;
+                if !entry.file_type().is_some_and(|t| t.is_file()) {
+                    continue;
+                }
+
+                // Outputs removed and then written again by the same build aren't gone.
+                let path = entry.path().strip_prefix(previous_dir)?.to_owned();
+                if !build_dir.join(&path).is_file() {
+                    changed.push(ChangedFile {
+                        path,
+                        change: Change::Deleted,
+                    });
+                }
+            }
+        }
+        changed.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let count = |change| changed.iter().filter(|c| c.change == change).count();
+        Ok(Self {
+            created: count(Change::Created),
+            modified: count(Change::Modified),
+            deleted: count(Change::Deleted),
+            unchanged,
+            elapsed_ms: elapsed.as_millis(),
+            changed,
+            templates: vec![],
+        })
+    }
+
+    /// Add the render timings of each template to this report.
+    #[must_use]
+    pub fn with_timings(mut self, timings: &Timings) -> Self {
+        self.templates = timings.templates();
+        self
+    }
+
+    /// Write this report to a file as JSON.
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// What a build would do, from a dry run that renders the site without touching its output
+/// directory or the cache.
+#[derive(Debug, Serialize)]
+pub struct BuildPlan {
+    /// Pages that would be written for the first time.
+    pub pages_created: usize,
+    /// Pages that would be written with different contents.
+    pub pages_updated: usize,
+    /// Outputs of previous builds that would be removed, relative to the output directory.
+    pub removed: Vec<PathBuf>,
+    /// What the assets that changed would be compiled to, relative to the output directory.
+    pub assets: Vec<PathBuf>,
+    /// Where the static files that changed would be copied to, relative to the output
+    /// directory.
+    pub static_files: Vec<PathBuf>,
+    /// The permalinks of every page in the site, sorted.
+    pub permalinks: Vec<Url>,
+    /// The output files that would be created, modified, or deleted.
+    #[serde(flatten)]
+    pub report: BuildReport,
+}
+
+impl BuildPlan {
+    /// Write this plan to a file as JSON.
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::{
+        Site,
+        database::{DatabaseSource, setup_database},
+        test_utils::{make_page, make_site},
+    };
+
+    #[test]
+    fn test_build_report() -> Result<()> {
+        let (root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+                ("_content/blog/other.md", &make_page("Other", "post.html")),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let mut config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        fs::write(
+            root.path().join("_content/blog/hello.md"),
+            make_page("Hello", "post.html") + "\nEdited",
+        )?;
+        config.site.output_path = out.path().join("next");
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        let report = BuildReport::compare(
+            out.path().join("next"),
+            out.path().join("public"),
+            site.removed_outputs(),
+            Duration::from_millis(5),
+        )?;
+        let report_path = out.path().join("report.json");
+        report.write_json(&report_path)?;
+
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(report_path)?)?;
+        assert_eq!(
+            json["changed"],
+            serde_json::json!([
+                { "path": "atom.xml", "change": "modified" },
+                { "path": "blog/Hello/index.html", "change": "modified" },
+                { "path": "sitemap.xml", "change": "modified" },
+            ])
+        );
+        assert_eq!(json["modified"], 3);
+        assert_eq!(json["created"], 0);
+        assert_eq!(json["elapsed_ms"], 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_report_deleted_pages() -> Result<()> {
+        let (root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+                ("_content/blog/other.md", &make_page("Other", "post.html")),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        // Keep the deleted page's output as the previous output to compare against.
+        let output = out.path().join("public/blog/Other/index.html");
+        let previous = out.path().join("previous/blog/Other/index.html");
+        fs::create_dir_all(previous.parent().unwrap())?;
+        fs::copy(&output, &previous)?;
+
+        fs::remove_file(root.path().join("_content/blog/other.md"))?;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+        assert!(!output.exists());
+
+        let report = BuildReport::compare(
+            out.path().join("public"),
+            out.path().join("previous"),
+            site.removed_outputs(),
+            Duration::ZERO,
+        )?;
+        assert!(report.changed.contains(&ChangedFile {
+            path: PathBuf::from("blog/Other/index.html"),
+            change: Change::Deleted,
+        }));
+        assert_eq!(report.deleted, 1);
+
+        Ok(())
+    }
+}
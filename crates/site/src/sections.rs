@@ -0,0 +1,137 @@
+use std::{cmp::Ordering, collections::HashMap, path::Path};
+
+use crate::page::Page;
+
+/// The pages before and after a page in its section.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Neighbors<'a> {
+    pub previous: Option<&'a Page>,
+    pub next: Option<&'a Page>,
+}
+
+/// Sort the pages of a section into reading order.
+///
+/// Pages with a `weight` come first, ordered by weight, followed by the rest ordered by
+/// date. Ties are broken by title and then path, so that the order is always the same.
+pub fn sort_section(pages: &mut [&Page]) {
+    pages.sort_by(|a, b| {
+        let (a_fm, b_fm) = (&a.document.frontmatter, &b.document.frontmatter);
+        let order = match (a_fm.weight, b_fm.weight) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => a.document.date.cmp(&b.document.date),
+        };
+
+        order
+            .then_with(|| a_fm.title.cmp(&b_fm.title))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+}
+
+/// Find the neighbors of each of the given pages in its section.
+///
+/// Section index pages aren't part of the reading order, so they have no neighbors.
+pub fn section_neighbors<'a>(pages: &[&'a Page]) -> HashMap<&'a Path, Neighbors<'a>> {
+    let mut sections: HashMap<Option<&str>, Vec<&Page>> = HashMap::new();
+    for page in pages.iter().filter(|p| !p.path.ends_with("index.md")) {
+        sections.entry(page.section()).or_default().push(page);
+    }
+
+    let mut neighbors = HashMap::new();
+    for section in sections.values_mut() {
+        sort_section(section);
+        for (i, page) in section.iter().enumerate() {
+            neighbors.insert(
+                page.path.as_path(),
+                Neighbors {
+                    previous: i.checked_sub(1).map(|i| section[i]),
+                    next: section.get(i + 1).copied(),
+                },
+            );
+        }
+    }
+
+    neighbors
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use color_eyre::Result;
+
+    use crate::test_utils::make_site;
+
+    #[test]
+    fn test_section_neighbors() -> Result<()> {
+        let page = |title: &str, extra: &str| {
+            format!("---\ntitle = \"{title}\"\ntags = []\n{extra}\n---\n\nHello\n")
+        };
+        let files = [
+            ("_content/docs/index.md", page("Docs", "weight = 0")),
+            ("_content/docs/intro.md", page("Introduction", "weight = 1")),
+            (
+                "_content/docs/install.md",
+                page("Installation", "weight = 2"),
+            ),
+            (
+                "_content/docs/config.md",
+                page("Configuration", "weight = 2"),
+            ),
+            (
+                "_content/docs/faq.md",
+                page("FAQ", "date = \"2025-02-01T00:00:00\""),
+            ),
+            (
+                "_content/docs/changelog.md",
+                page("Changelog", "date = \"2025-01-01T00:00:00\""),
+            ),
+            (
+                "_content/blog/second.md",
+                page("Second", "date = \"2025-02-01T00:00:00\""),
+            ),
+            (
+                "_content/blog/first.md",
+                page("First", "date = \"2025-01-01T00:00:00\""),
+            ),
+        ];
+        let (root, _out, mut site) = make_site(
+            &files
+                .iter()
+                .map(|(path, content)| (*path, content.as_str()))
+                .chain([(
+                    "templates/post.html",
+                    "{% if section_previous %}← {{ section_previous.document.frontmatter.title }}{% endif %} | {% if section_next %}{{ section_next.document.frontmatter.title }} →{% endif %}",
+                )])
+                .collect::<Vec<_>>(),
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+
+        let links = files
+            .iter()
+            .map(|(path, _)| {
+                Ok(format!(
+                    "{path}: {}",
+                    site.render_page_to_string(path, false)?
+                ))
+            })
+            .collect::<Result<Vec<String>>>()?;
+        insta::assert_snapshot!(links.join("\n"));
+
+        // Adding a page changes its neighbors' links, so they're rendered again.
+        fs::write(
+            root.path().join("_content/blog/third.md"),
+            page("Third", "date = \"2025-03-01T00:00:00\""),
+        )?;
+        site.load()?;
+        let invalidated = &site.library.invalidated_pages;
+        assert!(invalidated.contains(&site.config.site.root.join("_content/blog/second.md")));
+        assert!(!invalidated.contains(&site.config.site.root.join("_content/docs/faq.md")));
+
+        Ok(())
+    }
+}
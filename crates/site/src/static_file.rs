@@ -5,10 +5,14 @@ use std::{
 
 use blake3::Hash;
 use color_eyre::{Result, eyre::ContextCompat};
+use img_parts::Bytes;
 use serde::Serialize;
 use url::Url;
 
-use crate::utils::{build_permalink, fs::ensure_directory};
+use crate::{
+    images::{has_gps, has_metadata_extension, strip_metadata},
+    utils::{build_permalink, fs::ensure_directory, output_components},
+};
 
 /// Represents a static asset. These are copied over to the resulting
 /// site as-is.
@@ -39,31 +43,55 @@ impl StaticFile {
         })
     }
 
-    pub fn render(&self) -> Result<()> {
+    /// Copy the file to the output directory, stripping the EXIF metadata of images if
+    /// `strip_exif` is set. The source file is never changed.
+    pub fn render(&self, strip_exif: bool) -> Result<Metadata> {
         ensure_directory(
             self.out_path
                 .parent()
                 .context("Path should have a parent")?,
         )?;
-        fs::copy(&self.path, &self.out_path)?;
-        Ok(())
+        if !has_metadata_extension(&self.path) {
+            fs::copy(&self.path, &self.out_path)?;
+            return Ok(Metadata::Kept);
+        }
+
+        let source = Bytes::from(fs::read(&self.path)?);
+        if strip_exif && let Some(stripped) = strip_metadata(&source)? {
+            fs::write(&self.out_path, stripped)?;
+            return Ok(Metadata::Stripped);
+        }
+        // Images without metadata are copied as they are, so that their hash stays the
+        // same.
+        fs::write(&self.out_path, &source)?;
+        if !strip_exif && has_gps(&source) {
+            return Ok(Metadata::Gps);
+        }
+
+        Ok(Metadata::Kept)
     }
 }
 
-fn out_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
+/// What happened to a static file's metadata as it was copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metadata {
+    /// The file was copied as it is.
+    Kept,
+    /// The file is an image with GPS coordinates in its metadata, which was copied as it
+    /// is since stripping metadata is turned off.
+    Gps,
+    /// The file is an image whose metadata was stripped.
+    Stripped,
+}
+
+/// The path the static file at `path` is copied to.
+pub fn out_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
     path: P,
     out_dir: T,
     root: Z,
 ) -> PathBuf {
     let out_dir = out_dir.as_ref();
-    let mut components = path
-        .as_ref()
-        .components()
-        .filter(|c| !c.as_os_str().to_str().is_some_and(|s| s.starts_with('_')));
-
-    if root.as_ref() != Path::new(".") {
-        components.next();
-    }
+    let components = output_components(path.as_ref(), root);
 
     out_dir.components().chain(components).collect::<PathBuf>()
 }
@@ -71,6 +99,11 @@ fn out_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        Site,
+        database::{DatabaseSource, setup_database},
+        test_utils::make_site,
+    };
 
     #[test]
     fn test_out_path() {
@@ -86,4 +119,41 @@ mod tests {
         let path = out_path("image.png", "public", ".");
         insta::assert_yaml_snapshot!(path);
     }
+
+    #[test]
+    fn test_render_many_static_files() -> Result<()> {
+        let files = (0..400)
+            .map(|i| (format!("static/{}/{i}.txt", i % 7), format!("file {i}")))
+            .collect::<Vec<(String, String)>>();
+        let (root, out, mut site) = make_site(
+            &files
+                .iter()
+                .map(|(p, c)| (p.as_str(), c.as_str()))
+                .collect::<Vec<_>>(),
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+
+        for (path, content) in &files {
+            assert_eq!(
+                &fs::read_to_string(out.path().join("public").join(path))?,
+                content
+            );
+        }
+
+        // Every failure is reported, not just the first.
+        let mut site = Site::new(setup_database(DatabaseSource::Memory)?, site.config)?;
+        site.load()?;
+        for (path, _) in &files[..3] {
+            fs::remove_file(root.path().join(path))?;
+        }
+        let err = site.render().unwrap_err().to_string();
+        assert!(err.starts_with("3 operations failed"));
+        for (path, _) in &files[..3] {
+            assert!(err.contains(path.as_str()));
+        }
+
+        Ok(())
+    }
 }
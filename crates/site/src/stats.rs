@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{page::Page, tags::Tag};
+
+/// Totals across the whole site, available to templates as the `stats` global.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SiteStats {
+    /// The number of published pages, not counting section index pages.
+    pub pages: usize,
+    /// The number of words across those pages.
+    pub words: usize,
+    pub tags: usize,
+    /// The date of the newest page.
+    pub newest: Option<DateTime<Utc>>,
+}
+
+impl SiteStats {
+    /// Total up the given pages, leaving out drafts unless `development` is set.
+    ///
+    /// Hidden pages should already be left out, since they aren't listed anywhere.
+    #[must_use]
+    pub fn collect(pages: &[Page], tags: &[Tag], development: bool) -> Self {
+        let counted = pages
+            .iter()
+            .filter(|p| development || !p.document.frontmatter.draft)
+            .filter(|p| !p.path.ends_with("index.md"))
+            .collect::<Vec<&Page>>();
+
+        Self {
+            pages: counted.len(),
+            words: counted.iter().map(|p| p.document.word_count).sum(),
+            tags: tags.len(),
+            newest: counted.iter().map(|p| p.document.date).max(),
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
+use url::Url;
+
+use crate::{page::Page, templates::template_page::TemplatePage};
+
+/// A tag in the site's taxonomy, merging the variants pages spell it with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Tag {
+    /// The canonical form of the tag, either configured as an alias target or the
+    /// first spelling seen.
+    pub name: String,
+    pub slug: String,
+    /// The number of pages with this tag.
+    pub pages: usize,
+    /// The pages and template pages with this tag, ordered by path.
+    pub members: Vec<TagMember>,
+}
+
+/// Something tagged with a tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagMember {
+    pub title: String,
+    pub permalink: Url,
+}
+
+// The parts of a page or template page that tags are collected from.
+struct Tagged<'a> {
+    path: &'a Path,
+    title: &'a str,
+    permalink: &'a Url,
+    tags: Vec<&'a str>,
+}
+
+/// The identity of a tag, which variants of the same tag share.
+///
+/// Tags are normalized to NFKC so that compatibility variants such as full width
+/// characters match their plain forms, have their whitespace collapsed, and are lowercased.
+pub fn tag_key(tag: &str) -> String {
+    clean(tag).to_lowercase()
+}
+
+/// Collect the tags used by the given pages and template pages, merging tags with the
+/// same identity and resolving aliases, ordered by slug.
+///
+/// Pages are visited in order of their paths so that the first spelling seen doesn't
+/// depend on the order pages were loaded in.
+pub fn collect_tags(
+    pages: &[Page],
+    template_pages: &[&TemplatePage],
+    aliases: &BTreeMap<String, String>,
+) -> Vec<Tag> {
+    let aliases = aliases
+        .iter()
+        .map(|(from, to)| (tag_key(from), clean(to)))
+        .collect::<HashMap<String, String>>();
+    let configured = aliases
+        .values()
+        .map(|to| (tag_key(to), to.clone()))
+        .collect::<HashMap<String, String>>();
+
+    let mut tagged = pages
+        .iter()
+        .map(|p| Tagged {
+            path: &p.path,
+            title: &p.document.frontmatter.title,
+            permalink: &p.permalink,
+            tags: p
+                .document
+                .frontmatter
+                .tags
+                .iter()
+                .map(AsRef::as_ref)
+                .collect(),
+        })
+        .chain(template_pages.iter().map(|t| Tagged {
+            path: &t.path,
+            title: &t.frontmatter.title,
+            permalink: &t.permalink,
+            tags: t.frontmatter.tags.iter().map(String::as_str).collect(),
+        }))
+        .collect::<Vec<Tagged>>();
+    tagged.sort_by_key(|t| t.path);
+
+    let mut tags: BTreeMap<String, Tag> = BTreeMap::new();
+    for item in tagged {
+        let mut seen = vec![];
+        for tag in item.tags {
+            let mut key = tag_key(tag);
+            if key.is_empty() {
+                continue;
+            }
+            if let Some(to) = aliases.get(&key) {
+                key = tag_key(to);
+            }
+            if seen.contains(&key) {
+                continue;
+            }
+
+            let entry = tags.entry(key.clone()).or_insert_with(|| Tag {
+                name: configured.get(&key).cloned().unwrap_or_else(|| clean(tag)),
+                slug: slug(&key),
+                pages: 0,
+                members: vec![],
+            });
+            entry.pages += 1;
+            entry.members.push(TagMember {
+                title: item.title.to_owned(),
+                permalink: item.permalink.clone(),
+            });
+            seen.push(key);
+        }
+    }
+
+    let mut tags = tags.into_values().collect::<Vec<Tag>>();
+    tags.sort_by(|a, b| a.slug.cmp(&b.slug));
+    tags
+}
+
+// Normalize a tag, keeping its case.
+fn clean(tag: &str) -> String {
+    let normalized = tag.nfkc().collect::<String>();
+    normalized
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn slug(key: &str) -> String {
+    key.replace(' ', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+    use minijinja::Environment;
+    use url::Url;
+    use yar_markdown::MarkdownRenderer;
+
+    use super::*;
+
+    fn page(path: &str, tags: &[&str]) -> Result<Page> {
+        let content = format!(
+            "---\ntitle = \"{path}\"\ntags = {}\n---\n\nHello\n",
+            serde_json::to_string(tags)?
+        );
+        Page::new(
+            path,
+            &content,
+            blake3::hash(content.as_bytes()),
+            "public",
+            ".",
+            &Url::parse("https://example.com/")?,
+            &MarkdownRenderer::new::<&str>(None, None)?,
+            &Environment::empty(),
+        )
+    }
+
+    fn template_page(path: &str, tags: &[&str]) -> Result<TemplatePage> {
+        TemplatePage::new(
+            &format!(
+                "---\ntitle = \"{path}\"\ntags = {}\n---\ncontent",
+                serde_json::to_string(tags)?
+            ),
+            blake3::hash(b"hashplaceholder"),
+            path,
+            "public",
+            ".",
+            &Url::parse("https://example.com/")?,
+        )
+    }
+
+    #[test]
+    fn test_collect_tags() -> Result<()> {
+        let pages = [
+            page("b.md", &["rust ", "Ｒｕｓｔ", "Web  Dev"])?,
+            page("a.md", &["Rust", "rustlang", " "])?,
+            page("c.md", &["rust", "web dev", "Café"])?,
+            page("d.md", &["cafe\u{301}"])?,
+        ];
+        let aliases = BTreeMap::from([("RustLang".to_string(), "Rust".to_string())]);
+
+        let tags = collect_tags(&pages, &[], &aliases)
+            .into_iter()
+            .map(|t| Tag {
+                members: vec![],
+                ..t
+            })
+            .collect::<Vec<Tag>>();
+        assert_eq!(
+            tags,
+            vec![
+                Tag {
+                    name: "Café".to_string(),
+                    slug: "café".to_string(),
+                    pages: 2,
+                    members: vec![],
+                },
+                Tag {
+                    name: "Rust".to_string(),
+                    slug: "rust".to_string(),
+                    pages: 3,
+                    members: vec![],
+                },
+                Tag {
+                    name: "Web Dev".to_string(),
+                    slug: "web-dev".to_string(),
+                    pages: 2,
+                    members: vec![],
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_page_tags() -> Result<()> {
+        let pages = [
+            page("blog/yar.md", &["Projects"])?,
+            page("blog/other.md", &["Rust"])?,
+        ];
+        let template_pages = [
+            template_page("projects/index.html", &["projects", "Meta"])?,
+            template_page("archive/index.html", &[])?,
+        ];
+        let template_pages = template_pages.iter().collect::<Vec<&TemplatePage>>();
+
+        let tags = collect_tags(&pages, &template_pages, &BTreeMap::new());
+        let members = tags
+            .iter()
+            .map(|t| {
+                let members = t
+                    .members
+                    .iter()
+                    .map(|m| format!("{} ({})", m.title, m.permalink))
+                    .collect::<Vec<String>>();
+                (t.name.as_str(), t.pages, members)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            members,
+            vec![
+                (
+                    "Meta",
+                    1,
+                    vec!["projects/index.html (https://example.com/projects)".to_string()]
+                ),
+                (
+                    "Projects",
+                    2,
+                    vec![
+                        "blog/yar.md (https://example.com/blog/blog/yar.md)".to_string(),
+                        "projects/index.html (https://example.com/projects)".to_string(),
+                    ]
+                ),
+                (
+                    "Rust",
+                    1,
+                    vec!["blog/other.md (https://example.com/blog/blog/other.md)".to_string()]
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+}
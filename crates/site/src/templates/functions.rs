@@ -1,30 +1,469 @@
-use minijinja::{Value, value::ViaDeserialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
 
-use crate::page::Page;
+use chrono::{DateTime, Datelike};
+use chrono_tz::Tz;
+use minijinja::{
+    Error, ErrorKind, HtmlEscape, State, Value,
+    value::{Kwargs, ViaDeserialize},
+};
+use serde::Serialize;
+use url::Url;
+use yar_markdown::MarkdownRenderer;
 
+use crate::{
+    config::add_trailing_slash,
+    page::Page,
+    sections::sort_section,
+    templates::{Dependencies, Output, Outputs},
+    thumbnails::{Fit, Thumbnail, Thumbnails},
+    utils::url_path,
+};
+
+/// Get the pages in a section, in the order they were given, or in the section's reading
+/// order with `order_by="weight"`.
 #[allow(clippy::needless_pass_by_value)]
 pub fn pages_in_section(
     section_name: String,
     pages: ViaDeserialize<Vec<Page>>,
-) -> minijinja::Value {
-    let section_pages = pages.iter().filter(|page| {
-        page.path.parent().is_some_and(|path| {
-            path.file_name()
-                .is_some_and(|name| name == section_name.as_str())
-        })
-    });
-
-    Value::from_serialize(section_pages.collect::<Vec<&Page>>())
+    kwargs: Kwargs,
+) -> Result<Value, Error> {
+    let order_by = kwargs.get::<Option<&str>>("order_by")?;
+    kwargs.assert_all_used()?;
+
+    let mut section_pages = pages
+        .iter()
+        .filter(|page| page.section() == Some(section_name.as_str()))
+        .collect::<Vec<&Page>>();
+    match order_by {
+        Some("weight") => sort_section(&mut section_pages),
+        Some(other) => {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("pages_in_section can't order pages by {other}, only by weight"),
+            ));
+        }
+        None => (),
+    }
+
+    Ok(Value::from_serialize(section_pages))
+}
+
+/// Create the `include_file` function, which inlines the contents of a file relative to
+/// the site root, escaped, or highlighted if a `lang` is given.
+///
+/// Included files are recorded as dependencies of the template including them, or of the
+/// page's template when a page is rendered. See [`Dependencies::insert`].
+pub fn include_file(
+    root: PathBuf,
+    markdown_renderer: Arc<MarkdownRenderer>,
+    dependencies: Dependencies,
+) -> impl Fn(&State, String, Kwargs) -> Result<Value, Error> + Send + Sync + 'static {
+    move |state, path, kwargs| {
+        let lang = kwargs.get::<Option<String>>("lang")?;
+        kwargs.assert_all_used()?;
+
+        let template = state.name();
+        let error = |msg: String| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("include_file in template {template}: {msg}"),
+            )
+        };
+
+        // The language ends up in attributes, so it's kept to what names languages.
+        if let Some(lang) = &lang
+            && !lang
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-'))
+        {
+            return Err(error(format!("{lang:?} isn't a language")));
+        }
+
+        if !Path::new(&path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(error(format!("path {path} is outside of the site root")));
+        }
+
+        let full_path = root.join(&path);
+        let content = fs::read_to_string(&full_path)
+            .map_err(|e| error(format!("couldn't read {path}: {e}")))?;
+        dependencies.insert(template, full_path);
+
+        let html = match lang {
+            Some(lang) => {
+                let highlighted = markdown_renderer
+                    .highlight(&lang, &content)
+                    .map_err(|e| error(format!("couldn't highlight {path}: {e}")))?;
+                format!(
+                    "<pre lang=\"{lang}\"><code class=\"language-{lang}\">{highlighted}</code></pre>"
+                )
+            }
+            None => HtmlEscape(&content).to_string(),
+        };
+
+        Ok(Value::from_safe_string(html))
+    }
+}
+
+/// Create the `thumbnail` function, which resizes an image relative to the site root to
+/// the given width, and optionally height, returning the thumbnail's permalink.
+///
+/// Takes the keyword argument `fit`, either `"cover"` (the default) to crop the image to
+/// the size, or `"contain"` to fit it within the size. Thumbnails are only generated
+/// again when the image or the size changes, and the image is recorded as a dependency
+/// of the template, or of the page's template when it's called from a layout or partial
+/// while rendering a page.
+pub fn thumbnail(
+    root: PathBuf,
+    output_path: PathBuf,
+    site_url: Url,
+    thumbnails: Thumbnails,
+    outputs: Outputs,
+    dependencies: Dependencies,
+) -> impl Fn(&State, String, u32, Option<u32>, Kwargs) -> Result<Value, Error> + Send + Sync + 'static
+{
+    move |state, path, width, height, kwargs| {
+        let fit = kwargs.get::<Option<&str>>("fit")?;
+        kwargs.assert_all_used()?;
+
+        let template = state.name();
+        let error = |msg: String| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("thumbnail in template {template}: {msg}"),
+            )
+        };
+
+        let fit = match fit {
+            None | Some("cover") => Fit::Cover,
+            Some("contain") => Fit::Contain,
+            Some(other) => {
+                return Err(error(format!(
+                    "unknown fit {other}, expected cover or contain"
+                )));
+            }
+        };
+        if width == 0 || height == Some(0) {
+            return Err(error(format!("{path} can't be resized to nothing")));
+        }
+
+        let source = PathBuf::from(path.trim_start_matches('/'));
+        if !source
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(error(format!("path {path} is outside of the site root")));
+        }
+
+        let full_path = root.join(&source);
+        let content =
+            fs::read(&full_path).map_err(|e| error(format!("couldn't read {path}: {e}")))?;
+        dependencies.insert(template, full_path);
+
+        let thumbnail = Thumbnail {
+            source,
+            source_hash: blake3::hash(&content),
+            width,
+            height,
+            fit,
+        };
+        let out_path = thumbnail.out_path();
+        if thumbnails.claim(&thumbnail) {
+            thumbnail
+                .generate(&content, &output_path)
+                .map_err(|e| error(format!("couldn't resize {path}: {e}")))?;
+        }
+        outputs.insert(out_path.clone(), None);
+
+        let permalink = url_path(&out_path)
+            .ok_or_else(|| error(format!("{path} isn't valid unicode")))
+            .and_then(|p| {
+                site_url
+                    .join(&p)
+                    .map_err(|e| error(format!("invalid thumbnail path: {e}")))
+            })?;
+        Ok(Value::from(permalink.to_string()))
+    }
+}
+
+/// Create the `in_timezone` filter, which converts a UTC datetime to the configured
+/// timezone, so that it can be formatted with `datetimeformat`. Datetimes are left in
+/// UTC if no timezone is configured.
+pub fn in_timezone(timezone: Option<Tz>) -> impl Fn(String) -> Result<String, Error> {
+    move |value| {
+        let datetime = DateTime::parse_from_rfc3339(&value).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("in_timezone: {value} isn't a datetime: {e}"),
+            )
+        })?;
+
+        Ok(datetime
+            .with_timezone(&timezone.unwrap_or(Tz::UTC))
+            .to_rfc3339())
+    }
+}
+
+/// Encode an email address so that it shows up as usual in browsers, but not as plain
+/// text in the HTML, where scrapers would find it.
+///
+/// With `strategy="entities"` (the default), every character is written as an HTML
+/// entity. `strategy="mailto"` makes a `mailto:` link for `href` attributes instead, with
+/// the address alternating between HTML entities and percent encoding.
+#[allow(clippy::needless_pass_by_value)]
+pub fn obfuscate_email(value: String, kwargs: Kwargs) -> Result<Value, Error> {
+    let strategy = kwargs.get::<Option<String>>("strategy")?;
+    kwargs.assert_all_used()?;
+
+    let entity = |out: &mut String, c: char| write!(out, "&#{};", u32::from(c));
+    let mut out = String::new();
+    match strategy.as_deref().unwrap_or("entities") {
+        "entities" => {
+            for c in value.chars() {
+                entity(&mut out, c).ok();
+            }
+        }
+        "mailto" => {
+            // Browsers don't percent decode the scheme, so it's only written as entities.
+            for c in "mailto:".chars() {
+                entity(&mut out, c).ok();
+            }
+            for (i, c) in value.chars().enumerate() {
+                // Percent signs are always percent encoded, so that they can't be mistaken
+                // for the start of an escape once entities are decoded.
+                if i % 2 == 0 && c != '%' {
+                    entity(&mut out, c).ok();
+                } else {
+                    for byte in c.to_string().bytes() {
+                        write!(out, "%{byte:02X}").ok();
+                    }
+                }
+            }
+        }
+        other => {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("obfuscate_email: unknown strategy {other}, expected entities or mailto"),
+            ));
+        }
+    }
+
+    Ok(Value::from_safe_string(out))
+}
+
+/// Create the `get_url` function, which builds the URL of a path relative to the site root.
+///
+/// Takes the keyword arguments `cachebust` (default `false`), which appends a hash of an
+/// asset or static file's contents, and `trailing_slash` (default `true`), which controls
+/// whether URLs to pages end in a slash. Fragments are passed through. Links to targets
+/// that aren't part of the site warn, or error if `strict` is set.
+pub fn get_url(
+    site_url: Url,
+    output_path: PathBuf,
+    strict: bool,
+    outputs: Outputs,
+) -> impl Fn(String, Kwargs) -> Result<Value, Error> + Send + Sync + 'static {
+    move |path, kwargs| {
+        let cachebust = kwargs.get::<Option<bool>>("cachebust")?.unwrap_or(false);
+        let trailing_slash = kwargs
+            .get::<Option<bool>>("trailing_slash")?
+            .unwrap_or(true);
+        kwargs.assert_all_used()?;
+
+        let (path, fragment) = path
+            .split_once('#')
+            .map_or((path.as_str(), None), |(p, f)| (p, Some(f)));
+        let path = path.trim_start_matches('/');
+
+        if !Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("get_url: path {path} is outside of the site root"),
+            ));
+        }
+
+        // A path either points to a file, or to a directory with an index page. Anything
+        // missing from the outputs is looked for on disk.
+        let lookup = |p: &Path| {
+            outputs.get(p).or_else(|| {
+                output_path
+                    .join(p)
+                    .is_file()
+                    .then_some(Output { hash: None })
+            })
+        };
+        let file = lookup(Path::new(path));
+        let is_page = file.is_none() && lookup(&Path::new(path).join("index.html")).is_some();
+
+        if file.is_none() && !is_page {
+            let mut msg = format!("get_url: {path} doesn't point to anything in the site");
+            // Links are case sensitive once deployed, even if they work locally.
+            let suggestion = outputs.find_ignoring_case(Path::new(path)).or_else(|| {
+                outputs
+                    .find_ignoring_case(&Path::new(path).join("index.html"))
+                    .and_then(|p| p.parent().map(Path::to_path_buf))
+            });
+            if let Some(suggestion) = suggestion {
+                write!(msg, ", did you mean {}?", suggestion.display()).ok();
+            }
+            if strict {
+                return Err(Error::new(ErrorKind::InvalidOperation, msg));
+            }
+            println!("Warning: {msg}");
+        }
+
+        let mut url = site_url.join(path.trim_end_matches('/')).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("get_url: invalid path {path}: {e}"),
+            )
+        })?;
+        if is_page && trailing_slash {
+            add_trailing_slash(&mut url);
+        }
+        if cachebust && let Some(hash) = file.and_then(|f| f.hash) {
+            url.set_query(Some(&format!("v={}", &hash.to_hex()[..8])));
+        }
+        url.set_fragment(fragment);
+
+        Ok(Value::from(url.to_string()))
+    }
+}
+
+/// Create the `head_links` function, which writes the `<link>` tags for a page's head: a
+/// `rel="canonical"` link to its permalink, and a `rel="webmention"` link to the
+/// configured webmention endpoint.
+///
+/// The permalink is the `permalink` in the template's context unless it's passed as the
+/// `permalink` keyword argument, such as for template pages. Either link is left out if
+/// there's nothing for it to point to.
+pub fn head_links(
+    webmention_endpoint: Option<Url>,
+) -> impl Fn(&State, Kwargs) -> Result<Value, Error> + Send + Sync + 'static {
+    move |state, kwargs| {
+        let permalink = kwargs
+            .get::<Option<Value>>("permalink")?
+            .or_else(|| state.lookup("permalink"));
+        kwargs.assert_all_used()?;
+
+        // `HtmlEscape` would escape the slashes too, which are fine in attributes.
+        let attribute = |s: &str| s.replace('&', "&amp;").replace('"', "&quot;");
+        let mut out = String::new();
+        if let Some(permalink) = permalink.filter(|p| !p.is_undefined() && !p.is_none()) {
+            write!(
+                out,
+                r#"<link rel="canonical" href="{}">"#,
+                attribute(&permalink.to_string())
+            )
+            .ok();
+        }
+        if let Some(endpoint) = &webmention_endpoint {
+            write!(
+                out,
+                r#"<link rel="webmention" href="{}">"#,
+                attribute(endpoint.as_str())
+            )
+            .ok();
+        }
+
+        Ok(Value::from_safe_string(out))
+    }
+}
+
+/// A year in the `post_calendar`, with the months posts were published in.
+#[derive(Debug, Serialize)]
+struct CalendarYear {
+    year: i32,
+    months: Vec<CalendarMonth>,
+}
+
+/// A month in the `post_calendar`, with the number of posts published in it and the url
+/// of its archive page.
+#[derive(Debug, Serialize)]
+struct CalendarMonth {
+    month: u32,
+    count: usize,
+    url: Url,
+}
+
+/// Create the `post_calendar` function, which counts the posts in each month for archive
+/// navigation, as a list of years with the months posts were published in, newest first.
+///
+/// Months are numbered from 1 and dated in the configured timezone. Each links to
+/// `{archive_path}/{year}/{month}/` under the site url. Drafts (outside of development
+/// builds), hidden pages, and section index pages aren't counted.
+pub fn post_calendar(
+    site_url: Url,
+    archive_path: String,
+    timezone: Option<Tz>,
+    development: bool,
+) -> impl Fn(ViaDeserialize<Vec<Page>>) -> Result<Value, Error> {
+    move |pages| {
+        let timezone = timezone.unwrap_or(Tz::UTC);
+        let mut counts = BTreeMap::<(i32, u32), usize>::new();
+        for page in pages.iter().filter(|p| {
+            let frontmatter = &p.document.frontmatter;
+            (development || !frontmatter.draft)
+                && !frontmatter.hidden
+                && !p.path.ends_with("index.md")
+        }) {
+            let date = page.document.date.with_timezone(&timezone);
+            *counts.entry((date.year(), date.month())).or_default() += 1;
+        }
+
+        let mut years = Vec::<CalendarYear>::new();
+        for ((year, month), count) in counts.into_iter().rev() {
+            let url = site_url
+                .join(&format!(
+                    "{}/{year}/{month:02}/",
+                    archive_path.trim_matches('/')
+                ))
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidOperation,
+                        format!("post_calendar: {archive_path} isn't a valid path: {e}"),
+                    )
+                })?;
+            let month = CalendarMonth { month, count, url };
+            match years.last_mut() {
+                Some(last) if last.year == year => last.months.push(month),
+                _ => years.push(CalendarYear {
+                    year,
+                    months: vec![month],
+                }),
+            }
+        }
+
+        Ok(Value::from_serialize(years))
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+
     use color_eyre::Result;
     use minijinja::Environment;
     use url::Url;
     use yar_markdown::MarkdownRenderer;
 
     use super::*;
+    use crate::{
+        Site,
+        database::{DatabaseSource, get_dependents, setup_database},
+        test_utils::{make_page, make_site},
+    };
 
     #[test]
     fn test_pages_in_section() -> Result<()> {
@@ -64,9 +503,766 @@ Hello World
         let found = pages_in_section(
             "testing".to_string(),
             minijinja::value::ViaDeserialize(pages),
-        );
+            std::iter::empty::<(&str, Value)>().collect(),
+        )?;
         insta::assert_yaml_snapshot!(found);
 
         Ok(())
     }
+
+    #[test]
+    fn test_pages_in_section_by_weight() -> Result<()> {
+        let page = |path: &str, title: &str, weight: &str| {
+            let content = format!("---\ntitle = \"{title}\"\ntags = []\n{weight}\n---\n");
+            Page::new(
+                path,
+                &content,
+                blake3::hash(content.as_bytes()),
+                "public/",
+                "site/",
+                &Url::parse("https://example.com")?,
+                &MarkdownRenderer::new::<&str>(None, None)?,
+                &Environment::empty(),
+            )
+        };
+        let pages = vec![
+            page("site/docs/b.md", "Unweighted", "")?,
+            page("site/docs/c.md", "Second", "weight = 2")?,
+            page("site/docs/a.md", "First", "weight = 1")?,
+            page("site/blog/d.md", "Elsewhere", "weight = 0")?,
+        ];
+
+        let mut env = Environment::new();
+        env.add_function("pages_in_section", pages_in_section);
+        let render = |template: &str| env.render_str(template, minijinja::context! { pages });
+
+        let titles = "{% for page in pages_in_section('docs', pages, order_by='weight') %}{{ page.document.frontmatter.title }} {% endfor %}";
+        assert_eq!(render(titles)?, "First Second Unweighted ");
+        assert!(render("{{ pages_in_section('docs', pages, order_by='title') }}").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_calendar() -> Result<()> {
+        let page = |path: &str, date: &str, extra: &str| {
+            let content =
+                format!("---\ntitle = \"{path}\"\ntags = []\ndate = \"{date}\"\n{extra}\n---\n");
+            Page::new(
+                format!("site/_content/blog/{path}"),
+                &content,
+                blake3::hash(content.as_bytes()),
+                "public/",
+                "site/",
+                &Url::parse("https://example.com")?,
+                &MarkdownRenderer::new::<&str>(None, None)?,
+                &Environment::empty(),
+            )
+        };
+        let pages = vec![
+            page("a.md", "2023-11-02T12:00:00", "")?,
+            page("b.md", "2024-02-10T12:00:00", "")?,
+            page("c.md", "2024-02-20T12:00:00", "")?,
+            page("d.md", "2024-07-01T12:00:00", "")?,
+            // The first of the month in UTC, but still January in New York.
+            page("e.md", "2025-02-01T03:00:00", "")?,
+            page("f.md", "2025-03-15T12:00:00", "")?,
+            page("draft.md", "2025-05-01T12:00:00", "draft = true")?,
+            page("hidden.md", "2025-06-01T12:00:00", "hidden = true")?,
+            page("index.md", "2025-07-01T12:00:00", "")?,
+        ];
+
+        let calendar = post_calendar(
+            Url::parse("https://example.com/blog/")?,
+            "/archive/".to_owned(),
+            Some(Tz::America__New_York),
+            false,
+        );
+        insta::assert_yaml_snapshot!(calendar(minijinja::value::ViaDeserialize(pages.clone()))?);
+
+        // Drafts are counted in development builds.
+        let calendar = post_calendar(
+            Url::parse("https://example.com/")?,
+            "archive".to_owned(),
+            None,
+            true,
+        );
+        let years = calendar(minijinja::value::ViaDeserialize(pages))?;
+        let latest = years.get_item_by_index(0)?;
+        assert_eq!(latest.get_attr("year")?, Value::from(2025));
+        assert_eq!(
+            latest
+                .get_attr("months")?
+                .get_item_by_index(0)?
+                .get_attr("url")?
+                .to_string(),
+            "https://example.com/archive/2025/05/"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_timezone() -> Result<()> {
+        let mut env = Environment::new();
+        env.add_filter(
+            "in_timezone",
+            in_timezone(Some(chrono_tz::America::New_York)),
+        );
+        minijinja_contrib::add_to_environment(&mut env);
+
+        // Daylight saving time started at 2am local time on March 9th, 2025.
+        let render = |date: &str| {
+            env.render_str(
+                r#"{{ date | in_timezone }} {{ date | in_timezone | datetimeformat(format="[hour]:[minute]") }}"#,
+                minijinja::context! { date },
+            )
+        };
+        assert_eq!(
+            render("2025-03-09T06:30:00Z")?,
+            "2025-03-09T01:30:00-05:00 01:30"
+        );
+        assert_eq!(
+            render("2025-03-09T07:30:00Z")?,
+            "2025-03-09T03:30:00-04:00 03:30"
+        );
+
+        let mut utc = Environment::new();
+        utc.add_filter("in_timezone", in_timezone(None));
+        assert_eq!(
+            utc.render_str("{{ \"2025-03-09T07:30:00Z\" | in_timezone }}", ())?,
+            "2025-03-09T07:30:00+00:00"
+        );
+        assert!(
+            utc.render_str("{{ \"tomorrow\" | in_timezone }}", ())
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    fn include_env(root: &Path, dependencies: &Dependencies) -> Result<Environment<'static>> {
+        let mut env = Environment::new();
+        env.add_function(
+            "include_file",
+            include_file(
+                root.to_owned(),
+                Arc::new(MarkdownRenderer::new::<&str>(None, None)?),
+                dependencies.clone(),
+            ),
+        );
+        Ok(env)
+    }
+
+    #[test]
+    fn test_include_file() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::create_dir_all(root.path().join("static/snippets"))?;
+        fs::write(
+            root.path().join("static/snippets/install.sh"),
+            "echo \"<hi>\" && exit",
+        )?;
+
+        let dependencies = Dependencies::default();
+        let mut env = include_env(root.path(), &dependencies)?;
+        env.add_template(
+            "index.html",
+            r#"{{ include_file("static/snippets/install.sh") }}"#,
+        )?;
+
+        let rendered = env.get_template("index.html")?.render(())?;
+        assert_eq!(rendered, "echo &quot;&lt;hi&gt;&quot; &amp;&amp; exit");
+
+        let by_file = dependencies.by_file();
+        let dependents = &by_file[&root.path().join("static/snippets/install.sh")];
+        assert!(dependents.contains("index.html"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_file_highlighted() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::write(root.path().join("hello.py"), "print(\"hello\")")?;
+
+        let mut env = include_env(root.path(), &Dependencies::default())?;
+        env.add_template(
+            "index.html",
+            r#"{{ include_file("hello.py", lang="python") }}"#,
+        )?;
+
+        let rendered = env.get_template("index.html")?.render(())?;
+        assert!(rendered.starts_with(r#"<pre lang="python"><code class="language-python">"#));
+        assert!(rendered.contains("<a-f>print</a-f>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_file_errors() -> Result<()> {
+        let root = tempfile::tempdir()?;
+
+        let mut env = include_env(&root.path().join("site"), &Dependencies::default())?;
+        env.add_template("traversal.html", r#"{{ include_file("../secret") }}"#)?;
+        env.add_template("missing.html", r#"{{ include_file("missing.txt") }}"#)?;
+        env.add_template(
+            "lang.html",
+            r#"{{ include_file("missing.txt", lang='py" onclick="alert(1)') }}"#,
+        )?;
+
+        let err = env.get_template("traversal.html")?.render(()).unwrap_err();
+        assert!(err.to_string().contains("outside of the site root"));
+
+        let err = env.get_template("missing.html")?.render(()).unwrap_err();
+        assert!(err.to_string().contains("missing.html"));
+
+        let err = env.get_template("lang.html")?.render(()).unwrap_err();
+        assert!(err.to_string().contains("isn't a language"));
+
+        Ok(())
+    }
+
+    fn thumbnail_env(root: &Path, output_path: &Path) -> Environment<'static> {
+        let mut env = Environment::new();
+        env.add_function(
+            "thumbnail",
+            thumbnail(
+                root.to_owned(),
+                output_path.to_owned(),
+                Url::parse("https://example.com/").unwrap(),
+                Thumbnails::default(),
+                Outputs::default(),
+                Dependencies::default(),
+            ),
+        );
+        env
+    }
+
+    #[test]
+    fn test_thumbnail() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let out = tempfile::tempdir()?;
+        fs::create_dir_all(root.path().join("images"))?;
+        image::RgbImage::from_pixel(80, 40, image::Rgb([0, 120, 200]))
+            .save(root.path().join("images/cover.png"))?;
+
+        let mut env = thumbnail_env(root.path(), out.path());
+        env.add_template(
+            "cover.txt",
+            r#"{{ thumbnail("images/cover.png", 20, 20, fit="contain") }}"#,
+        )?;
+
+        let url = env.get_template("cover.txt")?.render(())?;
+        let path = url
+            .strip_prefix("https://example.com/")
+            .expect("Thumbnails should be under the site url");
+        assert!(path.starts_with("thumbs/cover-"));
+        assert!(path.ends_with("-20x20-contain.png"));
+        let image = image::open(out.path().join(path))?;
+        assert_eq!((image.width(), image.height()), (20, 10));
+
+        // The second call finds the thumbnail in the cache, rather than generating it again.
+        fs::remove_file(out.path().join(path))?;
+        assert_eq!(env.get_template("cover.txt")?.render(())?, url);
+        assert!(!out.path().join(path).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_thumbnail_in_layout_invalidates_pages() -> Result<()> {
+        let (root, out, mut site) = make_site(
+            &[
+                (
+                    "templates/base.html",
+                    r#"<img src="{{ thumbnail("images/cover.png", 20) }}">{% block main %}{% endblock %}"#,
+                ),
+                (
+                    "templates/post.html",
+                    r#"{% extends "base.html" %}{% block main %}{{ document.content | safe }}{% endblock %}"#,
+                ),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+            ],
+            |_| (),
+        )?;
+        let cover = root.path().join("images/cover.png");
+        fs::create_dir_all(cover.parent().unwrap())?;
+        image::RgbImage::from_pixel(80, 40, image::Rgb([0, 120, 200])).save(&cover)?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        let rendered = out.path().join("public/blog/Hello/index.html");
+        let before = fs::read_to_string(&rendered)?;
+
+        // A new image gets a new thumbnail, which the page using the layout links to.
+        image::RgbImage::from_pixel(80, 40, image::Rgb([200, 0, 0])).save(&cover)?;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        let after = fs::read_to_string(&rendered)?;
+        assert_ne!(after, before);
+        let path = after
+            .split_once("/thumbs/")
+            .and_then(|(_, rest)| rest.split_once('>'))
+            .expect("The page should link to a thumbnail")
+            .0;
+        assert!(out.path().join("public/thumbs").join(path).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_thumbnail_errors() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let mut env = thumbnail_env(root.path(), root.path());
+        env.add_template("missing.html", r#"{{ thumbnail("cover.png", 20) }}"#)?;
+        env.add_template(
+            "fit.html",
+            r#"{{ thumbnail("cover.png", 20, 20, fit="stretch") }}"#,
+        )?;
+
+        let err = env.get_template("missing.html")?.render(()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("thumbnail in template missing.html")
+        );
+        assert!(err.to_string().contains("couldn't read cover.png"));
+
+        let err = env.get_template("fit.html")?.render(()).unwrap_err();
+        assert!(err.to_string().contains("unknown fit stretch"));
+
+        Ok(())
+    }
+
+    fn url_env(output_path: &Path, strict: bool) -> Result<Environment<'static>> {
+        let outputs = Outputs::default();
+        outputs.insert(PathBuf::from("posts/hello/index.html"), None);
+        outputs.insert(
+            PathBuf::from("styles/main.css"),
+            Some(blake3::hash(b"body {}")),
+        );
+        outputs.insert(PathBuf::from("images/a b.png"), None);
+
+        let mut env = Environment::new();
+        env.add_function(
+            "get_url",
+            get_url(
+                Url::parse("https://example.com/blog/")?,
+                output_path.to_owned(),
+                strict,
+                outputs,
+            ),
+        );
+        Ok(env)
+    }
+
+    fn render(env: &Environment, source: &str) -> Result<String, Error> {
+        env.render_str(source, ())
+    }
+
+    #[test]
+    fn test_get_url() -> Result<()> {
+        let out = tempfile::tempdir()?;
+        fs::create_dir_all(out.path().join("tags"))?;
+        fs::write(out.path().join("tags/index.html"), "tags")?;
+        let env = url_env(out.path(), true)?;
+
+        let hash = &blake3::hash(b"body {}").to_hex()[..8];
+        let cases = [
+            (
+                r#"{{ get_url("posts/hello") }}"#,
+                "https://example.com/blog/posts/hello/".to_string(),
+            ),
+            (
+                r#"{{ get_url("/posts/hello/", trailing_slash=false) }}"#,
+                "https://example.com/blog/posts/hello".to_string(),
+            ),
+            (
+                r#"{{ get_url("posts/hello#comments") }}"#,
+                "https://example.com/blog/posts/hello/#comments".to_string(),
+            ),
+            // Fragments are percent-encoded like the links in tables of contents, whether
+            // or not they already are.
+            (
+                r#"{{ get_url("posts/hello#日本語") }}"#,
+                "https://example.com/blog/posts/hello/#%E6%97%A5%E6%9C%AC%E8%AA%9E".to_string(),
+            ),
+            (
+                r#"{{ get_url("posts/hello#%E6%97%A5%E6%9C%AC%E8%AA%9E") }}"#,
+                "https://example.com/blog/posts/hello/#%E6%97%A5%E6%9C%AC%E8%AA%9E".to_string(),
+            ),
+            (
+                r#"{{ get_url("styles/main.css") }}"#,
+                "https://example.com/blog/styles/main.css".to_string(),
+            ),
+            (
+                r#"{{ get_url("styles/main.css", cachebust=true) }}"#,
+                format!("https://example.com/blog/styles/main.css?v={hash}"),
+            ),
+            (
+                r#"{{ get_url("images/a b.png", cachebust=true) }}"#,
+                "https://example.com/blog/images/a%20b.png".to_string(),
+            ),
+            (
+                r#"{{ get_url("tags", cachebust=true) }}"#,
+                "https://example.com/blog/tags/".to_string(),
+            ),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(render(&env, source)?, expected, "{source}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_url_missing_target() -> Result<()> {
+        let out = tempfile::tempdir()?;
+
+        let strict = url_env(out.path(), true)?;
+        let err = render(&strict, r#"{{ get_url("posts/missing") }}"#).unwrap_err();
+        assert!(err.to_string().contains("posts/missing"));
+
+        let err = render(&strict, r#"{{ get_url("../secret") }}"#).unwrap_err();
+        assert!(err.to_string().contains("outside of the site root"));
+
+        let lenient = url_env(out.path(), false)?;
+        assert_eq!(
+            render(&lenient, r#"{{ get_url("posts/missing") }}"#)?,
+            "https://example.com/blog/posts/missing"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_url_wrong_case() -> Result<()> {
+        let out = tempfile::tempdir()?;
+        let env = url_env(out.path(), true)?;
+
+        let err = render(&env, r#"{{ get_url("Posts/Hello/") }}"#).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("get_url: Posts/Hello/ doesn't point to anything in the site, did you mean posts/hello?"),
+            "{err}"
+        );
+
+        let err = render(&env, r#"{{ get_url("styles/Main.css") }}"#).unwrap_err();
+        assert!(err.to_string().contains("did you mean styles/main.css?"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_head_links() -> Result<()> {
+        let endpoint = Url::parse("https://webmention.io/example.com/webmention")?;
+        let mut env = Environment::new();
+        env.add_function("head_links", head_links(Some(endpoint)));
+        let mut unconfigured = Environment::new();
+        unconfigured.add_function("head_links", head_links(None));
+
+        let ctx = minijinja::context! { permalink => "https://example.com/blog/hello/" };
+        assert_eq!(
+            env.render_str("{{ head_links() }}", &ctx)?,
+            r#"<link rel="canonical" href="https://example.com/blog/hello/"><link rel="webmention" href="https://webmention.io/example.com/webmention">"#
+        );
+        assert_eq!(
+            unconfigured.render_str("{{ head_links() }}", &ctx)?,
+            r#"<link rel="canonical" href="https://example.com/blog/hello/">"#
+        );
+
+        // Template pages don't have a permalink, so they pass their own.
+        assert_eq!(
+            unconfigured.render_str(
+                r#"{{ head_links(permalink="https://example.com/tags/") }}"#,
+                ()
+            )?,
+            r#"<link rel="canonical" href="https://example.com/tags/">"#
+        );
+        assert_eq!(
+            env.render_str("{{ head_links() }}", ())?,
+            r#"<link rel="webmention" href="https://webmention.io/example.com/webmention">"#
+        );
+        assert_eq!(unconfigured.render_str("{{ head_links() }}", ())?, "");
+
+        Ok(())
+    }
+
+    // Decode HTML entities, then percent encoding, the way browsers read an `href`.
+    fn decode(html: &str) -> Result<String> {
+        let mut decoded = String::new();
+        let mut rest = html;
+        while let Some(start) = rest.find("&#") {
+            decoded.push_str(&rest[..start]);
+            let end = rest[start..].find(';').unwrap() + start;
+            let code = rest[start + 2..end].parse::<u32>()?;
+            decoded.push(char::from_u32(code).unwrap());
+            rest = &rest[end + 1..];
+        }
+        decoded.push_str(rest);
+
+        Ok(percent_encoding::percent_decode_str(&decoded)
+            .decode_utf8()?
+            .into_owned())
+    }
+
+    #[test]
+    fn test_obfuscate_email() -> Result<()> {
+        let mut env = Environment::new();
+        env.add_filter("obfuscate_email", obfuscate_email);
+
+        for email in ["me@example.com", "josé@exämple.com", "100%me@example.com"] {
+            let ctx = minijinja::context! { email };
+
+            let entities = env.render_str("{{ email | obfuscate_email }}", &ctx)?;
+            assert!(!entities.contains('@'), "{entities}");
+            assert_eq!(decode(&entities)?, email);
+
+            let mailto =
+                env.render_str(r#"{{ email | obfuscate_email(strategy="mailto") }}"#, &ctx)?;
+            assert!(
+                !mailto.contains('@') && !mailto.contains("mailto"),
+                "{mailto}"
+            );
+            assert_eq!(decode(&mailto)?, format!("mailto:{email}"));
+        }
+
+        assert_eq!(
+            env.render_str(r#"{{ "a@b.c" | obfuscate_email(strategy="mailto") }}"#, ())?,
+            "&#109;&#97;&#105;&#108;&#116;&#111;&#58;&#97;%40&#98;%2E&#99;"
+        );
+        let err = env
+            .render_str(r#"{{ "a@b.c" | obfuscate_email(strategy="rot13") }}"#, ())
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown strategy rot13"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_included_file_invalidates_pages() -> Result<()> {
+        let (root, out, mut site) = make_site(
+            &[
+                (
+                    "templates/post.html",
+                    r#"{{ include_file("snippets/install.sh") }}"#,
+                ),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+                ("snippets/install.sh", "echo one"),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        let rendered = out.path().join("public/blog/Hello/index.html");
+        assert!(fs::read_to_string(&rendered)?.contains("echo one"));
+
+        fs::write(root.path().join("snippets/install.sh"), "echo two")?;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        assert!(fs::read_to_string(&rendered)?.contains("echo two"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_included_file_in_layout_invalidates_pages() -> Result<()> {
+        let (root, out, mut site) = make_site(
+            &[
+                (
+                    "templates/base.html",
+                    r#"<main>{% block main %}{% endblock %}</main>{{ include_file("snippets/install.sh") }}"#,
+                ),
+                (
+                    "templates/post.html",
+                    r#"{% extends "base.html" %}{% block main %}{% include "footer.html" %}{% endblock %}"#,
+                ),
+                (
+                    "templates/footer.html",
+                    r#"{{ include_file("snippets/footer.txt") }}"#,
+                ),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+                ("snippets/install.sh", "echo one"),
+                ("snippets/footer.txt", "footer one"),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        let rendered = out.path().join("public/blog/Hello/index.html");
+        assert_eq!(
+            fs::read_to_string(&rendered)?,
+            "<main>footer one</main>echo one"
+        );
+
+        // Files included from a layout or a partial rebuild the pages using them.
+        fs::write(root.path().join("snippets/install.sh"), "echo two")?;
+        fs::write(root.path().join("snippets/footer.txt"), "footer two")?;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        assert_eq!(
+            fs::read_to_string(&rendered)?,
+            "<main>footer two</main>echo two"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_included_files_are_replaced() -> Result<()> {
+        let (root, out, mut site) = make_site(
+            &[
+                (
+                    "templates/post.html",
+                    r#"{{ include_file("snippets/one.sh") }}"#,
+                ),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+                ("snippets/one.sh", "echo one"),
+                ("snippets/two.sh", "echo two"),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let config = site.config.clone();
+        let one = config.site.root.join("snippets/one.sh");
+        let two = config.site.root.join("snippets/two.sh");
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+        let db = setup_database(DatabaseSource::File(&db_path))?;
+        assert!(get_dependents(&db, &HashSet::from([one.clone()]))?.contains("post.html"));
+        drop(db);
+
+        // The template includes another file now, so the first no longer rebuilds it.
+        fs::write(
+            root.path().join("templates/post.html"),
+            r#"{{ include_file("snippets/two.sh") }}"#,
+        )?;
+        fs::write(
+            root.path().join("_content/blog/hello.md"),
+            make_page("Hello", "post.html") + "\nEdited",
+        )?;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+
+        assert!(get_dependents(&site.db, &HashSet::from([one]))?.is_empty());
+        assert!(get_dependents(&site.db, &HashSet::from([two]))?.contains("post.html"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_url_finds_unchanged_files() -> Result<()> {
+        let (root, out, mut site) = make_site(
+            &[
+                (
+                    "templates/post.html",
+                    r#"{{ get_url("images/logo.png", cachebust=true) }}"#,
+                ),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+                ("images/logo.png", "png"),
+            ],
+            |c| c.site.strict_urls = true,
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let mut config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        // Only the page changes, and the next build renders to a fresh directory.
+        fs::write(
+            root.path().join("_content/blog/hello.md"),
+            make_page("Hello", "post.html") + "\nEdited",
+        )?;
+        config.site.output_path = out.path().join("next");
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        let rendered = fs::read_to_string(out.path().join("next/blog/Hello/index.html"))?;
+        let hash = &blake3::hash(b"png").to_hex()[..8];
+        assert!(rendered.contains(&format!("/images/logo.png?v={hash}")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_webmention_links() -> Result<()> {
+        let files = [
+            ("templates/post.html", "<head>{{ head_links() }}</head>"),
+            ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+        ];
+
+        let (_root, out, mut site) = make_site(&files, |c| {
+            c.site.url = Url::parse("https://example.com/").unwrap();
+            c.site.webmention_endpoint =
+                Some(Url::parse("https://webmention.io/example.com/webmention").unwrap());
+        })?;
+        site.load()?;
+        site.render()?;
+
+        let page = site.render_page_to_string("_content/blog/hello.md", false)?;
+        assert_eq!(
+            page,
+            r#"<head><link rel="canonical" href="https://example.com/blog/Hello"><link rel="webmention" href="https://webmention.io/example.com/webmention"></head>"#
+        );
+        let host_meta = fs::read_to_string(out.path().join("public/.well-known/host-meta"))?;
+        assert!(
+            host_meta.contains(
+                r#"<Link rel="webmention" href="https://webmention.io/example.com/webmention" />"#
+            ),
+            "{host_meta}"
+        );
+
+        // Without an endpoint, only the canonical link is written.
+        let (_root, out, mut site) = make_site(&files, |c| {
+            c.site.url = Url::parse("https://example.com/").unwrap();
+        })?;
+        site.load()?;
+        site.render()?;
+
+        let page = site.render_page_to_string("_content/blog/hello.md", false)?;
+        assert_eq!(
+            page,
+            r#"<head><link rel="canonical" href="https://example.com/blog/Hello"></head>"#
+        );
+        assert!(!out.path().join("public/.well-known").exists());
+
+        Ok(())
+    }
 }
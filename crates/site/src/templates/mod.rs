@@ -2,14 +2,31 @@ pub mod template_page;
 
 mod functions;
 
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
 
 use blake3::Hash;
 use color_eyre::Result;
 use minijinja::{Environment, Value, context, path_loader, value::Object};
 use serde::Serialize;
+use yar_markdown::MarkdownRenderer;
 
-use crate::{config::Config, page::Page, templates::functions::pages_in_section};
+use crate::{
+    authors::all_authors,
+    config::Config,
+    entry::variant_key,
+    page::Page,
+    tags::Tag,
+    templates::functions::{
+        get_url, head_links, in_timezone, include_file, obfuscate_email, pages_in_section,
+        post_calendar, thumbnail,
+    },
+    thumbnails::Thumbnails,
+};
 
 const DEFAULT_404: &str = r#"<!DOCTYPE html>
 <h1> Page Not Found</h1>
@@ -23,32 +40,24 @@ const DEFAULT_ATOM_FEED: &str = r#"<?xml version="1.0" encoding="utf-8"?>
     <id>{{ feed_url | safe }}</id>
     <link href="{{ feed_url | safe }}" rel="self" />
     <link href="{{ site.url | safe }}"/>
-    {% for page in pages %}
-    {% if page.path is not endingwith "index.md" %}
+    {%- for page in pages if page.path is not endingwith "index.md" %}
     <entry>
         <title>{{ page.document.frontmatter.title }}</title>
         <published>{{ page.document.date | datetimeformat(format="iso") }}</published>
         <updated>{{ page.document.updated | datetimeformat(format="iso") }}</updated>
         <id>{{ page.permalink | safe }}</id>
-        <link rel="alternate" href="{{page.permalink}}" />
-        {% if site.authors %}
-            {% for author in site.authors %}
-            <author>
-                <name>{{ author }}</name>
-            </author>
-            {% endfor %}
-        {% else %}
-            <author>
-                <name>Unknown</name>
-            </author>
-        {% endif %}
+        <link rel="alternate" type="text/html" href="{{ page.permalink | safe }}" />
+        {%- for author in site.authors or ["Unknown"] %}
+        <author>
+            <name>{{ author }}</name>
+        </author>
+        {%- endfor %}
+        {%- if not page.protected %}
         <summary type="html">{{ page.document.summary | safe }}</summary>
-        <content type="html">
-            {{ page.document.content | safe }}
-        </content>
+        <content type="html">{{ page.document.content | safe }}</content>
+        {%- endif %}
     </entry>
-    {% endif %}
-    {% endfor %}
+    {%- endfor %}
 </feed>
 "#;
 
@@ -60,9 +69,73 @@ const DEFAULT_SITEMAP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
         <lastmod>{{ page.document.updated }}</lastmod>
     </url>
     {%- endfor %}
+    {%- for page in generated_pages %}
+    <url>
+        <loc>{{ page.permalink | safe }}</loc>
+        {%- if page.lastmod %}
+        <lastmod>{{ page.lastmod }}</lastmod>
+        {%- endif %}
+    </url>
+    {%- endfor %}
 </urlset>
 "#;
 
+// The form protected pages are rendered with in place of their content, which decrypts it
+// in the browser. See `Protected` for how it's encrypted.
+const DEFAULT_PROTECTED: &str = r#"<div class="protected" data-salt="{{ protected.salt }}" data-nonce="{{ protected.nonce }}" data-iterations="{{ protected.iterations }}" data-ciphertext="{{ protected.ciphertext }}">
+    <form>
+        <label>This page is protected. Passphrase: <input type="password" name="passphrase" autocomplete="current-password" required></label>
+        <button>Unlock</button>
+        <p class="protected-error" hidden>That passphrase didn't work.</p>
+    </form>
+</div>
+<script>
+(() => {
+    const gate = document.currentScript.previousElementSibling;
+    const form = gate.querySelector("form");
+    const bytes = (base64) => Uint8Array.from(atob(base64), (c) => c.charCodeAt(0));
+
+    form.addEventListener("submit", async (event) => {
+        event.preventDefault();
+        try {
+            const passphrase = new TextEncoder().encode(form.elements.passphrase.value);
+            const material = await crypto.subtle.importKey("raw", passphrase, "PBKDF2", false, ["deriveKey"]);
+            const key = await crypto.subtle.deriveKey(
+                {
+                    name: "PBKDF2",
+                    hash: "SHA-256",
+                    salt: bytes(gate.dataset.salt),
+                    iterations: Number(gate.dataset.iterations),
+                },
+                material,
+                { name: "AES-GCM", length: 256 },
+                false,
+                ["decrypt"],
+            );
+            const content = await crypto.subtle.decrypt(
+                { name: "AES-GCM", iv: bytes(gate.dataset.nonce) },
+                key,
+                bytes(gate.dataset.ciphertext),
+            );
+            gate.innerHTML = new TextDecoder().decode(content);
+        } catch {
+            gate.querySelector(".protected-error").hidden = false;
+        }
+    });
+})();
+</script>
+"#;
+
+/// The built-in templates, by name.
+///
+/// These are only used when the site doesn't provide a template with the same name.
+pub const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    ("404.html", DEFAULT_404),
+    ("atom.xml", DEFAULT_ATOM_FEED),
+    ("sitemap.xml", DEFAULT_SITEMAP),
+    ("protected.html", DEFAULT_PROTECTED),
+];
+
 /// A template, used for caching.
 #[derive(Debug, Serialize)]
 pub struct Template {
@@ -76,16 +149,132 @@ impl Template {
     }
 }
 
+thread_local! {
+    // The template of the page being rendered on this thread, if any.
+    static PAGE_TEMPLATE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Files that templates depend on, keyed by template name.
+///
+/// Filled in by template functions such as `include_file` as templates are rendered,
+/// so that pages can be rebuilt when a file their template depends on changes.
+#[derive(Debug, Clone, Default)]
+pub struct Dependencies(Arc<Mutex<HashMap<String, HashSet<PathBuf>>>>);
+
+impl Dependencies {
+    /// Record that a template depends on the file at the given path.
+    ///
+    /// While a page is rendered with [`Self::rendering_page`], the file is recorded
+    /// against the page's template instead, since `template` may be a layout it extends
+    /// or a partial it includes, which pages are never rebuilt by.
+    pub fn insert(&self, template: &str, path: PathBuf) {
+        let template = PAGE_TEMPLATE
+            .with_borrow(Clone::clone)
+            .unwrap_or_else(|| template.to_owned());
+        self.0
+            .lock()
+            .expect("Dependencies lock poisoned")
+            .entry(template)
+            .or_default()
+            .insert(path);
+    }
+
+    /// Run `render`, which renders a page with `template` on this thread, recording the
+    /// files it depends on against that template.
+    pub fn rendering_page<T>(template: &str, render: impl FnOnce() -> T) -> T {
+        let previous = PAGE_TEMPLATE.replace(Some(template.to_owned()));
+        let rendered = render();
+        PAGE_TEMPLATE.set(previous);
+        rendered
+    }
+
+    /// Forget the recorded dependencies, before the templates are rendered again.
+    pub fn clear(&self) {
+        self.0.lock().expect("Dependencies lock poisoned").clear();
+    }
+
+    /// Get the recorded dependencies, as a map from file path to the templates depending on it.
+    pub fn by_file(&self) -> HashMap<PathBuf, HashSet<String>> {
+        let mut by_file: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+        for (template, paths) in self.0.lock().expect("Dependencies lock poisoned").iter() {
+            for path in paths {
+                by_file
+                    .entry(path.clone())
+                    .or_default()
+                    .insert(template.clone());
+            }
+        }
+
+        by_file
+    }
+}
+
+/// A file written by a build.
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    /// The hash of the file's contents, for assets and static files, used for cache busting.
+    pub hash: Option<Hash>,
+}
+
+/// The files a build writes, keyed by their path relative to the output directory.
+#[derive(Debug, Clone, Default)]
+pub struct Outputs(Arc<RwLock<HashMap<PathBuf, Output>>>);
+
+impl Outputs {
+    /// Record an output file.
+    pub fn insert(&self, path: PathBuf, hash: Option<Hash>) {
+        self.0
+            .write()
+            .expect("Outputs lock poisoned")
+            .insert(path, Output { hash });
+    }
+
+    /// Look up an output file.
+    pub fn get(&self, path: &Path) -> Option<Output> {
+        self.0
+            .read()
+            .expect("Outputs lock poisoned")
+            .get(path)
+            .copied()
+    }
+
+    /// Find an output file with the same path as the given one once case is ignored.
+    pub fn find_ignoring_case(&self, path: &Path) -> Option<PathBuf> {
+        let key = variant_key(path)?;
+        self.0
+            .read()
+            .expect("Outputs lock poisoned")
+            .keys()
+            .find(|p| variant_key(p).as_ref() == Some(&key))
+            .cloned()
+    }
+
+    /// The paths of all the output files, sorted.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = self
+            .0
+            .read()
+            .expect("Outputs lock poisoned")
+            .keys()
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+        paths
+    }
+}
+
 /// The context that is passed to pages when they are rendered.
 #[derive(Debug)]
 pub struct PageContext {
     pub pages: Vec<Page>,
+    pub tags: Vec<Tag>,
 }
 
 impl Object for PageContext {
     fn get_value(self: &Arc<Self>, field: &Value) -> Option<Value> {
         match field.as_str()? {
             "pages" => Some(Value::from_serialize(&self.pages)),
+            "tags" => Some(Value::from_serialize(&self.tags)),
             _ => None,
         }
     }
@@ -94,14 +283,29 @@ impl Object for PageContext {
 /// Initialize the template environment.
 ///
 /// Loads all templates from the templates directory, some defaults
-/// defined in this file, and global variables.
-pub fn create_environment(config: &Config) -> Result<Environment<'static>> {
+/// defined in this file, and global variables. Templates in the templates
+/// directory take precedence over the defaults.
+#[allow(clippy::unnecessary_wraps)]
+pub fn create_environment(
+    config: &Config,
+    markdown_renderer: &Arc<MarkdownRenderer>,
+    dependencies: &Dependencies,
+    outputs: &Outputs,
+    thumbnails: &Thumbnails,
+) -> Result<Environment<'static>> {
     let mut env = Environment::new();
 
-    env.add_template("404.html", DEFAULT_404)?;
-    env.add_template("atom.xml", DEFAULT_ATOM_FEED)?;
-    env.add_template("sitemap.xml", DEFAULT_SITEMAP)?;
-    env.set_loader(path_loader(&config.site.root.join("templates")));
+    let loader = path_loader(config.site.root.join("templates"));
+    env.set_loader(move |name| {
+        if let Some(template) = loader(name)? {
+            return Ok(Some(template));
+        }
+
+        Ok(DEFAULT_TEMPLATES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, t)| (*t).to_owned()))
+    });
     env.add_global(
         "site",
         context! {
@@ -109,9 +313,59 @@ pub fn create_environment(config: &Config) -> Result<Environment<'static>> {
             authors => config.site.authors,
             title => config.site.title,
             description => config.site.description,
+            email => config.site.email,
+            webmention_endpoint => config.site.webmention_endpoint,
+            timezone => config.site.timezone,
         },
     );
+    env.add_global(
+        "authors",
+        Value::from_serialize(all_authors(&config.site.authors_meta, &config.site.url)),
+    );
     env.add_function("pages_in_section", pages_in_section);
+    env.add_function(
+        "include_file",
+        include_file(
+            config.site.root.clone(),
+            markdown_renderer.clone(),
+            dependencies.clone(),
+        ),
+    );
+    env.add_function(
+        "get_url",
+        get_url(
+            config.site.url.clone(),
+            config.site.output_path.clone(),
+            config.site.strict_urls,
+            outputs.clone(),
+        ),
+    );
+    env.add_function(
+        "thumbnail",
+        thumbnail(
+            config.site.root.clone(),
+            config.site.output_path.clone(),
+            config.site.url.clone(),
+            thumbnails.clone(),
+            outputs.clone(),
+            dependencies.clone(),
+        ),
+    );
+    env.add_function(
+        "head_links",
+        head_links(config.site.webmention_endpoint.clone()),
+    );
+    env.add_function(
+        "post_calendar",
+        post_calendar(
+            config.site.url.clone(),
+            config.site.archive_path.clone(),
+            config.site.timezone,
+            config.site.development,
+        ),
+    );
+    env.add_filter("in_timezone", in_timezone(config.site.timezone));
+    env.add_filter("obfuscate_email", obfuscate_email);
     minijinja_contrib::add_to_environment(&mut env);
 
     Ok(env)
@@ -119,6 +373,8 @@ pub fn create_environment(config: &Config) -> Result<Environment<'static>> {
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
     use chrono::{TimeZone, Utc};
     use url::Url;
     use yar_markdown::MarkdownRenderer;
@@ -127,6 +383,16 @@ mod tests {
 
     use super::*;
 
+    fn environment(cfg: &Config) -> Result<Environment<'static>> {
+        create_environment(
+            cfg,
+            &Arc::new(MarkdownRenderer::new::<&str>(None, None)?),
+            &Dependencies::default(),
+            &Outputs::default(),
+            &Thumbnails::default(),
+        )
+    }
+
     fn make_pages() -> Result<Vec<Page>> {
         let pages = (0..10)
             .collect::<Vec<_>>()
@@ -166,7 +432,7 @@ Hello World
 
     #[test]
     fn test_render_default_404_template() -> Result<()> {
-        let env = create_environment(&Config::default())?;
+        let env = environment(&Config::default())?;
         let rendered = env.get_template("404.html")?.render(context! {})?;
 
         insta::assert_yaml_snapshot!(rendered);
@@ -181,7 +447,7 @@ Hello World
         let pages = make_pages()?;
         let dt = Utc.with_ymd_and_hms(2025, 1, 1, 0, 1, 1);
 
-        let env = create_environment(&cfg)?;
+        let env = environment(&cfg)?;
         let rendered = env.get_template("atom.xml")?.render(context! {
             last_updated => dt.unwrap(),
             feed_url => feed_url,
@@ -193,12 +459,138 @@ Hello World
         Ok(())
     }
 
+    #[test]
+    fn test_atom_entries_match_permalinks() -> Result<()> {
+        let cfg = Config::default();
+        let pages = make_pages()?;
+
+        let env = environment(&cfg)?;
+        let rendered = env.get_template("atom.xml")?.render(context! {
+            last_updated => Utc::now(),
+            feed_url => cfg.site.url.join("atom.xml")?,
+            pages => pages
+        })?;
+
+        let feed = roxmltree::Document::parse(&rendered)?;
+        let entries = feed
+            .descendants()
+            .filter(|n| n.has_tag_name("entry"))
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), pages.len());
+
+        for (entry, page) in entries.iter().zip(&pages) {
+            let child = |name: &str| entry.children().find(|n| n.has_tag_name(name));
+
+            let id = child("id").and_then(|n| n.text());
+            assert_eq!(id, Some(page.permalink.as_str()));
+
+            let link = child("link").expect("entry should have a link");
+            assert_eq!(link.attribute("rel"), Some("alternate"));
+            assert_eq!(link.attribute("href"), Some(page.permalink.as_str()));
+
+            let updated = child("updated").and_then(|n| n.text());
+            assert_eq!(updated, Some("2025-03-12T08:00:00+00:00"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_atom_summary_prefers_description() -> Result<()> {
+        let cfg = Config::default();
+        let page = |description: &str| {
+            Page::new(
+                "site/_content/blog/post.md",
+                &format!("---\ntitle = \"Post\"\ntags = []\n{description}\n---\n\nThe summary"),
+                blake3::hash(b"hashplaceholder"),
+                "public/",
+                "site/",
+                &cfg.site.url,
+                &MarkdownRenderer::new::<&str>(None, None)?,
+                &Environment::empty(),
+            )
+        };
+        let pages = [
+            page(r#"description = "Fish & *chips*""#)?,
+            page(r#"description = """#)?,
+        ];
+
+        let env = environment(&cfg)?;
+        let rendered = env.get_template("atom.xml")?.render(context! {
+            last_updated => Utc::now(),
+            feed_url => cfg.site.url.join("atom.xml")?,
+            pages => pages
+        })?;
+
+        let feed = roxmltree::Document::parse(&rendered)?;
+        let summaries = feed
+            .descendants()
+            .filter(|n| n.has_tag_name("summary"))
+            .map(|n| {
+                let text = n
+                    .descendants()
+                    .filter(roxmltree::Node::is_text)
+                    .filter_map(|d| d.text())
+                    .collect::<String>();
+                (n.attribute("type"), text.trim().to_owned())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            [
+                (Some("html"), "Fish & chips".to_string()),
+                (Some("html"), "The summary".to_string()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_templates_without_user_files() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::create_dir_all(root.path().join("templates"))?;
+
+        let mut cfg = Config::default();
+        cfg.site.root = root.path().to_owned();
+
+        let env = environment(&cfg)?;
+        for (name, source) in DEFAULT_TEMPLATES {
+            assert_eq!(env.get_template(name)?.source(), *source);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_templates_override_defaults() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::create_dir_all(root.path().join("templates"))?;
+        for (name, _) in DEFAULT_TEMPLATES {
+            fs::write(
+                root.path().join("templates").join(name),
+                format!("custom {name}"),
+            )?;
+        }
+
+        let mut cfg = Config::default();
+        cfg.site.root = root.path().to_owned();
+
+        let env = environment(&cfg)?;
+        for (name, _) in DEFAULT_TEMPLATES {
+            let rendered = env.get_template(name)?.render(context! {})?;
+            assert_eq!(rendered, format!("custom {name}"));
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_render_default_sitemap_template() -> Result<()> {
         let cfg = Config::default();
         let pages = make_pages()?;
 
-        let env = create_environment(&cfg)?;
+        let env = environment(&cfg)?;
         let rendered = env.get_template("sitemap.xml")?.render(context! {
             pages => pages
         })?;
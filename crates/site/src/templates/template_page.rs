@@ -1,25 +1,34 @@
 use std::{
+    collections::HashMap,
     fs,
     hash::Hash as StdHash,
     path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
 use blake3::Hash;
 use chrono::{DateTime, Utc};
 use color_eyre::{
     Result,
-    eyre::{ContextCompat, OptionExt},
+    eyre::{ContextCompat, OptionExt, WrapErr, bail},
 };
-use minify_html::{Cfg, minify};
-use minijinja::{Environment, Value, context};
+use minijinja::{Environment, Expression, Value, context};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use url::Url;
+use yar_markdown::split_frontmatter;
 
 use crate::{
+    config::add_trailing_slash,
     page::Page,
+    tags::Tag,
     templates::PageContext,
-    utils::{build_permalink, fs::ensure_directory},
+    timings::{PageTiming, Timings},
+    utils::{
+        build_page_url, build_permalink, fs::ensure_directory, minify_html, output_components,
+        safe_join,
+    },
 };
 
 /// A template page.
@@ -46,23 +55,121 @@ pub struct TPFrontmatter {
     pub slug: Option<String>,
     #[serde(default)]
     pub draft: bool,
+    /// Tags to list the template page under alongside pages.
+    #[serde(default)]
+    pub tags: Vec<String>,
     #[serde(default)]
     pub dependencies: Vec<String>,
     pub pagination: Option<Pagination>,
+    /// Write the output to this path relative to the output directory, rather than to an
+    /// `index.html` named after the title. Implies `raw`.
+    pub output_path: Option<PathBuf>,
+    /// Write the output as-is instead of minifying it as HTML.
+    #[serde(default)]
+    pub raw: bool,
+    /// The path under the site url this template page is rendered to, like `/feed/`,
+    /// which is written to its `index.html`. Paths with an extension, like
+    /// `/.well-known/security.txt`, are written to as they are, which implies `raw`.
+    pub permalink: Option<String>,
 }
 
 /// Metadata passed to any pagination.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Pagination {
+    /// The collection to paginate, either `pages`, `tags`, or a global.
     pub from: String,
+    /// How many items are on each page when chunking.
+    #[serde(default = "default_every")]
     pub every: usize,
+    #[serde(default)]
+    pub mode: PaginationMode,
     pub name_template: Option<String>,
 }
 
+const fn default_every() -> usize {
+    1
+}
+
+/// How a paginated template page splits up its collection.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaginationMode {
+    /// Render a page for every `every` items, which are in `pagination.items`.
+    #[default]
+    Chunked,
+    /// Render a page for each item, which is in `pagination.item`. Pages rendered for
+    /// tags also get the pages with the tag in `pagination.pages`.
+    PerItem,
+}
+
+/// What was rendered for a template page, stored so the next build knows whether it has
+/// to be rendered again and which pagination chunks it no longer writes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RenderedState {
+    pub collection_hash: Hash,
+    pub chunks: Vec<String>,
+}
+
+/// The hashes of the files template pages are rendered to, keyed by their path relative
+/// to the output directory, so that files whose content hasn't changed aren't written again.
+#[derive(Debug, Default)]
+pub struct TemplatePageOutputs {
+    output_dir: PathBuf,
+    // The hashes of what each file held after previous builds.
+    previous: HashMap<PathBuf, Hash>,
+    // The hashes of the files written by this build.
+    written: Mutex<HashMap<PathBuf, Hash>>,
+}
+
+impl TemplatePageOutputs {
+    /// Track the files rendered to the given output directory, starting from the hashes
+    /// stored by previous builds.
+    pub fn new<P: AsRef<Path>>(output_dir: P, previous: HashMap<PathBuf, Hash>) -> Self {
+        Self {
+            output_dir: output_dir.as_ref().to_owned(),
+            previous,
+            written: Mutex::default(),
+        }
+    }
+
+    /// Write a rendered file, unless it already holds the same content.
+    pub fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let hash = blake3::hash(content);
+        let key = path.strip_prefix(&self.output_dir).unwrap_or(path);
+
+        // The file may have been written earlier in this build, such as in the first pass
+        // of a two-pass build.
+        let changed = {
+            let mut written = self.written.lock().expect("Outputs lock poisoned");
+            let changed = written.get(key).or_else(|| self.previous.get(key)) != Some(&hash);
+            if changed {
+                written.insert(key.to_owned(), hash);
+            }
+            changed
+        };
+        if !changed {
+            return Ok(());
+        }
+
+        ensure_directory(path.parent().context("Path should have a parent")?)?;
+        fs::write(path, content)?;
+
+        Ok(())
+    }
+
+    /// The files written by this build and their hashes.
+    pub fn written(&self) -> Vec<(PathBuf, Hash)> {
+        let written = self.written.lock().expect("Outputs lock poisoned");
+        written.iter().map(|(p, h)| (p.clone(), *h)).collect()
+    }
+}
+
 /// The pagination context passed to every meta template.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize)]
 pub struct PaginationContext {
-    items: Vec<String>,
+    items: Vec<Value>,
+    item: Option<Value>,
+    pages: Option<Vec<Value>>,
     next: Option<String>,
     previous: Option<String>,
 }
@@ -79,8 +186,58 @@ impl TemplatePage {
     ) -> Result<Self> {
         let (frontmatter, remaining) = parse_frontmatter(content)?;
 
-        let out_path = out_path(&path, &out_dir, root);
-        let permalink = build_permalink(&out_path, out_dir, url)?;
+        let out_path = if let Some(permalink) = &frontmatter.permalink {
+            if frontmatter.pagination.is_some() || frontmatter.output_path.is_some() {
+                bail!(
+                    "Template page {} can't set a permalink and be paginated or set an output path",
+                    path.as_ref().display()
+                );
+            }
+            permalink_out_path(permalink, &out_dir).wrap_err_with(|| {
+                format!(
+                    "The permalink of template page {} can't be used",
+                    path.as_ref().display()
+                )
+            })?
+        } else if let Some(output_path) = &frontmatter.output_path {
+            if frontmatter.pagination.is_some() {
+                bail!(
+                    "Template page {} can't set an output path and be paginated",
+                    path.as_ref().display()
+                );
+            }
+            // Otherwise the output directory itself would be written to.
+            if output_path.file_name().is_none() {
+                bail!(
+                    "The output_path of template page {} needs to name a file, not {}",
+                    path.as_ref().display(),
+                    output_path.display()
+                );
+            }
+            safe_join(&out_dir, output_path).wrap_err_with(|| {
+                format!(
+                    "The output_path of template page {} can't be used",
+                    path.as_ref().display()
+                )
+            })?
+        } else {
+            let out_path = out_path(&path, &out_dir, root);
+            // Checked here, so that finding the output file later can't fail.
+            if frontmatter.pagination.is_none() && !path.as_ref().ends_with("index.html") {
+                let (field, ending) = ending(&frontmatter);
+                safe_join(&out_path, ending).wrap_err_with(|| {
+                    format!(
+                        "The {field} of template page {} can't be used as its path",
+                        path.as_ref().display()
+                    )
+                })?;
+            }
+            out_path
+        };
+        let permalink = match &frontmatter.permalink {
+            Some(permalink) => url.join(permalink.trim_start_matches('/'))?,
+            None => build_permalink(&out_path, out_dir, url)?,
+        };
 
         Ok(Self {
             path: path.as_ref().to_owned(),
@@ -92,140 +249,366 @@ impl TemplatePage {
         })
     }
 
-    /// Render this template page.
+    /// Render this template page, returning the names of the pagination chunks rendered.
     ///
-    /// TODO: Currently, in regard to paginations, only collections of strings can be paginated
-    /// TODO: over. In the future, maybe something like `minijinja`s `DynObject` could be used to ease this restriction.
-    pub fn render(&self, index: &[Page], env: &Environment) -> Result<()> {
+    /// Files that would be written with the same content they already hold are skipped.
+    pub fn render(
+        &self,
+        index: &[Page],
+        tags: &[Tag],
+        env: &Environment,
+        outputs: &TemplatePageOutputs,
+        timings: &Timings,
+    ) -> Result<Vec<String>> {
         if let Some(pagination) = &self.frontmatter.pagination {
-            self.render_pagination(pagination, index, env)?;
+            return self.render_pagination(pagination, index, tags, env, outputs, timings);
+        }
+
+        let start = Instant::now();
+        let rendered = self.render_to_string(index, tags, env)?;
+        let render = start.elapsed();
+        let start = Instant::now();
+        outputs.write(&self.output_file(), &self.finish(&rendered))?;
+        timings.record(self.timing(render, start.elapsed()));
+
+        Ok(vec![])
+    }
+
+    /// Render this template page without minifying it or writing it to disk.
+    ///
+    /// Paginated template pages are rendered to several files, so they aren't supported.
+    pub fn render_to_string(
+        &self,
+        index: &[Page],
+        tags: &[Tag],
+        env: &Environment,
+    ) -> Result<String> {
+        if self.frontmatter.pagination.is_some() {
+            bail!(
+                "{} is paginated, so it can't be rendered to a single string",
+                self.path.display()
+            );
+        }
+
+        let template = env.template_from_str(&self.content)?;
+
+        let ctx = Value::from_object(PageContext {
+            pages: index.to_vec(),
+            tags: tags.to_vec(),
+        });
+        Ok(template.render(context! {
+            frontmatter => self.frontmatter, ..ctx
+        })?)
+    }
+
+    /// The collections this template page depends on, which are `pages`, `tags`, and globals.
+    pub fn collections(&self) -> impl Iterator<Item = &str> {
+        self.frontmatter
+            .pagination
+            .iter()
+            .map(|p| p.from.as_str())
+            .chain(self.frontmatter.dependencies.iter().map(String::as_str))
+    }
+
+    /// Hash the contents of the collections this template page depends on, so that it can
+    /// be rendered again when they change.
+    pub fn collection_hash(&self, index: &[Page], tags: &[Tag], env: &Environment) -> Result<Hash> {
+        let mut hasher = blake3::Hasher::new();
+
+        for collection in self.collections() {
+            hasher.update(collection.as_bytes());
+            if collection == "pages" {
+                let mut pages = index
+                    .iter()
+                    .map(|p| (&p.path, p.source_hash))
+                    .collect::<Vec<_>>();
+                pages.sort_by_key(|(path, _)| *path);
+                for (path, hash) in pages {
+                    hasher.update(path.as_os_str().as_encoded_bytes());
+                    hasher.update(hash.as_bytes());
+                }
+            } else if collection == "tags" {
+                hasher.update(&serde_json::to_vec(tags)?);
+            } else if let Some(value) = env.globals().find(|g| g.0 == collection) {
+                hasher.update(&serde_json::to_vec(&value.1)?);
+            }
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    /// The file this template page is rendered to, if it isn't paginated.
+    pub fn output_file(&self) -> PathBuf {
+        if self.frontmatter.output_path.is_some() || self.frontmatter.permalink.is_some() {
+            return self.out_path.clone();
+        }
+
+        if self.path.ends_with("index.html") {
+            return self.out_path.join("index.html");
+        }
+        self.out_path
+            .join(ending(&self.frontmatter).1)
+            .join("index.html")
+    }
+
+    // Template pages are their own template, so their timings are recorded under their
+    // path. Each page of a paginated template page is counted on its own.
+    fn timing(&self, render: Duration, write: Duration) -> PageTiming {
+        PageTiming {
+            path: self.path.clone(),
+            template: self.path.display().to_string(),
+            render,
+            write,
+        }
+    }
+
+    // Minify the rendered output, unless it's meant to be written as-is.
+    fn finish(&self, rendered: &str) -> Vec<u8> {
+        let file_permalink = self
+            .frontmatter
+            .permalink
+            .as_deref()
+            .is_some_and(is_file_permalink);
+        if self.frontmatter.raw || self.frontmatter.output_path.is_some() || file_permalink {
+            rendered.as_bytes().to_vec()
         } else {
-            let ending = if self.path.ends_with("index.html") {
-                PathBuf::from("index.html")
-            } else {
-                PathBuf::from(self.frontmatter.slug.as_ref().map_or_else(
-                    || self.frontmatter.title.replace(' ', "-"),
-                    ToOwned::to_owned,
-                ))
-                .join("index.html")
-            };
-            let out = self.out_path.join(ending);
-            ensure_directory(out.parent().context("Path should have a parent")?)?;
-
-            let template = env.template_from_str(&self.content)?;
-
-            let ctx = Value::from_object(PageContext {
-                pages: index.to_vec(),
-            });
-            let rendered_html = template.render(context! {
-                frontmatter => self.frontmatter, ..ctx
-            })?;
-
-            let cfg = Cfg::new();
-            let minified = minify(rendered_html.as_bytes(), &cfg);
-
-            fs::write(out, minified)?;
+            minify_html(rendered)
         }
+    }
 
-        Ok(())
+    /// The names of the pagination chunks this template page is rendered to, if it's
+    /// paginated.
+    pub fn chunk_names(
+        &self,
+        index: &[Page],
+        tags: &[Tag],
+        env: &Environment,
+    ) -> Result<Vec<String>> {
+        let Some(pagination) = &self.frontmatter.pagination else {
+            return Ok(vec![]);
+        };
+
+        Ok(self
+            .pagination_contexts(pagination, index, tags, env)?
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect())
     }
 
     fn render_pagination(
         &self,
         pagination: &Pagination,
         index: &[Page],
+        tags: &[Tag],
         env: &Environment,
-    ) -> Result<()> {
-        // Get global value that this template paginates on.
-        let value = env
-            .globals()
-            .find(|g| pagination.from == g.0)
-            .ok_or_eyre(format!("Global {} doesn't exist", pagination.from))?
-            .1;
-
-        // Value::downcast_object_ref doesn't seem to work here, and I can't chunk an iterator.
-        let items = value
-            .try_iter()?
-            .map(|v| v.to_string())
-            .collect::<Vec<String>>();
-
+        outputs: &TemplatePageOutputs,
+        timings: &Timings,
+    ) -> Result<Vec<String>> {
+        let chunks = self.pagination_contexts(pagination, index, tags, env)?;
         let template = env.template_from_str(&self.content)?;
-        let name_expr = pagination
-            .name_template
-            .as_ref()
-            .map(|s| env.compile_expression(s))
-            .transpose()?;
 
-        items
-            .par_chunks(pagination.every)
-            .enumerate()
-            .map(|(idx, chunk)| {
-                let pag = PaginationContext {
-                    items: chunk.into(),
-                    next: None,
-                    previous: None,
-                };
+        chunks
+            .into_par_iter()
+            .map(|(name, dir, pag)| {
                 let ctx = Value::from_object(PageContext {
                     pages: index.to_vec(),
+                    tags: tags.to_vec(),
                 });
 
+                let start = Instant::now();
                 let rendered = template.render(context! {
                     pagination => pag, ..ctx
                 })?;
+                let render = start.elapsed();
 
-                let name = name_expr
-                    .as_ref()
-                    .map(|e| e.eval(context! { pagination => pag }))
-                    .transpose()?
-                    .map_or_else(|| idx.to_string(), |v| v.to_string());
+                let start = Instant::now();
+                outputs.write(&dir.join("index.html"), &self.finish(&rendered))?;
+                timings.record(self.timing(render, start.elapsed()));
 
-                let out = self.out_path.join(name).join("index.html");
-                ensure_directory(out.parent().context("Path should have a parent")?)?;
+                Ok(name)
+            })
+            .collect::<Result<Vec<String>>>()
+    }
 
-                let cfg = Cfg::new();
-                let minified = minify(rendered.as_bytes(), &cfg);
+    // Split the collection this template page paginates on into the pages it's rendered
+    // to, along with their names and the directories they're rendered to.
+    fn pagination_contexts(
+        &self,
+        pagination: &Pagination,
+        index: &[Page],
+        tags: &[Tag],
+        env: &Environment,
+    ) -> Result<Vec<(String, PathBuf, PaginationContext)>> {
+        let items = pagination_items(pagination, index, tags, env)?;
+        let name_expr = pagination
+            .name_template
+            .as_ref()
+            .map(|s| env.compile_expression(s))
+            .transpose()?;
 
-                fs::write(out, minified)?;
+        let contexts = match pagination.mode {
+            PaginationMode::Chunked => items
+                .chunks(pagination.every)
+                .map(|chunk| PaginationContext {
+                    items: chunk.into(),
+                    ..PaginationContext::default()
+                })
+                .collect::<Vec<_>>(),
+            PaginationMode::PerItem => items
+                .iter()
+                .enumerate()
+                .map(|(idx, item)| PaginationContext {
+                    items: vec![item.clone()],
+                    item: Some(item.clone()),
+                    // Tags are collected in the same order they're paginated in.
+                    pages: (pagination.from == "tags").then(|| tagged_pages(&tags[idx], index)),
+                    ..PaginationContext::default()
+                })
+                .collect(),
+        };
 
-                Ok(())
+        let mut chunks = contexts
+            .into_iter()
+            .enumerate()
+            .map(|(idx, pag)| {
+                let name = chunk_name(name_expr.as_ref(), idx, &pag)?;
+                let dir = safe_join(&self.out_path, &name).wrap_err_with(|| {
+                    format!(
+                        "The pagination name of template page {} can't be used as a path",
+                        self.path.display()
+                    )
+                })?;
+                Ok((name, dir, pag))
             })
             .collect::<Result<Vec<_>>>()?;
 
-        Ok(())
+        // Chunks link to each other the way `get_url` and the sitemap link to them.
+        let mut base = self.permalink.clone();
+        add_trailing_slash(&mut base);
+        let urls = chunks
+            .iter()
+            .map(|(_, dir, _)| {
+                Ok(build_page_url(dir.join("index.html"), &self.out_path, &base)?.into())
+            })
+            .collect::<Result<Vec<String>>>()?;
+        for (idx, (_, _, pag)) in chunks.iter_mut().enumerate() {
+            pag.previous = idx.checked_sub(1).map(|i| urls[i].clone());
+            pag.next = urls.get(idx + 1).cloned();
+        }
+
+        Ok(chunks)
     }
 }
 
+// The pages with the given tag.
+fn tagged_pages(tag: &Tag, index: &[Page]) -> Vec<Value> {
+    index
+        .iter()
+        .filter(|p| tag.members.iter().any(|m| m.permalink == p.permalink))
+        .map(Value::from_serialize)
+        .collect()
+}
+
+// Get the collection that a template page paginates on, either the pages, the tags, or
+// a global.
+fn pagination_items(
+    pagination: &Pagination,
+    index: &[Page],
+    tags: &[Tag],
+    env: &Environment,
+) -> Result<Vec<Value>> {
+    if pagination.from == "pages" {
+        return Ok(index
+            .iter()
+            .map(Value::from_serialize)
+            .collect::<Vec<Value>>());
+    }
+    if pagination.from == "tags" {
+        return Ok(tags.iter().map(Value::from_serialize).collect());
+    }
+
+    let value = env
+        .globals()
+        .find(|g| pagination.from == g.0)
+        .ok_or_eyre(format!("Global {} doesn't exist", pagination.from))?
+        .1;
+
+    // Value::downcast_object_ref doesn't seem to work here, and I can't chunk an iterator.
+    Ok(value.try_iter()?.collect::<Vec<Value>>())
+}
+
+// The name of a pagination chunk, from the name template if there is one, or its index.
+//
+// Items with a slug, like tags, are named by it, both when the name template evaluates
+// to one and when a page is rendered for each of them.
+fn chunk_name(
+    name_expr: Option<&Expression<'_, '_>>,
+    idx: usize,
+    pag: &PaginationContext,
+) -> Result<String> {
+    if let Some(expr) = name_expr {
+        let name = expr.eval(context! { pagination => pag })?;
+        return Ok(slug(&name).unwrap_or_else(|| name.to_string()));
+    }
+
+    Ok(pag
+        .item
+        .as_ref()
+        .and_then(slug)
+        .unwrap_or_else(|| idx.to_string()))
+}
+
+// The slug of an item, if it has one.
+fn slug(item: &Value) -> Option<String> {
+    let slug = item.get_attr("slug").ok()?;
+    slug.as_str().map(ToOwned::to_owned)
+}
+
 impl StdHash for TemplatePage {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.path.hash(state);
     }
 }
 
-fn parse_frontmatter(content: &str) -> Result<(TPFrontmatter, String)> {
-    let mut in_frontmatter = false;
-    let mut frontmatter_content = String::new();
-    let mut remaining = String::new();
-
-    for line in content.lines() {
-        if line.trim() == "---" {
-            in_frontmatter = !in_frontmatter;
-            continue;
-        }
+// The frontmatter field a template page's output is named after, and its value as a path.
+fn ending(frontmatter: &TPFrontmatter) -> (&'static str, String) {
+    frontmatter.slug.as_ref().map_or_else(
+        || ("title", frontmatter.title.replace(' ', "-")),
+        |slug| ("slug", slug.clone()),
+    )
+}
 
-        if in_frontmatter {
-            frontmatter_content.push_str(line);
-            frontmatter_content.push('\n');
-        } else {
-            remaining.push_str(line);
-            remaining.push('\n');
-        }
+// The file a template page with a permalink is rendered to.
+fn permalink_out_path<T: AsRef<Path>>(permalink: &str, out_dir: T) -> Result<PathBuf> {
+    let Some(relative) = permalink.strip_prefix('/') else {
+        bail!("{permalink} doesn't start with /");
+    };
+    if relative.contains(['?', '#']) {
+        bail!("{permalink} has a query or fragment, which can't be part of a path");
     }
+    let out_path = safe_join(out_dir, relative)?;
 
-    let frontmatter = toml::from_str(&frontmatter_content)?;
-    Ok((frontmatter, remaining))
+    Ok(if is_file_permalink(permalink) {
+        out_path
+    } else {
+        out_path.join("index.html")
+    })
 }
 
-fn out_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
+// Whether a permalink is to a file, like `/.well-known/security.txt`, rather than to a
+// directory.
+fn is_file_permalink(permalink: &str) -> bool {
+    !permalink.ends_with('/') && Path::new(permalink).extension().is_some()
+}
+
+fn parse_frontmatter(content: &str) -> Result<(TPFrontmatter, String)> {
+    let split = split_frontmatter(content);
+    Ok((split.deserialize()?, split.body.to_owned()))
+}
+
+/// The directory the template page at `path` is rendered to, or its pagination chunks
+/// are rendered into.
+pub fn out_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
     path: P,
     out_dir: T,
     root: Z,
@@ -237,13 +620,453 @@ fn out_path<P: AsRef<Path>, T: AsRef<Path>, Z: AsRef<Path>>(
         .unwrap_or_else(|| path.as_ref())
         .with_extension("");
 
-    let mut components = path
-        .components()
-        .filter(|c| !c.as_os_str().to_str().is_some_and(|s| s.starts_with('_')));
+    let components = output_components(&path, root);
+
+    out_dir.components().chain(components).collect::<PathBuf>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Site,
+        config::Config,
+        database::{DatabaseSource, setup_database},
+        test_utils::{make_page, make_site},
+    };
 
-    if root.as_ref() != Path::new(".") {
-        components.next();
+    fn template_page(frontmatter: &str) -> Result<TemplatePage> {
+        TemplatePage::new(
+            &format!("---\ntitle = \"Test\"\n{frontmatter}\n---\ncontent"),
+            blake3::hash(b"hashplaceholder"),
+            "site/feeds/test.html",
+            "public",
+            "site",
+            &Url::parse("https://example.com")?,
+        )
     }
 
-    out_dir.components().chain(components).collect::<PathBuf>()
+    #[test]
+    fn test_output_path() -> Result<()> {
+        let page = template_page(r#"output_path = "llms.txt""#)?;
+        assert_eq!(page.output_file(), PathBuf::from("public/llms.txt"));
+        assert_eq!(page.permalink.as_str(), "https://example.com/llms.txt");
+
+        let page = template_page("")?;
+        assert_eq!(
+            page.output_file(),
+            PathBuf::from("public/feeds/Test/index.html")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_permalink() -> Result<()> {
+        let page = template_page(r#"permalink = "/feed/""#)?;
+        assert_eq!(page.output_file(), PathBuf::from("public/feed/index.html"));
+        assert_eq!(page.permalink.as_str(), "https://example.com/feed/");
+        assert_eq!(page.finish("<p> a </p>"), b"<p>a".to_vec());
+
+        // Permalinks with an extension are written to as they are, without minifying.
+        let page = template_page(r#"permalink = "/.well-known/security.txt""#)?;
+        assert_eq!(
+            page.output_file(),
+            PathBuf::from("public/.well-known/security.txt")
+        );
+        assert_eq!(
+            page.permalink.as_str(),
+            "https://example.com/.well-known/security.txt"
+        );
+        assert_eq!(page.finish("<p> a </p>"), b"<p> a </p>".to_vec());
+
+        // Sites under a path keep it.
+        let page = TemplatePage::new(
+            "---\ntitle = \"Test\"\npermalink = \"/feed/index.json\"\n---\ncontent",
+            blake3::hash(b"hashplaceholder"),
+            "site/feeds/test.html",
+            "public",
+            "site",
+            &Url::parse("https://example.com/blog/")?,
+        )?;
+        assert_eq!(
+            page.permalink.as_str(),
+            "https://example.com/blog/feed/index.json"
+        );
+
+        for invalid in [
+            r#"permalink = "feed/""#,
+            r#"permalink = "/../escape/""#,
+            r#"permalink = "/feed/?page=2""#,
+            "permalink = \"/feed/\"\noutput_path = \"feed.json\"",
+        ] {
+            assert!(template_page(invalid).is_err(), "{invalid}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_path_outside_output_dir() {
+        assert!(template_page(r#"output_path = "../escape.txt""#).is_err());
+        assert!(template_page(r#"output_path = "/etc/passwd""#).is_err());
+        for empty in ["", ".", "./"] {
+            let error = template_page(&format!("output_path = \"{empty}\"")).unwrap_err();
+            assert!(
+                error.to_string().contains("needs to name a file"),
+                "{empty}"
+            );
+        }
+
+        let error = template_page(r#"slug = "../../etc/whatever""#).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("The slug of template page site/feeds/test.html")
+        );
+        assert!(template_page(r#"slug = "nul""#).is_err());
+        assert!(template_page(r#"slug = "nested/path""#).is_ok());
+    }
+
+    #[test]
+    fn test_pagination_name_outside_output_dir() -> Result<()> {
+        let page = template_page(
+            "[pagination]\nfrom = \"names\"\nmode = \"per_item\"\nname_template = \"pagination.item\"",
+        )?;
+        let mut env = Environment::new();
+        env.add_global("names", vec!["fine", "nested/fine"]);
+        assert_eq!(page.chunk_names(&[], &[], &env)?, ["fine", "nested/fine"]);
+
+        for name in ["../../escape", "/etc/cron.d/job"] {
+            env.add_global("names", vec!["fine", name]);
+            let error = page.chunk_names(&[], &[], &env).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .contains("The pagination name of template page site/feeds/test.html")
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rule_in_body() -> Result<()> {
+        let page = TemplatePage::new(
+            "---\ntitle = \"Test\"\n---\n<h1>{{ frontmatter.title }}</h1>\n---\n<p>After</p>\n---\n",
+            blake3::hash(b"hashplaceholder"),
+            "site/test.html",
+            "public",
+            "site",
+            &Url::parse("https://example.com")?,
+        )?;
+        assert_eq!(page.frontmatter.title, "Test");
+
+        let rendered = page.render_to_string(&[], &[], &Environment::new())?;
+        assert_eq!(rendered, "<h1>Test</h1>\n---\n<p>After</p>\n---");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_yaml_frontmatter() -> Result<()> {
+        let page = TemplatePage::new(
+            "---\ntitle: Posts\ntags: [meta]\npagination:\n  from: pages\n  every: 5\n---\n{{ frontmatter.title }}",
+            blake3::hash(b"hashplaceholder"),
+            "site/posts.html",
+            "public",
+            "site",
+            &Url::parse("https://example.com")?,
+        )?;
+        assert_eq!(page.frontmatter.title, "Posts");
+        assert_eq!(page.frontmatter.tags, ["meta"]);
+        assert_eq!(
+            page.frontmatter.pagination,
+            Some(Pagination {
+                from: "pages".to_owned(),
+                every: 5,
+                mode: PaginationMode::Chunked,
+                name_template: None,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_page_raw_output() -> Result<()> {
+        let page = r#"---
+title = "Hello"
+tags = []
+aliases = ["/old/hello", "/hi"]
+---
+
+Hello
+"#;
+        let redirects = r#"---
+title = "Redirects"
+output_path = "_redirects"
+---
+{% for page in pages %}{% for alias in page.document.frontmatter.aliases -%}
+{{ alias }}  {{ page.permalink }}  301
+{% endfor %}{% endfor %}"#;
+
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("_content/blog/hello.md", page),
+                ("redirects.html", redirects),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let template_page = &site.library.template_pages[0];
+        assert_eq!(
+            template_page.permalink.as_str(),
+            "http://0.0.0.0:8000/_redirects"
+        );
+
+        let rendered = fs::read_to_string(out.path().join("public/_redirects"))?;
+        assert_eq!(
+            rendered,
+            "/old/hello  http://0.0.0.0:8000/blog/Hello  301\n/hi  http://0.0.0.0:8000/blog/Hello  301\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pagination_follows_pages() -> Result<()> {
+        let archive = r#"---
+title = "Archive"
+
+[pagination]
+from = "pages"
+every = 1
+---
+{% for page in pagination.items %}{{ page.document.frontmatter.title }}{% endfor %}"#;
+
+        let (root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("archive/index.html", archive),
+                ("_content/blog/a.md", &make_page("A", "post.html")),
+                ("_content/blog/b.md", &make_page("B", "post.html")),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let config = site.config.clone();
+
+        let build = |config: &Config| -> Result<Vec<PathBuf>> {
+            let mut site = Site::new(
+                setup_database(DatabaseSource::File(&db_path))?,
+                config.clone(),
+            )?;
+            site.load()?;
+            site.render()?;
+            site.save_to_cache()?;
+            Ok(site.removed_outputs().to_vec())
+        };
+        let chunks = || -> Result<Vec<String>> {
+            let mut names = fs::read_dir(out.path().join("public/archive"))?
+                .map(|e| Ok(e?.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<Vec<String>>>()?;
+            names.sort();
+            Ok(names)
+        };
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+        assert_eq!(chunks()?, ["0", "1"]);
+
+        // Adding a post renders the archive again, even though its source didn't change.
+        fs::write(
+            root.path().join("_content/blog/c.md"),
+            make_page("C", "post.html"),
+        )?;
+        assert!(build(&config)?.is_empty());
+        assert_eq!(chunks()?, ["0", "1", "2"]);
+
+        // Removing posts removes their outputs, and the chunks that are no longer needed.
+        fs::remove_file(root.path().join("_content/blog/a.md"))?;
+        fs::remove_file(root.path().join("_content/blog/b.md"))?;
+        let mut removed = build(&config)?;
+        removed.sort();
+        assert_eq!(
+            removed,
+            [
+                PathBuf::from("archive/1"),
+                PathBuf::from("archive/2"),
+                PathBuf::from("blog/A/index.html"),
+                PathBuf::from("blog/B/index.html"),
+            ]
+        );
+        assert_eq!(chunks()?, ["0"]);
+        let remaining = fs::read_to_string(out.path().join("public/archive/0/index.html"))?;
+        assert!(remaining.contains('C'));
+
+        // Nothing changing leaves the archive alone.
+        assert!(build(&config)?.is_empty());
+        assert_eq!(chunks()?, ["0"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tagged_template_pages() -> Result<()> {
+        let tags = r#"---
+title = "Tags"
+dependencies = ["tags"]
+---
+{% for tag in tags %}{{ tag.name }}: {% for m in tag.members %}{{ m.title }} {% endfor %}
+{% endfor %}"#;
+        let post = |title: &str| {
+            format!("---\ntitle = \"{title}\"\ntags = [\"projects\"]\n---\n\nHello\n")
+        };
+        let (root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                (
+                    "projects/index.html",
+                    "---\ntitle = \"Projects\"\ntags = [\"Projects\"]\n---\nMy projects",
+                ),
+                ("tags/index.html", tags),
+                ("_content/blog/yar.md", &post("Yar")),
+            ],
+            |_| (),
+        )?;
+        let db_path = out.path().join("yar.db");
+        site.db = setup_database(DatabaseSource::File(&db_path))?;
+        let config = site.config.clone();
+
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+        drop(site);
+
+        let rendered = out.path().join("public/tags/index.html");
+        assert!(fs::read_to_string(&rendered)?.contains("projects: Yar Projects"));
+
+        // The projects page isn't rendered again, but it's still listed under its tag.
+        fs::write(root.path().join("_content/blog/zed.md"), post("Zed"))?;
+        let mut site = Site::new(setup_database(DatabaseSource::File(&db_path))?, config)?;
+        site.load()?;
+        site.render()?;
+
+        assert!(
+            site.library
+                .template_pages
+                .iter()
+                .all(|t| !t.path.ends_with("projects/index.html"))
+        );
+        assert!(fs::read_to_string(&rendered)?.contains("projects: Yar Zed Projects"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pagination_per_tag() -> Result<()> {
+        let term = r#"---
+title = "Tag"
+
+[pagination]
+from = "tags"
+mode = "per_item"
+name_template = "pagination.item"
+---
+<h1>{{ pagination.item.name }}</h1><ul>{% for page in pagination.pages %}<li>{{ page.document.frontmatter.title }}</li>{% endfor %}</ul>"#;
+        let tagged = |title: &str, tags: &str| {
+            format!("---\ntitle = \"{title}\"\ntags = {tags}\ntemplate = \"post.html\"\n---\n")
+        };
+
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                ("tags/index.html", term),
+                ("_content/blog/a.md", &tagged("A", r#"["rust", "Web Dev"]"#)),
+                ("_content/blog/b.md", &tagged("B", r#"["rust"]"#)),
+                ("_content/blog/c.md", &tagged("C", r#"["meta"]"#)),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let read = |term: &str| {
+            fs::read_to_string(out.path().join("public/tags").join(term).join("index.html"))
+        };
+        assert_eq!(read("meta")?, "<h1>meta</h1><ul><li>C</ul>");
+        assert_eq!(read("rust")?, "<h1>rust</h1><ul><li>A<li>B</ul>");
+        assert_eq!(read("web-dev")?, "<h1>Web Dev</h1><ul><li>A</ul>");
+
+        let mut chunks = site
+            .generated_pages()
+            .iter()
+            .map(|g| g.permalink.path())
+            .collect::<Vec<&str>>();
+        chunks.sort_unstable();
+        assert_eq!(chunks, ["/tags/meta/", "/tags/rust/", "/tags/web-dev/"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pagination_links() -> Result<()> {
+        let archive = r#"---
+title = "Archive"
+
+[pagination]
+from = "pages"
+---
+{{ pagination.previous or "-" }} {{ pagination.next or "-" }} {{ get_url("archive/1") }}"#;
+        let post = |title: &str| format!("---\ntitle = \"{title}\"\ntags = []\n---\n");
+
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", ""),
+                ("archive/index.html", archive),
+                ("_content/blog/a.md", &post("A")),
+                ("_content/blog/b.md", &post("B")),
+                ("_content/blog/c.md", &post("C")),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+
+        // The links between chunks are the urls `get_url` and the sitemap give them.
+        let read = |chunk: &str| {
+            fs::read_to_string(
+                out.path()
+                    .join("public/archive")
+                    .join(chunk)
+                    .join("index.html"),
+            )
+        };
+        assert_eq!(
+            read("0")?,
+            "- http://0.0.0.0:8000/archive/1/ http://0.0.0.0:8000/archive/1/"
+        );
+        assert_eq!(
+            read("1")?,
+            "http://0.0.0.0:8000/archive/0/ http://0.0.0.0:8000/archive/2/ http://0.0.0.0:8000/archive/1/"
+        );
+        assert_eq!(
+            read("2")?,
+            "http://0.0.0.0:8000/archive/1/ - http://0.0.0.0:8000/archive/1/"
+        );
+        assert!(
+            site.generated_pages()
+                .iter()
+                .any(|g| g.permalink.as_str() == "http://0.0.0.0:8000/archive/1/")
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,51 @@
+//! Helpers for the tests building whole sites.
+
+use std::fs;
+
+use color_eyre::Result;
+use tempfile::TempDir;
+
+use crate::{
+    Site,
+    config::Config,
+    database::{DatabaseSource, setup_database},
+    utils::fs::ensure_directory,
+};
+
+/// Create a site from the given files in a temporary directory, rendering to `public` in
+/// another one.
+pub fn make_site(
+    files: &[(&str, &str)],
+    configure: impl FnOnce(&mut Config),
+) -> Result<(TempDir, TempDir, Site)> {
+    let root = tempfile::tempdir()?;
+    let out = tempfile::tempdir()?;
+
+    for (path, content) in files {
+        let path = root.path().join(path);
+        ensure_directory(path.parent().unwrap())?;
+        fs::write(path, content)?;
+    }
+
+    let mut config = Config::default();
+    config.site.root = root.path().to_owned();
+    config.site.output_path = out.path().join("public");
+    configure(&mut config);
+
+    let site = Site::new(setup_database(DatabaseSource::Memory)?, config)?;
+    Ok((root, out, site))
+}
+
+/// A markdown page with the given title, rendered with `template`.
+pub fn make_page(title: &str, template: &str) -> String {
+    format!(
+        r#"---
+title = "{title}"
+tags = []
+template = "{template}"
+---
+
+Hello from {title}
+"#
+    )
+}
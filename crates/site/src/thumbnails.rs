@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use blake3::Hash;
+use color_eyre::{Result, eyre::ContextCompat};
+use image::{DynamicImage, ImageDecoder, ImageReader, imageops::FilterType};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::fs::ensure_directory;
+
+/// How a thumbnail with both a width and a height is fitted to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Fill the whole size, cropping whatever doesn't fit.
+    Cover,
+    /// Fit the whole image within the size, keeping its aspect ratio.
+    Contain,
+}
+
+/// A resized copy of an image, generated by the `thumbnail` template function.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Thumbnail {
+    /// The image the thumbnail is made from, relative to the site root.
+    pub source: PathBuf,
+    pub source_hash: Hash,
+    pub width: u32,
+    /// The height to resize to, or `None` to keep the image's aspect ratio.
+    pub height: Option<u32>,
+    pub fit: Fit,
+}
+
+impl Thumbnail {
+    /// Where the thumbnail is written, relative to the output directory.
+    ///
+    /// The name includes the source's hash and the size, so that a thumbnail is only
+    /// generated again when either changes.
+    pub fn out_path(&self) -> PathBuf {
+        let stem = self
+            .source
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = self
+            .source
+            .extension()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let height = self.height.map(|h| h.to_string()).unwrap_or_default();
+        let fit = match self.fit {
+            Fit::Cover => "cover",
+            Fit::Contain => "contain",
+        };
+
+        PathBuf::from("thumbs").join(format!(
+            "{stem}-{}-{}x{height}-{fit}.{extension}",
+            &self.source_hash.to_hex()[..8],
+            self.width,
+        ))
+    }
+
+    /// Resize the source image, writing the thumbnail to the output directory.
+    ///
+    /// Thumbnails are encoded without the source's metadata, so any rotation in it is
+    /// applied to the image instead.
+    pub fn generate<P: AsRef<Path>>(&self, source: &[u8], output_path: P) -> Result<()> {
+        let mut decoder = ImageReader::new(Cursor::new(source))
+            .with_guessed_format()?
+            .into_decoder()?;
+        let orientation = decoder.orientation()?;
+        let mut image = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+        let resized = match (self.height, self.fit) {
+            (None, _) => image.resize(self.width, u32::MAX, FilterType::Lanczos3),
+            (Some(height), Fit::Cover) => {
+                image.resize_to_fill(self.width, height, FilterType::Lanczos3)
+            }
+            (Some(height), Fit::Contain) => image.resize(self.width, height, FilterType::Lanczos3),
+        };
+
+        let out = output_path.as_ref().join(self.out_path());
+        ensure_directory(out.parent().context("Path should have a parent")?)?;
+        resized.save(out)?;
+
+        Ok(())
+    }
+}
+
+/// The thumbnails of a site, keyed by their path relative to the output directory.
+#[derive(Debug, Clone, Default)]
+pub struct Thumbnails(Arc<Mutex<ThumbnailsInner>>);
+
+#[derive(Debug, Default)]
+struct ThumbnailsInner {
+    // Thumbnails generated by previous builds.
+    cached: HashMap<PathBuf, Thumbnail>,
+    // Thumbnails generated by this build.
+    generated: HashMap<PathBuf, Thumbnail>,
+}
+
+impl Thumbnails {
+    /// Replace the thumbnails known to have been generated by previous builds.
+    pub fn set_cached(&self, cached: HashMap<PathBuf, Thumbnail>) {
+        let mut inner = self.0.lock().expect("Thumbnails lock poisoned");
+        inner.cached = cached;
+        inner.generated.clear();
+    }
+
+    /// Record a thumbnail as generated by this build, returning whether it still needs
+    /// generating, or if this build or a previous one already generated it.
+    pub fn claim(&self, thumbnail: &Thumbnail) -> bool {
+        let mut inner = self.0.lock().expect("Thumbnails lock poisoned");
+        let path = thumbnail.out_path();
+        if inner.cached.contains_key(&path) || inner.generated.contains_key(&path) {
+            return false;
+        }
+
+        inner.generated.insert(path, thumbnail.clone());
+        true
+    }
+
+    /// Forget a thumbnail from a previous build, returning it if it was known.
+    pub fn remove(&self, path: &Path) -> Option<Thumbnail> {
+        self.0
+            .lock()
+            .expect("Thumbnails lock poisoned")
+            .cached
+            .remove(path)
+    }
+
+    /// The thumbnails from previous builds.
+    pub fn cached(&self) -> Vec<Thumbnail> {
+        let inner = self.0.lock().expect("Thumbnails lock poisoned");
+        inner.cached.values().cloned().collect()
+    }
+
+    /// The thumbnails generated by this build.
+    pub fn generated(&self) -> Vec<Thumbnail> {
+        let inner = self.0.lock().expect("Thumbnails lock poisoned");
+        inner.generated.values().cloned().collect()
+    }
+}
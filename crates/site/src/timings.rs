@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Serialize, Serializer};
+
+/// How long it took to render a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageTiming {
+    pub path: PathBuf,
+    /// The template the page was rendered with. Template pages are their own template, so
+    /// they're named after their path.
+    pub template: String,
+    /// The time spent rendering the template.
+    pub render: Duration,
+    /// The time spent minifying the output and writing it to disk.
+    pub write: Duration,
+}
+
+/// The time spent rendering all the pages that use a template.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TemplateTiming {
+    pub template: String,
+    pub count: usize,
+    #[serde(rename = "total_ms", serialize_with = "milliseconds")]
+    pub total: Duration,
+    #[serde(rename = "max_ms", serialize_with = "milliseconds")]
+    pub max: Duration,
+}
+
+/// The render timings of each page, collected as the site is rendered.
+///
+/// Pages are rendered in parallel, so this is shared behind a lock.
+#[derive(Debug, Clone, Default)]
+pub struct Timings(Arc<Mutex<Vec<PageTiming>>>);
+
+impl Timings {
+    pub fn record(&self, timing: PageTiming) {
+        self.0.lock().expect("Timings lock poisoned").push(timing);
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().expect("Timings lock poisoned").clear();
+    }
+
+    /// The timings of every page rendered, slowest first.
+    #[must_use]
+    pub fn pages(&self) -> Vec<PageTiming> {
+        let mut pages = self.0.lock().expect("Timings lock poisoned").clone();
+        pages.sort_by(|a, b| b.render.cmp(&a.render).then_with(|| a.path.cmp(&b.path)));
+        pages
+    }
+
+    /// The pages whose template took longer than `threshold` to render, slowest first.
+    #[must_use]
+    pub fn slow_pages(&self, threshold: Duration) -> Vec<PageTiming> {
+        let mut pages = self.pages();
+        pages.retain(|p| p.render > threshold);
+        pages
+    }
+
+    /// The render timings of each template, by the total time spent rendering them.
+    #[must_use]
+    pub fn templates(&self) -> Vec<TemplateTiming> {
+        let pages = self.pages();
+        let mut templates = HashMap::<&str, TemplateTiming>::new();
+        for page in &pages {
+            let timing = templates
+                .entry(&page.template)
+                .or_insert_with(|| TemplateTiming {
+                    template: page.template.clone(),
+                    count: 0,
+                    total: Duration::ZERO,
+                    max: Duration::ZERO,
+                });
+            timing.count += 1;
+            timing.total += page.render;
+            timing.max = timing.max.max(page.render);
+        }
+
+        let mut templates = templates.into_values().collect::<Vec<_>>();
+        templates.sort_by(|a, b| {
+            b.total
+                .cmp(&a.total)
+                .then_with(|| a.template.cmp(&b.template))
+        });
+        templates
+    }
+}
+
+fn milliseconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::Result;
+
+    use super::*;
+    use crate::{
+        report::BuildReport,
+        test_utils::{make_page, make_site},
+    };
+
+    fn page(path: &str, template: &str, render_ms: u64) -> PageTiming {
+        PageTiming {
+            path: PathBuf::from(path),
+            template: template.to_owned(),
+            render: Duration::from_millis(render_ms),
+            write: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let timings = Timings::default();
+        timings.record(page("a.md", "post.html", 10));
+        timings.record(page("b.md", "page.html", 30));
+        timings.record(page("c.md", "post.html", 25));
+        timings.record(page("d.md", "post.html", 5));
+
+        assert_eq!(
+            timings.templates(),
+            vec![
+                TemplateTiming {
+                    template: "post.html".to_owned(),
+                    count: 3,
+                    total: Duration::from_millis(40),
+                    max: Duration::from_millis(25),
+                },
+                TemplateTiming {
+                    template: "page.html".to_owned(),
+                    count: 1,
+                    total: Duration::from_millis(30),
+                    max: Duration::from_millis(30),
+                },
+            ]
+        );
+        assert_eq!(
+            timings.slow_pages(Duration::from_millis(20)),
+            vec![page("b.md", "page.html", 30), page("c.md", "post.html", 25)]
+        );
+    }
+
+    #[test]
+    fn test_render_timings() -> Result<()> {
+        let (_root, out, mut site) = make_site(
+            &[
+                ("templates/post.html", "{{ document.content | safe }}"),
+                (
+                    "templates/docs.html",
+                    "<main>{{ document.content | safe }}</main>",
+                ),
+                ("_content/blog/hello.md", &make_page("Hello", "post.html")),
+                ("_content/blog/world.md", &make_page("World", "post.html")),
+                (
+                    "_content/docs/install.md",
+                    &make_page("Install", "docs.html"),
+                ),
+                ("about.html", "---\ntitle = \"About\"\n---\nAbout"),
+            ],
+            |_| (),
+        )?;
+        site.load()?;
+        site.render()?;
+
+        let about = site.library.template_pages[0].path.display().to_string();
+        let mut templates = site
+            .timings()
+            .templates()
+            .into_iter()
+            .map(|t| (t.template, t.count))
+            .collect::<Vec<_>>();
+        templates.sort();
+        assert_eq!(
+            templates,
+            vec![
+                (about, 1),
+                ("docs.html".to_owned(), 1),
+                ("post.html".to_owned(), 2),
+            ]
+        );
+
+        let mut pages = site
+            .timings()
+            .pages()
+            .into_iter()
+            .filter(|p| p.template.ends_with("post.html"))
+            .map(|p| p.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        pages.sort();
+        assert_eq!(pages, vec!["hello.md", "world.md"]);
+
+        let report = BuildReport::compare(
+            &site.config.site.output_path,
+            out.path().join("previous"),
+            &[],
+            Duration::ZERO,
+        )?
+        .with_timings(site.timings());
+        let json = serde_json::to_value(&report)?;
+        assert_eq!(json["templates"].as_array().unwrap().len(), 3);
+
+        // Timings are kept for the last render only.
+        site.render()?;
+        assert!(site.timings().templates().iter().all(|t| t.count <= 2));
+
+        Ok(())
+    }
+}
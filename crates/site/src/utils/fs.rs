@@ -1,13 +1,109 @@
 use std::fs;
+use std::io;
 use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
 use color_eyre::Result;
 
 // If the given directory doesn't exist, creates it.
+//
+// Files are rendered in parallel, so the directory may be created by another thread at
+// the same time, which `create_dir_all` tolerates.
 pub fn ensure_directory<T: AsRef<Path>>(path: T) -> Result<()> {
-    if !path.as_ref().exists() {
+    if !path.as_ref().is_dir() {
         fs::create_dir_all(path)?;
     }
 
     Ok(())
 }
+
+// How many times a file operation is retried while the file is in use, which on Windows
+// happens when another process, like a virus scanner or an editor, briefly holds a handle
+// to a file that was just written. Elsewhere, files can be removed or replaced while
+// they're open, so failures are final.
+#[cfg(windows)]
+const RETRIES: u32 = 5;
+#[cfg(not(windows))]
+const RETRIES: u32 = 0;
+
+// How long to wait before the first retry, doubling after each one.
+const RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// Run a file operation, like removing or renaming a file, retrying it with backoff while
+/// it fails because the file is in use by another process.
+pub fn retry_io<T>(op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    retry_with(RETRIES, RETRY_DELAY, op)
+}
+
+// Run `op`, retrying it up to `retries` times while the file is in use.
+fn retry_with<T>(
+    retries: u32,
+    mut delay: Duration,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    for _ in 0..retries {
+        match op() {
+            Err(e) if is_in_use(&e) => {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+
+    op()
+}
+
+// Whether an operation failed because another process has the file open. Windows reports
+// sharing and lock violations, which the standard library maps to permission errors.
+fn is_in_use(e: &io::Error) -> bool {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+
+    e.kind() == io::ErrorKind::PermissionDenied
+        || cfg!(windows)
+            && matches!(
+                e.raw_os_error(),
+                Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+            )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn test_retry_while_in_use() {
+        let attempts = Cell::new(0);
+        let op = || {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() < 3 {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            } else {
+                Ok(attempts.get())
+            }
+        };
+        assert_eq!(retry_with(5, Duration::ZERO, op).ok(), Some(3));
+
+        // Retries run out.
+        attempts.set(0);
+        let in_use = || {
+            attempts.set(attempts.get() + 1);
+            Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied))
+        };
+        assert!(retry_with(2, Duration::ZERO, in_use).is_err());
+        assert_eq!(attempts.get(), 3);
+
+        // Other errors aren't retried.
+        attempts.set(0);
+        let missing = || {
+            attempts.set(attempts.get() + 1);
+            Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+        };
+        assert!(retry_with(5, Duration::ZERO, missing).is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -1,41 +1,259 @@
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
-use color_eyre::{Result, eyre::ContextCompat};
+use color_eyre::{
+    Result,
+    eyre::{ContextCompat, bail},
+};
+use minify_html::{Cfg, minify};
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
 use url::Url;
 
+use crate::config::add_trailing_slash;
+
 pub mod fs;
 
-/// Build permalink for a site item.
+// The characters that end a component in a url path, or start its query or fragment.
+const URL_PATH_SEPARATORS: &AsciiSet = &CONTROLS.add(b'/').add(b'\\').add(b'?').add(b'#');
+
+// Stand in for the character references kept through minification, around their index.
+const KEPT_START: char = '\u{e000}';
+const KEPT_END: char = '\u{e001}';
+// The start of the comments written by `--debug-annotations`.
+const ANNOTATION_START: &str = "<!-- yar: ";
+// The names Windows reserves for devices, which can't name a file there whatever its
+// extension is.
+const RESERVED_NAMES: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Join a path that comes from a page, like its slug, onto a directory in the output
+/// directory, making sure the result can't be anywhere outside of it.
+///
+/// Absolute paths, `..` components, and names reserved on Windows are errors. Nested
+/// paths are joined as they are.
+pub fn safe_join<P: AsRef<Path>, T: AsRef<Path>>(out_root: P, relative: T) -> Result<PathBuf> {
+    let relative = relative.as_ref();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => {
+                let name = name.to_string_lossy();
+                let stem = name.split('.').next().unwrap_or_default().trim_end();
+                if RESERVED_NAMES.contains(&stem.to_ascii_lowercase().as_str()) {
+                    bail!(
+                        "{} uses {name}, which is a reserved name",
+                        relative.display()
+                    );
+                }
+            }
+            Component::CurDir => (),
+            Component::ParentDir => bail!(
+                "{} goes outside of the output directory with `..`",
+                relative.display()
+            ),
+            Component::RootDir | Component::Prefix(_) => {
+                bail!("{} is an absolute path", relative.display());
+            }
+        }
+    }
+
+    Ok(out_root.as_ref().join(relative))
+}
+
+/// Resolve the `.` and `..` components of a path without looking at the filesystem, so
+/// that paths to the same file written differently can be compared.
+pub fn normalize_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            Component::CurDir => (),
+            c => normalized.push(c),
+        }
+    }
+
+    normalized
+}
+
+/// Build permalink for a site item from its path in the output directory.
 pub fn build_permalink<P: AsRef<Path>, T: AsRef<Path>>(
-    path: P,
+    out_path: P,
     out_dir: T,
     url: &Url,
 ) -> Result<Url> {
-    let out = out_dir
-        .as_ref()
-        .file_name()
-        .context("Output directory shouldn't terminate in ..")?;
+    let out_path = out_path.as_ref();
+    let mut relative = out_path
+        .strip_prefix(out_dir.as_ref())
+        .ok()
+        .with_context(|| {
+            format!(
+                "Can't build a permalink for {}, since it isn't in the output directory {}",
+                out_path.display(),
+                out_dir.as_ref().display()
+            )
+        })?;
+    if relative.ends_with("index.html") {
+        relative = relative.parent().context("path doesn't have parent?")?;
+    }
+
+    let url_ending = url_path(relative).with_context(|| {
+        format!(
+            "Can't build a permalink for {}, since it isn't valid unicode",
+            out_path.display()
+        )
+    })?;
+
+    Ok(url.join(&url_ending)?)
+}
 
-    let mut components = path
+/// Build the url a page rendered to an `index.html` is linked to, which is its permalink
+/// with a trailing slash, the way `get_url` links to pages.
+pub fn build_page_url<P: AsRef<Path>, T: AsRef<Path>>(
+    out_path: P,
+    out_dir: T,
+    url: &Url,
+) -> Result<Url> {
+    let mut permalink = build_permalink(out_path, out_dir, url)?;
+    add_trailing_slash(&mut permalink);
+    Ok(permalink)
+}
+
+/// The path of a url to a relative path in the output directory, with its components
+/// joined by `/` whatever the platform's separator is, or `None` if it isn't valid unicode.
+///
+/// Characters that would end a component in a url, like the backslashes that can be part
+/// of a file name outside of Windows, are percent-encoded.
+pub fn url_path<P: AsRef<Path>>(path: P) -> Option<String> {
+    let components = path
         .as_ref()
         .components()
-        .skip_while(|c| c.as_os_str() != out);
-    components.next();
-    let mut url_ending = components.collect::<PathBuf>();
-    if url_ending.ends_with("index.html") {
-        url_ending = url_ending
-            .parent()
-            .context("path doesn't have parent?")?
-            .to_path_buf();
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .map(|c| {
+            let component = c.as_os_str().to_str()?;
+            Some(utf8_percent_encode(component, URL_PATH_SEPARATORS).to_string())
+        })
+        .collect::<Option<Vec<String>>>()?;
+
+    Some(components.join("/"))
+}
+
+/// The components of the path to a source, relative to the site's root, that make up the
+/// path it's output to. Components starting with `_`, such as `_content`, are left out.
+pub fn output_components<P: AsRef<Path>>(
+    path: &Path,
+    root: P,
+) -> impl Iterator<Item = Component<'_>> {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .filter(|c| !c.as_os_str().to_str().is_some_and(|s| s.starts_with('_')))
+}
+
+/// Minify rendered HTML.
+///
+/// The minifier decodes character references, so runs of decimal ones, like those written
+/// by `obfuscate_email`, are set aside and put back afterwards to keep them out of the
+/// plain HTML. Autoescaping only writes single hexadecimal references, which are left
+/// for the minifier. Debug annotations are set aside too, since comments are removed.
+pub fn minify_html(html: &str) -> Vec<u8> {
+    let mut kept = vec![];
+    let mut protected = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = [rest.find("&#"), rest.find(ANNOTATION_START)]
+        .into_iter()
+        .flatten()
+        .min()
+    {
+        protected.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(len) = kept_len(rest) else {
+            // Both start with an ASCII character.
+            protected.push_str(&rest[..1]);
+            rest = &rest[1..];
+            continue;
+        };
+
+        protected.push(KEPT_START);
+        protected.push_str(&kept.len().to_string());
+        protected.push(KEPT_END);
+        kept.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+    protected.push_str(rest);
+
+    let minified = minify(protected.as_bytes(), &Cfg::new());
+    if kept.is_empty() {
+        return minified;
     }
 
-    let permalink = url.join(
-        url_ending
-            .to_str()
-            .context("Path should be valid unicode.")?,
-    )?;
+    let minified = String::from_utf8_lossy(&minified);
+    let mut restored = String::with_capacity(minified.len());
+    let mut rest = minified.as_ref();
+    while let Some(start) = rest.find(KEPT_START) {
+        restored.push_str(&rest[..start]);
+        rest = &rest[start + KEPT_START.len_utf8()..];
+        let index = rest
+            .find(KEPT_END)
+            .and_then(|end| Some((end, rest[..end].parse::<usize>().ok()?)));
+        match index.and_then(|(end, i)| Some((end, *kept.get(i)?))) {
+            Some((end, reference)) => {
+                restored.push_str(reference);
+                rest = &rest[end + KEPT_END.len_utf8()..];
+            }
+            None => restored.push(KEPT_START),
+        }
+    }
+    restored.push_str(rest);
 
-    Ok(permalink)
+    restored.into_bytes()
+}
+
+// The length of what's kept from the minifier at the start of `s`, which is either a
+// debug annotation or a run of at least two decimal character references.
+fn kept_len(s: &str) -> Option<usize> {
+    if s.starts_with(ANNOTATION_START) {
+        return s.find("-->").map(|end| end + "-->".len());
+    }
+
+    let mut len = 0;
+    let mut count = 0;
+    while let Some(reference) = decimal_reference(&s[len..]) {
+        len += reference;
+        count += 1;
+    }
+    (count >= 2).then_some(len)
+}
+
+// The length of the decimal character reference at the start of `s`, if there is one.
+fn decimal_reference(s: &str) -> Option<usize> {
+    let digits = s.strip_prefix("&#")?;
+    let end = digits.find(';')?;
+    (end > 0 && digits[..end].chars().all(|c| c.is_ascii_digit())).then_some(end + 3)
+}
+
+/// Combine the results of a batch of independent operations, reporting every failure
+/// rather than only the first.
+pub fn collect_errors<I: IntoIterator<Item = Result<()>>>(results: I) -> Result<()> {
+    let mut errors = results
+        .into_iter()
+        .filter_map(Result::err)
+        .collect::<Vec<_>>();
+
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        n => bail!(
+            "{n} operations failed:\n{}",
+            errors
+                .iter()
+                .map(|e| format!("  {e:#}"))
+                .collect::<Vec<String>>()
+                .join("\n")
+        ),
+    }
 }
 
 #[cfg(test)]
@@ -59,6 +277,86 @@ mod tests {
         let url = Url::parse("https://example.com/")?;
         insta::assert_yaml_snapshot!(build_permalink(path, out_dir, &url)?);
 
+        // Directories above the output directory with the same name aren't part of it.
+        let path = Path::new("/home/public/site/public/about/index.html");
+        let out_dir = Path::new("/home/public/site/public");
+        assert_eq!(
+            build_permalink(path, out_dir, &url)?.as_str(),
+            "https://example.com/about"
+        );
+        let path = Path::new("public/public/posts/public/index.html");
+        let out_dir = Path::new("public/public");
+        assert_eq!(
+            build_permalink(path, out_dir, &url)?.as_str(),
+            "https://example.com/posts/public"
+        );
+        assert!(build_permalink("elsewhere/index.html", out_dir, &url).is_err());
+
         Ok(())
     }
+
+    #[test]
+    fn test_url_path() {
+        let path = Path::new("posts").join("hello world").join("index.html");
+        assert_eq!(
+            url_path(path).as_deref(),
+            Some("posts/hello world/index.html")
+        );
+        assert_eq!(url_path("./thumbs/a.png").as_deref(), Some("thumbs/a.png"));
+        assert_eq!(url_path("what?#.html").as_deref(), Some("what%3F%23.html"));
+
+        // Backslashes only separate components on Windows, and are part of the name
+        // everywhere else.
+        if cfg!(windows) {
+            assert_eq!(url_path(r"posts\hello").as_deref(), Some("posts/hello"));
+        } else {
+            assert_eq!(url_path(r"posts\hello").as_deref(), Some("posts%5Chello"));
+        }
+    }
+
+    #[test]
+    fn test_safe_join() -> Result<()> {
+        // Nested paths are joined as they are.
+        for path in [
+            "hello-world",
+            "blog/2024/post",
+            "./notes",
+            "file.txt",
+            "console",
+        ] {
+            assert_eq!(safe_join("public", path)?, Path::new("public").join(path));
+        }
+
+        for path in [
+            "../../etc/whatever",
+            "posts/../../escape",
+            "..",
+            "/etc/passwd",
+            "CON",
+            "posts/nul.txt",
+            "Lpt1 .html",
+        ] {
+            assert!(safe_join("public", path).is_err(), "{path}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_minify_html_keeps_decimal_references() {
+        let html = "<p>  Mail <a href=\"&#109;&#58;a%40b\">&#97;&#64;&#98;</a> &#38; http:&#x2f;&#x2f;x  </p>";
+        assert_eq!(
+            String::from_utf8_lossy(&minify_html(html)),
+            "<p>Mail <a href=&#109;&#58;a%40b>&#97;&#64;&#98;</a> & http://x"
+        );
+    }
+
+    #[test]
+    fn test_minify_html_keeps_annotations() {
+        let html = "<!-- yar: a.md:1 -->\n<p>  Hi </p>\n<!-- other comment --><p>&#38;</p>";
+        assert_eq!(
+            String::from_utf8_lossy(&minify_html(html)),
+            "<!-- yar: a.md:1 --> <p>Hi<p>&"
+        );
+    }
 }
@@ -0,0 +1,397 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use color_eyre::Result;
+use common::TestSite;
+use yar_site::{
+    cache::{CacheImport, export_cache, import_cache},
+    config::RedirectsFormat,
+    database::{DatabaseSource, get_template_page_outputs, setup_database},
+};
+
+// Files written by every build, whether or not anything changed.
+const ALWAYS_WRITTEN: [&str; 4] = ["404.html", "atom.xml", "sitemap.xml", "styles/_syntax.css"];
+
+#[test]
+fn test_full_build() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    let build = site.build()?;
+
+    insta::assert_yaml_snapshot!(build.files()?);
+    assert_eq!(build.outputs, build.files()?);
+
+    build.assert_output_contains("blog/hello-world/index.html", "<h1>Hello World</h1>");
+    build.assert_output_contains(
+        "blog/hello-world/index.html",
+        "<a class=next href=http://0.0.0.0:8000/blog/second-post>Second Post</a>",
+    );
+    build.assert_output_contains("blog/second-post/index.html", "<time>2025-02-01</time>");
+    build.assert_output_contains("index.html", "Second Post");
+    build.assert_output_contains("tags/index.html", "<li>meta (1)<li>Rust (2)");
+    build.assert_output_contains("styles/main.css", "a{color:#c0392b}");
+    build.assert_output_contains("static/robots.txt", "User-agent: *");
+
+    // Drafts aren't published.
+    let feed = build.read("atom.xml");
+    let feed = roxmltree::Document::parse(&feed)?;
+    let titles = feed
+        .descendants()
+        .filter(|n| n.has_tag_name("entry"))
+        .filter_map(|n| n.children().find(|c| c.has_tag_name("title"))?.text())
+        .collect::<Vec<&str>>();
+    assert_eq!(titles, ["Hello World", "Second Post"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_incremental_build() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    let full = site.build()?;
+
+    // Nothing changed, so only the files written by every build are.
+    let build = site.build()?;
+    assert_eq!(build.files()?, ALWAYS_WRITTEN);
+    assert_eq!(build.outputs, full.outputs);
+
+    // Editing a post only renders it and the pages next to it again.
+    site.write(
+        "_content/blog/second-post.md",
+        "---\ntitle = \"Second Post\"\ntags = [\"rust\"]\nslug = \"second-post\"\ndate = \"2025-02-01T12:00:00\"\n---\n\nEdited.\n",
+    )?;
+    let build = site.build()?;
+    let mut expected = ALWAYS_WRITTEN.to_vec();
+    expected.extend(["blog/hello-world/index.html", "blog/second-post/index.html"]);
+    expected.sort_unstable();
+    assert_eq!(build.files()?, expected);
+    build.assert_output_contains("blog/second-post/index.html", "<p>Edited.");
+
+    Ok(())
+}
+
+#[test]
+fn test_base_url_change() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    site.config.site.url = "https://staging.example.com/".parse()?;
+    let staging = site.build()?;
+    staging.assert_output_contains("atom.xml", "https://staging.example.com/blog/hello-world");
+
+    // Every page is rendered again, so nothing links to the staging site.
+    site.config.site.url = "https://example.com/".parse()?;
+    let production = site.build()?;
+    assert_eq!(production.files()?, production.outputs);
+    for file in production.files()? {
+        let content = String::from_utf8_lossy(&fs::read(production.dir.join(&file))?).into_owned();
+        assert!(
+            !content.contains("staging.example.com"),
+            "{file} links to the staging site:\n{content}"
+        );
+    }
+    production.assert_output_contains("atom.xml", "https://example.com/blog/hello-world");
+
+    Ok(())
+}
+
+// Sites hosted under a path keep it in every url they link to, even without the url's
+// trailing slash.
+#[test]
+fn test_subpath_url() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    site.config.site.url = "https://example.com/project".parse()?;
+    site.config.site.redirects_format = RedirectsFormat::Netlify;
+    site.write(
+        "_content/about.md",
+        "---\ntitle = \"About\"\ntags = []\nslug = \"about\"\naliases = [\"/me\"]\n---\n\nAbout me.\n",
+    )?;
+    let build = site.build()?;
+
+    let mut urls = 0;
+    for file in build.files()? {
+        let content = String::from_utf8_lossy(&fs::read(build.dir.join(&file))?).into_owned();
+        // Links from the root of the host would leave the site's path.
+        for attribute in ["href=/", "href=\"/", "src=/", "src=\"/"] {
+            for (i, _) in content.match_indices(attribute) {
+                let link = &content[i + attribute.len() - 1..];
+                assert!(
+                    link.starts_with("/project/"),
+                    "{file} links outside of the site's path: {}",
+                    link.lines().next().unwrap_or_default()
+                );
+            }
+        }
+        for (i, _) in content.match_indices("https://example.com") {
+            let url = &content[i..];
+            assert!(
+                url.starts_with("https://example.com/project/"),
+                "{file} links outside of the site's path: {}",
+                url.lines().next().unwrap_or_default()
+            );
+            urls += 1;
+        }
+    }
+    assert!(urls > 0);
+    build.assert_output_contains("atom.xml", "https://example.com/project/atom.xml");
+    build.assert_output_contains("atom.xml", "https://example.com/project/blog/hello-world");
+    build.assert_output_contains(
+        "sitemap.xml",
+        "<loc>https://example.com/project/blog/hello-world</loc>",
+    );
+    build.assert_output_contains("_redirects", "/project/me  /project/about  301");
+
+    Ok(())
+}
+
+// Where the project is checked out doesn't change where anything is built to, even when
+// its directories share a name with the output directory.
+#[test]
+fn test_nested_project() -> Result<()> {
+    let about = "---\ntitle = \"About\"\ntags = []\nslug = \"about\"\n---\n\nAbout me.\n";
+    let mut site = TestSite::from_fixture("basic-site")?;
+    site.write("_content/about.md", about)?;
+    let expected = site.build()?;
+
+    for dir in ["public", "site", "public/site", "site/public"] {
+        let mut site = TestSite::from_fixture_in("basic-site", dir)?;
+        site.write("_content/about.md", about)?;
+        let build = site.build()?;
+
+        assert_eq!(build.files()?, expected.files()?, "under {dir}");
+        build.assert_output_contains("about/index.html", "<p>About me.");
+        build.assert_output_contains("sitemap.xml", "<loc>http://0.0.0.0:8000/about</loc>");
+        build.assert_output_contains("atom.xml", "http://0.0.0.0:8000/blog/hello-world");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_thumbnails() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    let full = site.build()?;
+
+    let thumb = full
+        .files()?
+        .into_iter()
+        .find(|f| f.starts_with("thumbs/"))
+        .expect("A thumbnail should be generated");
+    full.assert_output_contains("index.html", &format!("http://0.0.0.0:8000/{thumb}"));
+    let image = image::open(full.dir.join(&thumb))?;
+    assert_eq!((image.width(), image.height()), (32, 16));
+
+    // Cached thumbnails aren't generated again.
+    let build = site.build()?;
+    assert_eq!(build.files()?, ALWAYS_WRITTEN);
+    assert!(build.outputs.contains(&thumb));
+
+    // Changing the image replaces its thumbnail, and renders the pages using it again.
+    image::RgbImage::from_pixel(40, 40, image::Rgb([200, 40, 40]))
+        .save(site.root.path().join("static/images/cover.png"))?;
+    let build = site.build()?;
+    let new_thumb = build
+        .files()?
+        .into_iter()
+        .find(|f| f.starts_with("thumbs/"))
+        .expect("The thumbnail should be generated again");
+    assert_ne!(new_thumb, thumb);
+    assert!(!build.outputs.contains(&thumb));
+    build.assert_output_contains("index.html", &new_thumb);
+
+    Ok(())
+}
+
+#[test]
+fn test_template_page_outputs() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    site.write(
+        "archive/titles.html",
+        "---\ntitle = \"Titles\"\nslug = \"titles\"\ndependencies = [\"pages\"]\n---\n\
+         {% for page in pages | sort(attribute=\"document.frontmatter.title\") %}\
+         {{ page.document.frontmatter.title }}\n{% endfor %}",
+    )?;
+    site.write(
+        "archive/posts.html",
+        "---\ntitle = \"Posts\"\n[pagination]\nfrom = \"pages\"\nevery = 1\n\
+         name_template = \"pagination.items[0].document.frontmatter.title | lower | replace(' ', '-')\"\n---\n\
+         {% for page in pagination.items %}{{ page.document.frontmatter.title }}: \
+         {{ page.document.frontmatter.tags | join(\", \") }}{% endfor %}",
+    )?;
+    let full = site.build()?;
+    full.assert_output_contains("archive/titles/index.html", "Hello World");
+    full.assert_output_contains("archive/second-post/index.html", "Second Post: rust");
+    let db_file = site.config.site.db_file.clone();
+    let outputs = || get_template_page_outputs(&setup_database(DatabaseSource::File(&db_file))?);
+    let before = outputs()?;
+
+    // Editing a post's body renders the template pages again, but what they render is the
+    // same, so none of it is written.
+    site.write(
+        "_content/blog/second-post.md",
+        "---\ntitle = \"Second Post\"\ntags = [\"rust\"]\nslug = \"second-post\"\ndate = \"2025-02-01T12:00:00\"\n---\n\nEdited.\n",
+    )?;
+    let build = site.build()?;
+    assert!(build.files()?.iter().all(|f| !f.starts_with("archive/")));
+    assert_eq!(outputs()?, before);
+
+    // Retagging it only changes its own chunk.
+    site.write(
+        "_content/blog/second-post.md",
+        "---\ntitle = \"Second Post\"\ntags = [\"rust\", \"web\"]\nslug = \"second-post\"\ndate = \"2025-02-01T12:00:00\"\n---\n\nEdited.\n",
+    )?;
+    let build = site.build()?;
+    assert_eq!(
+        build
+            .files()?
+            .into_iter()
+            .filter(|f| f.starts_with("archive/"))
+            .collect::<Vec<String>>(),
+        ["archive/second-post/index.html"]
+    );
+    let after = outputs()?;
+    let changed = after
+        .iter()
+        .filter(|(path, hash)| before.get(*path) != Some(*hash))
+        .map(|(path, _)| path.to_string_lossy().into_owned())
+        .collect::<Vec<String>>();
+    assert_eq!(changed, ["archive/second-post/index.html"]);
+
+    // Removing it removes its chunk's row.
+    fs::remove_file(site.root.path().join("_content/blog/second-post.md"))?;
+    site.build()?;
+    let after = outputs()?;
+    assert!(!after.contains_key(Path::new("archive/second-post/index.html")));
+    assert!(after.contains_key(Path::new("archive/hello-world/index.html")));
+
+    Ok(())
+}
+
+#[test]
+fn test_hidden_page() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    site.write(
+        "_content/blog/now.md",
+        "---\ntitle = \"Now\"\ntags = [\"meta\"]\nslug = \"now\"\nhidden = true\n---\n\nWhat I'm up to.\n",
+    )?;
+    let build = site.build()?;
+
+    // Hidden pages are rendered, but aren't listed anywhere but the sitemap.
+    build.assert_output_contains("blog/now/index.html", "<p>What I'm up to.");
+    assert!(!build.read("index.html").contains("Now"));
+    assert!(!build.read("atom.xml").contains("blog/now"));
+    build.assert_output_contains("tags/index.html", "<li>meta (1)");
+    build.assert_output_contains("sitemap.xml", "http://0.0.0.0:8000/blog/now");
+
+    site.config.site.sitemap.include_hidden = false;
+    let build = site.build()?;
+    assert!(!build.read("sitemap.xml").contains("blog/now"));
+
+    Ok(())
+}
+
+#[test]
+fn test_site_stats() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    site.write(
+        "stats.html",
+        "---\ntitle = \"Stats\"\nslug = \"stats\"\noutput_path = \"stats.txt\"\ndependencies = [\"stats\"]\n---\n{{ stats.pages }} posts · {{ stats.words }} words · {{ stats.tags }} tags · newest {{ stats.newest | datetimeformat(format=\"short\") }}\n",
+    )?;
+    let build = site.build()?;
+    insta::assert_snapshot!(build.read("stats.txt"));
+
+    // The stats only change with the content, so they don't render the template page
+    // again on their own.
+    let build = site.build()?;
+    assert_eq!(build.files()?, ALWAYS_WRITTEN);
+
+    Ok(())
+}
+
+#[test]
+fn test_dry_run() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    let full = site.build()?;
+    let files = full.files()?;
+    let db = fs::read(&site.config.site.db_file)?;
+
+    site.write(
+        "_content/blog/third-post.md",
+        "---\ntitle = \"Third Post\"\ntags = []\nslug = \"third-post\"\ndate = \"2025-03-01T12:00:00\"\n---\n\nThird.\n",
+    )?;
+    let plan = site.dry_run(&full)?;
+    assert_eq!(plan.pages_created, 1);
+    assert!(
+        plan.report
+            .changed
+            .iter()
+            .any(|c| c.path == Path::new("blog/third-post/index.html"))
+    );
+    assert_eq!(
+        plan.permalinks
+            .iter()
+            .map(url::Url::path)
+            .collect::<Vec<&str>>(),
+        [
+            "/",
+            "/blog/hello-world",
+            "/blog/second-post",
+            "/blog/third-post"
+        ]
+    );
+
+    // Neither the previous output nor the cache was touched.
+    assert_eq!(full.files()?, files);
+    assert_eq!(fs::read(&site.config.site.db_file)?, db);
+
+    // So the next build still sees the new post as changed.
+    let build = site.build()?;
+    assert!(
+        build
+            .files()?
+            .contains(&"blog/third-post/index.html".to_owned())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_cache_export_import() -> Result<()> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    let full = site.build()?;
+    let file = site.work.path().join("cache.gz");
+    let db = setup_database(DatabaseSource::File(&site.config.site.db_file))?;
+    assert_eq!(export_cache(&db, &full.dir, &file)?, full.files()?.len());
+    drop(db);
+
+    // Like CI, start over with the previous output but without the database.
+    fs::remove_file(&site.config.site.db_file)?;
+    let db = setup_database(DatabaseSource::File(&site.config.site.db_file))?;
+    assert_eq!(
+        import_cache(&db, &site.config, &full.dir, &file)?,
+        CacheImport::Imported {
+            outputs: full.files()?.len()
+        }
+    );
+    drop(db);
+
+    let plan = site.dry_run(&full)?;
+    assert_eq!((plan.pages_created, plan.pages_updated), (0, 0));
+    let build = site.build()?;
+    assert_eq!(build.files()?, ALWAYS_WRITTEN);
+
+    // A cache built with a different config builds everything again.
+    site.config.site.title = Some("Renamed".to_owned());
+    let db = setup_database(DatabaseSource::File(&site.config.site.db_file))?;
+    assert_eq!(
+        import_cache(&db, &site.config, &full.dir, &file)?,
+        CacheImport::Rejected("The config changed since the cache was exported".to_owned())
+    );
+    drop(db);
+    let build = site.build()?;
+    assert!(
+        build
+            .files()?
+            .contains(&"blog/hello-world/index.html".to_owned())
+    );
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! Helpers for building fixture sites end to end.
+
+// Each test file uses a different subset of the helpers.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::Result;
+use ignore::WalkBuilder;
+use tempfile::TempDir;
+use yar_site::{
+    BuildPlan, Site,
+    config::Config,
+    database::{DatabaseSource, setup_database},
+};
+
+/// A copy of a fixture site, built the way `yar build` builds a site.
+///
+/// Like `yar build`, each build renders to the same empty directory, so that it only holds
+/// the files that build wrote, and the cache is kept in a database file so that it carries
+/// over between builds. The output is then moved aside to be checked.
+pub struct TestSite {
+    pub root: TempDir,
+    pub work: TempDir,
+    pub config: Config,
+    builds: usize,
+}
+
+/// The output of one build of a [`TestSite`].
+pub struct Build {
+    /// The directory holding the files this build wrote.
+    pub dir: PathBuf,
+    /// Every file the site's output holds after this build, according to the site.
+    pub outputs: Vec<String>,
+}
+
+impl TestSite {
+    /// Copy the fixture in `tests/fixtures/<name>` to a temporary directory.
+    ///
+    /// Site roots are expected to be a relative path, so the copy is made in the working
+    /// directory.
+    pub fn from_fixture(name: &str) -> Result<Self> {
+        Self::from_fixture_in(name, "")
+    }
+
+    /// Copy the fixture in `tests/fixtures/<name>` to `dir` in a temporary directory, like
+    /// a project checked out under directories with names of its own.
+    pub fn from_fixture_in(name: &str, dir: &str) -> Result<Self> {
+        let root = tempfile::Builder::new()
+            .prefix("test-fixture")
+            .tempdir_in(".")?;
+        let work = tempfile::tempdir()?;
+        copy_dir(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures")
+                .join(name),
+            root.path().join(dir),
+        )?;
+
+        let mut config = Config::default();
+        config.site.root = PathBuf::from(root.path().file_name().unwrap()).join(dir);
+        config.site.db_file = work.path().join("site.redb");
+
+        Ok(Self {
+            root,
+            work,
+            config,
+            builds: 0,
+        })
+    }
+
+    /// Load, render, and cache the site.
+    pub fn build(&mut self) -> Result<Build> {
+        // Cached pages remember where they were rendered to, so every build has to render
+        // to the same place.
+        let build_dir = self.work.path().join("public");
+        let mut config = self.config.clone();
+        config.site.output_path.clone_from(&build_dir);
+        let db = setup_database(DatabaseSource::File(&config.site.db_file))?;
+
+        let mut site = Site::new(db, config)?;
+        site.load()?;
+        site.render()?;
+        site.save_to_cache()?;
+
+        self.builds += 1;
+        let dir = self.work.path().join(format!("build-{}", self.builds));
+        fs::rename(build_dir, &dir)?;
+
+        let outputs = site
+            .output_files()
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        Ok(Build { dir, outputs })
+    }
+
+    /// Render the site the way `yar build --dry-run` does, without caching it, planning
+    /// what it would change in the output of `previous`.
+    pub fn dry_run(&self, previous: &Build) -> Result<BuildPlan> {
+        let build_dir = self.work.path().join("public");
+        let mut config = self.config.clone();
+        config.site.output_path.clone_from(&build_dir);
+        let db = setup_database(DatabaseSource::Snapshot(&config.site.db_file))?;
+
+        let mut site = Site::new(db, config)?;
+        site.load()?;
+        site.render()?;
+        let plan = site.plan(&previous.dir, Duration::ZERO)?;
+
+        fs::remove_dir_all(build_dir)?;
+        Ok(plan)
+    }
+
+    /// Write a file in the site's root, replacing it if it exists.
+    pub fn write<P: AsRef<Path>>(&self, path: P, content: &str) -> Result<()> {
+        let path = self.config.site.root.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+impl Build {
+    /// The files this build wrote, relative to the output directory and sorted.
+    pub fn files(&self) -> Result<Vec<String>> {
+        let mut files = vec![];
+        for entry in WalkBuilder::new(&self.dir).standard_filters(false).build() {
+            let entry = entry?;
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                let path = entry.path().strip_prefix(&self.dir)?;
+                files.push(path.to_string_lossy().into_owned());
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+
+    /// Read the output file at `path`.
+    pub fn read(&self, path: &str) -> String {
+        let file = self.dir.join(path);
+        fs::read_to_string(&file)
+            .unwrap_or_else(|e| panic!("Couldn't read output {}: {e}", file.display()))
+    }
+
+    /// Assert that the output file at `path` contains `needle`.
+    pub fn assert_output_contains(&self, path: &str, needle: &str) {
+        let content = self.read(path);
+        assert!(
+            content.contains(needle),
+            "Expected {path} to contain {needle:?}, but it was:\n{content}"
+        );
+    }
+}
+
+fn copy_dir<P: AsRef<Path>, T: AsRef<Path>>(src: P, out: T) -> Result<()> {
+    fs::create_dir_all(&out)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let out = out.as_ref().join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(entry.path(), out)?;
+        } else {
+            fs::copy(entry.path(), out)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! Builds with a fixed number of jobs. These run in their own process, since the global
+//! thread pool can only be configured once.
+
+mod common;
+
+use std::{fs, num::NonZeroUsize};
+
+use color_eyre::Result;
+use common::{Build, TestSite};
+use yar_site::configure_jobs;
+
+// The site is returned along with its build, since the build is removed with it.
+fn build_with_jobs(jobs: usize) -> Result<(TestSite, Build)> {
+    let mut site = TestSite::from_fixture("basic-site")?;
+    site.config.site.jobs = NonZeroUsize::new(jobs);
+    let build = site.build()?;
+    Ok((site, build))
+}
+
+fn assert_same_output(a: &Build, b: &Build) -> Result<()> {
+    assert_eq!(a.files()?, b.files()?);
+    assert_eq!(a.outputs, b.outputs);
+    // The feed and sitemap are dated by when they were built, so they can't match.
+    for file in a.files()? {
+        if file == "atom.xml" || file == "sitemap.xml" {
+            continue;
+        }
+        assert!(
+            fs::read(a.dir.join(&file))? == fs::read(b.dir.join(&file))?,
+            "{file} differs between builds"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sequential_build() -> Result<()> {
+    configure_jobs(NonZeroUsize::new(1))?;
+    assert_eq!(rayon::current_num_threads(), 1);
+
+    let (_site, sequential) = build_with_jobs(1)?;
+    assert_same_output(&sequential, &build_with_jobs(1)?.1)?;
+
+    let (_site, parallel) = rayon::ThreadPoolBuilder::new()
+        .num_threads(4)
+        .build()?
+        .install(|| {
+            assert_eq!(rayon::current_num_threads(), 4);
+            build_with_jobs(4)
+        })?;
+    assert_same_output(&sequential, &parallel)?;
+
+    Ok(())
+}
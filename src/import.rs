@@ -0,0 +1,816 @@
+use std::{
+    collections::HashSet,
+    fs, mem,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use clap::ValueEnum;
+use color_eyre::{Result, eyre::bail};
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use yar_site::split_frontmatter;
+
+/// The static site generators a site can be imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportSource {
+    /// Jekyll, with YAML frontmatter and Liquid `{% highlight %}` blocks.
+    Jekyll,
+}
+
+/// Something in an imported file that couldn't be converted, which needs to be looked at
+/// by hand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attention {
+    /// The file, relative to the source directory.
+    pub path: PathBuf,
+    /// The line in the source file, if it's about one place in it.
+    pub line: Option<usize>,
+    pub reason: String,
+}
+
+/// What importing a site did, or would do in a dry run.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// The files converted, as their path relative to the source directory and the path
+    /// they're written to relative to the destination.
+    pub converted: Vec<(PathBuf, PathBuf)>,
+    /// Files that aren't markdown, which are left out, relative to the source directory.
+    pub skipped: Vec<PathBuf>,
+    pub attention: Vec<Attention>,
+}
+
+/// Convert the markdown files in `src` from another static site generator's conventions
+/// to yar's, writing them to the same place under `dest`.
+///
+/// Nothing is written in a dry run, though the report is the same. Existing files are never
+/// overwritten.
+pub fn import_site<P: AsRef<Path>, T: AsRef<Path>>(
+    source: ImportSource,
+    src: P,
+    dest: T,
+    dry_run: bool,
+) -> Result<ImportReport> {
+    let (src, dest) = (src.as_ref(), dest.as_ref());
+    if !src.is_dir() {
+        bail!("There's no directory at {} to import", src.display());
+    }
+
+    let mut files = vec![];
+    collect_files(src, Path::new(""), &mut files)?;
+    files.sort();
+
+    let mut report = ImportReport::default();
+    let mut written = HashSet::new();
+    let mut outputs = vec![];
+    for path in files {
+        if !path
+            .extension()
+            .is_some_and(|e| e == "md" || e == "markdown")
+        {
+            report.skipped.push(path);
+            continue;
+        }
+
+        let content = fs::read_to_string(src.join(&path))?;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let converted = match source {
+            ImportSource::Jekyll => convert_jekyll(&content, &file_name),
+        };
+        report.attention.extend(
+            converted
+                .attention
+                .into_iter()
+                .map(|(line, reason)| Attention {
+                    path: path.clone(),
+                    line,
+                    reason,
+                }),
+        );
+        let Some(content) = converted.content else {
+            continue;
+        };
+
+        let out = path.with_file_name(&converted.file_name);
+        if !written.insert(out.clone()) {
+            bail!(
+                "More than one file would be imported to {}, such as {}",
+                out.display(),
+                path.display()
+            );
+        }
+        if dest.join(&out).exists() {
+            bail!(
+                "{} already exists, refusing to overwrite it",
+                dest.join(&out).display()
+            );
+        }
+        outputs.push((dest.join(&out), content));
+        report.converted.push((path, out));
+    }
+
+    // Files are only written once every one of them has been converted, so that a failed
+    // import doesn't leave half of the site behind.
+    if !dry_run {
+        for (path, content) in outputs {
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, content)?;
+        }
+    }
+
+    Ok(report)
+}
+
+// Collect the paths of the files under `dir`, relative to the directory being imported,
+// leaving out hidden directories and Jekyll's output.
+fn collect_files(dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let path = relative.join(&name);
+        if entry.file_type()?.is_dir() {
+            let name = name.to_string_lossy();
+            if !name.starts_with('.') && name != "_site" {
+                collect_files(&entry.path(), &path, files)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+// A converted file, along with the lines that need to be looked at by hand. The content is
+// missing if the file couldn't be converted at all.
+struct Converted {
+    file_name: String,
+    content: Option<String>,
+    attention: Vec<(Option<usize>, String)>,
+}
+
+// The frontmatter a Jekyll page is converted to, with the keys yar knows about first.
+#[derive(Debug, Serialize)]
+struct ImportedFrontmatter {
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slug: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    draft: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    aliases: Vec<String>,
+    #[serde(flatten)]
+    extra: toml::Table,
+}
+
+// Convert a Jekyll page, taking the date out of its file name if it starts with one.
+fn convert_jekyll(content: &str, file_name: &str) -> Converted {
+    let (file_date, file_name) = split_file_date(file_name);
+    let mut converted = Converted {
+        file_name: file_name.to_owned(),
+        content: None,
+        attention: vec![],
+    };
+
+    let split = split_frontmatter(content);
+    let line_offset = content[..content.len() - split.body.len()]
+        .matches('\n')
+        .count();
+    let mapping = match serde_yaml::from_str::<Option<Mapping>>(split.frontmatter) {
+        Ok(mapping) => mapping.unwrap_or_default(),
+        Err(e) => {
+            converted
+                .attention
+                .push((None, format!("couldn't parse the frontmatter: {e}")));
+            return converted;
+        }
+    };
+
+    let stem = Path::new(file_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let frontmatter = convert_frontmatter(mapping, file_date, &stem, &mut converted.attention);
+    let body = convert_liquid(split.body, line_offset, &mut converted.attention);
+
+    match toml::to_string(&frontmatter) {
+        Ok(frontmatter) => converted.content = Some(format!("---\n{frontmatter}---\n{body}")),
+        Err(e) => converted
+            .attention
+            .push((None, format!("couldn't write the frontmatter: {e}"))),
+    }
+
+    converted
+}
+
+// Split the `2024-05-17-` date off the start of a Jekyll post's file name.
+fn split_file_date(file_name: &str) -> (Option<NaiveDate>, &str) {
+    file_name
+        .get(..11)
+        .filter(|prefix| prefix.ends_with('-'))
+        .and_then(|prefix| NaiveDate::parse_from_str(&prefix[..10], "%Y-%m-%d").ok())
+        .map_or((None, file_name), |date| (Some(date), &file_name[11..]))
+}
+
+fn convert_frontmatter(
+    mut mapping: Mapping,
+    file_date: Option<NaiveDate>,
+    stem: &str,
+    attention: &mut Vec<(Option<usize>, String)>,
+) -> ImportedFrontmatter {
+    let title = mapping.remove("title").as_ref().and_then(scalar);
+    let title = title.unwrap_or_else(|| {
+        let title = stem.replace('-', " ");
+        attention.push((None, format!("there's no title, so it's {title:?}")));
+        title
+    });
+
+    // Jekyll's categories are another set of tags as far as yar is concerned, and either
+    // can be a list or a string of space separated words.
+    let mut tags = vec![];
+    for key in ["categories", "category", "tags"] {
+        let words = match mapping.remove(key) {
+            Some(Value::Sequence(values)) => values.iter().filter_map(scalar).collect(),
+            Some(value) => scalar(&value).map_or_else(Vec::new, |s| {
+                s.split_whitespace().map(str::to_owned).collect::<Vec<_>>()
+            }),
+            None => vec![],
+        };
+        for word in words {
+            if !tags.contains(&word) {
+                tags.push(word);
+            }
+        }
+    }
+
+    let date = date_key(&mut mapping, "date", attention)
+        .or_else(|| file_date.map(|d| d.and_time(NaiveTime::MIN).format(DATE_FORMAT).to_string()));
+    let updated = date_key(&mut mapping, "last_modified_at", attention);
+
+    // Old URLs are kept working by redirecting them to the page.
+    let mut aliases = vec![];
+    for key in ["permalink", "redirect_from"] {
+        match mapping.remove(key) {
+            Some(Value::Sequence(values)) => aliases.extend(values.iter().filter_map(scalar)),
+            Some(value) => aliases.extend(scalar(&value)),
+            None => (),
+        }
+    }
+
+    let template = mapping
+        .remove("layout")
+        .as_ref()
+        .and_then(scalar)
+        .filter(|layout| layout != "none")
+        .map(|layout| format!("{layout}.html"));
+    let description = ["description", "excerpt"]
+        .iter()
+        .filter_map(|key| mapping.remove(*key))
+        .find_map(|value| scalar(&value));
+    // Posts are named after their file in Jekyll, rather than their title.
+    let slug = mapping
+        .remove("slug")
+        .as_ref()
+        .and_then(scalar)
+        .or_else(|| file_date.map(|_| stem.to_owned()));
+    let draft = mapping
+        .remove("published")
+        .is_some_and(|published| published == Value::Bool(false));
+
+    let mut extra = toml::Table::new();
+    for (key, value) in mapping {
+        let Some(key) = scalar(&key) else {
+            continue;
+        };
+        let Some(value) = without_nulls(value) else {
+            continue;
+        };
+        match toml::Value::try_from(&value) {
+            Ok(value) => {
+                extra.insert(key, value);
+            }
+            Err(e) => attention.push((None, format!("couldn't convert {key}: {e}"))),
+        }
+    }
+
+    ImportedFrontmatter {
+        title,
+        description,
+        tags,
+        template,
+        date,
+        updated,
+        slug,
+        draft,
+        aliases,
+        extra,
+    }
+}
+
+// Convert the date under the given key, noting it if it can't be read.
+fn date_key(
+    mapping: &mut Mapping,
+    key: &str,
+    attention: &mut Vec<(Option<usize>, String)>,
+) -> Option<String> {
+    let value = mapping.remove(key)?;
+    let converted = scalar(&value).as_deref().and_then(convert_date);
+    if converted.is_none() {
+        attention.push((None, format!("couldn't read the {key} {value:?}")));
+    }
+    converted
+}
+
+// The format yar reads dates in, which are in UTC.
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+// Convert a date in one of the forms Jekyll reads, like `2024-05-17 10:30:00 +0100`, to UTC.
+fn convert_date(value: &str) -> Option<String> {
+    let value = value.trim();
+    let datetime = DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S %z")
+        .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%d %H:%M %z"))
+        .or_else(|_| DateTime::parse_from_rfc3339(value))
+        .map(|d| d.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M"))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, DATE_FORMAT))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|d| d.and_time(NaiveTime::MIN))
+        })
+        .ok()?;
+
+    Some(datetime.format(DATE_FORMAT).to_string())
+}
+
+// Leave out the nulls in a YAML value, which TOML doesn't have.
+fn without_nulls(value: Value) -> Option<Value> {
+    match value {
+        Value::Null => None,
+        Value::Sequence(values) => Some(Value::Sequence(
+            values.into_iter().filter_map(without_nulls).collect(),
+        )),
+        Value::Mapping(mapping) => Some(Value::Mapping(
+            mapping
+                .into_iter()
+                .filter_map(|(k, v)| Some((k, without_nulls(v)?)))
+                .collect(),
+        )),
+        Value::Tagged(tagged) => without_nulls(tagged.value),
+        value => Some(value),
+    }
+}
+
+// A YAML string, number, or boolean as a string.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// Rewrite `{% highlight %}` blocks into fenced code blocks and drop `{% raw %}` tags,
+// since yar doesn't run markdown through a template engine. Any other Liquid is left as
+// it is, and noted at its line in the file.
+fn convert_liquid(
+    body: &str,
+    line_offset: usize,
+    attention: &mut Vec<(Option<usize>, String)>,
+) -> String {
+    let line = |offset: usize| Some(line_offset + body[..offset].matches('\n').count() + 1);
+    let mut out = String::new();
+    let mut raw = false;
+    let mut position = 0;
+
+    while let Some(start) = body[position..].find('{').map(|i| position + i) {
+        let rest = &body[start..];
+        let close = if rest.starts_with("{%") {
+            "%}"
+        } else if rest.starts_with("{{") {
+            "}}"
+        } else {
+            out.push_str(&body[position..=start]);
+            position = start + 1;
+            continue;
+        };
+        out.push_str(&body[position..start]);
+        let Some(end) = rest.find(close).map(|i| start + i + close.len()) else {
+            position = start;
+            break;
+        };
+        let tag = &body[start..end];
+        position = end;
+
+        let inner = tag[2..tag.len() - 2].trim_matches('-').trim();
+        let (name, arguments) = inner.split_once(char::is_whitespace).unwrap_or((inner, ""));
+        match (close, name) {
+            (_, "endraw") if raw => raw = false,
+            _ if raw => out.push_str(tag),
+            ("%}", "raw") => raw = true,
+            ("%}", "highlight") => {
+                let (lang, options) = highlight_options(arguments);
+                for option in options.unknown {
+                    attention.push((
+                        line(start),
+                        format!("the highlight option {option} isn't supported"),
+                    ));
+                }
+                start_line(&mut out);
+                out.push_str("```");
+                out.push_str(&lang);
+                for attribute in options.attributes {
+                    out.push(',');
+                    out.push_str(&attribute);
+                }
+            }
+            ("%}", "endhighlight") => {
+                start_line(&mut out);
+                out.push_str("```");
+            }
+            ("%}", _) => {
+                attention.push((line(start), format!("unknown liquid tag {tag}")));
+                out.push_str(tag);
+            }
+            _ => {
+                attention.push((line(start), format!("liquid output {tag}")));
+                out.push_str(tag);
+            }
+        }
+    }
+    out.push_str(&body[position..]);
+
+    out
+}
+
+// Start a new line, unless the output is already at the start of one.
+fn start_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+#[derive(Debug, Default)]
+struct HighlightOptions {
+    // The code fence attributes the options are converted to.
+    attributes: Vec<String>,
+    unknown: Vec<String>,
+}
+
+// Split the arguments of a `{% highlight %}` tag into its language and options.
+fn highlight_options(arguments: &str) -> (String, HighlightOptions) {
+    // Values can be quoted, like `mark_lines="1 2"`.
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in arguments.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    let mut words = words.into_iter();
+    let lang = words.next().unwrap_or_default();
+    let mut options = HighlightOptions::default();
+    for option in words {
+        match option.split_once('=').unwrap_or((&option, "")) {
+            ("linenos", _) => options.attributes.push("linenos".to_owned()),
+            ("mark_lines", lines) => options.attributes.push(format!("hl_lines={lines}")),
+            _ => options.unknown.push(option),
+        }
+    }
+
+    (lang, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use yar_site::{SiteBuilder, config::Config};
+
+    use super::*;
+
+    const POST: &str = r#"---
+layout: post
+title: "Hello, Jekyll"
+date: 2019-03-04 10:30:00 +0100
+categories: rust release
+tags: [announcements, rust]
+permalink: /blog/hello/
+excerpt: The first post.
+published: false
+comments: true
+hero:
+  image: /assets/hero.png
+  alt: null
+---
+
+Some *markdown*.
+
+{% highlight rust linenos %}
+fn main() {
+    println!("{}", 1);
+}
+{% endhighlight %}
+
+{% include note.html content="Careful" %}
+
+{% raw %}Written as {{ page.title }} and {% if %}{% endraw %}
+
+Posted in {{ page.categories }}.
+"#;
+
+    fn convert(content: &str, file_name: &str) -> (String, String, Vec<(Option<usize>, String)>) {
+        let converted = convert_jekyll(content, file_name);
+        (
+            converted.file_name,
+            converted.content.expect("the file should be converted"),
+            converted.attention,
+        )
+    }
+
+    #[test]
+    fn test_convert_post() {
+        let (file_name, content, attention) = convert(POST, "2019-03-04-hello.md");
+        assert_eq!(file_name, "hello.md");
+        assert_eq!(
+            content,
+            r#"---
+title = "Hello, Jekyll"
+description = "The first post."
+tags = ["rust", "release", "announcements"]
+template = "post.html"
+date = "2019-03-04T09:30:00"
+slug = "hello"
+draft = true
+aliases = ["/blog/hello/"]
+comments = true
+
+[hero]
+image = "/assets/hero.png"
+---
+
+Some *markdown*.
+
+```rust,linenos
+fn main() {
+    println!("{}", 1);
+}
+```
+
+{% include note.html content="Careful" %}
+
+Written as {{ page.title }} and {% if %}
+
+Posted in {{ page.categories }}.
+"#
+        );
+        assert_eq!(
+            attention,
+            [
+                (
+                    Some(24),
+                    r#"unknown liquid tag {% include note.html content="Careful" %}"#.to_owned()
+                ),
+                (Some(28), "liquid output {{ page.categories }}".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_dates() {
+        let post = "---\ntitle: Dated\n---\nHello\n";
+        let (file_name, content, _) = convert(post, "2024-05-17-dated-post.markdown");
+        assert_eq!(file_name, "dated-post.markdown");
+        assert!(
+            content.contains("date = \"2024-05-17T00:00:00\"\n"),
+            "{content}"
+        );
+        assert!(content.contains("slug = \"dated-post\"\n"), "{content}");
+
+        // A date in the frontmatter wins over the one in the file name.
+        let post = "---\ntitle: Dated\ndate: 2024-05-18\n---\nHello\n";
+        let (_, content, _) = convert(post, "2024-05-17-dated-post.md");
+        assert!(
+            content.contains("date = \"2024-05-18T00:00:00\"\n"),
+            "{content}"
+        );
+
+        // Pages without a date in their name keep it, and aren't given a slug.
+        let (file_name, content, _) = convert(post, "about.md");
+        assert_eq!(file_name, "about.md");
+        assert!(!content.contains("slug"), "{content}");
+
+        for name in ["2024-5-17-post.md", "2024-13-01-post.md", "2024-05-17.md"] {
+            assert_eq!(split_file_date(name), (None, name));
+        }
+    }
+
+    #[test]
+    fn test_convert_dates() {
+        for (date, expected) in [
+            ("2019-03-04 10:30:00 +0100", Some("2019-03-04T09:30:00")),
+            ("2019-03-04 10:30 -0500", Some("2019-03-04T15:30:00")),
+            ("2019-03-04T10:30:00Z", Some("2019-03-04T10:30:00")),
+            ("2019-03-04 10:30:00", Some("2019-03-04T10:30:00")),
+            ("2019-03-04", Some("2019-03-04T00:00:00")),
+            ("March 4th", None),
+        ] {
+            assert_eq!(convert_date(date).as_deref(), expected, "{date}");
+        }
+    }
+
+    #[test]
+    fn test_frontmatter_needing_attention() {
+        let post = "---\ndate: last tuesday\n---\nHello\n";
+        let (_, content, attention) = convert(post, "untitled-page.md");
+        assert!(content.starts_with("---\ntitle = \"untitled page\"\ntags = []\n---\n"));
+        assert_eq!(
+            attention,
+            [
+                (
+                    None,
+                    r#"there's no title, so it's "untitled page""#.to_owned()
+                ),
+                (
+                    None,
+                    r#"couldn't read the date String("last tuesday")"#.to_owned()
+                ),
+            ]
+        );
+
+        let converted = convert_jekyll("---\ntitle: [unclosed\n---\n", "broken.md");
+        assert!(converted.content.is_none());
+        assert_eq!(converted.attention.len(), 1);
+        assert!(
+            converted.attention[0]
+                .1
+                .starts_with("couldn't parse the frontmatter")
+        );
+    }
+
+    #[test]
+    fn test_highlight_options() {
+        let post = "---\ntitle: Code\n---\nText {% highlight python mark_lines=\"1 3\" hl_linenos %}\nprint()\n{% endhighlight %}\n";
+        let (_, content, attention) = convert(post, "code.md");
+        assert!(
+            content.ends_with("Text \n```python,hl_lines=1 3\nprint()\n```\n"),
+            "{content}"
+        );
+        assert_eq!(
+            attention,
+            [(
+                Some(4),
+                "the highlight option hl_linenos isn't supported".to_owned()
+            )]
+        );
+    }
+
+    fn write_files(root: &Path, files: &[(&str, &str)]) -> Result<()> {
+        for (path, content) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, content)?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_site() -> Result<()> {
+        let src = tempfile::tempdir()?;
+        let dest = tempfile::tempdir()?;
+        write_files(
+            src.path(),
+            &[
+                ("_posts/2019-03-04-hello.md", POST),
+                (
+                    "_posts/2020-01-01-second.markdown",
+                    "---\ntitle: Second\n---\n",
+                ),
+                (
+                    "about.md",
+                    "---\ntitle: About\nlayout: page\n---\nAbout me\n",
+                ),
+                ("assets/style.css", "body {}"),
+                ("_site/about.html", "Built"),
+                ("_site/index.md", "---\ntitle: Built\n---\n"),
+                (".git/README.md", "Not a page"),
+            ],
+        )?;
+
+        let report = import_site(ImportSource::Jekyll, src.path(), dest.path(), true)?;
+        let converted = [
+            ("_posts/2019-03-04-hello.md", "_posts/hello.md"),
+            (
+                "_posts/2020-01-01-second.markdown",
+                "_posts/second.markdown",
+            ),
+            ("about.md", "about.md"),
+        ]
+        .map(|(from, to)| (PathBuf::from(from), PathBuf::from(to)));
+        assert_eq!(report.converted, converted);
+        assert_eq!(report.skipped, [PathBuf::from("assets/style.css")]);
+        assert_eq!(report.attention.len(), 2);
+        assert_eq!(
+            report.attention[0].path,
+            Path::new("_posts/2019-03-04-hello.md")
+        );
+        assert_eq!(report.attention[0].line, Some(24));
+        // Dry runs don't write anything.
+        assert_eq!(fs::read_dir(dest.path())?.count(), 0);
+
+        import_site(ImportSource::Jekyll, src.path(), dest.path(), false)?;
+        let about = fs::read_to_string(dest.path().join("about.md"))?;
+        assert_eq!(
+            about,
+            "---\ntitle = \"About\"\ntags = []\ntemplate = \"page.html\"\n---\nAbout me\n"
+        );
+        assert!(dest.path().join("_posts/hello.md").exists());
+
+        // Importing again would overwrite the files, so nothing is written.
+        let err = import_site(ImportSource::Jekyll, src.path(), dest.path(), false).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("already exists, refusing to overwrite it")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_collisions() -> Result<()> {
+        let src = tempfile::tempdir()?;
+        let dest = tempfile::tempdir()?;
+        write_files(
+            src.path(),
+            &[
+                ("_posts/2019-03-04-post.md", "---\ntitle: One\n---\n"),
+                ("_posts/2020-03-04-post.md", "---\ntitle: Two\n---\n"),
+            ],
+        )?;
+
+        let err = import_site(ImportSource::Jekyll, src.path(), dest.path(), false).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("More than one file would be imported to")
+        );
+        assert_eq!(fs::read_dir(dest.path())?.count(), 0);
+
+        Ok(())
+    }
+
+    // Imported pages are rendered by yar as they are.
+    #[test]
+    fn test_imported_pages_render() -> Result<()> {
+        // The site's root is expected to be a directory in the working directory.
+        let src = tempfile::tempdir()?;
+        let root = tempfile::Builder::new()
+            .prefix("test-import-site")
+            .tempdir_in(".")?;
+        write_files(src.path(), &[("_posts/2019-03-04-hello.md", POST)])?;
+        write_files(
+            root.path(),
+            &[(
+                "templates/post.html",
+                "{{ document.frontmatter.title }} {{ document.date }}\n{{ document.content | safe }}",
+            )],
+        )?;
+        import_site(
+            ImportSource::Jekyll,
+            src.path(),
+            root.path().join("_content"),
+            false,
+        )?;
+
+        let mut config = Config::default();
+        config.site.root = PathBuf::from(root.path().file_name().unwrap());
+        config.site.development = true;
+        let site = SiteBuilder::from_config(config).with_memory_db().load()?;
+        let page = site.render_page_to_string("_content/_posts/hello.md", false)?;
+        assert!(
+            page.starts_with("Hello, Jekyll 2019-03-04T09:30:00"),
+            "{page}"
+        );
+        assert!(page.contains("<pre"), "{page}");
+
+        Ok(())
+    }
+}
@@ -1,32 +1,45 @@
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::missing_panics_doc)]
 
+mod import;
 mod new;
 mod server;
+mod watch;
 
 use std::{
     fs,
+    io::{self, Write},
+    num::NonZeroUsize,
     path::Path,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
-use clap::{Parser, Subcommand};
-use color_eyre::Result;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use color_eyre::{Result, eyre::bail};
 use figment::{
     Figment,
     providers::{Format, Serialized, Toml},
 };
-use notify_debouncer_mini::{DebounceEventResult, DebouncedEvent, new_debouncer, notify::Error};
 use tempfile::Builder;
-use tokio::signal::ctrl_c;
-use tower_livereload::{LiveReloadLayer, Reloader};
+use url::Url;
 use yar_site::{
-    Site,
-    config::Config,
+    BuildReport, Site, Timings,
+    cache::{CacheImport, export_cache, import_cache},
+    cancel::CancellationToken,
+    config::{Config, add_trailing_slash},
+    configure_jobs,
     database::{DatabaseSource, setup_database},
+    retry_io,
 };
 
-use crate::{new::create_site_template, server::run_server};
+use crate::{
+    import::{ImportReport, ImportSource, import_site},
+    new::{create_site_template, export_defaults},
+    server::{ADDRESS, Generations, livereload_layer, run_server},
+    watch::{rebuild, watch_and_rebuild},
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None, arg_required_else_help = true)]
@@ -45,131 +58,550 @@ enum Commands {
         /// Run a development build. In development builds, drafts are rendered.
         #[arg(long)]
         dev: bool,
+        /// Only render pages using the given template. Assets and static files aren't copied.
+        #[arg(long)]
+        only_template: Option<String>,
+        /// Only render pages in the given section, and copy the assets and static files in
+        /// directories named after it.
+        #[arg(long)]
+        only_section: Option<String>,
+        /// In partial builds, render the feed and sitemap with only the selected pages
+        /// instead of skipping them.
+        #[arg(long)]
+        filter_feeds: bool,
+        /// Write a JSON report of the output files this build created, modified, or deleted to the given path.
+        #[arg(long, conflicts_with = "watch")]
+        report_json: Option<String>,
+        /// Keep rebuilding the site into the output directory as files change, without
+        /// serving it.
+        #[arg(long)]
+        watch: bool,
+        /// The number of threads to build with, overriding `jobs` in the config. A single
+        /// job builds sequentially, in a deterministic order.
+        #[arg(long)]
+        jobs: Option<NonZeroUsize>,
+        /// Build the site for the given url instead of the one in the config, such as for
+        /// deploying to a staging site.
+        #[arg(long, value_parser = parse_base_url)]
+        base_url: Option<Url>,
+        /// Print what the build would change without writing to the output directory or
+        /// the cache. Post hooks aren't run. With `--report-json`, the plan is written as
+        /// JSON instead.
+        #[arg(long, conflicts_with_all = ["watch", "clean"])]
+        dry_run: bool,
+        /// Import a cache exported with `yar cache export` before building, so that builds
+        /// from a clean checkout are incremental. If it can't be used, such as when the
+        /// config changed, everything is built again.
+        #[arg(long, conflicts_with_all = ["watch", "clean", "dry_run"])]
+        cache_import: Option<String>,
+        /// Annotate rendered markdown with the file and line each paragraph, heading, and
+        /// shortcode comes from, as HTML comments, for debugging.
+        #[arg(long)]
+        debug_annotations: bool,
+        /// Print how long the pages using each template took to render, by the total time
+        /// spent rendering them. With `--report-json`, they're added to the report.
+        #[arg(long, conflicts_with_all = ["watch", "dry_run"])]
+        timings: bool,
+        /// Warn about pages whose template took longer than the given number of
+        /// milliseconds to render.
+        #[arg(long, value_name = "MS", conflicts_with_all = ["watch", "dry_run"])]
+        slow_page_ms: Option<u64>,
     },
     /// Create a new site.
     New { path: String },
+    /// Export the built-in templates so they can be copied into `templates/` and customized.
+    ExportDefaults {
+        /// The directory to write the templates to.
+        #[arg(default_value = "defaults")]
+        path: String,
+    },
     /// Build the site and serve it on a development web server.
     /// Hot reloading on file changes.
     Serve {
         #[arg(long)]
         clean: bool,
+        /// The number of threads to build with, overriding `jobs` in the config.
+        #[arg(long)]
+        jobs: Option<NonZeroUsize>,
+        /// Serve the site under a path, like `/project/`, to preview a site that's hosted
+        /// under one. The site is built for the server's url with that path.
+        #[arg(long, value_parser = parse_base_path)]
+        base_path: Option<String>,
+    },
+    /// Convert a site made with another static site generator, writing its markdown files
+    /// with yar's frontmatter to the same places under `dest`.
+    Import {
+        /// The static site generator the site was made with.
+        #[arg(long)]
+        from: ImportSource,
+        /// The directory of the site to import.
+        src: String,
+        /// The directory to write the converted files to, such as `site/_content`.
+        dest: String,
+        /// Print what would be converted without writing anything.
+        #[arg(long)]
+        dry_run: bool,
     },
+    /// Manage the build cache.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Print a completion script for the given shell, to be saved in its completions
+    /// directory.
+    Completions { shell: Shell },
+    /// Print the man page.
+    #[command(hide = true)]
+    Man,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Export the build cache and the hashes of the output files to a single compressed
+    /// file, to be imported with `yar build --cache-import`.
+    Export { file: String },
 }
 
+/// The file the config is read from, in the directory `yar` is run from.
+const CONFIG_FILE: &str = "Config.toml";
+
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
-    ensure_removed("temp/")?;
-
     let arguments = Args::parse();
-    let mut config: Config = Figment::from(Serialized::defaults(Config::default()))
-        .merge(Toml::file("Config.toml"))
-        .extract()?;
 
+    // These only print, so they shouldn't touch the site.
     match arguments.command {
-        Some(Commands::Build { clean, dev }) => {
-            config.site.development = dev;
-            let tmp_dir = Builder::new()
-                .prefix("temp")
-                .rand_bytes(0)
-                .tempdir_in(".")?;
-
-            // Build site in a temporary directory and copy it over once everything is built
-            let original_output_path = config.site.output_path;
-            config.site.output_path = tmp_dir.path().join("public");
-
-            // Clean build
-            if clean {
-                println!("Clean build, removing existing databases and output file");
-                ensure_removed(&config.site.db_file)?;
-                ensure_removed(&original_output_path)?;
-            }
-
-            let source = DatabaseSource::File(&config.site.db_file);
-            let conn = setup_database(source)?;
-            let now = Instant::now();
+        Some(Commands::Completions { shell }) => {
+            write_completions(shell, &mut io::stdout());
+            return Ok(());
+        }
+        Some(Commands::Man) => return write_man_page(&mut io::stdout()),
+        Some(Commands::Import {
+            from,
+            src,
+            dest,
+            dry_run,
+        }) => {
+            let report = import_site(from, &src, &dest, dry_run)?;
+            print_import_report(&report, &dest, dry_run);
+            return Ok(());
+        }
+        _ => (),
+    }
 
-            let mut site = Site::new(conn, config)?;
-            site.load()?;
-            site.render()?;
-            site.save_to_cache()?;
-            site.run_post_hooks()?;
+    ensure_removed("temp/")?;
+    let mut config = load_config(CONFIG_FILE)?;
 
-            let elapsed = now.elapsed();
-            println!("Built site in {elapsed:.2?}");
-            copy_dir_all(tmp_dir.path().join("public"), original_output_path)?;
+    match arguments.command {
+        Some(Commands::Build {
+            clean,
+            dev,
+            only_template,
+            only_section,
+            filter_feeds,
+            report_json,
+            watch,
+            jobs,
+            base_url,
+            dry_run,
+            cache_import,
+            debug_annotations,
+            timings,
+            slow_page_ms,
+        }) => {
+            if let Some(url) = base_url {
+                config.site.url = url;
+            }
+            if jobs.is_some() {
+                config.site.jobs = jobs;
+            }
+            configure_jobs(config.site.jobs)?;
+            config.site.development = dev;
+            config.site.filter.only_template = only_template;
+            config.site.filter.only_section = only_section;
+            config.site.filter.filter_feeds = filter_feeds;
+            config.site.markdown.debug_annotations = debug_annotations;
+            if watch {
+                build_and_watch(config, clean).await?;
+            } else if dry_run {
+                dry_run_build(config, report_json.as_deref())?;
+            } else {
+                build(
+                    config,
+                    clean,
+                    report_json.as_deref(),
+                    cache_import.as_deref(),
+                    timings,
+                    slow_page_ms.map(Duration::from_millis),
+                )?;
+            }
         }
         Some(Commands::New { path }) => {
             println!("Creating new site at {path}");
             create_site_template(path)?;
             println!("Created site");
         }
-        Some(Commands::Serve { clean }) => {
-            config.site.development = true;
-            let tmp_dir = Builder::new()
-                .prefix("temp")
-                .rand_bytes(0)
-                .tempdir_in(".")?;
-            let serve_path = tmp_dir.path().join("public"); // The path the static file server will serve files from.
-
-            // Build site in a temporary directory
-            config.site.output_path = tmp_dir.path().join("public");
-
-            // Clean build
-            if clean {
-                println!("Clean build, removing existing databases and output file");
-                ensure_removed(&config.site.db_file)?;
+        Some(Commands::ExportDefaults { path }) => {
+            export_defaults(&path)?;
+            println!("Exported default templates to {path}");
+        }
+        Some(Commands::Cache {
+            command: CacheCommand::Export { file },
+        }) => export(&config, &file)?,
+        Some(Commands::Serve {
+            clean,
+            jobs,
+            base_path,
+        }) => serve(config, clean, jobs, base_path).await?,
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Build the site and serve it, rebuilding it and reloading browsers as files change.
+async fn serve(
+    mut config: Config,
+    clean: bool,
+    jobs: Option<NonZeroUsize>,
+    base_path: Option<String>,
+) -> Result<()> {
+    if jobs.is_some() {
+        config.site.jobs = jobs;
+    }
+    let base_path: Arc<str> = base_path.unwrap_or_else(|| "/".to_owned()).into();
+    if base_path.as_ref() != "/" {
+        config.site.url = Url::parse(&format!("http://{ADDRESS}{base_path}"))?;
+    }
+    configure_jobs(config.site.jobs)?;
+    config.site.development = true;
+    let tmp_dir = Builder::new()
+        .prefix("temp")
+        .rand_bytes(0)
+        .tempdir_in(".")?;
+
+    // Build site in a temporary directory, which is copied into a new generation
+    // for the server to serve once each build is finished, so that requests
+    // during a rebuild don't get half written files.
+    let rendered = tmp_dir.path().join("public-next");
+    config.site.output_path.clone_from(&rendered);
+
+    // Clean build
+    if clean {
+        println!("Clean build, removing existing databases and output file");
+        ensure_removed(&config.site.db_file)?;
+    }
+
+    let root = config.site.root.clone();
+    let theme = config.site.syntax_theme_path.clone();
+    let development = config.site.development;
+    let conn = setup_database(DatabaseSource::Memory)?;
+    let mut site = Site::new(conn, config)?;
+
+    println!("Building site.");
+    rebuild(&mut site, &CancellationToken::new())?;
+    let generations = Generations::new(tmp_dir.path(), &rendered, Duration::from_secs(10))?;
+    let serve_root = generations.root();
+
+    let livereload = livereload_layer(&base_path);
+    let reloader = livereload.reloader();
+
+    let server_task = tokio::spawn(async move {
+        run_server(serve_root, livereload, tmp_dir, development, base_path).await
+    });
+    // Each build is published before the next one starts writing to the output directory.
+    let generations = Arc::new(tokio::sync::Mutex::new(generations));
+    let livereload_task = tokio::spawn(async move {
+        watch_and_rebuild(site, root, theme, || {
+            let generations = generations.clone();
+            let reloader = reloader.clone();
+            let rendered = rendered.clone();
+            async move {
+                // Browsers are only reloaded once the new build is being served.
+                generations.lock().await.publish(rendered).await?;
+                reloader.reload();
+                Ok(())
+            }
+        })
+        .await
+    });
+
+    livereload_task.await??;
+    server_task.await??;
+
+    Ok(())
+}
+
+/// Read the config from a file, with the defaults for anything it leaves out.
+fn load_config<P: AsRef<Path>>(path: P) -> Result<Config> {
+    Ok(Figment::from(Serialized::defaults(Config::default()))
+        .merge(Toml::file(path))
+        .extract()?)
+}
+
+/// Build the site into the output directory.
+fn build(
+    mut config: Config,
+    clean: bool,
+    report_json: Option<&str>,
+    cache_import: Option<&str>,
+    timings: bool,
+    slow_page: Option<Duration>,
+) -> Result<()> {
+    let tmp_dir = Builder::new()
+        .prefix("temp")
+        .rand_bytes(0)
+        .tempdir_in(".")?;
+
+    // Build site in a temporary directory and copy it over once everything is built
+    let original_output_path = config.site.output_path;
+    config.site.output_path = tmp_dir.path().join("public");
+
+    // Clean build
+    if clean {
+        println!("Clean build, removing existing databases and output file");
+        ensure_removed(&config.site.db_file)?;
+        ensure_removed(&original_output_path)?;
+    }
+
+    let source = DatabaseSource::File(&config.site.db_file);
+    let conn = setup_database(source)?;
+    if let Some(file) = cache_import {
+        match import_cache(&conn, &config, &original_output_path, file)? {
+            CacheImport::Imported { outputs } => {
+                println!("Imported the cache from {file}, built with {outputs} output files");
             }
+            CacheImport::Rejected(reason) => {
+                println!("Not using the cache from {file}, building everything: {reason}");
+            }
+        }
+    }
+    let now = Instant::now();
+
+    let mut site = Site::new(conn, config)?;
+    site.load()?;
+    site.render()?;
+    site.run_post_hooks()?;
+    site.render_second_pass()?;
+    site.save_to_cache()?;
+
+    let elapsed = now.elapsed();
+    println!("Built site in {elapsed:.2?}");
+    if timings {
+        print_timings(site.timings());
+    }
+    if let Some(threshold) = slow_page {
+        for page in site.timings().slow_pages(threshold) {
+            println!(
+                "Warning: {} took {:.2?} to render with {}, and {:.2?} to minify and write",
+                page.path.display(),
+                page.render,
+                page.template,
+                page.write
+            );
+        }
+    }
 
-            let root = config.site.root.clone();
-            let conn = setup_database(DatabaseSource::Memory)?;
-            let mut site = Site::new(conn, config)?;
-
-            let now = Instant::now();
-            println!("Building site.");
-            site.load()?;
-            site.render()?;
-            site.save_to_cache()?;
-            site.run_post_hooks()?;
-
-            let elapsed = now.elapsed();
-            println!("Built site in {elapsed:.2?}");
-
-            let livereload = LiveReloadLayer::new();
-            let reloader = livereload.reloader();
-
-            let (tx, rx) = tokio::sync::mpsc::channel(32);
-
-            let mut debouncer = new_debouncer(
-                Duration::from_millis(50),
-                move |res: DebounceEventResult| {
-                    tx.blocking_send(res).expect("Problem with sending message");
-                },
-            )?;
-            debouncer
-                .watcher()
-                .watch(&root, notify::RecursiveMode::Recursive)?;
-
-            let server_task =
-                tokio::spawn(async move { run_server(serve_path, livereload, tmp_dir).await });
-            let livereload_task = tokio::spawn(run_livereload(reloader, site, rx));
-
-            livereload_task.await??;
-            server_task.await??;
+    // Compare against the previous output before it's overwritten.
+    if let Some(report_path) = report_json {
+        let mut report = BuildReport::compare(
+            tmp_dir.path().join("public"),
+            &original_output_path,
+            site.removed_outputs(),
+            elapsed,
+        )?;
+        if timings {
+            report = report.with_timings(site.timings());
         }
-        _ => unreachable!(),
+        report.write_json(report_path)?;
+        println!(
+            "Wrote build report to {report_path} ({} created, {} modified, {} deleted)",
+            report.created, report.modified, report.deleted
+        );
+    }
+
+    copy_dir_all(tmp_dir.path().join("public"), &original_output_path)?;
+    for removed in site.removed_outputs() {
+        ensure_removed(original_output_path.join(removed))?;
+    }
+
+    for warning in site.audit_sizes(&original_output_path)? {
+        println!("Warning: {warning}");
+    }
+
+    Ok(())
+}
+
+/// Render the site to a temporary directory, and print what building it would change in the
+/// output directory, leaving the output directory and the cache as they are.
+fn dry_run_build(mut config: Config, report_json: Option<&str>) -> Result<()> {
+    let tmp_dir = Builder::new()
+        .prefix("temp")
+        .rand_bytes(0)
+        .tempdir_in(".")?;
+    let output_path = config.site.output_path;
+    config.site.output_path = tmp_dir.path().join("public");
+
+    let conn = setup_database(DatabaseSource::Snapshot(&config.site.db_file))?;
+    let now = Instant::now();
+
+    let mut site = Site::new(conn, config)?;
+    site.load()?;
+    site.render()?;
+    let plan = site.plan(&output_path, now.elapsed())?;
+
+    if let Some(report_path) = report_json {
+        plan.write_json(report_path)?;
+        println!("Wrote build plan to {report_path}");
+        return Ok(());
+    }
+
+    println!("Dry run, nothing was written");
+    println!(
+        "{} pages to create, {} to update",
+        plan.pages_created, plan.pages_updated
+    );
+    println!(
+        "{} output files to create, {} to modify, {} to delete",
+        plan.report.created, plan.report.modified, plan.report.deleted
+    );
+    for changed in &plan.report.changed {
+        println!("  {:?} {}", changed.change, changed.path.display());
+    }
+    println!(
+        "{} assets to compile, {} static files to copy",
+        plan.assets.len(),
+        plan.static_files.len()
+    );
+    println!("Permalinks:");
+    for permalink in &plan.permalinks {
+        println!("  {permalink}");
     }
 
     Ok(())
 }
 
+/// Export the build cache, along with the hashes of the files in the output directory.
+fn export(config: &Config, file: &str) -> Result<()> {
+    if !config.site.db_file.exists() {
+        bail!(
+            "There's no cache at {}, so the site needs to be built before it's exported",
+            config.site.db_file.display()
+        );
+    }
+
+    let conn = setup_database(DatabaseSource::File(&config.site.db_file))?;
+    let outputs = export_cache(&conn, &config.site.output_path, file)?;
+    println!("Exported the cache to {file}, along with the hashes of {outputs} output files");
+
+    Ok(())
+}
+
+/// Build the site straight into the output directory, and keep rebuilding it as files change.
+async fn build_and_watch(config: Config, clean: bool) -> Result<()> {
+    if clean {
+        println!("Clean build, removing existing databases and output file");
+        ensure_removed(&config.site.db_file)?;
+        ensure_removed(&config.site.output_path)?;
+    }
+
+    let root = config.site.root.clone();
+    let theme = config.site.syntax_theme_path.clone();
+    let conn = setup_database(DatabaseSource::File(&config.site.db_file))?;
+    let mut site = Site::new(conn, config)?;
+    rebuild(&mut site, &CancellationToken::new())?;
+
+    println!("Watching for changes");
+    watch_and_rebuild(site, root, theme, || std::future::ready(Ok(()))).await
+}
+
+/// Print what an import converted, and what in it needs to be looked at by hand.
+fn print_timings(timings: &Timings) {
+    println!("Template render timings:");
+    for template in timings.templates() {
+        println!(
+            "  {}: {:.2?} total, {:.2?} max over {} pages",
+            template.template, template.total, template.max, template.count
+        );
+    }
+}
+
+fn print_import_report(report: &ImportReport, dest: &str, dry_run: bool) {
+    if dry_run {
+        println!("Dry run, nothing was written");
+    }
+    let converted = if dry_run {
+        "Would convert"
+    } else {
+        "Converted"
+    };
+    println!(
+        "{converted} {} files to {dest}, leaving out {} files that aren't markdown",
+        report.converted.len(),
+        report.skipped.len()
+    );
+    for (from, to) in &report.converted {
+        println!("  {} -> {}", from.display(), to.display());
+    }
+
+    if !report.attention.is_empty() {
+        println!(
+            "{} things need to be looked at by hand:",
+            report.attention.len()
+        );
+    }
+    for attention in &report.attention {
+        match attention.line {
+            Some(line) => println!(
+                "  {}:{line}: {}",
+                attention.path.display(),
+                attention.reason
+            ),
+            None => println!("  {}: {}", attention.path.display(), attention.reason),
+        }
+    }
+}
+
+/// Write the completion script for the given shell.
+fn write_completions<W: Write>(shell: Shell, out: &mut W) {
+    clap_complete::generate(shell, &mut Args::command(), "yar", out);
+}
+
+/// Write the man page, generated from the command line arguments.
+fn write_man_page<W: Write>(out: &mut W) -> Result<()> {
+    clap_mangen::Man::new(Args::command()).render(out)?;
+    Ok(())
+}
+
+/// Parse a site url, adding the trailing slash that relative links are resolved against.
+fn parse_base_url(url: &str) -> Result<Url, url::ParseError> {
+    let mut url = Url::parse(url)?;
+    add_trailing_slash(&mut url);
+    Ok(url)
+}
+
+/// Parse a path to serve the site under, giving it the slashes it's joined with.
+fn parse_base_path(path: &str) -> Result<String, String> {
+    let path = path.trim_matches('/');
+    if path.contains(['?', '#', '\\']) {
+        return Err("The base path can only be a path".to_owned());
+    }
+    if path.is_empty() {
+        return Ok("/".to_owned());
+    }
+
+    Ok(format!("/{path}/"))
+}
+
 fn copy_dir_all<T: AsRef<Path>, Z: AsRef<Path>>(src: T, out: Z) -> Result<()> {
     fs::create_dir_all(&out)?;
 
     for entry in fs::read_dir(src)? {
         let entry = entry?;
         if entry.file_type()?.is_file() {
-            fs::copy(entry.path(), out.as_ref().join(entry.file_name()))?;
+            let out = out.as_ref().join(entry.file_name());
+            retry_io(|| fs::copy(entry.path(), &out))?;
         } else {
             copy_dir_all(entry.path(), out.as_ref().join(entry.file_name()))?;
         }
@@ -177,48 +609,84 @@ fn copy_dir_all<T: AsRef<Path>, Z: AsRef<Path>>(src: T, out: Z) -> Result<()> {
     Ok(())
 }
 
-// If the given file exists, delete it.
+// If the given file exists, delete it, retrying while another process has it open.
 fn ensure_removed<T: AsRef<Path>>(path: T) -> Result<()> {
     let path = path.as_ref();
 
     if path.exists() {
         if path.is_dir() {
-            fs::remove_dir_all(path)?;
+            retry_io(|| fs::remove_dir_all(path))?;
         } else {
-            fs::remove_file(path)?;
+            retry_io(|| fs::remove_file(path))?;
         }
     }
 
     Ok(())
 }
 
-async fn run_livereload(
-    reloader: Reloader,
-    mut site: Site<'_>,
-    mut rx: tokio::sync::mpsc::Receiver<Result<Vec<DebouncedEvent>, Error>>,
-) -> Result<()> {
-    loop {
-        tokio::select! {
-            Some(Ok(events)) = rx.recv() => {
-                for _ in events {
-                    let now = Instant::now();
-                    println!("Filesystem changes detected...rebuilding site");
-                    site.load()?;
-                    site.render()?;
-                    site.save_to_cache()?;
-                    site.run_post_hooks()?;
-
-                    let elapsed = now.elapsed();
-                    println!("Built site in {elapsed:.2?}");
-
-                    reloader.reload();
-                }
-            },
-            _ = ctrl_c() => {
-                break;
-            }
-        };
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
     }
 
-    Ok(())
+    #[test]
+    fn test_completions() {
+        for shell in Shell::value_variants() {
+            let mut out = vec![];
+            write_completions(*shell, &mut out);
+
+            // Fish lists long flags without their dashes.
+            let script = String::from_utf8_lossy(&out);
+            assert!(
+                script.contains("--clean") || script.contains("-l clean"),
+                "{shell} completions:\n{script}"
+            );
+            assert!(script.contains("serve"), "{shell} completions:\n{script}");
+        }
+    }
+
+    #[test]
+    fn test_man_page() -> Result<()> {
+        let mut out = vec![];
+        write_man_page(&mut out)?;
+
+        let page = String::from_utf8(out)?;
+        assert!(page.contains("yar\\-build"));
+        assert!(!page.contains("yar\\-man"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_base_url() -> Result<()> {
+        assert_eq!(
+            parse_base_url("https://staging.example.com")?.as_str(),
+            "https://staging.example.com/"
+        );
+        assert_eq!(
+            parse_base_url("https://example.com/blog")?.as_str(),
+            "https://example.com/blog/"
+        );
+        assert_eq!(
+            parse_base_url("https://example.com/blog/")?.as_str(),
+            "https://example.com/blog/"
+        );
+        assert!(parse_base_url("example.com").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_base_path() {
+        assert_eq!(parse_base_path("project"), Ok("/project/".to_owned()));
+        assert_eq!(parse_base_path("/docs/v2/"), Ok("/docs/v2/".to_owned()));
+        assert_eq!(parse_base_path("/"), Ok("/".to_owned()));
+        assert!(parse_base_path("/project?a=b").is_err());
+    }
 }
@@ -5,6 +5,7 @@ use std::{
 };
 
 use color_eyre::{Result, eyre::bail};
+use yar_site::DEFAULT_TEMPLATES;
 
 const DEFAULT_CONFIG: &str = "
 [site]
@@ -14,47 +15,89 @@ const DEFAULT_CONFIG: &str = "
 # Hook related config.
 ";
 
-const DEFAULT_PAGE_TEMPLATE: &str = r#"
+const DEFAULT_BASE_TEMPLATE: &str = r#"
 <!DOCTYPE html>
-<html lang="eng">
+<html lang="en">
     <head>
-        <title> {{ document.frontmatter.title }} </title>
-        <meta name="viewport" content="width device-width, initial-scale=1" />
-        <meta name="description" content="{{ document.summary | safe }}" />
+        <meta charset="utf-8" />
+        <meta name="viewport" content="width=device-width, initial-scale=1" />
+        <title>{% block title %}{{ site.title or "My Site" }}{% endblock %}</title>
+        {% block head %}{% endblock %}
+        <link rel="alternate" type="application/atom+xml" title="{{ site.title or "Feed" }}" href="{{ site.url }}atom.xml" />
+        {{ head_links() }}
     </head>
 
-    <div>
-        <h1> {{ document.frontmatter.title }} </h1>
-        <p> {{ document.date }} </p>
-        <p> {{ document.frontmatter.tags }} </p>
+    <body>
+        {% set current = permalink %}
+        <nav>
+            <a href="{{ site.url }}"{% if current == site.url %} aria-current="page"{% endif %}>Home</a>
+            {% for page in pages %}
+            {% if page.path is not endingwith "index.md" %}
+            <a href="{{ page.permalink }}"{% if current == page.permalink %} aria-current="page"{% endif %}>{{ page.document.frontmatter.title }}</a>
+            {% endif %}
+            {% endfor %}
+        </nav>
 
-        <div>
-            {{ document.content | safe }}
-        </div>
-    </div>
+        <main>
+            {% block content %}{% endblock %}
+        </main>
+
+        {% if site.email %}
+        <footer>
+            <a href="{{ site.email | obfuscate_email(strategy="mailto") }}">{{ site.email | obfuscate_email }}</a>
+        </footer>
+        {% endif %}
+    </body>
 </html>
 "#;
 
-const DEFAULT_INDEX_TEMPLATE: &str = r#"
-<!DOCTYPE html>
-<html lang="eng">
-    <head>
-        <title> All Pages </title>
-        <meta name="viewport" content="width device-width, initial-scale=1" />
-    </head>
+const DEFAULT_PAGE_TEMPLATE: &str = r##"
+{% extends "base.html" %}
 
-    <div>
-        <h1> All Pages </h1>
-        {% for page in pages %}
-        {% if page.path is not endingwith "index.md" %}
-            <div>
-                <h1> {{ page.document.frontmatter.title }} </h1>
-                <a href="{{ page.permalink}}"> {{ page.permalink }} </a>
-            </div>
-        {% endif %}
+{% block title %}{{ document.frontmatter.title }}{% endblock %}
+
+{% block head %}
+<meta name="description" content="{{ document.summary_text }}" />
+{% endblock %}
+
+{% block content %}
+<article>
+    <h1>{{ document.frontmatter.title }}</h1>
+    <p>{{ document.date | in_timezone | datetimeformat(format="long") }}</p>
+    <p>{{ document.frontmatter.tags | join(", ") }}</p>
+
+    {% if document.has_toc %}
+    <nav>
+        <ul>
+        {% for heading in document.toc recursive %}
+            <li>
+                <a href="#{{ heading.id | urlencode }}">{{ heading.text }}</a>
+                {% if heading.children %}<ul>{{ loop(heading.children) }}</ul>{% endif %}
+            </li>
         {% endfor %}
-    </div>
-</html>
+        </ul>
+    </nav>
+    {% endif %}
+
+    {{ document.content | safe }}
+</article>
+{% endblock %}
+"##;
+
+const DEFAULT_INDEX_TEMPLATE: &str = r#"
+{% extends "base.html" %}
+
+{% block content %}
+<h1>All Pages</h1>
+{% for page in pages %}
+{% if page.path is not endingwith "index.md" %}
+<article>
+    <h2><a href="{{ page.permalink }}">{{ page.document.frontmatter.title }}</a></h2>
+    {{ page.document.summary | safe }}
+</article>
+{% endif %}
+{% endfor %}
+{% endblock %}
 "#;
 
 const DEFAULT_PAGE: &str = r#"---
@@ -64,6 +107,16 @@ template = "page.html"
 ---
 
 This is a page!
+
+<!-- more -->
+
+## Getting Started
+
+Edit this page in `site/_content/hello-world.md`.
+
+## Next Steps
+
+Templates live in `site/templates`, starting with `base.html`.
 "#;
 
 const DEFAULT_INDEX: &str = r#"---
@@ -73,6 +126,13 @@ template = "index.html"
 ---
 "#;
 
+/// The templates the site scaffold starts out with, by name.
+const SCAFFOLD_TEMPLATES: &[(&str, &str)] = &[
+    ("base.html", DEFAULT_BASE_TEMPLATE),
+    ("page.html", DEFAULT_PAGE_TEMPLATE),
+    ("index.html", DEFAULT_INDEX_TEMPLATE),
+];
+
 pub fn create_site_template<P: AsRef<Path>>(path: P) -> Result<()> {
     let path = path.as_ref();
 
@@ -82,11 +142,14 @@ pub fn create_site_template<P: AsRef<Path>>(path: P) -> Result<()> {
     fs::create_dir_all(path)?;
 
     write_to_file(path.join("Config.toml"), DEFAULT_CONFIG)?;
-    write_to_file(path.join("site/templates/page.html"), DEFAULT_PAGE_TEMPLATE)?;
-    write_to_file(
-        path.join("site/templates/index.html"),
-        DEFAULT_INDEX_TEMPLATE,
-    )?;
+    // The form protected pages are unlocked with is built in, but it's scaffolded too, so
+    // that it's at hand to be styled.
+    let protected = DEFAULT_TEMPLATES
+        .iter()
+        .filter(|(name, _)| *name == "protected.html");
+    for (name, template) in SCAFFOLD_TEMPLATES.iter().chain(protected) {
+        write_to_file(path.join("site/templates").join(name), template)?;
+    }
     write_to_file(path.join("site/_content/hello-world.md"), DEFAULT_PAGE)?;
     write_to_file(path.join("site/_content/index.md"), DEFAULT_INDEX)?;
     write_to_file(path.join("site/.ignore"), "templates/")?;
@@ -94,8 +157,103 @@ pub fn create_site_template<P: AsRef<Path>>(path: P) -> Result<()> {
     Ok(())
 }
 
+/// Write all of the built-in templates and the scaffold templates to the given directory.
+///
+/// Any of these can be copied into a site's `templates/` directory and customized, which
+/// overrides the built-in version.
+pub fn export_defaults<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+
+    for (name, template) in DEFAULT_TEMPLATES.iter().chain(SCAFFOLD_TEMPLATES) {
+        let out = path.join(name);
+        if fs::exists(&out)? {
+            bail!("File {out:?} already exists, refusing to overwrite it")
+        }
+        write_to_file(out, template)?;
+    }
+
+    Ok(())
+}
+
 fn write_to_file<P: AsRef<Path>>(path: P, contents: &str) -> Result<()> {
     fs::create_dir_all(path.as_ref().parent().unwrap())?;
     File::create(path)?.write_all(contents.as_bytes())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use yar_site::{SiteBuilder, config::Config};
+
+    use super::*;
+
+    #[test]
+    fn test_export_defaults() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        export_defaults(dir.path())?;
+
+        for (name, template) in DEFAULT_TEMPLATES.iter().chain(SCAFFOLD_TEMPLATES) {
+            assert_eq!(fs::read_to_string(dir.path().join(name))?, *template);
+        }
+
+        // Exporting again shouldn't clobber anything.
+        assert!(export_defaults(dir.path()).is_err());
+
+        Ok(())
+    }
+
+    // An example of snapshot testing a site's pages, using the scaffold site.
+    #[test]
+    fn test_scaffold_index() -> Result<()> {
+        // The site's root is expected to be a directory in the working directory.
+        let scaffold = tempfile::Builder::new()
+            .prefix("test-scaffold")
+            .tempdir_in(".")?;
+        let root = tempfile::Builder::new()
+            .prefix("test-scaffold-site")
+            .tempdir_in(".")?;
+        create_site_template(scaffold.path().join("new"))?;
+        fs::remove_dir(root.path())?;
+        fs::rename(scaffold.path().join("new/site"), root.path())?;
+
+        let mut config = Config::default();
+        config.site.root = PathBuf::from(root.path().file_name().unwrap());
+        let site = SiteBuilder::from_config(config.clone())
+            .with_memory_db()
+            .load()?;
+
+        let index = site.render_page_to_string("_content/index.md", true)?;
+        insta::assert_snapshot!(index);
+
+        // The page template formats its date with filters that have to be registered.
+        let page = site.render_page_to_string("_content/hello-world.md", true)?;
+        assert!(page.contains(r#"<meta content="This is a page!" name=description>"#));
+        assert!(page.contains("<link href=http://0.0.0.0:8000/hello-world rel=canonical>"));
+        assert!(page.contains("<a aria-current=page href=http://0.0.0.0:8000/hello-world>"));
+        assert!(page.contains("<a href=#getting-started>Getting Started</a>"));
+
+        let page = site.render_page_to_string("_content/hello-world.md", false)?;
+        let head = &page[page.find("<head>").unwrap()..page.find("</head>").unwrap()];
+        insta::assert_snapshot!("scaffold_page_head", head);
+
+        let page = fs::read_to_string(root.path().join("_content/hello-world.md"))?.replacen(
+            "---\n",
+            "---\ndescription = 'Hand \"written\" & <b>bold</b>'\n",
+            1,
+        );
+        fs::write(root.path().join("_content/hello-world.md"), page)?;
+        config.site.email = Some("me@example.com".to_owned());
+        let site = SiteBuilder::from_config(config).with_memory_db().load()?;
+        let page = site.render_page_to_string("_content/hello-world.md", true)?;
+        // The description's quotes and markup are escaped rather than breaking the tag.
+        assert!(page.contains(r#"<meta content='Hand "written" & bold' name=description>"#));
+
+        // The footer's email survives minification without being decoded.
+        assert!(page.contains("<footer>"), "{page}");
+        assert!(!page.contains("me@example.com"), "{page}");
+
+        Ok(())
+    }
+}
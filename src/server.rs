@@ -1,28 +1,48 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 
 use axum::Router;
+use axum::extract::{OriginalUri, Request};
+use axum::handler::HandlerWithoutStateExt;
+use axum::http::{HeaderValue, StatusCode, Uri, header};
+use axum::response::{Html, IntoResponse, Redirect, Response};
 use color_eyre::Result;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
 use tempfile::TempDir;
 use tokio::signal::ctrl_c;
+use tower::ServiceExt;
 use tower_http::services::{ServeDir, ServeFile};
 use tower_http::trace::TraceLayer;
 use tower_livereload::LiveReloadLayer;
+use yar_site::retry_io;
 
-pub async fn run_server<P: AsRef<Path>>(
-    output_dir: P,
+use crate::ensure_removed;
+
+/// The address the server listens on.
+pub const ADDRESS: &str = "127.0.0.1:5050";
+
+/// Serve the site under `base_path`, like `/project/` for a site hosted at
+/// `https://user.github.io/project/`, or `/` for the root.
+pub async fn run_server(
+    root: ServeRoot,
     livereload: LiveReloadLayer,
     tmp_dir: TempDir,
+    development: bool,
+    base_path: Arc<str>,
 ) -> Result<()> {
-    let static_files = ServeDir::new(&output_dir)
-        .not_found_service(ServeFile::new(output_dir.as_ref().join("404.html")));
-
+    println!("Listening on http://{ADDRESS}{base_path}");
     let router = Router::new()
-        .fallback_service(static_files)
+        .fallback(move |request: Request| {
+            serve(root.current(), development, base_path.clone(), request)
+        })
         .layer(livereload)
         .layer(TraceLayer::new_for_http());
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:5050").await?;
-    println!("Listening on http://127.0.0.1:5050/");
+    let listener = tokio::net::TcpListener::bind(ADDRESS).await?;
     axum::serve(listener, router)
         .with_graceful_shutdown(shutdown_signal(tmp_dir))
         .await?;
@@ -30,9 +50,551 @@ pub async fn run_server<P: AsRef<Path>>(
     Ok(())
 }
 
+/// The directory the server serves files from, which is swapped for a new one once a
+/// rebuild is finished.
+///
+/// Requests take the current root when they start, and keep using it even if it's
+/// swapped partway through, so they never see files from two different builds.
+#[derive(Debug, Clone)]
+pub struct ServeRoot(Arc<RwLock<Arc<PathBuf>>>);
+
+impl ServeRoot {
+    pub fn new(path: PathBuf) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(path))))
+    }
+
+    /// The directory to serve files from.
+    pub fn current(&self) -> Arc<PathBuf> {
+        self.0.read().expect("Serve root lock poisoned").clone()
+    }
+
+    /// Serve files from another directory, returning the previous one.
+    pub fn swap(&self, path: PathBuf) -> Arc<PathBuf> {
+        let mut current = self.0.write().expect("Serve root lock poisoned");
+        std::mem::replace(&mut *current, Arc::new(path))
+    }
+}
+
+/// The builds the server has served, each copied from the output directory into a
+/// generation of its own so that rebuilds never touch the files being served.
+///
+/// Files that haven't changed since the last generation are hard linked to it rather than
+/// copied again. Generations are never written to, so sharing files between them is safe.
+pub struct Generations {
+    dir: PathBuf,
+    next: usize,
+    root: ServeRoot,
+    grace_period: Duration,
+    // The size and modification time of each rendered file when the current generation
+    // was published, relative to the output directory.
+    published: HashMap<PathBuf, FileStamp>,
+}
+
+type FileStamp = (u64, SystemTime);
+
+impl Generations {
+    /// Serve the site rendered to `rendered` as the first generation, keeping generations
+    /// in `dir`. Generations that are no longer served are removed after `grace_period`,
+    /// so that requests still reading from them can finish.
+    pub fn new<P: AsRef<Path>, T: AsRef<Path>>(
+        dir: P,
+        rendered: T,
+        grace_period: Duration,
+    ) -> Result<Self> {
+        let first = dir.as_ref().join("public-0");
+        let published = link_generation(rendered.as_ref(), &first, &first, &HashMap::new())?;
+
+        Ok(Self {
+            dir: dir.as_ref().to_owned(),
+            next: 1,
+            root: ServeRoot::new(first),
+            grace_period,
+            published,
+        })
+    }
+
+    /// The root the server serves the current generation from.
+    pub fn root(&self) -> ServeRoot {
+        self.root.clone()
+    }
+
+    /// Copy the site rendered to `rendered` into a new generation, and serve it instead of
+    /// the current one.
+    ///
+    /// The files are copied on a blocking thread, so that the server keeps answering
+    /// requests from the current generation in the meantime.
+    pub async fn publish(&mut self, rendered: PathBuf) -> Result<()> {
+        let generation = self.dir.join(format!("public-{}", self.next));
+        let current = self.root.current();
+        // If publishing fails, the next generation is copied in full.
+        let published = std::mem::take(&mut self.published);
+        let (generation, published) = tokio::task::spawn_blocking(move || {
+            let published = link_generation(&rendered, &current, &generation, &published)?;
+            Result::<_>::Ok((generation, published))
+        })
+        .await??;
+        self.published = published;
+        self.next += 1;
+
+        let previous = self.root.swap(generation);
+        let grace_period = self.grace_period;
+        tokio::spawn(async move {
+            tokio::time::sleep(grace_period).await;
+            if let Err(e) = ensure_removed(&*previous) {
+                println!("Error removing {}: {e}", previous.display());
+            }
+        });
+
+        Ok(())
+    }
+}
+
+// Fill `generation` with the files in `rendered`, hard linking the ones that are the same
+// as when `current` was published to it, and copying the rest. Returns the stamps of the
+// files in `rendered`, for publishing the next generation.
+fn link_generation(
+    rendered: &Path,
+    current: &Path,
+    generation: &Path,
+    published: &HashMap<PathBuf, FileStamp>,
+) -> Result<HashMap<PathBuf, FileStamp>> {
+    let mut stamps = HashMap::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(relative) = dirs.pop() {
+        fs::create_dir_all(generation.join(&relative))?;
+        for entry in fs::read_dir(rendered.join(&relative))? {
+            let entry = entry?;
+            let relative = relative.join(entry.file_name());
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                dirs.push(relative);
+                continue;
+            }
+
+            let stamp = (metadata.len(), metadata.modified()?);
+            let out = generation.join(&relative);
+            let linked = published.get(&relative) == Some(&stamp)
+                && fs::hard_link(current.join(&relative), &out).is_ok();
+            if !linked {
+                retry_io(|| fs::copy(entry.path(), &out))?;
+            }
+            stamps.insert(relative, stamp);
+        }
+    }
+
+    Ok(stamps)
+}
+
+/// The layer reloading browsers, with its routes under `base_path` like everything else.
+pub fn livereload_layer(base_path: &str) -> LiveReloadLayer {
+    if base_path == "/" {
+        LiveReloadLayer::new()
+    } else {
+        LiveReloadLayer::new().custom_prefix(format!(
+            "{base_path}tower-livereload/long-name-to-avoid-collisions"
+        ))
+    }
+}
+
+// Serve a request for a path under the base path from the given root.
+async fn serve(
+    root: Arc<PathBuf>,
+    development: bool,
+    base_path: Arc<str>,
+    mut request: Request,
+) -> Response {
+    let original = request.uri().clone();
+    let Some(uri) = strip_base_path(&original, &base_path) else {
+        // The root leads to the site, since it's where browsers start.
+        if original.path() == "/" {
+            return Redirect::temporary(&base_path).into_response();
+        }
+        let message = format!("Not found, the site is served under {base_path}");
+        return (StatusCode::NOT_FOUND, message).into_response();
+    };
+    *request.uri_mut() = uri;
+    request.extensions_mut().insert(OriginalUri(original));
+
+    let mut response = match router(&root, development).oneshot(request).await {
+        Ok(response) => response,
+        Err(e) => match e {},
+    };
+    // Redirects, like from a directory to the path with a trailing slash, are relative
+    // to the base path too.
+    if let Some(location) = response.headers().get(header::LOCATION)
+        && let Some(path) = location.to_str().ok().filter(|l| l.starts_with('/'))
+        && let Ok(location) =
+            HeaderValue::from_str(&format!("{}{path}", base_path.trim_end_matches('/')))
+    {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+
+    response
+}
+
+// Take the base path off the start of a uri, or `None` if it's outside of the base path.
+fn strip_base_path(uri: &Uri, base_path: &str) -> Option<Uri> {
+    let path_and_query = uri.path_and_query().map_or("/", |p| p.as_str());
+    let base = base_path.trim_end_matches('/');
+    let rest = path_and_query.strip_prefix(base)?;
+    let rest = match rest.chars().next() {
+        None => "/".to_owned(),
+        Some('/') => rest.to_owned(),
+        Some('?') => format!("/{rest}"),
+        Some(_) => return None,
+    };
+
+    rest.parse().ok()
+}
+
+/// Build the router serving files from the output directory.
+///
+/// In development, requests for a directory without an `index.html` get a
+/// generated listing of its contents instead of the 404 page.
+fn router(output_dir: &Path, development: bool) -> Router {
+    if development {
+        let root = output_dir.to_owned();
+        let listing =
+            move |uri: Uri, original: OriginalUri| directory_listing(root.clone(), uri, original);
+        let static_files = ServeDir::new(output_dir).fallback(listing.into_service());
+        Router::new().fallback_service(static_files)
+    } else {
+        let static_files = ServeDir::new(output_dir)
+            .not_found_service(ServeFile::new(output_dir.join("404.html")));
+        Router::new().fallback_service(static_files)
+    }
+}
+
+// Render a listing for the requested directory if it exists, otherwise fall back to the 404 page.
+//
+// Links in the listing are to the path the directory was requested at, which includes the
+// base path the site is served under.
+async fn directory_listing(
+    root: PathBuf,
+    uri: Uri,
+    OriginalUri(original): OriginalUri,
+) -> Response {
+    let request_path = percent_decode_str(uri.path()).decode_utf8_lossy();
+    let link_path = percent_decode_str(original.path()).decode_utf8_lossy();
+
+    if let Some(dir) = resolve_directory(&root, &request_path)
+        && let Ok(listing) = render_listing(&dir, &link_path).await
+    {
+        return Html(listing).into_response();
+    }
+
+    tokio::fs::read_to_string(root.join("404.html"))
+        .await
+        .map_or_else(
+            |_| StatusCode::NOT_FOUND.into_response(),
+            |page| (StatusCode::NOT_FOUND, Html(page)).into_response(),
+        )
+}
+
+// Map a request path onto a directory inside of root, rejecting anything that could escape it.
+fn resolve_directory(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let mut dir = root.to_owned();
+    for component in Path::new(request_path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(c) => dir.push(c),
+            Component::CurDir => (),
+            _ => return None,
+        }
+    }
+
+    (dir.is_dir() && !dir.join("index.html").exists()).then_some(dir)
+}
+
+async fn render_listing(dir: &Path, request_path: &str) -> std::io::Result<String> {
+    let base = if request_path.ends_with('/') {
+        request_path.to_owned()
+    } else {
+        format!("{request_path}/")
+    };
+
+    let mut names = vec![];
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type().await?.is_dir() {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    names.sort();
+
+    let title = escape_html(&base);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<title>Index of {title}</title>\n<h1>Index of {title}</h1>\n\
+         <p><em>Development only: generated by yar serve because this directory has no index.html. \
+         This listing is not part of the built site.</em></p>\n<ul>\n"
+    );
+    let link_base = utf8_percent_encode(&base, LINK).to_string();
+    for name in names {
+        let href = escape_html(&format!("{link_base}{}", utf8_percent_encode(&name, LINK)));
+        let _ = writeln!(
+            html,
+            "<li><a href=\"{href}\">{}</a></li>",
+            escape_html(&name)
+        );
+    }
+    html.push_str("</ul>\n");
+
+    Ok(html)
+}
+
+// The characters percent-encoded in the links of a listing. Slashes are left alone, since
+// they separate the segments of the path.
+const LINK: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 async fn shutdown_signal(tmp_dir: TempDir) {
     ctrl_c().await.expect("Failed to wait for CTRL + C signal.");
 
     println!("Gracefully shutting down...");
     tmp_dir.close().expect("Error closing temporary directory.");
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+
+    use super::*;
+
+    fn make_output_dir() -> Result<TempDir> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("posts/nested"))?;
+        fs::create_dir_all(dir.path().join("docs"))?;
+        fs::write(dir.path().join("posts/hello.html"), "hello")?;
+        fs::write(dir.path().join("docs/index.html"), "docs index")?;
+        fs::write(dir.path().join("404.html"), "not found page")?;
+        Ok(dir)
+    }
+
+    async fn get(router: Router, path: &str) -> Result<(StatusCode, String)> {
+        let response = router
+            .oneshot(Request::get(path).body(Body::empty())?)
+            .await?;
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok((status, String::from_utf8(body.to_vec())?))
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing() -> Result<()> {
+        let dir = make_output_dir()?;
+
+        let (status, body) = get(router(dir.path(), true), "/posts/").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Development only"));
+        assert!(body.contains(r#"<a href="/posts/hello.html">hello.html</a>"#));
+        assert!(body.contains(r#"<a href="/posts/nested/">nested/</a>"#));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_directory_listing_encodes_links() -> Result<()> {
+        let dir = make_output_dir()?;
+        fs::create_dir_all(dir.path().join("a b#1"))?;
+        fs::write(dir.path().join("a b#1/50% off?.html"), "sale")?;
+
+        let (status, body) = get(router(dir.path(), true), "/a%20b%231/").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"<a href="/a%20b%231/50%25%20off%3F.html">50% off?.html</a>"#));
+
+        let (status, body) =
+            get(router(dir.path(), true), "/a%20b%231/50%25%20off%3F.html").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "sale");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_directory_with_index() -> Result<()> {
+        let dir = make_output_dir()?;
+
+        let (status, body) = get(router(dir.path(), true), "/docs/").await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "docs index");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_path_falls_back_to_404() -> Result<()> {
+        let dir = make_output_dir()?;
+
+        let (status, body) = get(router(dir.path(), true), "/missing/").await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, "not found page");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_serve_root() -> Result<()> {
+        let old = tempfile::tempdir()?;
+        let new = tempfile::tempdir()?;
+        for (dir, content) in [(&old, "old"), (&new, "new")] {
+            fs::write(dir.path().join("index.html"), content)?;
+            fs::write(dir.path().join("styles.css"), content)?;
+        }
+
+        // Readers keep to the root they started with, so every file they read comes from
+        // the same build, however often the root is swapped while they read.
+        let root = ServeRoot::new(old.path().to_owned());
+        // Scoped threads are joined at the end of the scope, which panics if a reader did.
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..200 {
+                        let current = root.current();
+                        let read = |name| fs::read_to_string(current.join(name)).expect("Read");
+                        assert_eq!(read("index.html"), read("styles.css"));
+                    }
+                });
+            }
+
+            for i in 0..200 {
+                let next = if i % 2 == 0 { &new } else { &old };
+                root.swap(next.path().to_owned());
+            }
+        });
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_publish_generation() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let rendered = tmp.path().join("public-next");
+        fs::create_dir_all(&rendered)?;
+        fs::write(rendered.join("index.html"), "first")?;
+
+        let mut generations = Generations::new(tmp.path(), &rendered, Duration::from_millis(10))?;
+        let root = generations.root();
+        let first = root.current();
+
+        // Rendering doesn't change what's served until the build is published.
+        fs::write(rendered.join("index.html"), "second")?;
+        let request = || Request::get("/index.html").body(axum::body::Body::empty());
+        let response = serve(root.current(), false, "/".into(), request()?).await;
+        assert_eq!(to_bytes(response.into_body(), usize::MAX).await?, "first");
+
+        generations.publish(rendered.clone()).await?;
+        let response = serve(root.current(), false, "/".into(), request()?).await;
+        assert_eq!(to_bytes(response.into_body(), usize::MAX).await?, "second");
+
+        // The previous generation is removed once the grace period is over.
+        assert!(first.exists());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!first.exists());
+        assert!(root.current().exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_publish_links_unchanged_files() -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let tmp = tempfile::tempdir()?;
+        let rendered = tmp.path().join("public-next");
+        fs::create_dir_all(rendered.join("styles"))?;
+        fs::write(rendered.join("index.html"), "first")?;
+        fs::write(rendered.join("styles/main.css"), "body {}")?;
+
+        let mut generations = Generations::new(tmp.path(), &rendered, Duration::from_secs(10))?;
+        let first = generations.root().current();
+        fs::write(rendered.join("index.html"), "second")?;
+        generations.publish(rendered.clone()).await?;
+        let second = generations.root().current();
+
+        // The unchanged stylesheet is shared with the previous generation, while the page
+        // is copied again.
+        let inode = |dir: &Path, name| fs::metadata(dir.join(name)).map(|m| m.ino());
+        assert_eq!(
+            inode(&first, "styles/main.css")?,
+            inode(&second, "styles/main.css")?
+        );
+        assert_ne!(inode(&first, "index.html")?, inode(&second, "index.html")?);
+        assert_eq!(fs::read_to_string(first.join("index.html"))?, "first");
+        assert_eq!(fs::read_to_string(second.join("index.html"))?, "second");
+
+        // Neither is the file in the output directory, which later builds write to.
+        assert_ne!(
+            inode(&rendered, "styles/main.css")?,
+            inode(&second, "styles/main.css")?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_listing_outside_development() -> Result<()> {
+        let dir = make_output_dir()?;
+
+        let (status, body) = get(router(dir.path(), false), "/posts/").await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, "not found page");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_base_path() -> Result<()> {
+        let dir = make_output_dir()?;
+        let root = Arc::new(dir.path().to_owned());
+        let request = |path: &str| {
+            let request = Request::get(path).body(Body::empty())?;
+            Ok::<_, axum::http::Error>(serve(root.clone(), true, "/project/".into(), request))
+        };
+        let location = |response: &Response| response.headers()[header::LOCATION].clone();
+
+        let response = request("/project/posts/hello.html")?.await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(to_bytes(response.into_body(), usize::MAX).await?, "hello");
+
+        // Listings and redirects stay under the base path.
+        let response = request("/project/posts/")?.await;
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        assert!(
+            String::from_utf8(body.to_vec())?.contains(r#"<a href="/project/posts/hello.html">"#)
+        );
+        let response = request("/project/docs")?.await;
+        assert!(response.status().is_redirection());
+        assert_eq!(location(&response), "/project/docs/");
+
+        // Paths outside of it aren't served, though the root leads to it.
+        let response = request("/posts/hello.html")?.await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = request("/projectile/")?.await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = request("/")?.await;
+        assert_eq!(location(&response), "/project/");
+
+        Ok(())
+    }
+}
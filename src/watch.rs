@@ -0,0 +1,566 @@
+use std::{
+    future::Future,
+    iter,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+use notify::RecursiveMode;
+use notify_debouncer_mini::{DebounceEventResult, DebouncedEvent, new_debouncer};
+use tokio::{signal::ctrl_c, sync::mpsc::Receiver, task::JoinHandle};
+use yar_site::{
+    Site,
+    cancel::{CancellationToken, Cancelled, check},
+};
+
+use crate::{CONFIG_FILE, load_config};
+
+/// Build the site, running its post hooks.
+///
+/// Once `token` is cancelled, the build stops with [`Cancelled`] at the next chance it
+/// gets, without caching anything, so the next build picks up where it left off.
+pub fn rebuild(site: &mut Site, token: &CancellationToken) -> Result<()> {
+    let now = Instant::now();
+    site.load_cancellable(token)?;
+    site.render_cancellable(token)?;
+    check(token)?;
+    site.run_post_hooks()?;
+    check(token)?;
+    site.render_second_pass()?;
+    check(token)?;
+    site.save_to_cache()?;
+
+    let elapsed = now.elapsed();
+    println!("Built site in {elapsed:.2?}");
+
+    Ok(())
+}
+
+/// Watch the site's root for changes, rebuilding the site and calling `on_rebuilt` after
+/// each rebuild until Ctrl-C is pressed.
+///
+/// The config and the syntax theme's file, `theme`, are watched too, wherever they are.
+/// Changes to them reload the markdown renderer before rebuilding, so that the markdown
+/// options and highlighting can be changed without starting over.
+pub async fn watch_and_rebuild<P, F, R>(
+    site: Site,
+    root: P,
+    theme: Option<PathBuf>,
+    on_rebuilt: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut() -> R,
+    R: Future<Output = Result<()>>,
+{
+    let watched = Watched::new(root.as_ref(), Path::new(CONFIG_FILE), theme.as_deref())?;
+    let (tx, rx) = tokio::sync::mpsc::channel(32);
+
+    let mut debouncer = new_debouncer(
+        Duration::from_millis(50),
+        move |res: DebounceEventResult| {
+            // Nothing is listening anymore once we've stopped watching.
+            let _ = tx.blocking_send(res);
+        },
+    )?;
+    debouncer
+        .watcher()
+        .watch(&watched.root, RecursiveMode::Recursive)?;
+    for dir in watched.outside_root() {
+        debouncer
+            .watcher()
+            .watch(&dir, RecursiveMode::NonRecursive)?;
+    }
+
+    rebuild_on_changes(site, rx, watched, ctrl_c(), on_rebuilt).await
+}
+
+// The paths being watched, made absolute so that they can be compared against the paths
+// of events.
+struct Watched {
+    root: PathBuf,
+    config: PathBuf,
+    theme: Option<PathBuf>,
+}
+
+// What has to be reloaded before the next rebuild.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Reload {
+    config: bool,
+    theme: bool,
+}
+
+impl Watched {
+    fn new(root: &Path, config: &Path, theme: Option<&Path>) -> Result<Self> {
+        Ok(Self {
+            root: std::path::absolute(root)?,
+            config: std::path::absolute(config)?,
+            theme: theme.map(std::path::absolute).transpose()?,
+        })
+    }
+
+    // The directories holding the config and the theme outside the root, which are
+    // watched on their own. Files are replaced rather than written to by some editors, so
+    // their directories are watched instead of them.
+    fn outside_root(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![];
+        for file in iter::once(&self.config).chain(&self.theme) {
+            if let Some(dir) = file.parent()
+                && !dir.starts_with(&self.root)
+                && !dirs.iter().any(|d| d == dir)
+            {
+                dirs.push(dir.to_owned());
+            }
+        }
+        dirs
+    }
+
+    // What the events call for, or `None` if they're all for other files in the
+    // directories watched for the config and the theme, like the database.
+    fn classify(&self, events: &[DebouncedEvent]) -> Option<Reload> {
+        let mut reload = None::<Reload>;
+        for event in events {
+            let Ok(path) = std::path::absolute(&event.path) else {
+                continue;
+            };
+            let config = path == self.config;
+            let theme = self.theme.as_ref().is_some_and(|t| path == *t);
+            if config || theme || path.starts_with(&self.root) {
+                let r = reload.get_or_insert_default();
+                r.config |= config;
+                r.theme |= theme;
+            }
+        }
+        reload
+    }
+}
+
+impl Reload {
+    // Reload the renderer if the config or the theme changed. The config is read again
+    // for the markdown options and the syntax theme, the only parts of it that are picked
+    // up without starting over.
+    fn apply(self, site: &mut Site, config: &Path) -> Result<()> {
+        if self.config {
+            println!("The config changed, reloading the markdown options and syntax theme");
+            site.update_renderer_config(&load_config(config)?)?;
+        } else if self.theme {
+            println!("The syntax theme changed, reloading it");
+            site.reload_renderer()?;
+        }
+
+        Ok(())
+    }
+
+    const fn merge(self, other: Self) -> Self {
+        Self {
+            config: self.config || other.config,
+            theme: self.theme || other.theme,
+        }
+    }
+}
+
+// A rebuild running on a blocking thread, which hands the site back once it's done.
+struct Running {
+    token: CancellationToken,
+    handle: JoinHandle<(Site, Result<()>)>,
+}
+
+impl Running {
+    fn start(mut site: Site, reload: Reload, config: &Path) -> Self {
+        let token = CancellationToken::new();
+        let child = token.clone();
+        let config = config.to_owned();
+        let handle = tokio::task::spawn_blocking(move || {
+            let result = reload
+                .apply(&mut site, &config)
+                .and_then(|()| rebuild(&mut site, &child));
+            (site, result)
+        });
+
+        Self { token, handle }
+    }
+}
+
+/// Rebuild the site for each batch of filesystem events received, until `shutdown`
+/// resolves or there are no more events.
+///
+/// Rebuilds run on a blocking thread. Changes arriving during one cancel it, and it's
+/// started again once it stops, picking up every change since. Shutting down or running
+/// out of events waits for an in-flight rebuild to finish.
+async fn rebuild_on_changes<S, F, R>(
+    site: Site,
+    mut rx: Receiver<DebounceEventResult>,
+    watched: Watched,
+    shutdown: S,
+    mut on_rebuilt: F,
+) -> Result<()>
+where
+    S: Future,
+    F: FnMut() -> R,
+    R: Future<Output = Result<()>>,
+{
+    tokio::pin!(shutdown);
+
+    let mut idle = Some(site);
+    let mut running: Option<Running> = None;
+    // What the changes that came in during a rebuild need reloaded before it's started
+    // again. Reloading happens before the rebuild, so it isn't undone by cancelling it.
+    let mut pending = Reload::default();
+    loop {
+        tokio::select! {
+            events = rx.recv() => match events {
+                Some(Ok(events)) => {
+                    let Some(reload) = watched.classify(&events) else {
+                        continue;
+                    };
+                    if let Some(r) = &running {
+                        println!("Filesystem changes detected...restarting rebuild");
+                        pending = pending.merge(reload);
+                        r.token.cancel();
+                    } else if let Some(site) = idle.take() {
+                        println!("Filesystem changes detected...rebuilding site");
+                        running = Some(Running::start(site, reload, &watched.config));
+                    }
+                }
+                Some(Err(e)) => println!("Error watching for changes: {e}"),
+                None => break,
+            },
+            finished = async { (&mut running.as_mut().unwrap().handle).await }, if running.is_some() => {
+                let changed = running.take().is_some_and(|r| r.token.is_cancelled());
+                let site = stopped(finished?, changed, &mut on_rebuilt).await?;
+                // Changes that came in while it was running are built now.
+                if changed {
+                    let reload = std::mem::take(&mut pending);
+                    running = Some(Running::start(site, reload, &watched.config));
+                } else {
+                    idle = Some(site);
+                }
+            }
+            _ = &mut shutdown => {
+                if let Some(r) = running.take() {
+                    let changed = r.token.is_cancelled();
+                    stopped(r.handle.await?, changed, &mut on_rebuilt).await?;
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    while let Some(r) = running.take() {
+        let changed = r.token.is_cancelled();
+        let site = stopped(r.handle.await?, changed, &mut on_rebuilt).await?;
+        if changed {
+            let reload = std::mem::take(&mut pending);
+            running = Some(Running::start(site, reload, &watched.config));
+        }
+    }
+
+    Ok(())
+}
+
+// Take the site back from a stopped rebuild, calling `on_rebuilt` if it finished. Rebuilds
+// cancelled because of changes that came in while they were running aren't errors.
+async fn stopped<F: FnMut() -> R, R: Future<Output = Result<()>>>(
+    (site, result): (Site, Result<()>),
+    changed: bool,
+    on_rebuilt: &mut F,
+) -> Result<Site> {
+    match result {
+        Ok(()) => on_rebuilt().await?,
+        Err(e) if changed && e.is::<Cancelled>() => (),
+        Err(e) => return Err(e),
+    }
+
+    Ok(site)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, thread};
+
+    use notify_debouncer_mini::{DebouncedEvent, DebouncedEventKind};
+    use tempfile::TempDir;
+    use yar_site::{
+        ShortcodeFn,
+        config::Config,
+        database::{DatabaseSource, setup_database},
+    };
+
+    use super::*;
+
+    // A site with a single page, `_content/hello.md`, with the given body.
+    fn watched_site(body: &str) -> Result<(TempDir, TempDir, PathBuf, Config)> {
+        let root = tempfile::Builder::new()
+            .prefix("test-watch")
+            .tempdir_in(".")?;
+        let out = tempfile::tempdir()?;
+        let page = root.path().join("_content/hello.md");
+        fs::create_dir_all(page.parent().unwrap())?;
+        fs::create_dir_all(root.path().join("templates"))?;
+        fs::write(
+            root.path().join("templates/post.html"),
+            "{{ document.content | safe }}",
+        )?;
+        fs::write(&page, page_content(body))?;
+
+        let mut config = Config::default();
+        config.site.root = PathBuf::from(root.path().file_name().unwrap());
+        config.site.output_path = out.path().to_owned();
+
+        Ok((root, out, page, config))
+    }
+
+    fn page_content(body: &str) -> String {
+        format!("---\ntitle = \"Hello\"\ntags = []\n---\n\n{body}\n")
+    }
+
+    // The site's root, along with a config and no theme in it.
+    fn watched(root: &TempDir) -> Result<Watched> {
+        Watched::new(root.path(), &root.path().join("Config.toml"), None)
+    }
+
+    async fn send_changes(site: Site, watched: Watched, paths: &[&Path]) -> Result<usize> {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let events = paths
+            .iter()
+            .map(|p| DebouncedEvent::new(p.to_path_buf(), DebouncedEventKind::Any))
+            .collect();
+        tx.send(Ok(events)).await?;
+        drop(tx);
+
+        let mut rebuilds = 0;
+        rebuild_on_changes(site, rx, watched, std::future::pending::<()>(), || {
+            rebuilds += 1;
+            std::future::ready(Ok(()))
+        })
+        .await?;
+
+        Ok(rebuilds)
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_on_changes() -> Result<()> {
+        let (root, out, page, config) = watched_site("First")?;
+        let mut site = Site::new(setup_database(DatabaseSource::Memory)?, config)?;
+        rebuild(&mut site, &CancellationToken::new())?;
+
+        let output = out.path().join("Hello/index.html");
+        assert!(fs::read_to_string(&output)?.contains("First"));
+
+        fs::write(&page, page_content("Second"))?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let event = DebouncedEvent::new(page.clone(), DebouncedEventKind::Any);
+        tx.send(Ok(vec![event.clone(), event])).await?;
+        drop(tx);
+
+        let mut rebuilds = 0;
+        rebuild_on_changes(
+            site,
+            rx,
+            watched(&root)?,
+            std::future::pending::<()>(),
+            || {
+                rebuilds += 1;
+                std::future::ready(Ok(()))
+            },
+        )
+        .await?;
+
+        assert_eq!(rebuilds, 1);
+        assert!(fs::read_to_string(&output)?.contains("Second"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_changes_cancel_rebuild() -> Result<()> {
+        // The page takes a while to build, so changes come in while it's being rebuilt.
+        let (root, out, page, config) = watched_site("First {{! slow() !}}{{! end !}}")?;
+        let slow: ShortcodeFn = Box::new(|_| {
+            thread::sleep(Duration::from_millis(300));
+            Ok(String::new())
+        });
+        let mut site = Site::with_shortcodes(
+            setup_database(DatabaseSource::Memory)?,
+            config,
+            [("slow".to_string(), slow)],
+        )?;
+        rebuild(&mut site, &CancellationToken::new())?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let event = DebouncedEvent::new(page.clone(), DebouncedEventKind::Any);
+        fs::write(&page, page_content("Second {{! slow() !}}{{! end !}}"))?;
+        tx.send(Ok(vec![event.clone()])).await?;
+        let changes = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            fs::write(&page, page_content("Third {{! slow() !}}{{! end !}}"))?;
+            tx.send(Ok(vec![event])).await?;
+            Result::<()>::Ok(())
+        });
+
+        let mut rebuilds = 0;
+        rebuild_on_changes(
+            site,
+            rx,
+            watched(&root)?,
+            std::future::pending::<()>(),
+            || {
+                rebuilds += 1;
+                std::future::ready(Ok(()))
+            },
+        )
+        .await?;
+        changes.await??;
+
+        // The first rebuild was cancelled partway through, so only the one started after
+        // it finished, with every change since.
+        assert_eq!(rebuilds, 1);
+        let output = fs::read_to_string(out.path().join("Hello/index.html"))?;
+        assert!(output.contains("Third"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_finishes_rebuild() -> Result<()> {
+        let (root, out, page, config) = watched_site("First {{! slow() !}}{{! end !}}")?;
+        let slow: ShortcodeFn = Box::new(|_| {
+            thread::sleep(Duration::from_millis(300));
+            Ok(String::new())
+        });
+        let mut site = Site::with_shortcodes(
+            setup_database(DatabaseSource::Memory)?,
+            config,
+            [("slow".to_string(), slow)],
+        )?;
+        rebuild(&mut site, &CancellationToken::new())?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        fs::write(&page, page_content("Second {{! slow() !}}{{! end !}}"))?;
+        tx.send(Ok(vec![DebouncedEvent::new(page, DebouncedEventKind::Any)]))
+            .await?;
+
+        // Shutting down partway through the rebuild waits for it instead of cancelling it.
+        let mut rebuilds = 0;
+        rebuild_on_changes(
+            site,
+            rx,
+            watched(&root)?,
+            tokio::time::sleep(Duration::from_millis(100)),
+            || {
+                rebuilds += 1;
+                std::future::ready(Ok(()))
+            },
+        )
+        .await?;
+        drop(tx);
+
+        assert_eq!(rebuilds, 1);
+        let output = fs::read_to_string(out.path().join("Hello/index.html"))?;
+        assert!(output.contains("Second"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_theme_change_reloads_renderer() -> Result<()> {
+        let (root, out, _page, mut config) = watched_site("```rust\nfn main() {}\n```")?;
+        // The theme is outside the site's root, like in a shared directory.
+        let themes = tempfile::tempdir()?;
+        let theme = themes.path().join("theme.toml");
+        fs::write(&theme, "keyword = \"#ff0000\"")?;
+        config.site.syntax_theme_path = Some(theme.clone());
+        let mut site = Site::new(setup_database(DatabaseSource::Memory)?, config)?;
+        rebuild(&mut site, &CancellationToken::new())?;
+
+        let css = out.path().join("styles/_syntax.css");
+        assert!(fs::read_to_string(&css)?.contains("#ff0000"));
+
+        fs::write(&theme, "keyword = \"#00ff00\"")?;
+        let watched = Watched::new(root.path(), &root.path().join("Config.toml"), Some(&theme))?;
+        assert_eq!(
+            watched.outside_root(),
+            [std::path::absolute(themes.path())?]
+        );
+        assert_eq!(send_changes(site, watched, &[&theme]).await?, 1);
+        assert!(fs::read_to_string(&css)?.contains("#00ff00"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_change_reloads_renderer() -> Result<()> {
+        let (root, out, _page, config) = watched_site(":rocket:")?;
+        let mut site = Site::new(setup_database(DatabaseSource::Memory)?, config)?;
+        rebuild(&mut site, &CancellationToken::new())?;
+
+        let output = out.path().join("Hello/index.html");
+        assert!(fs::read_to_string(&output)?.contains(":rocket:"));
+
+        let config_file = root.path().join("Config.toml");
+        fs::write(&config_file, "[site.markdown]\nemoji = true\n")?;
+        assert_eq!(
+            send_changes(site, watched(&root)?, &[&config_file]).await?,
+            1
+        );
+        assert!(fs::read_to_string(&output)?.contains("🚀"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_classify() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let outside = tempfile::tempdir()?;
+        let theme = outside.path().join("theme.toml");
+        let watched = Watched::new(
+            root.path(),
+            &outside.path().join("Config.toml"),
+            Some(&theme),
+        )?;
+        let classify = |paths: &[PathBuf]| {
+            let events = paths
+                .iter()
+                .map(|p| DebouncedEvent::new(p.clone(), DebouncedEventKind::Any))
+                .collect::<Vec<_>>();
+            watched.classify(&events)
+        };
+
+        assert_eq!(
+            classify(&[root.path().join("_content/hello.md")]),
+            Some(Reload::default())
+        );
+        assert_eq!(
+            classify(&[outside.path().join("Config.toml"), theme]),
+            Some(Reload {
+                config: true,
+                theme: true
+            })
+        );
+        // Other files next to the config, like the database, don't rebuild the site.
+        assert_eq!(classify(&[outside.path().join("site.db")]), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancelled_rebuild_isnt_cached() -> Result<()> {
+        let (_root, out, page, config) = watched_site("First")?;
+        let mut site = Site::new(setup_database(DatabaseSource::Memory)?, config)?;
+        rebuild(&mut site, &CancellationToken::new())?;
+
+        fs::write(&page, page_content("Second"))?;
+        let token = CancellationToken::new();
+        token.cancel();
+        assert!(rebuild(&mut site, &token).unwrap_err().is::<Cancelled>());
+
+        // Nothing was cached, so the next rebuild still sees the change.
+        rebuild(&mut site, &CancellationToken::new())?;
+        let output = fs::read_to_string(out.path().join("Hello/index.html"))?;
+        assert!(output.contains("Second"));
+
+        Ok(())
+    }
+}